@@ -0,0 +1,46 @@
+//! Applies arbitrary patches to a small old file. Any error is fine, so long
+//! as nothing panics or runs away with memory. The checked-in corpus of
+//! malformed patches makes for a good starting point:
+//!
+//! ```sh
+//! cargo +nightly fuzz run patch_file ../tests/fixtures/malformed_patches
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use shticker_book_unwritten::{hash::Hash, patch};
+use std::{env, fs, path::PathBuf, sync::LazyLock};
+
+const OLD: &[u8] = b"hello old world\n\
+                     hello old world\n\
+                     hello old world\n\
+                     hello old world\n";
+
+static DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    let dir = env::temp_dir()
+        .join(format!("sbu-fuzz-patch_file-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("old"), OLD).unwrap();
+
+    dir
+});
+
+/// Whatever the patch produces is checked against this, which it almost
+/// certainly won't match.
+static HASH: LazyLock<Hash> = LazyLock::new(|| {
+    Hash::from_hex("0123456789abcdef0123456789abcdef01234567").unwrap()
+});
+
+fuzz_target!(|data: &[u8]| {
+    let patch_path = DIR.join("patch");
+    fs::write(&patch_path, data).unwrap();
+
+    let _ = patch::patch_file(
+        &patch_path,
+        DIR.join("old"),
+        DIR.join("new"),
+        1 << 24,
+        &HASH,
+    );
+});
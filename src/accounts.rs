@@ -1,39 +1,94 @@
 //! `accounts`/`logins` command & its subcommands.
 
-use crate::{
+use shticker_book_unwritten::{
     config::{Config, commit_config},
     error::Error,
+    output::{self, OutputFormat},
+    totp,
 };
-use std::{
-    io::{self, Write},
-    path::Path,
-    process, time,
-};
+use std::{io, path::Path, process, time};
 
 const ACCOUNTS_HELP_TEXT: &str = "\
 Account-management subcommands
 ==============================
 accounts help       Display this message.
-accounts forget     Forget the specified account, erasing its username &
-  [username]          password from the config and from the Secret Service
-                      keyring, where applicable.
+accounts forget     Forget the specified account (or, given @group, every
+  [username]          account in the group), erasing its username & password
+                      from the config and from the Secret Service keyring,
+                      where applicable.
+accounts alias      Give the account a nickname that it can be referred to
+  <username>          by wherever a username is expected, or take its
+  [alias]             nickname away if alias isn't given.
+accounts group      List every group, along with the accounts in it.
+accounts group add  Put the accounts in the group, which can then be referred
+  <group>             to as @group wherever a username is expected, e.g. to
+  <usernames...>      log into all of its accounts at once.
+accounts group      Take the accounts out of the group, or, if none are
+  remove <group>      given, take every account out of it.
+  [usernames...]
 accounts savepws    Set the value of store_passwords in your config. Note that
   <true | false>      setting the value to false will NOT cause any passwords
                       to be forgotten.
+accounts 2fa set    Save the two-factor authentication secret (the base32
+  <username>          key, or otpauth:// URI, shown when setting up an
+  [secret]            authenticator app) for the account, so that codes are
+                      generated automatically when logging in. If the secret
+                      isn't given, you will be asked for it.
+accounts 2fa clear  Forget the two-factor authentication secret for the
+  <username>          account.
 ";
 
-pub(crate) fn accounts_help() {
-    print!("{ACCOUNTS_HELP_TEXT}");
+pub(crate) fn accounts_help(output: OutputFormat) {
+    output::print_text(output, "help", ACCOUNTS_HELP_TEXT);
 }
 
 pub(crate) fn display_accounts(
     config: &Config,
     children: &[(String, process::Child, time::Instant)],
+    output: OutputFormat,
 ) -> Result<(), Error> {
     #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
     let stored_accounts: Vec<String> = Vec::new();
     #[cfg(all(target_os = "linux", feature = "secret-store"))]
-    let stored_accounts = crate::keyring::stored_accounts()?;
+    let stored_accounts = shticker_book_unwritten::keyring::stored_accounts()?;
+
+    #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
+    let accounts = config
+        .accounts
+        .keys()
+        .map(|un| (un, config.saved_password(un).is_some()));
+    #[cfg(all(target_os = "linux", feature = "secret-store"))]
+    let accounts = stored_accounts.iter().map(|u| (u, true)).chain(
+        config
+            .accounts
+            .keys()
+            // E.g. accounts that are only in the config for their alias.
+            .filter(|un| !stored_accounts.contains(un))
+            .map(|un| (un, config.saved_password(un).is_some())),
+    );
+
+    if output.is_json() {
+        output::emit(serde_json::json!({
+            "accounts": accounts
+                .map(|(username, saved_password)| {
+                    serde_json::json!({
+                        "username": username,
+                        "password_saved": saved_password,
+                        "password_command":
+                            config.password_command(username).is_some(),
+                        "running": children
+                            .iter()
+                            .any(|(un, _, _)| un == username),
+                        "alias": config.alias(username),
+                        "groups": config.groups(username),
+                        "tags": config.tags(username),
+                    })
+                })
+                .collect::<Vec<_>>(),
+        }));
+
+        return Ok(());
+    }
 
     let Some(max_name_len) = config
         .accounts
@@ -45,14 +100,6 @@ pub(crate) fn display_accounts(
         return Ok(());
     };
 
-    #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
-    let accounts = config.accounts.iter().map(|(un, p)| (un, p.is_string()));
-    #[cfg(all(target_os = "linux", feature = "secret-store"))]
-    let accounts = stored_accounts
-        .iter()
-        .map(|u| (u, true))
-        .chain(config.accounts.iter().map(|(un, p)| (un, p.is_string())));
-
     for (username, saved_password) in accounts {
         print!(
             "{} {username}   ",
@@ -65,7 +112,28 @@ pub(crate) fn display_accounts(
         for _ in 0..max_name_len - username.len() {
             print!(" ");
         }
-        println!("Password?: {}", if saved_password { "yes" } else { "no" });
+        print!(
+            "Password?: {}",
+            if config.password_command(username).is_some() {
+                "command"
+            } else if saved_password {
+                "yes"
+            } else {
+                "no"
+            },
+        );
+        if let Some(alias) = config.alias(username) {
+            print!("   Alias: {alias}");
+        }
+        let groups = config.groups(username);
+        if !groups.is_empty() {
+            print!("   Groups: {}", groups.join(", "));
+        }
+        let tags = config.tags(username);
+        if !tags.is_empty() {
+            print!("   Tags: {}", tags.join(", "));
+        }
+        println!();
     }
 
     Ok(())
@@ -79,22 +147,50 @@ fn account_exists(config: &Config, username: &str) -> Result<bool, Error> {
 #[cfg(all(target_os = "linux", feature = "secret-store"))]
 fn account_exists(config: &Config, username: &str) -> Result<bool, Error> {
     Ok(config.accounts.contains_key(username)
-        || crate::keyring::account_exists(username)?)
+        || shticker_book_unwritten::keyring::account_exists(username)?)
+}
+
+/// The usernames of the accounts that `names` refer to (see
+/// `Config::resolve_accounts`), leaving out any that don't exist. `None` if
+/// one of `names` is a group that doesn't exist, which has been said so.
+fn existing_accounts<'a, I: IntoIterator<Item = &'a str>>(
+    config: &Config,
+    output: OutputFormat,
+    names: I,
+) -> Result<Option<Vec<String>>, Error> {
+    let resolved = match config.resolve_accounts(names) {
+        Ok(resolved) => resolved,
+        Err(e @ Error::UnknownGroup(_)) => {
+            output::command_error(output, &e.to_string());
+
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut usernames = Vec::with_capacity(resolved.len());
+    for username in resolved {
+        if account_exists(config, &username)? {
+            usernames.push(username);
+        }
+    }
+
+    Ok(Some(usernames))
 }
 
 pub(crate) fn forget_account<P: AsRef<Path>>(
     config: &mut Config,
     config_path: P,
     quiet: bool,
+    output: OutputFormat,
     maybe_username: Option<&str>,
 ) -> Result<(), Error> {
     let mut username_buf;
-    let username = if let Some(u) = maybe_username {
+    let name = if let Some(u) = maybe_username {
         u
     } else {
         username_buf = String::with_capacity(0x10);
-        print!("Enter the username of the account to forget: ");
-        io::stdout().flush().map_err(Error::Stdout)?;
+        eprint!("Enter the username of the account to forget: ");
         io::stdin()
             .read_line(&mut username_buf)
             .map_err(Error::Stdin)?;
@@ -102,28 +198,39 @@ pub(crate) fn forget_account<P: AsRef<Path>>(
         username_buf.trim()
     };
 
-    if !account_exists(config, username)? {
-        println!("No account with that username was found.");
+    let Some(usernames) = existing_accounts(config, output, [name])? else {
+        return Ok(());
+    };
+    if usernames.is_empty() {
+        output::command_error(
+            output,
+            "No account with that username was found.",
+        );
 
         return Ok(());
     }
+    let accounts = if let [username] = usernames.as_slice() {
+        format!("the account with username {username}")
+    } else {
+        format!("the accounts with usernames {}", usernames.join(", "))
+    };
 
     let mut confirm_buf = String::with_capacity(4);
+    // On stderr, so that it doesn't end up mixed in with JSON output.
     #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
-    print!(
-        "Are you absolutely certain that you want to forget the account with \
-         username {username}?\nThis will ERASE the username & its associated \
-         password from your config file.\n\
+    eprint!(
+        "Are you absolutely certain that you want to forget {accounts}?\n\
+         This will ERASE the username & its associated password from your \
+         config file.\n\
          Type yes to confirm: "
     );
     #[cfg(all(target_os = "linux", feature = "secret-store"))]
-    print!(
-        "Are you absolutely certain that you want to forget the account with \
-         username {username}?\nThis will ERASE the username & its associated \
-         password from both your config file & your Secret Service keyring.\n\
+    eprint!(
+        "Are you absolutely certain that you want to forget {accounts}?\n\
+         This will ERASE the username & its associated password from both \
+         your config file & your Secret Service keyring.\n\
          Type yes to confirm: "
     );
-    io::stdout().flush().map_err(Error::Stdout)?;
     io::stdin()
         .read_line(&mut confirm_buf)
         .map_err(Error::Stdin)?;
@@ -136,13 +243,22 @@ pub(crate) fn forget_account<P: AsRef<Path>>(
         return Ok(());
     }
 
-    config.forget_account(username);
+    for username in &usernames {
+        config.forget_account(username);
+    }
     commit_config(config, config_path)?;
     #[cfg(all(target_os = "linux", feature = "secret-store"))]
-    crate::keyring::forget_account(username)?;
+    for username in &usernames {
+        shticker_book_unwritten::keyring::forget_account(username)?;
+        shticker_book_unwritten::keyring::forget_totp_secret(username)?;
+    }
 
     if !quiet {
-        println!("The account has been forgotten.");
+        if usernames.len() == 1 {
+            println!("The account has been forgotten.");
+        } else {
+            println!("{} accounts have been forgotten.", usernames.len());
+        }
     }
 
     Ok(())
@@ -152,14 +268,17 @@ pub(crate) fn set_store_passwords<P: AsRef<Path>>(
     config: &mut Config,
     config_path: P,
     quiet: bool,
+    output: OutputFormat,
     val: Option<&str>,
 ) -> Result<(), Error> {
     let store_passwords = match val {
         Some("true") => true,
         Some("false") => false,
         _ => {
-            println!(
-                "store_passwords can only have a value of either true or false"
+            output::command_error(
+                output,
+                "store_passwords can only have a value of either true or \
+                 false",
             );
 
             return Ok(());
@@ -188,3 +307,287 @@ pub(crate) fn set_store_passwords<P: AsRef<Path>>(
 
     Ok(())
 }
+
+pub(crate) fn two_factor<P: AsRef<Path>>(
+    config: &mut Config,
+    config_path: P,
+    quiet: bool,
+    output: OutputFormat,
+    subcommand: Option<&str>,
+    maybe_username: Option<&str>,
+    maybe_secret: Option<&str>,
+) -> Result<(), Error> {
+    #[cfg(all(target_os = "linux", feature = "secret-store"))]
+    let _ = (&config, &config_path);
+
+    let Some(username) = maybe_username else {
+        output::command_error(
+            output,
+            "Expected a username. Type accounts help for usage.",
+        );
+
+        return Ok(());
+    };
+
+    match subcommand {
+        Some("set") => {
+            let secret = if let Some(s) = maybe_secret {
+                s.to_owned()
+            } else {
+                eprint!("Two-factor secret for {username}: ");
+
+                rpassword::read_password().map_err(Error::PasswordRead)?
+            };
+            // Catch typos now, rather than at login time.
+            let code = match totp::code(&secret) {
+                Ok(code) => code,
+                Err(e) => {
+                    output::command_error(
+                        output,
+                        &format!("{e}\nNo two-factor secret was saved."),
+                    );
+
+                    return Ok(());
+                }
+            };
+
+            #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
+            {
+                config.set_totp_secret(username, Some(secret));
+                commit_config(config, &config_path)?;
+            }
+            #[cfg(all(target_os = "linux", feature = "secret-store"))]
+            shticker_book_unwritten::keyring::save_totp_secret(
+                username, &secret,
+            )?;
+
+            if !quiet {
+                #[cfg(not(all(
+                    target_os = "linux",
+                    feature = "secret-store"
+                )))]
+                println!(
+                    "The two-factor secret is now stored IN PLAIN TEXT at\n{}",
+                    config_path.as_ref().display(),
+                );
+                #[cfg(all(target_os = "linux", feature = "secret-store"))]
+                println!(
+                    "The two-factor secret is now stored in your default \
+                     Secret Service keyring."
+                );
+                println!(
+                    "The current code is {code}, which should match your \
+                     authenticator app."
+                );
+            }
+        }
+        Some("clear") => {
+            #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
+            if config.totp_secret(username).is_some() {
+                config.set_totp_secret(username, None);
+                commit_config(config, &config_path)?;
+            }
+            #[cfg(all(target_os = "linux", feature = "secret-store"))]
+            shticker_book_unwritten::keyring::forget_totp_secret(username)?;
+
+            if !quiet {
+                println!("{username} has no saved two-factor secret now.");
+            }
+        }
+        _ => output::command_error(
+            output,
+            "Unrecognized accounts 2fa subcommand. Type accounts help for \
+             usage.",
+        ),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn set_alias<P: AsRef<Path>>(
+    config: &mut Config,
+    config_path: P,
+    quiet: bool,
+    output: OutputFormat,
+    maybe_username: Option<&str>,
+    alias: Option<&str>,
+) -> Result<(), Error> {
+    let Some(name) = maybe_username else {
+        output::command_error(
+            output,
+            "Expected a username. Type accounts help for usage.",
+        );
+
+        return Ok(());
+    };
+    let Some(usernames) = existing_accounts(config, output, [name])? else {
+        return Ok(());
+    };
+    let [username] = usernames.as_slice() else {
+        output::command_error(
+            output,
+            "No account with that username was found.",
+        );
+
+        return Ok(());
+    };
+
+    if let Err(e) = config.set_alias(username, alias.map(str::to_owned)) {
+        output::command_error(output, &e.to_string());
+
+        return Ok(());
+    }
+    save_metadata(config, config_path, &usernames)?;
+
+    if !quiet {
+        match alias {
+            Some(alias) => {
+                println!("{username} is now also known as {alias}.")
+            }
+            None => println!("{username} has no alias now."),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn group<'a, P: AsRef<Path>, A: Iterator<Item = &'a str>>(
+    config: &mut Config,
+    config_path: P,
+    quiet: bool,
+    output: OutputFormat,
+    mut argv: A,
+) -> Result<(), Error> {
+    let subcommand = argv.next();
+    if subcommand.is_none() {
+        let groups = config.all_groups();
+        if output.is_json() {
+            output::emit(serde_json::json!({ "groups": groups }));
+        } else if groups.is_empty() {
+            println!("There are no groups.");
+        } else {
+            for (group, usernames) in groups {
+                println!("@{group}: {}", usernames.join(", "));
+            }
+        }
+
+        return Ok(());
+    }
+
+    let Some(group) = argv.next() else {
+        output::command_error(
+            output,
+            "Expected a group. Type accounts help for usage.",
+        );
+
+        return Ok(());
+    };
+    let group = group
+        .strip_prefix(shticker_book_unwritten::config::GROUP_PREFIX)
+        .unwrap_or(group);
+    let names: Vec<_> = argv.collect();
+
+    let mut changed = Vec::new();
+    match subcommand {
+        Some("add") => {
+            let Some(usernames) = existing_accounts(config, output, names)?
+            else {
+                return Ok(());
+            };
+            if usernames.is_empty() {
+                output::command_error(
+                    output,
+                    "Expected the usernames of existing accounts.",
+                );
+
+                return Ok(());
+            }
+
+            for username in usernames {
+                match config.add_to_group(&username, group) {
+                    Ok(true) => changed.push(username),
+                    Ok(false) => (),
+                    Err(e) => {
+                        output::command_error(output, &e.to_string());
+
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Some("remove") => {
+            let usernames = if names.is_empty() {
+                config
+                    .all_groups()
+                    .remove(group)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect()
+            } else {
+                let Some(usernames) =
+                    existing_accounts(config, output, names)?
+                else {
+                    return Ok(());
+                };
+
+                usernames
+            };
+
+            for username in usernames {
+                if config.remove_from_group(&username, group) {
+                    changed.push(username);
+                }
+            }
+        }
+        _ => {
+            output::command_error(
+                output,
+                "Unrecognized accounts group subcommand. Type accounts help \
+                 for usage.",
+            );
+
+            return Ok(());
+        }
+    }
+
+    save_metadata(config, config_path, &changed)?;
+
+    if !quiet {
+        println!(
+            "{} account(s) {} @{group}.",
+            changed.len(),
+            if subcommand == Some("add") {
+                "added to"
+            } else {
+                "removed from"
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// Persists the aliases & groups of `usernames`, in the config & (where
+/// applicable) in the Secret Service keyring.
+fn save_metadata<P: AsRef<Path>>(
+    config: &Config,
+    config_path: P,
+    usernames: &[String],
+) -> Result<(), Error> {
+    if usernames.is_empty() {
+        return Ok(());
+    }
+
+    commit_config(config, config_path)?;
+    #[cfg(all(target_os = "linux", feature = "secret-store"))]
+    for username in usernames {
+        shticker_book_unwritten::keyring::save_account_metadata(
+            username,
+            config.alias(username),
+            &config.groups(username),
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,13 +1,31 @@
-use crate::{accounts, config::Config, error::Error, login, update};
+use crate::accounts;
 use clap::{crate_name, crate_version};
 use reqwest::blocking as rb;
+use serde_json::json;
+use shticker_book_unwritten::{
+    config::{self, Config},
+    error::Error,
+    history::{self, Outcome, Period, Session},
+    login::{self, LaunchMode},
+    logs,
+    observer::Observer,
+    output::{self, OutputFormat},
+    supervisor::{Decision, Supervisor},
+    update::{self, Updater},
+};
 use std::{
     io::{self, prelude::*},
     num::NonZeroUsize,
     path::Path,
-    process, time,
+    process,
+    sync::mpsc,
+    thread, time,
 };
 
+/// How often supervised instances are checked on while waiting for a
+/// command.
+const SUPERVISOR_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
 const HELP_TEXT: &str = "\
 Commands
 ========
@@ -15,13 +33,38 @@ help, ?                    Display this help text.
 about                      Display info about this program.
 quit, exit                 Quit this program.
 update, up                 Update the game files. Specify -y or --dry-update to
-  [-y | --dry-update]        only check whether updates are available.
+  [-y | --dry-update]        only check whether updates are available. If any
+                             game instances are running, the update is staged
+                             & then applied once they have all exited.
+update plan <plan_file>    Write a plan of what updating would do to each file
+                             to plan_file, without downloading anything.
+update apply <plan_file>   Carry out exactly what plan_file says to do,
+                             refusing to if any of the files that it would
+                             touch have changed since the plan was made.
 login, play, launch        Launch the game. Specify -n or --no-save to not save
-  [usernames...]             logins, even if successful.
-  [-n | --no-save]
+  [usernames...]             logins, even if successful. Specify --print-env
+  [-n | --no-save]           to print the play cookie & gameserver as shell
+  [--print-env |             exports (or JSON) instead of launching, or --exec
+   --exec <command...>]      to run command (which is the rest of the line)
+                             with them in its environment instead of the
+                             game. With several usernames, their logins all
+                             happen at once. Before launching, the game files
+                             are checked for updates (see launch_check in the
+                             config).
 instances, running         List currently running game instances.
 kill, close <instance>     Forcibly close a running game instance. The instance
                              is specified by its PID or by its username.
+logs <instance> [lines]    Display the last lines (20 by default) of the
+                             output of a running or exited game instance,
+                             specified by its PID or by its username.
+stats [day | week]         Display playtime, sessions & crashes per account, in
+                             total & per day (the default) or week.
+stats export <file>        Write playtime, sessions & crashes per account per
+  [day | week]               day or week to file, as CSV (or as JSON, if file
+                             ends with .json).
+supervise <instance>       Turn relaunching a game instance (specified by its
+  [on | off]                 PID or by its username) whenever it crashes on
+                             (the default) or off.
 accounts, logins           List all saved accounts/logins. Use the help
                              subcommand for info on account-management
                              subcommands.
@@ -35,25 +78,35 @@ const ABOUT_TEXT: &str = concat!(
     ">\n",
 );
 
+#[allow(clippy::too_many_arguments)]
 pub fn enter_command_mode<'a, P: AsRef<Path>, U: Iterator<Item = &'a str>>(
     config: &mut Config,
     config_path: P,
     client: &rb::Client,
     quiet: bool,
+    output: OutputFormat,
+    observer: &dyn Observer,
     maybe_usernames: Option<U>,
+    launch_mode: LaunchMode,
     detach: bool,
     max_tries: NonZeroUsize,
 ) -> Result<(), Error> {
     let mut children = Vec::new();
+    let mut supervisor = Supervisor::new();
+    let mut staged_update = None;
     if let Some(usernames) = maybe_usernames {
-        login::login(
+        let mode = login::login(
             config,
             &config_path,
             client,
             quiet,
+            output,
+            observer,
             usernames,
+            launch_mode,
             &mut children,
         )?;
+        watch_new(config, &mut supervisor, &children, 0, &mode)?;
 
         if !detach && !quiet {
             println!();
@@ -72,18 +125,53 @@ pub fn enter_command_mode<'a, P: AsRef<Path>, U: Iterator<Item = &'a str>>(
         ));
     }
     let mut command_buf = String::with_capacity(0x10);
+    let mut stdin_lines = StdinLines::spawn()?;
 
     'outer: loop {
-        print!("> ");
-        io::stdout().flush().map_err(Error::Stdout)?;
+        apply_staged_update_if_idle(
+            config,
+            observer,
+            &children,
+            &mut staged_update,
+        )?;
+
+        if !output.is_json() {
+            print!("> ");
+            io::stdout().flush().map_err(Error::Stdout)?;
+        }
         command_buf.clear();
-        io::stdin()
-            .read_line(&mut command_buf)
-            .map_err(Error::Stdin)?;
+        stdin_lines.read_line(&mut command_buf, || {
+            if supervisor.is_active() || staged_update.is_some() {
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
+                relaunch_due(
+                    config,
+                    &config_path,
+                    client,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
+            }
+
+            Ok(())
+        })?;
 
         // ^D
         if command_buf.is_empty() {
-            println!();
+            if !output.is_json() {
+                println!();
+            }
             command_buf.push_str("quit");
         }
 
@@ -91,33 +179,75 @@ pub fn enter_command_mode<'a, P: AsRef<Path>, U: Iterator<Item = &'a str>>(
             .split(char::is_whitespace)
             .filter(|arg| !arg.is_empty());
         match argv.next() {
-            None => check_children(quiet, &mut children)?,
+            None => check_children(
+                config,
+                quiet,
+                output,
+                observer,
+                &mut children,
+                &mut supervisor,
+                &mut staged_update,
+            )?,
             Some("help" | "?") => {
-                help();
-                check_children(quiet, &mut children)?;
+                help(output);
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
             }
             Some("about") => {
-                about();
-                check_children(quiet, &mut children)?;
+                about(output);
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
             }
             Some("quit" | "exit") => {
-                check_children(quiet, &mut children)?;
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
                 if children.is_empty() {
                     break;
-                } else if children.len() == 1 {
-                    print!(
+                }
+
+                // On stderr, like all of the other prompts, so that they don't
+                // end up mixed in with JSON output.
+                if staged_update.is_some() {
+                    eprintln!(
+                        "There's a staged update waiting for all game \
+                         instances to exit. If you exit now, it won't be \
+                         applied until you update again."
+                    );
+                }
+                if children.len() == 1 {
+                    eprint!(
                         "Are you sure that you want to exit? There's still a \
                          game instance running. [y/n]\n> ",
                     );
                 } else {
-                    print!(
+                    eprint!(
                         "Are you sure that you want to exit? There are still \
                          {} game instances running. [y/n]\n> ",
                         children.len(),
                     );
                 }
 
-                io::stdout().flush().map_err(Error::Stdout)?;
                 command_buf.clear();
                 io::stdin()
                     .read_line(&mut command_buf)
@@ -130,8 +260,7 @@ pub fn enter_command_mode<'a, P: AsRef<Path>, U: Iterator<Item = &'a str>>(
                         _ => (),
                     }
 
-                    print!("[y/n]?\n> ");
-                    io::stdout().flush().map_err(Error::Stdout)?;
+                    eprint!("[y/n]?\n> ");
                     command_buf.clear();
                     io::stdin()
                         .read_line(&mut command_buf)
@@ -139,82 +268,312 @@ pub fn enter_command_mode<'a, P: AsRef<Path>, U: Iterator<Item = &'a str>>(
                 }
             }
             Some("update" | "up") => {
-                check_children(quiet, &mut children)?;
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
 
-                let mut dry = false;
-                for arg in argv {
-                    match arg {
-                        "-y" | "--dry-update" => dry = true,
-                        _ => {
-                            println!("Unexpected argument: {arg}");
+                let (mut dry, mut plan) = (false, None);
+                match argv.next() {
+                    Some("plan") => {
+                        let Some(plan_path) = argv.next() else {
+                            output::command_error(
+                                output,
+                                "Expected the <plan_file> argument!",
+                            );
+
+                            continue;
+                        };
+
+                        Updater::new(config, client)
+                            .max_tries(max_tries)
+                            .observer(observer)
+                            .plan()?
+                            .write(plan_path)?;
+                        if !quiet {
+                            println!("Update plan written to {plan_path}");
+                        }
+
+                        continue;
+                    }
+                    Some("apply") => {
+                        let Some(plan_path) = argv.next() else {
+                            output::command_error(
+                                output,
+                                "Expected the <plan_file> argument!",
+                            );
+
+                            continue;
+                        };
 
-                            continue 'outer;
+                        plan = Some(update::Plan::read(plan_path)?);
+                    }
+                    first => {
+                        for arg in first.into_iter().chain(argv.by_ref()) {
+                            match arg {
+                                "-y" | "--dry-update" => dry = true,
+                                _ => {
+                                    output::command_error(
+                                        output,
+                                        &format!("Unexpected argument: {arg}"),
+                                    );
+
+                                    continue 'outer;
+                                }
+                            }
                         }
                     }
                 }
+                if let Some(arg) = argv.next() {
+                    output::command_error(
+                        output,
+                        &format!("Unexpected argument: {arg}"),
+                    );
+
+                    continue;
+                }
 
+                let updater = Updater::new(config, client)
+                    .max_tries(max_tries)
+                    .observer(observer);
                 if dry || children.is_empty() {
-                    update::update(config, client, quiet, max_tries, dry)?;
-                } else if children.len() == 1 {
-                    println!(
-                        "There's still a game instance running; can't update \
-                         now!\n(Pass in -y or --dry-update if you just want \
-                         to check for updates.)",
-                    );
+                    if let Some(plan) = &plan {
+                        updater.apply_plan(plan)?;
+                    } else {
+                        updater.update(dry)?;
+                    }
                 } else {
-                    println!(
-                        "There are still {} game instances running; can't \
-                         update now!\n(Pass in -y or --dry-update if you just \
-                         want to check for updates.)",
-                        children.len(),
-                    );
+                    let staged = if let Some(plan) = &plan {
+                        updater.stage_plan(plan)?
+                    } else {
+                        updater.stage()?
+                    };
+                    if staged.is_empty() {
+                        staged_update = None;
+                    } else {
+                        if !quiet {
+                            println!(
+                                "Game instance(s) still running; the update \
+                                 has been staged, and will be applied once \
+                                 all instances have exited.",
+                            );
+                        }
+
+                        staged_update = Some(staged);
+                    }
                 }
             }
             Some("login" | "play" | "launch") => {
-                login::login(
+                let (line, exec) = login::split_exec(&command_buf);
+                let mode = match exec {
+                    Some("") => {
+                        eprintln!("Expected a command after --exec!");
+
+                        continue;
+                    }
+                    Some(command) => LaunchMode::Exec(command.to_owned()),
+                    None => LaunchMode::Launch,
+                };
+                let argv = line
+                    .split(char::is_whitespace)
+                    .filter(|arg| !arg.is_empty())
+                    .skip(1);
+                let launched_before = children.len();
+                let mode = login::login(
                     config,
                     &config_path,
                     client,
                     quiet,
+                    output,
+                    observer,
                     argv,
+                    mode,
+                    &mut children,
+                )?;
+                watch_new(
+                    config,
+                    &mut supervisor,
+                    &children,
+                    launched_before,
+                    &mode,
+                )?;
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
                     &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
                 )?;
-                check_children(quiet, &mut children)?;
             }
             Some("instances" | "running") => {
-                check_children(quiet, &mut children)?;
-                display_instances(&children);
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
+                if output.is_json() {
+                    output::emit(instances_json(
+                        &children,
+                        &supervisor,
+                        staged_update.as_ref(),
+                    ));
+                } else {
+                    display_instances(&children, &supervisor);
+                    if let Some(staged) = &staged_update {
+                        println!(
+                            "Pending update: {} staged file(s) will be \
+                             applied once all instances have exited.",
+                            staged.len(),
+                        );
+                    }
+                }
             }
             Some("kill" | "close") => {
-                check_children(quiet, &mut children)?;
-                kill_instance(quiet, &mut children, argv.next())?;
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
+                kill_instance(
+                    config,
+                    quiet,
+                    output,
+                    &mut children,
+                    &mut supervisor,
+                    argv.next(),
+                )?;
+            }
+            Some("supervise") => {
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
+                supervise(
+                    config,
+                    quiet,
+                    output,
+                    &children,
+                    &mut supervisor,
+                    argv.next(),
+                    argv.next(),
+                )?;
+            }
+            Some("stats") => {
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
+                show_stats(config, quiet, output, argv)?;
+            }
+            Some("logs") => {
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
+                show_log(config, output, argv.next(), argv.next())?;
             }
             Some("accounts" | "logins") => {
-                check_children(quiet, &mut children)?;
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
                 match argv.next() {
-                    None => accounts::display_accounts(config, &children)?,
-                    Some("help" | "?") => accounts::accounts_help(),
+                    None => {
+                        accounts::display_accounts(config, &children, output)?
+                    }
+                    Some("help" | "?") => accounts::accounts_help(output),
                     Some("forget") => accounts::forget_account(
                         config,
                         &config_path,
                         quiet,
+                        output,
                         argv.next(),
                     )?,
                     Some("savepws") => accounts::set_store_passwords(
                         config,
                         &config_path,
                         quiet,
+                        output,
+                        argv.next(),
+                    )?,
+                    Some("alias") => accounts::set_alias(
+                        config,
+                        &config_path,
+                        quiet,
+                        output,
+                        argv.next(),
+                        argv.next(),
+                    )?,
+                    Some("group") => accounts::group(
+                        config,
+                        &config_path,
+                        quiet,
+                        output,
+                        argv.by_ref(),
+                    )?,
+                    Some("2fa") => accounts::two_factor(
+                        config,
+                        &config_path,
+                        quiet,
+                        output,
+                        argv.next(),
+                        argv.next(),
                         argv.next(),
                     )?,
-                    _ => println!(
+                    _ => output::command_error(
+                        output,
                         "Unrecognized accounts subcommand.\nType accounts \
-                         help or accounts ? to get a list of subcommands."
+                         help or accounts ? to get a list of subcommands.",
                     ),
                 }
             }
             _ => {
-                check_children(quiet, &mut children)?;
-                println!(
+                check_children(
+                    config,
+                    quiet,
+                    output,
+                    observer,
+                    &mut children,
+                    &mut supervisor,
+                    &mut staged_update,
+                )?;
+                output::command_error(
+                    output,
                     "Unrecognized command. Type help or ? to get a list of \
                      commands.",
                 );
@@ -222,18 +581,53 @@ pub fn enter_command_mode<'a, P: AsRef<Path>, U: Iterator<Item = &'a str>>(
         }
     }
 
+    for (username, child, started) in &children {
+        record_session(
+            config,
+            username,
+            child.id(),
+            *started,
+            Outcome::Detached,
+            None,
+        );
+    }
+
     Ok(())
 }
 
-fn help() {
-    print!("{HELP_TEXT}");
+fn help(output: OutputFormat) {
+    output::print_text(output, "help", HELP_TEXT);
+}
+
+fn about(output: OutputFormat) {
+    output::print_text(output, "about", ABOUT_TEXT);
 }
 
-fn about() {
-    print!("{ABOUT_TEXT}");
+fn display_instances(
+    instances: &[(String, process::Child, time::Instant)],
+    supervisor: &Supervisor,
+) {
+    display_instance_table(instances, supervisor);
+    for (name, _, _) in instances {
+        display_restarts(supervisor, name);
+    }
+    for pending in supervisor.pending() {
+        display_restarts(supervisor, &pending.username);
+        println!(
+            "{}'s instance will be relaunched in {}s.",
+            pending.username,
+            pending
+                .due
+                .saturating_duration_since(time::Instant::now())
+                .as_secs(),
+        );
+    }
 }
 
-fn display_instances(instances: &[(String, process::Child, time::Instant)]) {
+fn display_instance_table(
+    instances: &[(String, process::Child, time::Instant)],
+    supervisor: &Supervisor,
+) {
     fn count_decimal_digits(n: u32) -> usize {
         if n >= 100_000 {
             if n >= 10_000_000 {
@@ -308,54 +702,180 @@ fn display_instances(instances: &[(String, process::Child, time::Instant)]) {
         let secs = uptime_sec % 60;
         let minutes = (uptime_sec / 60) % 60;
         let hours = uptime_sec / (60 * 60);
-        println!("| {hours}h {minutes:02}m {secs:02}s");
+        print!("| {hours}h {minutes:02}m {secs:02}s");
+        if supervisor.is_supervised(pid) {
+            print!(" (supervised)");
+        }
+        println!();
     }
 }
 
+fn display_restarts(supervisor: &Supervisor, username: &str) {
+    let history = supervisor.history(username);
+    let Some(last) = history.last() else {
+        return;
+    };
+
+    let ago = last.at.elapsed().as_secs();
+    print!(
+        "{username}'s instances have crashed {} time(s); the last time was \
+         {}h {:02}m {:02}s ago",
+        history.len(),
+        ago / (60 * 60),
+        (ago / 60) % 60,
+        ago % 60,
+    );
+    match (last.pid, last.exit_code) {
+        (None, _) => println!(", when relaunching failed."),
+        (Some(pid), Some(exit_code)) => {
+            println!(" (PID {pid}, exit code {exit_code}).");
+        }
+        (Some(pid), None) => println!(" (PID {pid}, killed by a signal)."),
+    }
+}
+
+fn instances_json(
+    instances: &[(String, process::Child, time::Instant)],
+    supervisor: &Supervisor,
+    staged_update: Option<&update::StagedUpdate>,
+) -> serde_json::Value {
+    json!({
+        "instances": instances
+            .iter()
+            .map(|(name, child, timestamp)| {
+                json!({
+                    "username": name,
+                    "pid": child.id(),
+                    "uptime_secs": timestamp.elapsed().as_secs(),
+                    "supervised": supervisor.is_supervised(child.id()),
+                    "restarts": supervisor
+                        .history(name)
+                        .iter()
+                        .map(|restart| {
+                            json!({
+                                "pid": restart.pid,
+                                "exit_code": restart.exit_code,
+                                "secs_ago": restart.at.elapsed().as_secs(),
+                                "relaunched_pid": restart.relaunched_pid,
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>(),
+        "pending_relaunches": supervisor
+            .pending()
+            .iter()
+            .map(|pending| {
+                json!({
+                    "username": pending.username,
+                    "in_secs": pending
+                        .due
+                        .saturating_duration_since(time::Instant::now())
+                        .as_secs(),
+                })
+            })
+            .collect::<Vec<_>>(),
+        "staged_update": staged_update.map(|staged| {
+            json!({ "files": staged.len() })
+        }),
+    })
+}
+
+/// The index into `children` of the instance with the PID, username, or
+/// username's alias `instance`. PIDs take precedence over usernames, which
+/// take precedence over aliases.
+fn find_instance(
+    config: &Config,
+    children: &[(String, process::Child, time::Instant)],
+    instance: &str,
+) -> Option<usize> {
+    let by_name = |name: &str| {
+        children
+            .iter()
+            .position(|(username, _, _)| username == name)
+    };
+
+    instance
+        .parse::<u32>()
+        .ok()
+        .and_then(|pid| children.iter().position(|(_, c, _)| c.id() == pid))
+        .or_else(|| by_name(instance))
+        .or_else(|| config.resolve_alias(instance).and_then(by_name))
+}
+
+/// Like `find_instance`, but `instance` may also be a group's name prefixed
+/// with `@`, in which case every instance of the group's accounts is found.
+/// The indices are in ascending order.
+fn find_instances(
+    config: &Config,
+    children: &[(String, process::Child, time::Instant)],
+    instance: &str,
+) -> Result<Vec<usize>, Error> {
+    if !instance.starts_with(config::GROUP_PREFIX) {
+        return Ok(find_instance(config, children, instance)
+            .into_iter()
+            .collect());
+    }
+
+    let usernames = config.resolve_accounts([instance])?;
+
+    Ok(children
+        .iter()
+        .enumerate()
+        .filter(|(_, (username, _, _))| usernames.contains(username))
+        .map(|(i, _)| i)
+        .collect())
+}
+
 fn kill_instance(
+    config: &Config,
     quiet: bool,
+    output: OutputFormat,
     children: &mut Vec<(String, process::Child, time::Instant)>,
+    supervisor: &mut Supervisor,
     arg: Option<&str>,
 ) -> Result<(), Error> {
     let Some(instance_str) = arg else {
-        println!("Expected the <instance> argument!");
+        output::command_error(output, "Expected the <instance> argument!");
 
         return Ok(());
     };
-    let maybe_instance_int: Option<u32> = instance_str.parse().ok();
 
-    let maybe_instance = if let Some(pid) = maybe_instance_int {
-        if let Some(c) = children
-            .iter_mut()
-            .enumerate()
-            .find(|(_, (_, child, _))| child.id() == pid)
-        {
-            Some(c)
-        } else {
-            children
-                .iter_mut()
-                .enumerate()
-                .find(|(_, (name, _, _))| name == instance_str)
+    let indices = match find_instances(config, children, instance_str) {
+        Ok(indices) => indices,
+        Err(e @ Error::UnknownGroup(_)) => {
+            output::command_error(output, &e.to_string());
+
+            return Ok(());
         }
-    } else {
-        children
-            .iter_mut()
-            .enumerate()
-            .find(|(_, (name, _, _))| name == instance_str)
+        Err(e) => return Err(e),
     };
+    if indices.is_empty() {
+        output::command_error(
+            output,
+            "No currently-running instances have that username or PID.",
+        );
 
-    if let Some((i, (name, child, timestamp))) = maybe_instance {
+        return Ok(());
+    }
+
+    // Back to front, so that removing each one doesn't shift the others.
+    for i in indices.into_iter().rev() {
+        let (name, child, timestamp) = &mut children[i];
         let pid = child.id();
         let uptime_sec = timestamp.elapsed().as_secs();
+        // Closed on purpose, so it shouldn't be relaunched.
+        supervisor.forget(pid);
 
         if !quiet {
             println!("Killing instance...");
         }
 
-        if let Err(ioe) = child.kill() {
-            if ioe.kind() != io::ErrorKind::InvalidInput {
-                return Err(Error::ProcessKill(pid, ioe));
-            }
+        if let Err(ioe) = child.kill()
+            && ioe.kind() != io::ErrorKind::InvalidInput
+        {
+            return Err(Error::ProcessKill(pid, ioe));
         }
 
         if !quiet {
@@ -375,25 +895,414 @@ fn kill_instance(
             );
         }
 
+        record_session(config, name, pid, *timestamp, Outcome::Killed, None);
         children.remove(i);
+    }
+
+    Ok(())
+}
+
+fn supervise(
+    config: &Config,
+    quiet: bool,
+    output: OutputFormat,
+    children: &[(String, process::Child, time::Instant)],
+    supervisor: &mut Supervisor,
+    instance: Option<&str>,
+    on_off: Option<&str>,
+) -> Result<(), Error> {
+    let Some(instance) = instance else {
+        output::command_error(output, "Expected the <instance> argument!");
+
+        return Ok(());
+    };
+    let on = match on_off {
+        None | Some("on") => true,
+        Some("off") => false,
+        Some(_) => {
+            output::command_error(output, "Expected on or off!");
+
+            return Ok(());
+        }
+    };
+    let Some(i) = find_instance(config, children, instance) else {
+        output::command_error(
+            output,
+            "No currently-running instances have that username or PID.",
+        );
+
+        return Ok(());
+    };
+    let (name, child, _) = &children[i];
+
+    let policy = if on {
+        Some(config.restart_policy(name)?.unwrap_or_default())
+    } else {
+        None
+    };
+    if !supervisor.set_policy(child.id(), policy) {
+        output::command_error(
+            output,
+            &format!(
+                "{name}'s instance wasn't launched in a way that it can be \
+                 relaunched in."
+            ),
+        );
+    } else if !quiet {
+        if on {
+            println!("{name}'s instance will be relaunched if it crashes.");
+        } else {
+            println!("{name}'s instance will no longer be relaunched.");
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps track of the instances in `children[since..]`, which were just
+/// launched as `mode` says, supervising those whose accounts have a restart
+/// policy.
+fn watch_new(
+    config: &Config,
+    supervisor: &mut Supervisor,
+    children: &[(String, process::Child, time::Instant)],
+    since: usize,
+    mode: &LaunchMode,
+) -> Result<(), Error> {
+    for (name, child, _) in children.get(since..).unwrap_or_default() {
+        supervisor.watch(
+            child.id(),
+            name.clone(),
+            mode.clone(),
+            config.restart_policy(name)?,
+        );
+    }
+
+    Ok(())
+}
+
+/// Logs in again & relaunches any supervised instances that crashed & whose
+/// backoff is up.
+#[allow(clippy::too_many_arguments)]
+fn relaunch_due<P: AsRef<Path>>(
+    config: &mut Config,
+    config_path: P,
+    client: &rb::Client,
+    quiet: bool,
+    output: OutputFormat,
+    observer: &dyn Observer,
+    children: &mut Vec<(String, process::Child, time::Instant)>,
+    supervisor: &mut Supervisor,
+    staged_update: &mut Option<update::StagedUpdate>,
+) -> Result<(), Error> {
+    for pending in supervisor.due(time::Instant::now()) {
+        if !quiet {
+            println!("Relaunching {}'s instance...", pending.username);
+        }
+
+        // Nothing else is running that the update could pull the rug out
+        // from under, so this is the last chance to apply it before the
+        // relaunched instance starts running too.
+        apply_staged_update_if_idle(
+            config,
+            observer,
+            children,
+            staged_update,
+        )?;
+        match login::relaunch(
+            config,
+            config_path.as_ref(),
+            client,
+            quiet,
+            observer,
+            &pending.username,
+            &pending.mode,
+            !children.is_empty(),
+        ) {
+            Ok(Some(c)) => {
+                supervisor.relaunched(pending, c.1.id());
+                children.push(c);
+            }
+            Ok(None) => (),
+            Err(e) => {
+                eprintln!(
+                    "Failed to relaunch {}'s instance: {e}",
+                    pending.username,
+                );
+                output::emit_if_json(output, || {
+                    json!({
+                        "event": "relaunch_failed",
+                        "username": pending.username,
+                        "error": output::error_value(&e),
+                    })
+                });
+
+                let username = pending.username.clone();
+                let decision =
+                    supervisor.relaunch_failed(pending, time::Instant::now());
+                report_decision(quiet, output, &username, None, decision);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Tells the user what `supervisor` decided to do about `username`'s
+/// instance (with PID `pid`, if it got as far as being launched).
+fn report_decision(
+    quiet: bool,
+    output: OutputFormat,
+    username: &str,
+    pid: Option<u32>,
+    decision: Decision,
+) {
+    match decision {
+        Decision::Restart { delay } => {
+            output::emit_if_json(output, || {
+                json!({
+                    "event": "relaunch_scheduled",
+                    "username": username,
+                    "pid": pid,
+                    "delay_secs": delay.as_secs(),
+                })
+            });
+
+            if !quiet {
+                println!(
+                    "Relaunching {username}'s instance in {}s...",
+                    delay.as_secs(),
+                );
+            }
+        }
+        Decision::GaveUp => {
+            output::emit_if_json(output, || {
+                json!({
+                    "event": "relaunch_gave_up",
+                    "username": username,
+                    "pid": pid,
+                })
+            });
+
+            eprintln!(
+                "{username}'s instance has crashed too many times, so it \
+                 won't be relaunched."
+            );
+        }
+        Decision::Unsupervised | Decision::Exited => (),
+    }
+}
+
+fn show_log(
+    config: &Config,
+    output: OutputFormat,
+    instance: Option<&str>,
+    lines: Option<&str>,
+) -> Result<(), Error> {
+    const DEFAULT_LINES: usize = 20;
+
+    let Some(instance) = instance else {
+        output::command_error(output, "Expected the <instance> argument!");
+
+        return Ok(());
+    };
+    let lines = match lines.map(str::parse) {
+        None => DEFAULT_LINES,
+        Some(Ok(lines)) => lines,
+        Some(Err(_)) => {
+            output::command_error(output, "Expected [lines] to be a number!");
+
+            return Ok(());
+        }
+    };
+    let instance = match config.resolve_alias(instance) {
+        Some(username) if instance.parse::<u32>().is_err() => username,
+        _ => instance,
+    };
+    let Some(log) = logs::find(config, instance)? else {
+        output::command_error(
+            output,
+            "No logs were found for any instance with that username or PID.",
+        );
+
+        return Ok(());
+    };
+    let tail = logs::tail(&log.path, lines)?;
+
+    if output.is_json() {
+        output::emit(json!({
+            "username": log.username,
+            "pid": log.pid,
+            "log": log.path,
+            "lines": tail,
+        }));
     } else {
-        println!("No currently-running instances have that username or PID.");
+        println!(
+            "Output of {}'s instance with PID {} ({}):",
+            log.username,
+            log.pid,
+            log.path.display(),
+        );
+        for line in tail {
+            println!("{line}");
+        }
+    }
+
+    Ok(())
+}
+
+fn show_stats<'a, A: Iterator<Item = &'a str>>(
+    config: &Config,
+    quiet: bool,
+    output: OutputFormat,
+    mut argv: A,
+) -> Result<(), Error> {
+    let mut arg = argv.next();
+    let export_path = if arg == Some("export") {
+        let Some(path) = argv.next() else {
+            output::command_error(output, "Expected the <file> argument!");
+
+            return Ok(());
+        };
+        arg = argv.next();
+
+        Some(path)
+    } else {
+        None
+    };
+    let period = match arg.map(Period::from_arg) {
+        None => Period::Day,
+        Some(Some(period)) => period,
+        Some(None) => {
+            output::command_error(output, "Expected day or week!");
+
+            return Ok(());
+        }
+    };
+
+    let sessions = history::load(config)?;
+    let stats = history::stats(&sessions, period);
+    if let Some(path) = export_path {
+        history::export(&stats, path)?;
+        if !quiet {
+            println!("Exported stats for {} period(s) to {path}", stats.len());
+        }
+
+        return Ok(());
+    }
+    let totals = history::totals(&sessions);
+
+    if output.is_json() {
+        let values = |stats: &[history::Stats]| {
+            stats.iter().map(history::stats_value).collect::<Vec<_>>()
+        };
+        output::emit(json!({
+            "totals": values(&totals),
+            "period": match period {
+                Period::Day => "day",
+                Period::Week => "week",
+            },
+            "stats": values(&stats),
+        }));
+
+        return Ok(());
+    }
+    if sessions.is_empty() {
+        println!("No game sessions have been recorded yet.");
+
+        return Ok(());
     }
 
+    println!("All time:");
+    display_stats(&totals, false);
+    println!(
+        "\nPer {}:",
+        match period {
+            Period::Day => "day",
+            Period::Week => "week (starting on Monday)",
+        },
+    );
+    display_stats(&stats, true);
+
     Ok(())
 }
 
+fn display_stats(stats: &[history::Stats], with_period: bool) {
+    let name_len = stats
+        .iter()
+        .map(|s| s.username.len())
+        .fold("username".len(), usize::max);
+    if with_period {
+        print!("period     | ");
+    }
+    println!(
+        "{:name_len$} | sessions | crashes | crash rate | playtime",
+        "username",
+    );
+    for s in stats {
+        if with_period {
+            print!("{} | ", s.period);
+        }
+        let playtime = s.playtime_secs;
+        println!(
+            "{:name_len$} | {:8} | {:7} | {:9.1}% | {}h {:02}m {:02}s",
+            s.username,
+            s.sessions,
+            s.crashes,
+            100.0 * s.crash_rate(),
+            playtime / (60 * 60),
+            (playtime / 60) % 60,
+            playtime % 60,
+        );
+    }
+}
+
+/// Adds a session to the history. Failing to is only worth a warning, since
+/// the game itself is unaffected.
+fn record_session(
+    config: &Config,
+    username: &str,
+    pid: u32,
+    started: time::Instant,
+    outcome: Outcome,
+    exit_code: Option<i32>,
+) {
+    let session =
+        Session::ended(username.to_owned(), pid, started, outcome, exit_code);
+    if let Err(e) = history::record(config, &session) {
+        eprintln!("Failed to record {username}'s session in the history: {e}");
+    }
+}
+
+/// Notices any instances that have exited, & applies the staged update (if
+/// any) once none are left running.
+///
 /// Naïve implementation because, let's be real, how many instances of the game
 /// are you really going to run concurrently?
 fn check_children(
+    config: &Config,
     quiet: bool,
+    output: OutputFormat,
+    observer: &dyn Observer,
     children: &mut Vec<(String, process::Child, time::Instant)>,
+    supervisor: &mut Supervisor,
+    staged_update: &mut Option<update::StagedUpdate>,
 ) -> Result<(), Error> {
     let mut i = 0;
-    while let Some((username, child, _)) = children.get_mut(i) {
+    while let Some((username, child, started)) = children.get_mut(i) {
         if let Some(exit_status) =
             child.try_wait().map_err(Error::ThreadJoin)?
         {
+            output::emit_if_json(output, || {
+                json!({
+                    "event": "instance_exited",
+                    "username": username,
+                    "pid": child.id(),
+                    "success": exit_status.success(),
+                    "exit_code": exit_status.code(),
+                })
+            });
+
             if !quiet {
                 if exit_status.success() {
                     println!("{username}'s instance exited normally.");
@@ -407,11 +1316,107 @@ fn check_children(
                 }
             }
 
+            let pid = child.id();
+            let outcome = if exit_status.success() {
+                Outcome::Exited
+            } else {
+                Outcome::Crashed
+            };
+            record_session(
+                config,
+                username,
+                pid,
+                *started,
+                outcome,
+                exit_status.code(),
+            );
+            let decision =
+                supervisor.exited(pid, exit_status, time::Instant::now());
+            report_decision(quiet, output, username, Some(pid), decision);
+
             children.remove(i);
         } else {
             i += 1;
         }
     }
 
+    apply_staged_update_if_idle(config, observer, children, staged_update)
+}
+
+fn apply_staged_update_if_idle(
+    config: &Config,
+    observer: &dyn Observer,
+    children: &[(String, process::Child, time::Instant)],
+    staged_update: &mut Option<update::StagedUpdate>,
+) -> Result<(), Error> {
+    if children.is_empty()
+        && let Some(staged) = staged_update.take()
+    {
+        staged.apply(config, observer)?;
+    }
+
     Ok(())
 }
+
+/// Reads lines from stdin on another thread, but only when asked to, so that
+/// supervised instances can be checked on while waiting for a command, &
+/// stdin can still be read from directly (e.g. for passwords) otherwise.
+struct StdinLines {
+    requests: mpsc::Sender<()>,
+    lines: mpsc::Receiver<io::Result<String>>,
+    /// Whether a line has been asked for, but not yet received.
+    requested: bool,
+}
+
+impl StdinLines {
+    fn spawn() -> Result<Self, Error> {
+        let (requests, requests_rx) = mpsc::channel();
+        let (lines_tx, lines) = mpsc::channel();
+        thread::Builder::new()
+            .name("stdin".to_owned())
+            .spawn(move || {
+                for () in requests_rx {
+                    let mut line = String::new();
+                    let read = io::stdin().read_line(&mut line).map(|_| line);
+                    if lines_tx.send(read).is_err() {
+                        break;
+                    }
+                }
+            })
+            .map_err(Error::ThreadSpawn)?;
+
+        Ok(Self {
+            requests,
+            lines,
+            requested: false,
+        })
+    }
+
+    /// Like `io::Stdin::read_line`, but calls `idle` every
+    /// `SUPERVISOR_INTERVAL` while waiting.
+    fn read_line<F: FnMut() -> Result<(), Error>>(
+        &mut self,
+        buf: &mut String,
+        mut idle: F,
+    ) -> Result<(), Error> {
+        if !self.requested {
+            // If the thread is gone, `recv_timeout` says so below.
+            let _ = self.requests.send(());
+            self.requested = true;
+        }
+
+        loop {
+            match self.lines.recv_timeout(SUPERVISOR_INTERVAL) {
+                Ok(line) => {
+                    self.requested = false;
+                    buf.push_str(&line.map_err(Error::Stdin)?);
+
+                    return Ok(());
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => idle()?,
+                // Treated like EOF.
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
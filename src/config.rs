@@ -2,6 +2,7 @@ use crate::{error::Error, util};
 use clap::crate_name;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     env,
     fs::{self, File},
     io::{self, Write},
@@ -12,8 +13,40 @@ const DEFAULT_MANIFEST_URI: &str =
     "https://cdn.toontownrewritten.com/content/patchmanifest.txt";
 const DEFAULT_CDN_URI: &str =
     "https://download.toontownrewritten.com/patches/";
+pub const DEFAULT_LOGIN_API_URI: &str =
+    "https://www.toontownrewritten.com/api/login?format=json";
+#[cfg(target_os = "linux")]
+const DEFAULT_GAME_EXECUTABLE: &str = "TTREngine";
+#[cfg(all(windows, target_arch = "x86"))]
+const DEFAULT_GAME_EXECUTABLE: &str = "TTREngine.exe";
+#[cfg(all(windows, target_arch = "x86_64"))]
+const DEFAULT_GAME_EXECUTABLE: &str = "TTREngine64.exe";
+#[cfg(target_os = "macos")]
+const DEFAULT_GAME_EXECUTABLE: &str = "Toontown Rewritten";
+/// 2 GiB, which is far larger than any game file.
+const DEFAULT_MAX_PATCH_OUTPUT_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+const DEFAULT_PASSWORD_COMMAND_TIMEOUT: u64 = 60;
+const DEFAULT_MAX_LOGS: usize = 20;
+/// 64 MiB.
+const DEFAULT_MAX_LOG_SIZE: u64 = 64 * 1024 * 1024;
+/// Keys of the accounts that are objects, rather than just a password (or
+/// `null`).
+const PASSWORD_KEY: &str = "password";
+const PASSWORD_COMMAND_KEY: &str = "password_command";
+const TOTP_SECRET_KEY: &str = "totp_secret";
+const LAUNCH_KEY: &str = "launch";
+const SUPERVISE_KEY: &str = "supervise";
+const ALIAS_KEY: &str = "alias";
+const TAGS_KEY: &str = "tags";
+const GROUPS_KEY: &str = "groups";
+/// Prefixes a group's name, wherever an account may be referred to.
+pub const GROUP_PREFIX: char = '@';
+const DEFAULT_MAX_RESTARTS: u32 = 3;
+const DEFAULT_RESTART_WINDOW: u64 = 60 * 60;
+const DEFAULT_RESTART_BACKOFF: u64 = 10;
+const DEFAULT_MAX_RESTART_BACKOFF: u64 = 5 * 60;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Config {
     pub install_dir: PathBuf,
     /// TODO: Implement a way to clear the cache, presumably automatically
@@ -22,27 +55,797 @@ pub struct Config {
     pub cdn_uri: String,
     pub store_passwords: bool,
     pub accounts: serde_json::Map<String, serde_json::Value>,
+    /// Patches that claim to produce files larger than this many bytes are
+    /// rejected without being applied.
+    #[serde(default = "default_max_patch_output_size")]
+    pub max_patch_output_size: u64,
+    #[serde(default = "default_login_api_uri")]
+    pub login_api_uri: String,
+    /// Relative to `install_dir`, unless absolute.
+    #[serde(default = "default_game_executable")]
+    pub game_executable: PathBuf,
+    /// Extra command line arguments to launch the game with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub launch_args: Vec<String>,
+    /// How to launch the game for every account. Accounts may have a
+    /// `"launch"` of their own, which is layered on top of this one (see
+    /// `Config::launch_spec`).
+    #[serde(default, skip_serializing_if = "LaunchSpec::is_empty")]
+    pub launch: LaunchSpec,
+    /// Command whose output is used as the password for any account that
+    /// doesn't have a `"password_command"` of its own. See
+    /// `password_command::run`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
+    /// In seconds.
+    #[serde(default = "default_password_command_timeout")]
+    pub password_command_timeout: u64,
+    /// In seconds. Logins that take longer than this are given up on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login_timeout: Option<u64>,
+    /// In seconds. Logins that spend longer than this in the login queue
+    /// are given up on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_queue_wait: Option<u64>,
+    /// In seconds. When logging into several accounts at once, games are
+    /// launched at least this far apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launch_stagger: Option<u64>,
+    /// Relaunches instances of any account that crash, unless the account
+    /// has a `"supervise"` of its own (which may be `null`, to opt out).
+    /// See `Config::restart_policy`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supervise: Option<RestartPolicy>,
+    /// Where the game's output is logged. Defaults to a `logs` directory
+    /// inside of `cache_dir`. See `logs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logs_dir: Option<PathBuf>,
+    /// How many log files of the game's output to keep. `0` means that the
+    /// game's output isn't logged at all.
+    #[serde(default = "default_max_logs")]
+    pub max_logs: usize,
+    /// In days. Log files older than this are deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_log_age: Option<u64>,
+    /// In bytes. Each instance's log file is rotated once it grows past
+    /// this, for as long as shticker_book_unwritten keeps running. `0`
+    /// means that log files are never rotated. See `logs::rotate`.
+    #[serde(default = "default_max_log_size")]
+    pub max_log_size: u64,
+    /// Where the history of game sessions is kept. Defaults to
+    /// `history.jsonl` inside of `cache_dir`. See `history`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_file: Option<PathBuf>,
+    /// What to do when the install doesn't match the manifest, right
+    /// before launching the game.
+    #[serde(default, skip_serializing_if = "LaunchCheck::is_default")]
+    pub launch_check: LaunchCheck,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// The values that the currently applied profile replaced, so that the
+    /// profile doesn't get written back into the config file.
+    #[serde(skip)]
+    overridden: Option<Profile>,
+}
+
+/// A named set of overrides for some of the config's values, selected with
+/// `--profile`. Useful for e.g. pointing at a test server, or at a local
+/// stand-in for the login API.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cdn_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login_api_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_executable: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launch_args: Option<Vec<String>>,
+}
+
+/// Wrappers, environment variables, etc. for launching the game, e.g. to
+/// run it under `gamemoderun` or `wine`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LaunchSpec {
+    /// A command (& its arguments) to run the game with, e.g.
+    /// `["taskset", "-c", "2,3"]`: the game executable & its arguments are
+    /// appended to it. An account's wrapper replaces the global one, so an
+    /// account can opt out of the global one with `[]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapper: Option<Vec<String>>,
+    /// Environment variables to set, on top of the ones that the game gets
+    /// anyway.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Environment variables to remove.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_remove: Vec<String>,
+    /// Relative to `install_dir`, unless absolute. Defaults to
+    /// `install_dir`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    /// Command line arguments to launch the game with, after `launch_args`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+/// What to do when, right before launching the game, the install turns out
+/// not to match the manifest. See `Updater::check`.
+#[derive(
+    Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LaunchCheck {
+    /// Ask whether to update, launch anyway, or not launch at all. Without
+    /// anyone there to ask, this is the same as `Warn`.
+    #[default]
+    Ask,
+    /// Always update first.
+    Update,
+    /// Always launch anyway, but say so.
+    Warn,
+    /// Don't check at all.
+    Never,
+}
+
+/// How to relaunch game instances that crash. See `supervisor`.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RestartPolicy {
+    /// At most this many restarts are done within `window`, after which the
+    /// instance is left alone.
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    /// In seconds.
+    #[serde(default = "default_restart_window")]
+    pub window: u64,
+    /// In seconds. How long to wait before the first restart within
+    /// `window`; each restart after that waits twice as long as the last.
+    #[serde(default = "default_restart_backoff")]
+    pub backoff: u64,
+    /// In seconds. The most that `backoff` can double up to.
+    #[serde(default = "default_max_restart_backoff")]
+    pub max_backoff: u64,
+}
+
+fn default_max_patch_output_size() -> u64 {
+    DEFAULT_MAX_PATCH_OUTPUT_SIZE
+}
+
+fn default_max_restarts() -> u32 {
+    DEFAULT_MAX_RESTARTS
+}
+
+fn default_restart_window() -> u64 {
+    DEFAULT_RESTART_WINDOW
+}
+
+fn default_restart_backoff() -> u64 {
+    DEFAULT_RESTART_BACKOFF
+}
+
+fn default_max_restart_backoff() -> u64 {
+    DEFAULT_MAX_RESTART_BACKOFF
+}
+
+fn default_login_api_uri() -> String {
+    DEFAULT_LOGIN_API_URI.to_owned()
+}
+
+fn default_game_executable() -> PathBuf {
+    PathBuf::from(DEFAULT_GAME_EXECUTABLE)
+}
+
+fn default_password_command_timeout() -> u64 {
+    DEFAULT_PASSWORD_COMMAND_TIMEOUT
+}
+
+fn default_max_logs() -> usize {
+    DEFAULT_MAX_LOGS
+}
+
+fn default_max_log_size() -> u64 {
+    DEFAULT_MAX_LOG_SIZE
 }
 
 impl Config {
-    /// Same return type as `BTreeMap::insert`.
+    /// Same return type as `BTreeMap::insert`. Accounts that are objects
+    /// (see `accounts`) keep their other keys, & only have their
+    /// `"password"` replaced.
     #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
     pub fn add_account(
         &mut self,
         username: String,
         password: String,
     ) -> Option<serde_json::Value> {
-        if self.store_passwords {
-            self.accounts
-                .insert(username, serde_json::Value::String(password))
+        let password = if self.store_passwords {
+            serde_json::Value::String(password)
         } else {
-            self.accounts.insert(username, serde_json::Value::Null)
+            serde_json::Value::Null
+        };
+
+        match self.accounts.get_mut(&username) {
+            Some(serde_json::Value::Object(account)) => {
+                if password.is_null() {
+                    account.remove(PASSWORD_KEY)
+                } else {
+                    account.insert(PASSWORD_KEY.to_owned(), password)
+                }
+            }
+            _ => self.accounts.insert(username, password),
         }
     }
 
     pub fn forget_account(&mut self, username: &str) {
         self.accounts.remove(username);
     }
+
+    /// The plaintext password saved for `username` in this config, if any.
+    /// This doesn't take `store_passwords` into account.
+    pub fn saved_password(&self, username: &str) -> Option<&str> {
+        match self.accounts.get(username)? {
+            serde_json::Value::String(password) => Some(password),
+            account => account.get(PASSWORD_KEY)?.as_str(),
+        }
+    }
+
+    /// The TOTP secret saved for `username` in this config, if any.
+    pub fn totp_secret(&self, username: &str) -> Option<&str> {
+        self.accounts.get(username)?.get(TOTP_SECRET_KEY)?.as_str()
+    }
+
+    /// Saves `secret` as `username`'s TOTP secret in this config, or removes
+    /// its TOTP secret if `secret` is `None`. This turns the account into an
+    /// object if it wasn't one already.
+    pub fn set_totp_secret(&mut self, username: &str, secret: Option<String>) {
+        let account = self.account_object(username);
+        match secret {
+            Some(secret) => {
+                account.insert(
+                    TOTP_SECRET_KEY.to_owned(),
+                    serde_json::Value::String(secret),
+                );
+            }
+            None => {
+                account.remove(TOTP_SECRET_KEY);
+            }
+        }
+    }
+
+    /// `username`'s account, which is turned into an object (keeping its
+    /// password, if any) if it wasn't one already, & is added if there was
+    /// no such account.
+    fn account_object(
+        &mut self,
+        username: &str,
+    ) -> &mut serde_json::Map<String, serde_json::Value> {
+        let account = self
+            .accounts
+            .entry(username)
+            .or_insert(serde_json::Value::Null);
+        if !account.is_object() {
+            let mut obj = serde_json::Map::new();
+            if let serde_json::Value::String(password) = account.take() {
+                obj.insert(
+                    PASSWORD_KEY.to_owned(),
+                    serde_json::Value::String(password),
+                );
+            }
+            *account = serde_json::Value::Object(obj);
+        }
+
+        match account {
+            serde_json::Value::Object(obj) => obj,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The nickname that `username` can also be referred to by, if any.
+    pub fn alias(&self, username: &str) -> Option<&str> {
+        self.accounts.get(username)?.get(ALIAS_KEY)?.as_str()
+    }
+
+    /// Gives `username` the nickname `alias`, or takes its nickname away if
+    /// `alias` is `None`. Fails if `alias` is already taken, by another
+    /// account's username or nickname.
+    pub fn set_alias(
+        &mut self,
+        username: &str,
+        alias: Option<String>,
+    ) -> Result<(), Error> {
+        match alias {
+            Some(alias) => {
+                check_alias(&alias)?;
+                let other = self.resolve_alias(&alias).or_else(|| {
+                    self.accounts
+                        .get_key_value(alias.as_str())
+                        .map(|(other, _)| other.as_str())
+                });
+                if let Some(other) = other
+                    && other != username
+                {
+                    return Err(Error::InvalidConfig(format!(
+                        "{alias:?} already refers to the account {other:?}",
+                    )));
+                }
+
+                self.account_object(username)
+                    .insert(ALIAS_KEY.to_owned(), alias.into());
+            }
+            None => {
+                if let Some(serde_json::Value::Object(account)) =
+                    self.accounts.get_mut(username)
+                {
+                    account.remove(ALIAS_KEY);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Freeform labels for `username`, which are only ever displayed.
+    pub fn tags(&self, username: &str) -> Vec<&str> {
+        self.string_list(username, TAGS_KEY)
+    }
+
+    /// The names of the groups (see `resolve_accounts`) that `username` is
+    /// in.
+    pub fn groups(&self, username: &str) -> Vec<&str> {
+        self.string_list(username, GROUPS_KEY)
+    }
+
+    fn string_list(&self, username: &str, key: &str) -> Vec<&str> {
+        self.accounts
+            .get(username)
+            .and_then(|account| account.get(key))
+            .and_then(serde_json::Value::as_array)
+            .map_or_else(Vec::new, |list| {
+                list.iter().filter_map(serde_json::Value::as_str).collect()
+            })
+    }
+
+    /// Every group, by name, along with the usernames of its members.
+    pub fn all_groups(&self) -> BTreeMap<&str, Vec<&str>> {
+        let mut groups: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for username in self.accounts.keys() {
+            for group in self.groups(username) {
+                groups.entry(group).or_default().push(username.as_str());
+            }
+        }
+
+        groups
+    }
+
+    /// Puts `username` in the group named `group`. Returns `false` if it was
+    /// already in it.
+    pub fn add_to_group(
+        &mut self,
+        username: &str,
+        group: &str,
+    ) -> Result<bool, Error> {
+        check_group(group)?;
+        if self.groups(username).contains(&group) {
+            return Ok(false);
+        }
+
+        let groups = self
+            .account_object(username)
+            .entry(GROUPS_KEY)
+            .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+        if let serde_json::Value::Array(groups) = groups {
+            groups.push(group.into());
+        }
+
+        Ok(true)
+    }
+
+    /// Takes `username` out of the group named `group`. Returns `false` if
+    /// it wasn't in it.
+    pub fn remove_from_group(&mut self, username: &str, group: &str) -> bool {
+        let Some(serde_json::Value::Object(account)) =
+            self.accounts.get_mut(username)
+        else {
+            return false;
+        };
+        let Some(serde_json::Value::Array(groups)) =
+            account.get_mut(GROUPS_KEY)
+        else {
+            return false;
+        };
+
+        let len = groups.len();
+        groups.retain(|g| g.as_str() != Some(group));
+        let removed = groups.len() != len;
+        if groups.is_empty() {
+            account.remove(GROUPS_KEY);
+        }
+
+        removed
+    }
+
+    /// The username of the account whose nickname is `alias`, if any.
+    pub fn resolve_alias(&self, alias: &str) -> Option<&str> {
+        self.accounts
+            .keys()
+            .find(|username| self.alias(username) == Some(alias))
+            .map(String::as_str)
+    }
+
+    /// The usernames that `names` refer to, in order & without duplicates.
+    /// Each name is either a username, an account's nickname (see
+    /// `alias`), or a group's name prefixed with `@`, which refers to every
+    /// account in the group. Names that are none of these are taken to be
+    /// usernames of accounts that aren't in this config (yet).
+    pub fn resolve_accounts<'a, I: IntoIterator<Item = &'a str>>(
+        &self,
+        names: I,
+    ) -> Result<Vec<String>, Error> {
+        let mut usernames: Vec<String> = Vec::new();
+        let mut push = |username: &str| {
+            if !usernames.iter().any(|u| u == username) {
+                usernames.push(username.to_owned());
+            }
+        };
+
+        for name in names {
+            if let Some(group) = name.strip_prefix(GROUP_PREFIX) {
+                let all_groups = self.all_groups();
+                let members = all_groups
+                    .get(group)
+                    .ok_or_else(|| Error::UnknownGroup(group.to_owned()))?;
+                members.iter().for_each(|username| push(username));
+            } else if self.accounts.contains_key(name) {
+                push(name);
+            } else {
+                push(self.resolve_alias(name).unwrap_or(name));
+            }
+        }
+
+        Ok(usernames)
+    }
+
+    /// The command that `username`'s password should be gotten from: the
+    /// account's own `"password_command"`, if it has one, or else the
+    /// global `password_command`.
+    pub fn password_command(&self, username: &str) -> Option<&str> {
+        self.accounts
+            .get(username)
+            .and_then(|account| account.get(PASSWORD_COMMAND_KEY))
+            .and_then(serde_json::Value::as_str)
+            .or(self.password_command.as_deref())
+    }
+
+    /// How to launch the game for `username`: the global `launch`, with the
+    /// account's own `"launch"` (if any) layered on top. The account's
+    /// `wrapper` & `working_dir` replace the global ones, its `env` is
+    /// merged into the global `env` (after removing its `env_remove` from
+    /// it), & its `env_remove` & `args` come after the global ones.
+    pub fn launch_spec(&self, username: &str) -> Result<LaunchSpec, Error> {
+        let Some(account_spec) = self
+            .accounts
+            .get(username)
+            .and_then(|account| account.get(LAUNCH_KEY))
+        else {
+            return Ok(self.launch.clone());
+        };
+        let account_spec: LaunchSpec =
+            serde_json::from_value(account_spec.clone()).map_err(|e| {
+                Error::InvalidConfig(format!(
+                    "The account {username:?} has an invalid \
+                     `\"{LAUNCH_KEY}\"`: {e}",
+                ))
+            })?;
+
+        let mut spec = self.launch.clone();
+        if account_spec.wrapper.is_some() {
+            spec.wrapper = account_spec.wrapper;
+        }
+        for var in &account_spec.env_remove {
+            spec.env.remove(var);
+        }
+        spec.env.extend(account_spec.env);
+        spec.env_remove.extend(account_spec.env_remove);
+        if account_spec.working_dir.is_some() {
+            spec.working_dir = account_spec.working_dir;
+        }
+        spec.args.extend(account_spec.args);
+
+        Ok(spec)
+    }
+
+    /// How to relaunch `username`'s instances if they crash, if at all: the
+    /// account's own `"supervise"` if it has one, & the global `supervise`
+    /// otherwise.
+    pub fn restart_policy(
+        &self,
+        username: &str,
+    ) -> Result<Option<RestartPolicy>, Error> {
+        let Some(policy) = self
+            .accounts
+            .get(username)
+            .and_then(|account| account.get(SUPERVISE_KEY))
+        else {
+            return Ok(self.supervise);
+        };
+
+        serde_json::from_value(policy.clone()).map_err(|e| {
+            Error::InvalidConfig(format!(
+                "The account {username:?} has an invalid \
+                 `\"{SUPERVISE_KEY}\"`: {e}",
+            ))
+        })
+    }
+
+    /// Reads the config file at `path`. Unlike `get_config`, this never
+    /// prompts for anything, and fails if there is no such file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let config: Self = serde_json::from_reader(io::BufReader::new(
+            util::open_file(path)?,
+        ))
+        .map_err(Error::Deserialize)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Overrides this config's values with those of the profile named
+    /// `name`, replacing any profile that was applied before.
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), Error> {
+        let profile = self
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::UnknownProfile(name.to_owned()))?;
+
+        if let Some(overridden) = self.overridden.take() {
+            overridden.apply_to(self);
+        }
+        self.overridden = Some(profile.apply_to(self));
+
+        Ok(())
+    }
+
+    /// Checks for values that can't possibly work, so that they're caught
+    /// before they're used.
+    pub fn validate(&self) -> Result<(), Error> {
+        for (key, uri) in [
+            ("manifest_uri", &self.manifest_uri),
+            ("cdn_uri", &self.cdn_uri),
+            ("login_api_uri", &self.login_api_uri),
+        ] {
+            match reqwest::Url::parse(uri) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => (),
+                _ => {
+                    return Err(Error::InvalidConfig(format!(
+                        "`{key}` must be an http(s) URL, not {uri:?}",
+                    )));
+                }
+            }
+        }
+
+        if !self.cdn_uri.ends_with('/') {
+            return Err(Error::InvalidConfig(format!(
+                "`cdn_uri` must end with a slash, because file names are \
+                 appended to it: {:?}",
+                self.cdn_uri,
+            )));
+        }
+
+        if self.game_executable.as_os_str().is_empty() {
+            return Err(Error::InvalidConfig(
+                "`game_executable` must not be empty".to_owned(),
+            ));
+        }
+
+        if self.password_command_timeout == 0 {
+            return Err(Error::InvalidConfig(
+                "`password_command_timeout` must be at least 1 second"
+                    .to_owned(),
+            ));
+        }
+        self.launch.validate("`launch`")?;
+        if let Some(policy) = &self.supervise {
+            policy.validate("`supervise`")?;
+        }
+        for (username, account) in &self.accounts {
+            if let Some(policy) = self.restart_policy(username)? {
+                policy.validate(&format!(
+                    "The account {username:?}'s `\"{SUPERVISE_KEY}\"`",
+                ))?;
+            }
+            if account.get(LAUNCH_KEY).is_some() {
+                self.launch_spec(username)?.validate(&format!(
+                    "The account {username:?}'s `\"{LAUNCH_KEY}\"`",
+                ))?;
+            }
+
+            let valid = match account {
+                serde_json::Value::Null | serde_json::Value::String(_) => true,
+                serde_json::Value::Object(obj) => {
+                    [
+                        PASSWORD_KEY,
+                        PASSWORD_COMMAND_KEY,
+                        TOTP_SECRET_KEY,
+                        ALIAS_KEY,
+                    ]
+                    .into_iter()
+                    .filter_map(|key| obj.get(key))
+                    .all(serde_json::Value::is_string)
+                        && [TAGS_KEY, GROUPS_KEY]
+                            .into_iter()
+                            .filter_map(|key| obj.get(key))
+                            .all(|list| {
+                                list.as_array().is_some_and(|list| {
+                                    list.iter()
+                                        .all(serde_json::Value::is_string)
+                                })
+                            })
+                }
+                _ => false,
+            };
+            if !valid {
+                return Err(Error::InvalidConfig(format!(
+                    "The account {username:?} must be either a password, \
+                     null, or an object whose `\"{PASSWORD_KEY}\"`, \
+                     `\"{PASSWORD_COMMAND_KEY}\"`, `\"{TOTP_SECRET_KEY}\"` \
+                     & `\"{ALIAS_KEY}\"` (if any) are strings, & whose \
+                     `\"{TAGS_KEY}\"` & `\"{GROUPS_KEY}\"` (if any) are \
+                     arrays of strings",
+                )));
+            }
+
+            if let Some(alias) = self.alias(username) {
+                check_alias(alias)?;
+                if (alias != username && self.accounts.contains_key(alias))
+                    || self.resolve_alias(alias) != Some(username)
+                {
+                    return Err(Error::InvalidConfig(format!(
+                        "The account {username:?}'s `\"{ALIAS_KEY}\"` \
+                         ({alias:?}) is already another account's username \
+                         or alias",
+                    )));
+                }
+            }
+            for group in self.groups(username) {
+                check_group(group)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn game_executable_path(&self) -> PathBuf {
+        self.install_dir.join(&self.game_executable)
+    }
+
+    pub fn logs_dir(&self) -> PathBuf {
+        self.logs_dir
+            .clone()
+            .unwrap_or_else(|| self.cache_dir.join("logs"))
+    }
+
+    pub fn history_path(&self) -> PathBuf {
+        self.history_file
+            .clone()
+            .unwrap_or_else(|| self.cache_dir.join("history.jsonl"))
+    }
+}
+
+impl LaunchCheck {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl LaunchSpec {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// `what` is how this spec is referred to in error messages.
+    fn validate(&self, what: &str) -> Result<(), Error> {
+        if let Some(wrapper) = &self.wrapper
+            && wrapper.first().is_some_and(String::is_empty)
+        {
+            return Err(Error::InvalidConfig(format!(
+                "{what} has an empty `wrapper` command",
+            )));
+        }
+
+        for var in self.env.keys().chain(&self.env_remove) {
+            if var.is_empty() || var.contains(['=', '\0']) {
+                return Err(Error::InvalidConfig(format!(
+                    "{what} has an invalid environment variable name: \
+                     {var:?}",
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: DEFAULT_MAX_RESTARTS,
+            window: DEFAULT_RESTART_WINDOW,
+            backoff: DEFAULT_RESTART_BACKOFF,
+            max_backoff: DEFAULT_MAX_RESTART_BACKOFF,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// `what` is how this policy is referred to in error messages.
+    fn validate(&self, what: &str) -> Result<(), Error> {
+        if self.window == 0 {
+            return Err(Error::InvalidConfig(format!(
+                "{what} must have a `window` of at least 1 second",
+            )));
+        }
+        if self.max_backoff < self.backoff {
+            return Err(Error::InvalidConfig(format!(
+                "{what} must have a `max_backoff` of at least its `backoff`",
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Profile {
+    /// Overrides `config`'s values with this profile's, returning a profile
+    /// that undoes this.
+    fn apply_to(&self, config: &mut Config) -> Self {
+        fn swap<T: Clone>(slot: &mut T, value: &Option<T>) -> Option<T> {
+            value.as_ref().map(|v| std::mem::replace(slot, v.clone()))
+        }
+
+        Self {
+            install_dir: swap(&mut config.install_dir, &self.install_dir),
+            manifest_uri: swap(&mut config.manifest_uri, &self.manifest_uri),
+            cdn_uri: swap(&mut config.cdn_uri, &self.cdn_uri),
+            login_api_uri: swap(
+                &mut config.login_api_uri,
+                &self.login_api_uri,
+            ),
+            game_executable: swap(
+                &mut config.game_executable,
+                &self.game_executable,
+            ),
+            launch_args: swap(&mut config.launch_args, &self.launch_args),
+        }
+    }
+}
+
+fn check_alias(alias: &str) -> Result<(), Error> {
+    if alias.is_empty() || alias.starts_with(GROUP_PREFIX) {
+        return Err(Error::InvalidConfig(format!(
+            "An alias must not be empty, nor start with \"{GROUP_PREFIX}\": \
+             {alias:?}",
+        )));
+    }
+
+    Ok(())
+}
+
+fn check_group(group: &str) -> Result<(), Error> {
+    if group.is_empty() || group.starts_with(GROUP_PREFIX) {
+        return Err(Error::InvalidConfig(format!(
+            "A group's name must not be empty, nor start with \
+             \"{GROUP_PREFIX}\": {group:?}",
+        )));
+    }
+
+    Ok(())
 }
 
 pub fn get_config(
@@ -50,23 +853,24 @@ pub fn get_config(
     config_path: Option<PathBuf>,
     install_path: Option<PathBuf>,
     cache_path: Option<PathBuf>,
+    profile: Option<&str>,
     quiet: bool,
 ) -> Result<(Config, PathBuf), Error> {
-    let inject_arg_values = |c| {
-        let c = if let Some(ip) = install_path.clone() {
-            Config {
-                install_dir: ip,
-                ..c
-            }
-        } else {
-            c
-        };
-
+    // Command line arguments take precedence over the profile, which takes
+    // precedence over the rest of the config.
+    let finish = |mut c: Config| {
+        if let Some(name) = profile {
+            c.apply_profile(name)?;
+        }
+        if let Some(ip) = install_path.clone() {
+            c.install_dir = ip;
+        }
         if let Some(cp) = cache_path.clone() {
-            Config { cache_dir: cp, ..c }
-        } else {
-            c
+            c.cache_dir = cp;
         }
+        c.validate()?;
+
+        Ok(c)
     };
 
     if no_config {
@@ -75,106 +879,44 @@ pub fn get_config(
         }
 
         Ok((
-            Config {
-                install_dir: install_path.ok_or_else(|| {
+            finish(Config {
+                install_dir: install_path.clone().ok_or_else(|| {
                     Error::MissingCommandLineArg("--install-dir")
                 })?,
-                cache_dir: cache_path.ok_or_else(|| {
+                cache_dir: cache_path.clone().ok_or_else(|| {
                     Error::MissingCommandLineArg("--cache-dir")
                 })?,
                 manifest_uri: DEFAULT_MANIFEST_URI.to_owned(),
                 cdn_uri: DEFAULT_CDN_URI.to_owned(),
                 store_passwords: false,
                 accounts: serde_json::Map::default(),
-            },
+                max_patch_output_size: DEFAULT_MAX_PATCH_OUTPUT_SIZE,
+                login_api_uri: default_login_api_uri(),
+                game_executable: default_game_executable(),
+                launch_args: Vec::new(),
+                launch: LaunchSpec::default(),
+                password_command: None,
+                password_command_timeout: DEFAULT_PASSWORD_COMMAND_TIMEOUT,
+                login_timeout: None,
+                max_queue_wait: None,
+                launch_stagger: None,
+                supervise: None,
+                logs_dir: None,
+                max_logs: DEFAULT_MAX_LOGS,
+                max_log_age: None,
+                max_log_size: DEFAULT_MAX_LOG_SIZE,
+                history_file: None,
+                launch_check: LaunchCheck::default(),
+                profiles: BTreeMap::new(),
+                overridden: None,
+            })?,
             PathBuf::new(),
         ))
     } else {
         let config_path = if let Some(s) = config_path {
             s
         } else {
-            #[cfg(target_os = "linux")]
-            {
-                let mut xdg_config_home = String::new();
-                let mut home = String::new();
-
-                for (key, value) in env::vars() {
-                    match key.as_str() {
-                        "XDG_CONFIG_HOME" => xdg_config_home = value,
-                        "HOME" => home = value,
-                        _ => {
-                            if !(home.is_empty() || xdg_config_home.is_empty())
-                            {
-                                break;
-                            }
-                        }
-                    }
-                }
-
-                if !xdg_config_home.is_empty() {
-                    [xdg_config_home.as_str(), crate_name!(), "config.json"]
-                        .iter()
-                        .collect()
-                } else if !home.is_empty() {
-                    [home.as_str(), ".config", crate_name!(), "config.json"]
-                        .iter()
-                        .collect()
-                } else {
-                    return Err(Error::NoPossibleConfigPath);
-                }
-            }
-            #[cfg(windows)]
-            {
-                let mut appdata = String::new();
-
-                for (key, value) in env::vars() {
-                    match key.as_str() {
-                        "APPDATA" => appdata = value,
-                        _ => {
-                            if !appdata.is_empty() {
-                                break;
-                            }
-                        }
-                    }
-                }
-
-                if !appdata.is_empty() {
-                    [appdata.as_str(), crate_name!(), "config.json"]
-                        .iter()
-                        .collect()
-                } else {
-                    return Err(Error::NoPossibleConfigPath);
-                }
-            }
-            #[cfg(target_os = "macos")]
-            {
-                let mut home = String::new();
-
-                for (key, value) in env::vars() {
-                    match key.as_str() {
-                        "HOME" => home = value,
-                        _ => {
-                            if !(home.is_empty()) {
-                                break;
-                            }
-                        }
-                    }
-                }
-
-                if !home.is_empty() {
-                    [
-                        home.as_str(),
-                        "Library",
-                        "Preferences",
-                        crate_name!(),
-                        "config.json",
-                    ]
-                    .iter()
-                    .collect()
-                } else {
-                    return Err(Error::NoPossibleConfigPath);
-                }
-            }
+            default_config_path()?
         };
 
         if !quiet {
@@ -184,7 +926,7 @@ pub fn get_config(
         match File::open(&config_path) {
             Ok(f) => serde_json::from_reader(f)
                 .map_err(Error::Deserialize)
-                .map(|c| (inject_arg_values(c), config_path)),
+                .and_then(|c| Ok((finish(c)?, config_path))),
             Err(ioe) => match ioe.kind() {
                 io::ErrorKind::NotFound => {
                     let config_dir =
@@ -204,7 +946,7 @@ pub fn get_config(
                     )
                     .map_err(Error::Serialize)?;
 
-                    Ok((inject_arg_values(new_config), config_path))
+                    Ok((finish(new_config)?, config_path))
                 }
                 io::ErrorKind::PermissionDenied => {
                     Err(Error::PermissionDenied(
@@ -221,6 +963,91 @@ pub fn get_config(
     }
 }
 
+/// Where the config lives when no path is explicitly given.
+pub fn default_config_path() -> Result<PathBuf, Error> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut xdg_config_home = String::new();
+        let mut home = String::new();
+
+        for (key, value) in env::vars() {
+            match key.as_str() {
+                "XDG_CONFIG_HOME" => xdg_config_home = value,
+                "HOME" => home = value,
+                _ => {
+                    if !(home.is_empty() || xdg_config_home.is_empty()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if !xdg_config_home.is_empty() {
+            Ok([xdg_config_home.as_str(), crate_name!(), "config.json"]
+                .iter()
+                .collect())
+        } else if !home.is_empty() {
+            Ok([home.as_str(), ".config", crate_name!(), "config.json"]
+                .iter()
+                .collect())
+        } else {
+            Err(Error::NoPossibleConfigPath)
+        }
+    }
+    #[cfg(windows)]
+    {
+        let mut appdata = String::new();
+
+        for (key, value) in env::vars() {
+            match key.as_str() {
+                "APPDATA" => appdata = value,
+                _ => {
+                    if !appdata.is_empty() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if !appdata.is_empty() {
+            Ok([appdata.as_str(), crate_name!(), "config.json"]
+                .iter()
+                .collect())
+        } else {
+            Err(Error::NoPossibleConfigPath)
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let mut home = String::new();
+
+        for (key, value) in env::vars() {
+            match key.as_str() {
+                "HOME" => home = value,
+                _ => {
+                    if !(home.is_empty()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if !home.is_empty() {
+            Ok([
+                home.as_str(),
+                "Library",
+                "Preferences",
+                crate_name!(),
+                "config.json",
+            ]
+            .iter()
+            .collect())
+        } else {
+            Err(Error::NoPossibleConfigPath)
+        }
+    }
+}
+
 fn prompt_for_config_values<P: AsRef<Path>>(
     config_path: P,
 ) -> Result<Config, Error> {
@@ -272,6 +1099,25 @@ fn prompt_for_config_values<P: AsRef<Path>>(
                 cdn_uri: DEFAULT_CDN_URI.to_owned(),
                 store_passwords: yes_no_trimmed == "yes",
                 accounts: serde_json::Map::default(),
+                max_patch_output_size: DEFAULT_MAX_PATCH_OUTPUT_SIZE,
+                login_api_uri: default_login_api_uri(),
+                game_executable: default_game_executable(),
+                launch_args: Vec::new(),
+                launch: LaunchSpec::default(),
+                password_command: None,
+                password_command_timeout: DEFAULT_PASSWORD_COMMAND_TIMEOUT,
+                login_timeout: None,
+                max_queue_wait: None,
+                launch_stagger: None,
+                supervise: None,
+                logs_dir: None,
+                max_logs: DEFAULT_MAX_LOGS,
+                max_log_age: None,
+                max_log_size: DEFAULT_MAX_LOG_SIZE,
+                history_file: None,
+                launch_check: LaunchCheck::default(),
+                profiles: BTreeMap::new(),
+                overridden: None,
             });
         }
 
@@ -294,6 +1140,17 @@ pub fn commit_config<P: AsRef<Path>>(
         .join(".config.json.temp");
     let mut temp_config_file = util::create_file(&temp_config_path)?;
 
+    let unprofiled;
+    let config = if let Some(overridden) = &config.overridden {
+        let mut c = config.clone();
+        overridden.apply_to(&mut c);
+        unprofiled = c;
+
+        &unprofiled
+    } else {
+        config
+    };
+
     serde_json::to_writer_pretty(&mut temp_config_file, config)
         .map_err(Error::Serialize)?;
     temp_config_file
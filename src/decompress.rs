@@ -0,0 +1,98 @@
+//! Decompression of downloaded files, & of the blocks inside of patches.
+//!
+//! The official CDN only ever uses bzip2, but mirrors may recompress content
+//! using zstd or xz, which are much faster to decode.
+
+use bzip2::read::BzDecoder as BzReadDecoder;
+use lzma_rust2::XzReader;
+use ruzstd::decoding::StreamingDecoder as ZstdReadDecoder;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read};
+
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+/// Length of the longest magic number above.
+const MAX_MAGIC_LEN: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Bzip2,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// For the optional `"compression"` field of manifest entries.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bzip2" | "bz2" => Some(Self::Bzip2),
+            "zstd" | "zst" => Some(Self::Zstd),
+            "xz" => Some(Self::Xz),
+            _ => None,
+        }
+    }
+
+    /// Guesses based on the file extension, defaulting to bzip2.
+    pub fn from_file_name(file_name: &str) -> Self {
+        match file_name.rsplit_once('.') {
+            Some((_, "zst" | "zstd")) => Self::Zstd,
+            Some((_, "xz")) => Self::Xz,
+            _ => Self::Bzip2,
+        }
+    }
+
+    /// Identifies the compression format from the magic number at the start
+    /// of the compressed data.
+    pub fn from_magic(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(BZIP2_MAGIC) {
+            Some(Self::Bzip2)
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Some(Self::Zstd)
+        } else if magic.starts_with(XZ_MAGIC) {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// Wraps `r`, which yields compressed data, in a decoder that yields the
+    /// decompressed data. Exactly one compressed stream is decoded, so `r`
+    /// may have other data following the stream.
+    pub fn decoder<'a, R: Read + 'a>(
+        self,
+        r: R,
+    ) -> Result<Box<dyn Read + 'a>, io::Error> {
+        Ok(match self {
+            Self::Bzip2 => Box::new(BzReadDecoder::new(r)),
+            Self::Zstd => {
+                Box::new(ZstdReadDecoder::new(r).map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, e)
+                })?)
+            }
+            Self::Xz => Box::new(XzReader::new(r, false)),
+        })
+    }
+}
+
+/// Like `Compression::decoder`, but identifies the compression format from
+/// its magic number. Unrecognized data is assumed to be bzip2, which is what
+/// the original bsdiff uses.
+pub fn sniffing_decoder<'a, R: Read + 'a>(
+    mut r: R,
+) -> Result<Box<dyn Read + 'a>, io::Error> {
+    let mut magic = [0u8; MAX_MAGIC_LEN];
+    let mut magic_len = 0;
+    while magic_len < magic.len() {
+        match r.read(&mut magic[magic_len..])? {
+            0 => break,
+            n => magic_len += n,
+        }
+    }
+    let magic = &magic[..magic_len];
+
+    Compression::from_magic(magic)
+        .unwrap_or(Compression::Bzip2)
+        .decoder(io::Cursor::new(magic.to_vec()).chain(r))
+}
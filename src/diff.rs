@@ -0,0 +1,437 @@
+//! The diffing half of bsdiff 4.3, used to produce the `BSDIFF40` patches
+//! that `patch` applies. Like `patch`, this has been adapted more or less
+//! line-for-line from the original C source, including the original variable
+//! names, and uses the same suffix sorting algorithm (Larsson & Sadakane's
+//! qsufsort) to find matches between the old & new files.
+//!
+//! bsdiff4 is licensed under a slight variation of the FreeBSD license, which
+//! requires that the licensing text be reproduced alongside any modified or
+//! unmodified redistributions. So here it is (the same text can be found in
+//! the LICENSE.bsdiff4 file):
+//!
+//! ```c
+//! /*-
+//!  * Copyright 2003-2005 Colin Percival
+//!  * All rights reserved
+//!  *
+//!  * Redistribution and use in source and binary forms, with or without
+//!  * modification, are permitted providing that the following conditions
+//!  * are met:
+//!  * 1. Redistributions of source code must retain the above copyright
+//!  *    notice, this list of conditions and the following disclaimer.
+//!  * 2. Redistributions in binary form must reproduce the above copyright
+//!  *    notice, this list of conditions and the following disclaimer in the
+//!  *    documentation and/or other materials provided with the distribution.
+//!  *
+//!  * THIS SOFTWARE IS PROVIDED BY THE AUTHOR ``AS IS'' AND ANY EXPRESS OR
+//!  * IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+//!  * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+//!  * ARE DISCLAIMED.  IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR ANY
+//!  * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+//!  * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS
+//!  * OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION)
+//!  * HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+//!  * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING
+//!  * IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+//!  * POSSIBILITY OF SUCH DAMAGE.
+//!  */
+//! ```
+
+use crate::{error::Error, util};
+use bzip2::{Compression, write::BzEncoder};
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// Produces a patch at `patch_file_path` that turns `old_file_path` into
+/// `new_file_path`. The patch can be applied by `patch::patch_file`.
+pub fn diff_files<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
+    old_file_path: P,
+    new_file_path: Q,
+    patch_file_path: R,
+) -> Result<(), Error> {
+    let old = fs::read(&old_file_path).map_err(|ioe| {
+        Error::FileRead(old_file_path.as_ref().to_path_buf(), ioe)
+    })?;
+    let new = fs::read(&new_file_path).map_err(|ioe| {
+        Error::FileRead(new_file_path.as_ref().to_path_buf(), ioe)
+    })?;
+
+    let mut pf = util::create_file(&patch_file_path)?;
+    bsdiff(&old, &new, &mut pf).map_err(|ioe| {
+        Error::FileWrite(patch_file_path.as_ref().to_path_buf(), ioe)
+    })
+}
+
+#[allow(non_snake_case)]
+fn bsdiff<W: Write>(old: &[u8], new: &[u8], pf: &mut W) -> io::Result<()> {
+    let oldsize = old.len() as i64;
+    let newsize = new.len() as i64;
+
+    let mut I = vec![0i64; old.len() + 1];
+    let mut V = vec![0i64; old.len() + 1];
+    qsufsort(&mut I, &mut V, old);
+    drop(V);
+
+    let mut db = Vec::with_capacity(new.len());
+    let mut eb = Vec::with_capacity(new.len());
+    let mut ctrl = Vec::new();
+
+    /*
+     * File format:
+     *
+     *   | offset | len | data
+     * --+--------+-----+----------------------
+     *   | 0      | 8   | "BSDIFF40"
+     *   | 8      | 8   | X
+     *   | 16     | 8   | Y
+     *   | 24     | 8   | sizeof(newfile)
+     *   | 32     | X   | bzip2(control block)
+     *   | 32+X   | Y   | bzip2(diff block)
+     *   | 32+X+Y | ??? | bzip2(extra block)
+     *
+     * with control block a set of triples (x,y,z) meaning "add x bytes
+     * from oldfile to x bytes from the diff block; copy y bytes from the
+     * extra block; seek forwards in oldfile by z bytes".
+     */
+
+    // Compute the differences, writing ctrl as we go
+    let mut scan = 0i64;
+    let mut len = 0i64;
+    let mut pos = 0i64;
+    let mut lastscan = 0i64;
+    let mut lastpos = 0i64;
+    let mut lastoffset = 0i64;
+    while scan < newsize {
+        let mut oldscore = 0i64;
+
+        scan += len;
+        let mut scsc = scan;
+        while scan < newsize {
+            len = search(&I, old, &new[scan as usize..], 0, oldsize, &mut pos);
+
+            while scsc < scan + len {
+                if (scsc + lastoffset < oldsize)
+                    && (old[(scsc + lastoffset) as usize]
+                        == new[scsc as usize])
+                {
+                    oldscore += 1;
+                }
+                scsc += 1;
+            }
+
+            if ((len == oldscore) && (len != 0)) || (len > oldscore + 8) {
+                break;
+            }
+
+            if (scan + lastoffset < oldsize)
+                && (old[(scan + lastoffset) as usize] == new[scan as usize])
+            {
+                oldscore -= 1;
+            }
+            scan += 1;
+        }
+
+        if (len != oldscore) || (scan == newsize) {
+            let mut s = 0i64;
+            let mut Sf = 0i64;
+            let mut lenf = 0i64;
+            let mut i = 0i64;
+            while (lastscan + i < scan) && (lastpos + i < oldsize) {
+                if old[(lastpos + i) as usize] == new[(lastscan + i) as usize]
+                {
+                    s += 1;
+                }
+                i += 1;
+                if s * 2 - i > Sf * 2 - lenf {
+                    Sf = s;
+                    lenf = i;
+                }
+            }
+
+            let mut lenb = 0i64;
+            if scan < newsize {
+                let mut s = 0i64;
+                let mut Sb = 0i64;
+                let mut i = 1i64;
+                while (scan >= lastscan + i) && (pos >= i) {
+                    if old[(pos - i) as usize] == new[(scan - i) as usize] {
+                        s += 1;
+                    }
+                    if s * 2 - i > Sb * 2 - lenb {
+                        Sb = s;
+                        lenb = i;
+                    }
+                    i += 1;
+                }
+            }
+
+            if lastscan + lenf > scan - lenb {
+                let overlap = (lastscan + lenf) - (scan - lenb);
+                let mut s = 0i64;
+                let mut Ss = 0i64;
+                let mut lens = 0i64;
+                for i in 0..overlap {
+                    if new[(lastscan + lenf - overlap + i) as usize]
+                        == old[(lastpos + lenf - overlap + i) as usize]
+                    {
+                        s += 1;
+                    }
+                    if new[(scan - lenb + i) as usize]
+                        == old[(pos - lenb + i) as usize]
+                    {
+                        s -= 1;
+                    }
+                    if s > Ss {
+                        Ss = s;
+                        lens = i + 1;
+                    }
+                }
+
+                lenf += lens - overlap;
+                lenb -= lens;
+            }
+
+            for i in 0..lenf {
+                db.push(
+                    new[(lastscan + i) as usize]
+                        .wrapping_sub(old[(lastpos + i) as usize]),
+                );
+            }
+            for i in 0..(scan - lenb) - (lastscan + lenf) {
+                eb.push(new[(lastscan + lenf + i) as usize]);
+            }
+
+            ctrl.extend_from_slice(&offtout(lenf));
+            ctrl.extend_from_slice(&offtout(
+                (scan - lenb) - (lastscan + lenf),
+            ));
+            ctrl.extend_from_slice(&offtout((pos - lenb) - (lastpos + lenf)));
+
+            lastscan = scan - lenb;
+            lastpos = pos - lenb;
+            lastoffset = pos - scan;
+        }
+    }
+
+    let ctrl = bzip2_compress(&ctrl)?;
+    let db = bzip2_compress(&db)?;
+    let eb = bzip2_compress(&eb)?;
+
+    // Header
+    let mut header = [0u8; 32];
+    header[..8].copy_from_slice(b"BSDIFF40");
+    header[8..16].copy_from_slice(&offtout(ctrl.len() as i64));
+    header[16..24].copy_from_slice(&offtout(db.len() as i64));
+    header[24..].copy_from_slice(&offtout(newsize));
+
+    pf.write_all(&header)?;
+    pf.write_all(&ctrl)?;
+    pf.write_all(&db)?;
+    pf.write_all(&eb)?;
+
+    pf.flush()
+}
+
+fn bzip2_compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = BzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+
+    encoder.finish()
+}
+
+#[allow(non_snake_case)]
+fn split(I: &mut [i64], V: &mut [i64], start: i64, len: i64, h: i64) {
+    let v = |V: &[i64], i: i64| V[i as usize];
+
+    if len < 16 {
+        let mut k = start;
+        while k < start + len {
+            let mut j = 1;
+            let mut x = v(V, I[k as usize] + h);
+            let mut i = 1;
+            while k + i < start + len {
+                if v(V, I[(k + i) as usize] + h) < x {
+                    x = v(V, I[(k + i) as usize] + h);
+                    j = 0;
+                }
+                if v(V, I[(k + i) as usize] + h) == x {
+                    I.swap((k + j) as usize, (k + i) as usize);
+                    j += 1;
+                }
+                i += 1;
+            }
+            for i in 0..j {
+                V[I[(k + i) as usize] as usize] = k + j - 1;
+            }
+            if j == 1 {
+                I[k as usize] = -1;
+            }
+            k += j;
+        }
+
+        return;
+    }
+
+    let x = v(V, I[(start + len / 2) as usize] + h);
+    let mut jj = 0;
+    let mut kk = 0;
+    for i in start..start + len {
+        if v(V, I[i as usize] + h) < x {
+            jj += 1;
+        }
+        if v(V, I[i as usize] + h) == x {
+            kk += 1;
+        }
+    }
+    jj += start;
+    kk += jj;
+
+    let mut i = start;
+    let mut j = 0;
+    let mut k = 0;
+    while i < jj {
+        if v(V, I[i as usize] + h) < x {
+            i += 1;
+        } else if v(V, I[i as usize] + h) == x {
+            I.swap(i as usize, (jj + j) as usize);
+            j += 1;
+        } else {
+            I.swap(i as usize, (kk + k) as usize);
+            k += 1;
+        }
+    }
+
+    while jj + j < kk {
+        if v(V, I[(jj + j) as usize] + h) == x {
+            j += 1;
+        } else {
+            I.swap((jj + j) as usize, (kk + k) as usize);
+            k += 1;
+        }
+    }
+
+    if jj > start {
+        split(I, V, start, jj - start, h);
+    }
+
+    for i in 0..kk - jj {
+        V[I[(jj + i) as usize] as usize] = kk - 1;
+    }
+    if jj == kk - 1 {
+        I[jj as usize] = -1;
+    }
+
+    if start + len > kk {
+        split(I, V, kk, start + len - kk, h);
+    }
+}
+
+#[allow(non_snake_case)]
+fn qsufsort(I: &mut [i64], V: &mut [i64], old: &[u8]) {
+    let oldsize = old.len() as i64;
+    let mut buckets = [0i64; 256];
+
+    for &b in old {
+        buckets[b as usize] += 1;
+    }
+    for i in 1..256 {
+        buckets[i] += buckets[i - 1];
+    }
+    for i in (1..256).rev() {
+        buckets[i] = buckets[i - 1];
+    }
+    buckets[0] = 0;
+
+    for (i, &b) in old.iter().enumerate() {
+        buckets[b as usize] += 1;
+        I[buckets[b as usize] as usize] = i as i64;
+    }
+    I[0] = oldsize;
+    for (i, &b) in old.iter().enumerate() {
+        V[i] = buckets[b as usize];
+    }
+    V[old.len()] = 0;
+    for i in 1..256 {
+        if buckets[i] == buckets[i - 1] + 1 {
+            I[buckets[i] as usize] = -1;
+        }
+    }
+    I[0] = -1;
+
+    let mut h = 1;
+    while I[0] != -(oldsize + 1) {
+        let mut len = 0;
+        let mut i = 0;
+        while i < oldsize + 1 {
+            if I[i as usize] < 0 {
+                len -= I[i as usize];
+                i -= I[i as usize];
+            } else {
+                if len != 0 {
+                    I[(i - len) as usize] = -len;
+                }
+                len = V[I[i as usize] as usize] + 1 - i;
+                split(I, V, i, len, h);
+                i += len;
+                len = 0;
+            }
+        }
+        if len != 0 {
+            I[(i - len) as usize] = -len;
+        }
+
+        h += h;
+    }
+
+    for i in 0..old.len() + 1 {
+        I[V[i] as usize] = i as i64;
+    }
+}
+
+fn matchlen(old: &[u8], new: &[u8]) -> i64 {
+    old.iter().zip(new).take_while(|(o, n)| o == n).count() as i64
+}
+
+#[allow(non_snake_case)]
+fn search(
+    I: &[i64],
+    old: &[u8],
+    new: &[u8],
+    st: i64,
+    en: i64,
+    pos: &mut i64,
+) -> i64 {
+    if en - st < 2 {
+        let x = matchlen(&old[I[st as usize] as usize..], new);
+        let y = matchlen(&old[I[en as usize] as usize..], new);
+
+        return if x > y {
+            *pos = I[st as usize];
+            x
+        } else {
+            *pos = I[en as usize];
+            y
+        };
+    }
+
+    let x = st + (en - st) / 2;
+    let old_suffix = &old[I[x as usize] as usize..];
+    let n = old_suffix.len().min(new.len());
+    if old_suffix[..n] < new[..n] {
+        search(I, old, new, x, en, pos)
+    } else {
+        search(I, old, new, st, x, pos)
+    }
+}
+
+fn offtout(x: i64) -> [u8; 8] {
+    let mut buf = x.unsigned_abs().to_le_bytes();
+    if x < 0 {
+        buf[7] |= 0x80;
+    }
+
+    buf
+}
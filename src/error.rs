@@ -1,3 +1,4 @@
+use crate::hash::Hash;
 use std::{error, fmt, io, path::PathBuf};
 
 #[derive(Debug)]
@@ -23,36 +24,52 @@ pub enum Error {
     BadPatchVersion,
     BadPatchSize,
     Seek(PathBuf, io::Error),
-    PatchSanityCheckFail(u8),
+    BadPatch(PathBuf, String),
+    PatchTooLarge(PathBuf, u64, u64),
     FileRename(PathBuf, PathBuf),
     NotDir(PathBuf),
     RemoveFile(PathBuf, io::Error),
     #[allow(dead_code)]
-    MissingFile(&'static str),
+    MissingFile(PathBuf),
     #[allow(dead_code)]
     PermissionsSet(PathBuf, io::Error),
     MissingCommandLineArg(&'static str),
     PasswordRead(io::Error),
     HttpClientCreate(reqwest::Error),
     Post(reqwest::Error),
-    BadLoginResponse(&'static str),
+    BadLoginResponse(String),
     UnexpectedSuccessValue(String),
     ThreadSpawn(io::Error),
     ThreadJoin(io::Error),
     ProcessKill(u32, io::Error),
-    HashMismatch(PathBuf, [u8; 20]),
+    HashMismatch(PathBuf, Hash),
+    // The `secret_service` errors are boxed, because they're several times
+    // larger than any other variant.
     #[cfg(all(target_os = "linux", feature = "secret-store"))]
-    SessionStoreConnect(secret_service::Error),
+    SessionStoreConnect(Box<secret_service::Error>),
     #[cfg(all(target_os = "linux", feature = "secret-store"))]
-    PasswordUnlock(secret_service::Error),
+    PasswordUnlock(Box<secret_service::Error>),
     #[cfg(all(target_os = "linux", feature = "secret-store"))]
-    PasswordGet(secret_service::Error),
+    PasswordGet(Box<secret_service::Error>),
     #[cfg(all(target_os = "linux", feature = "secret-store"))]
     PasswordUtf8(std::string::FromUtf8Error),
     #[cfg(all(target_os = "linux", feature = "secret-store"))]
-    PasswordSave(secret_service::Error),
+    PasswordSave(Box<secret_service::Error>),
     #[cfg(all(target_os = "linux", feature = "secret-store"))]
-    DeleteSecretItem(secret_service::Error),
+    DeleteSecretItem(Box<secret_service::Error>),
+    BadPlan(String),
+    PlanOutdated(PathBuf),
+    UnsafePath(String, &'static str),
+    InvalidConfig(String),
+    UnknownProfile(String),
+    PasswordCommandSpawn(String, io::Error),
+    PasswordCommandTimeout(String, u64),
+    PasswordCommandOutput(String, String),
+    BadTotpSecret(String),
+    ExecSpawn(String, io::Error),
+    LaunchWrapperSpawn(String, io::Error),
+    UnattendedLogin(String, String),
+    UnknownGroup(String),
 }
 
 impl fmt::Display for Error {
@@ -119,7 +136,7 @@ impl fmt::Display for Error {
                 "Failure copying HTTP-downloaded data into {path:?}:\n\t{cife}",
             ),
             Self::Decode(path, ioe) => {
-                write!(f, "Error decoding bzip2 in file {path:?}:\n\t{ioe}",)
+                write!(f, "Error decompressing file {path:?}:\n\t{ioe}",)
             }
             Self::BadPatchVersion => f.write_str(
                 "Unable to determine patch's version, or patch is invalid",
@@ -131,9 +148,15 @@ impl fmt::Display for Error {
                 f,
                 "Error while seeking through file {path:?}:\n\t{ioe}",
             ),
-            Self::PatchSanityCheckFail(i) => {
-                write!(f, "During patching, sanity check #{i} failed")
+            Self::BadPatch(path, s) => {
+                write!(f, "Patch {path:?} is corrupt or malicious:\n\t{s}")
             }
+            Self::PatchTooLarge(path, size, max) => write!(
+                f,
+                "Patch {path:?} would produce a {size}-byte file, but the \
+                 maximum is {max} bytes (see `max_patch_output_size` in the \
+                 config)",
+            ),
             Self::FileRename(from, to) => {
                 write!(f, "Error renaming file from {from:?} to {to:?}")
             }
@@ -141,8 +164,8 @@ impl fmt::Display for Error {
             Self::RemoveFile(path, ioe) => {
                 write!(f, "Error removing file {path:?}:\n\t{ioe}")
             }
-            Self::MissingFile(name) => {
-                write!(f, "Expected \"{name}\" file to exist")
+            Self::MissingFile(path) => {
+                write!(f, "Expected {path:?} to exist")
             }
             Self::PermissionsSet(path, ioe) => write!(
                 f,
@@ -177,18 +200,12 @@ impl fmt::Display for Error {
                 f,
                 "Error killing child process with pid {pid}:\n\t{ioe}",
             ),
-            Self::HashMismatch(path, expected) => {
-                write!(
-                    f,
-                    "SHA-1 hash of local file {path:?} did not match manifest's \
-                     hash of ",
-                )?;
-                for b in expected {
-                    write!(f, "{b:02x}")?;
-                }
-
-                Ok(())
-            }
+            Self::HashMismatch(path, expected) => write!(
+                f,
+                "{} hash of local file {path:?} did not match manifest's hash \
+                 of {expected}",
+                expected.algorithm(),
+            ),
             #[cfg(all(target_os = "linux", feature = "secret-store"))]
             Self::SessionStoreConnect(error) => {
                 write!(
@@ -235,6 +252,53 @@ impl fmt::Display for Error {
                 "Failed to delete item in secret store:\n\t{:?}",
                 error
             ),
+            Self::BadPlan(s) => write!(f, "Bad update plan:\n\t{s}"),
+            Self::PlanOutdated(path) => write!(
+                f,
+                "{path:?} no longer matches the hash that the update plan \
+                 was based on, so the plan was not applied",
+            ),
+            Self::UnsafePath(name, reason) => write!(
+                f,
+                "Refusing to use the file name {name:?}, because it {reason}",
+            ),
+            Self::InvalidConfig(s) => write!(f, "Invalid config:\n\t{s}"),
+            Self::UnknownProfile(name) => {
+                write!(f, "The config has no profile named {name:?}")
+            }
+            Self::PasswordCommandSpawn(cmd, ioe) => write!(
+                f,
+                "Failed to run the password command {cmd:?}:\n\t{ioe}",
+            ),
+            Self::PasswordCommandTimeout(cmd, secs) => write!(
+                f,
+                "The password command {cmd:?} was still running after {secs} \
+                 seconds, so it was killed",
+            ),
+            Self::PasswordCommandOutput(cmd, reason) => write!(
+                f,
+                "Couldn't get a password from the password command {cmd:?}, \
+                 because {reason}",
+            ),
+            Self::BadTotpSecret(reason) => {
+                write!(f, "Invalid two-factor secret: {reason}")
+            }
+            Self::ExecSpawn(cmd, ioe) => {
+                write!(f, "Failed to run {cmd:?}:\n\t{ioe}")
+            }
+            Self::LaunchWrapperSpawn(wrapper, ioe) => write!(
+                f,
+                "Failed to run the launch wrapper {wrapper:?} (is it \
+                 installed, & on your PATH?):\n\t{ioe}",
+            ),
+            Self::UnattendedLogin(username, reason) => write!(
+                f,
+                "Couldn't log in as {username} without anyone there: \
+                 {reason}",
+            ),
+            Self::UnknownGroup(group) => {
+                write!(f, "No accounts are in the group {group:?}")
+            }
         }
     }
 }
@@ -265,7 +329,7 @@ impl Error {
             Self::BadPatchVersion => 19,
             Self::BadPatchSize => 20,
             Self::Seek(_, _) => 21,
-            Self::PatchSanityCheckFail(_) => 22,
+            Self::BadPatch(_, _) => 22,
             Self::FileRename(_, _) => 23,
             Self::NotDir(_) => 24,
             Self::RemoveFile(_, _) => 25,
@@ -293,6 +357,20 @@ impl Error {
             Self::PasswordSave(_) => 42,
             #[cfg(all(target_os = "linux", feature = "secret-store"))]
             Self::DeleteSecretItem(_) => 43,
+            Self::BadPlan(_) => 44,
+            Self::PlanOutdated(_) => 45,
+            Self::UnsafePath(_, _) => 46,
+            Self::PatchTooLarge(_, _, _) => 47,
+            Self::InvalidConfig(_) => 48,
+            Self::UnknownProfile(_) => 49,
+            Self::PasswordCommandSpawn(_, _) => 50,
+            Self::PasswordCommandTimeout(_, _) => 51,
+            Self::PasswordCommandOutput(_, _) => 52,
+            Self::BadTotpSecret(_) => 53,
+            Self::ExecSpawn(_, _) => 54,
+            Self::LaunchWrapperSpawn(_, _) => 55,
+            Self::UnattendedLogin(_, _) => 56,
+            Self::UnknownGroup(_) => 57,
         }
     }
 }
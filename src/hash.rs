@@ -0,0 +1,189 @@
+//! Verification of file contents against the hashes found in the manifest.
+//!
+//! Historically, the manifest has only ever carried SHA-1 hashes, but entries
+//! may also carry SHA-256 hashes, which are preferred when present.
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{fmt, io::Read};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Hash {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+/// Incrementally computes a `Hash`.
+#[derive(Clone, Debug)]
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "SHA-1",
+            Self::Sha256 => "SHA-256",
+        }
+    }
+
+    /// Length of the digest, in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Self::Sha1 => 20,
+            Self::Sha256 => 32,
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha1 => Hasher::Sha1(Sha1::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    /// Infers the algorithm from the length of a hexadecimal digest.
+    pub fn from_hex_len(hex_len: usize) -> Option<Self> {
+        [Self::Sha1, Self::Sha256]
+            .into_iter()
+            .find(|alg| 2 * alg.digest_len() == hex_len)
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Hash {
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Self::Sha1(_) => HashAlgorithm::Sha1,
+            Self::Sha256(_) => HashAlgorithm::Sha256,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Sha1(bytes) => bytes,
+            Self::Sha256(bytes) => bytes,
+        }
+    }
+
+    /// Parses a hexadecimal digest, inferring the algorithm from its length.
+    /// On failure, the error is a human-readable description of what went
+    /// wrong.
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        let alg = HashAlgorithm::from_hex_len(hex.len()).ok_or_else(|| {
+            format!(
+                "Expected hash string to be 40 (SHA-1) or 64 (SHA-256) \
+                 characters long: {hex:?}",
+            )
+        })?;
+
+        Self::from_hex_as(alg, hex)
+    }
+
+    /// Parses a hexadecimal digest that is expected to be produced by `alg`.
+    /// On failure, the error is a human-readable description of what went
+    /// wrong.
+    pub fn from_hex_as(alg: HashAlgorithm, hex: &str) -> Result<Self, String> {
+        if hex.len() != 2 * alg.digest_len() {
+            return Err(format!(
+                "Expected {alg} hash string to be {} characters long: {hex:?}",
+                2 * alg.digest_len(),
+            ));
+        }
+
+        let mut bytes = [0u8; 32];
+        for (i, &b) in hex.as_bytes().iter().enumerate() {
+            let nibble_val = match b {
+                b if b.is_ascii_digit() => b - b'0',
+                b'a' | b'A' => 0x0a,
+                b'b' | b'B' => 0x0b,
+                b'c' | b'C' => 0x0c,
+                b'd' | b'D' => 0x0d,
+                b'e' | b'E' => 0x0e,
+                b'f' | b'F' => 0x0f,
+                _ => {
+                    return Err(format!(
+                        "Unexpected character in {alg} hash string: {:?}",
+                        b as char,
+                    ));
+                }
+            };
+
+            bytes[i / 2] |= nibble_val << if i % 2 == 0 { 4 } else { 0 };
+        }
+
+        Ok(Self::from_bytes(alg, &bytes[..alg.digest_len()]))
+    }
+
+    /// `bytes` must be exactly `alg.digest_len()` bytes long.
+    fn from_bytes(alg: HashAlgorithm, bytes: &[u8]) -> Self {
+        match alg {
+            HashAlgorithm::Sha1 => Self::Sha1(bytes.try_into().unwrap()),
+            HashAlgorithm::Sha256 => Self::Sha256(bytes.try_into().unwrap()),
+        }
+    }
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha1(d) => d.update(data),
+            Self::Sha256(d) => d.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Hash {
+        match self {
+            Self::Sha1(d) => Hash::Sha1(d.finalize().into()),
+            Self::Sha256(d) => Hash::Sha256(d.finalize().into()),
+        }
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.as_bytes() {
+            write!(f, "{b:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Hashes everything that `r` yields, using each of `algs` in a single pass.
+/// The returned hashes are in the same order as `algs`.
+pub fn hash_reader<R: Read>(
+    r: &mut R,
+    buf: &mut [u8],
+    algs: &[HashAlgorithm],
+) -> Result<Vec<Hash>, std::io::Error> {
+    if algs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut hashers: Vec<_> = algs.iter().map(|alg| alg.hasher()).collect();
+    loop {
+        let n = r.read(buf)?;
+        if n == 0 {
+            break;
+        }
+
+        for hasher in &mut hashers {
+            hasher.update(&buf[..n]);
+        }
+    }
+
+    Ok(hashers.into_iter().map(Hasher::finalize).collect())
+}
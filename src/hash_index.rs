@@ -0,0 +1,128 @@
+//! A cache of the hashes of the files in the install directory, keyed by
+//! each file's size & modification time, so that files that haven't changed
+//! since they were last hashed don't need to be hashed again just to check
+//! whether or not the install is up to date (see `Updater::check`). Updating
+//! itself never trusts the index, & always hashes every file.
+//!
+//! The index is only a cache, so failing to read or write it is never an
+//! error.
+
+use crate::{
+    config::Config,
+    hash::{Hash, HashAlgorithm},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const FILE_NAME: &str = "hash_index.json";
+
+#[derive(Debug)]
+pub struct HashIndex {
+    path: PathBuf,
+    files: BTreeMap<String, Entry>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct Entry {
+    len: u64,
+    /// Since the Unix epoch.
+    modified_secs: u64,
+    modified_nanos: u32,
+    /// Hexadecimal digests, from which the algorithms can be inferred.
+    hashes: Vec<String>,
+}
+
+impl HashIndex {
+    /// Reads the index from `config.cache_dir`, or starts a new one if there
+    /// isn't one (or if it can't be read).
+    pub fn load(config: &Config) -> Self {
+        let path = config.cache_dir.join(FILE_NAME);
+        let files = fs::read(&path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default();
+
+        Self { path, files }
+    }
+
+    /// The hashes (by each of `algs`, in order) of the file named `name`,
+    /// if they're known & the file (whose metadata is `metadata`) hasn't
+    /// changed since.
+    pub fn get(
+        &self,
+        name: &str,
+        metadata: &fs::Metadata,
+        algs: &[HashAlgorithm],
+    ) -> Option<Vec<Hash>> {
+        let entry = self.files.get(name)?;
+        let current = Entry::new(metadata, &[])?;
+        if (entry.len, entry.modified_secs, entry.modified_nanos)
+            != (current.len, current.modified_secs, current.modified_nanos)
+        {
+            return None;
+        }
+
+        let hashes: Vec<_> = entry
+            .hashes
+            .iter()
+            .filter_map(|hex| Hash::from_hex(hex).ok())
+            .collect();
+        algs.iter()
+            .map(|&alg| {
+                hashes.iter().find(|hash| hash.algorithm() == alg).cloned()
+            })
+            .collect()
+    }
+
+    /// Remembers `hashes` as the hashes of the file named `name`, whose
+    /// metadata (from before it was hashed) is `metadata`.
+    pub fn insert(
+        &mut self,
+        name: &str,
+        metadata: &fs::Metadata,
+        hashes: &[Hash],
+    ) {
+        match Entry::new(metadata, hashes) {
+            Some(entry) => self.files.insert(name.to_owned(), entry),
+            None => self.files.remove(name),
+        };
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.files.remove(name);
+    }
+
+    pub fn save(&self) {
+        if let Ok(json) = serde_json::to_vec(&self.files) {
+            if let Some(dir) = self.path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let _ = fs::write(&self.path, json);
+        }
+    }
+}
+
+impl Entry {
+    /// `None` if the modification time isn't available.
+    fn new(metadata: &fs::Metadata, hashes: &[Hash]) -> Option<Self> {
+        let modified = metadata.modified().ok()?;
+        // Files modified in the future can't be trusted not to be modified
+        // again without their modification time changing.
+        if modified > SystemTime::now() {
+            return None;
+        }
+        let modified = modified.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(Self {
+            len: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            hashes: hashes.iter().map(Hash::to_string).collect(),
+        })
+    }
+}
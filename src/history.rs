@@ -0,0 +1,334 @@
+//! A history of every game session (i.e. every instance, from launch to
+//! exit), kept in a file with one JSON object per line, so that playtime &
+//! crashes can be looked back on after the launcher has exited.
+//!
+//! Days & weeks are counted in UTC, & weeks start on Mondays.
+
+use crate::{config::Config, error::Error, util};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+/// 1970-01-01 was a Thursday, so this many days before it was a Monday.
+const EPOCH_WEEKDAY: u64 = 3;
+
+/// A single instance of the game, from launch to exit.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub username: String,
+    pub pid: u32,
+    /// Seconds since the Unix epoch.
+    pub start: u64,
+    /// Seconds since the Unix epoch.
+    pub end: u64,
+    pub outcome: Outcome,
+    /// `None` if the instance didn't exit by itself, or was killed by a
+    /// signal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+/// How a session ended.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Exited normally.
+    Exited,
+    /// Exited abnormally.
+    Crashed,
+    /// Closed on purpose, with the `kill` command.
+    Killed,
+    /// Still running when the launcher exited, so `end` is when it was last
+    /// seen running.
+    Detached,
+}
+
+/// What to group sessions by in `stats`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Week,
+}
+
+/// An account's sessions within a single period (or all time, in the case of
+/// `totals`).
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub username: String,
+    /// The first day of the period, as `YYYY-MM-DD`. Empty for all time.
+    pub period: String,
+    /// Only the part of each session that fell within the period counts.
+    pub playtime_secs: u64,
+    /// Sessions that started within the period.
+    pub sessions: u64,
+    /// Sessions that started within the period, & crashed.
+    pub crashes: u64,
+}
+
+impl Session {
+    /// A session of the instance with PID `pid`, which was launched at
+    /// `started` & ended just now.
+    pub fn ended(
+        username: String,
+        pid: u32,
+        started: Instant,
+        outcome: Outcome,
+        exit_code: Option<i32>,
+    ) -> Self {
+        let end = now();
+
+        Self {
+            username,
+            pid,
+            start: end.saturating_sub(started.elapsed().as_secs()),
+            end,
+            outcome,
+            exit_code,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.end.saturating_sub(self.start))
+    }
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Exited => "exited",
+            Self::Crashed => "crashed",
+            Self::Killed => "killed",
+            Self::Detached => "detached",
+        }
+    }
+}
+
+impl Period {
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "day" | "daily" => Some(Self::Day),
+            "week" | "weekly" => Some(Self::Week),
+            _ => None,
+        }
+    }
+
+    /// The start of the period that `timestamp` is in, & of the next one.
+    fn bounds(self, timestamp: u64) -> (u64, u64) {
+        match self {
+            Self::Day => {
+                let start = timestamp - timestamp % SECS_PER_DAY;
+
+                (start, start + SECS_PER_DAY)
+            }
+            Self::Week => {
+                let days = timestamp / SECS_PER_DAY + EPOCH_WEEKDAY;
+                let start = (days - days % 7).saturating_sub(EPOCH_WEEKDAY)
+                    * SECS_PER_DAY;
+
+                (start, start + 7 * SECS_PER_DAY)
+            }
+        }
+    }
+}
+
+impl Stats {
+    /// Sessions that crashed, out of all sessions.
+    pub fn crash_rate(&self) -> f64 {
+        if self.sessions == 0 {
+            0.0
+        } else {
+            self.crashes as f64 / self.sessions as f64
+        }
+    }
+
+    fn add(&mut self, session: &Session, playtime_secs: u64, started: bool) {
+        self.playtime_secs += playtime_secs;
+        if started {
+            self.sessions += 1;
+            if session.outcome == Outcome::Crashed {
+                self.crashes += 1;
+            }
+        }
+    }
+}
+
+/// Appends `session` to `config`'s history file (see
+/// `Config::history_path`).
+pub fn record(config: &Config, session: &Session) -> Result<(), Error> {
+    let path = config.history_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|ioe| Error::Mkdir(dir.to_owned(), ioe))?;
+    }
+
+    let mut line = serde_json::to_string(session).map_err(Error::Serialize)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|ioe| Error::FileWrite(path, ioe))
+}
+
+/// Every session in `config`'s history file, in the order that they ended.
+/// Lines that can't be parsed (e.g. because the launcher was killed while
+/// writing one) are skipped.
+pub fn load(config: &Config) -> Result<Vec<Session>, Error> {
+    let path = config.history_path();
+    let file = match fs::File::open(&path) {
+        Ok(file) => file,
+        Err(ioe) if ioe.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(_) => util::open_file(&path)?,
+    };
+
+    let mut sessions = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|ioe| Error::FileRead(path.clone(), ioe))?;
+        if let Ok(session) = serde_json::from_str(&line) {
+            sessions.push(session);
+        }
+    }
+
+    Ok(sessions)
+}
+
+/// Each account's playtime, sessions, & crashes, per `period`, sorted by
+/// username & then by period. Sessions that span several periods have their
+/// playtime split between them.
+pub fn stats(sessions: &[Session], period: Period) -> Vec<Stats> {
+    let mut stats: BTreeMap<(&str, u64), Stats> = BTreeMap::new();
+    for session in sessions {
+        let mut start = session.start;
+        loop {
+            let (period_start, next) = period.bounds(start);
+            let end = session.end.min(next);
+            stats
+                .entry((&session.username, period_start))
+                .or_insert_with(|| Stats {
+                    username: session.username.clone(),
+                    period: date(period_start),
+                    ..Stats::default()
+                })
+                .add(
+                    session,
+                    end.saturating_sub(start),
+                    start == session.start,
+                );
+
+            if end >= session.end {
+                break;
+            }
+            start = end;
+        }
+    }
+
+    stats.into_values().collect()
+}
+
+/// Each account's playtime, sessions, & crashes, of all time.
+pub fn totals(sessions: &[Session]) -> Vec<Stats> {
+    let mut totals: BTreeMap<&str, Stats> = BTreeMap::new();
+    for session in sessions {
+        totals
+            .entry(&session.username)
+            .or_insert_with(|| Stats {
+                username: session.username.clone(),
+                ..Stats::default()
+            })
+            .add(session, session.duration().as_secs(), true);
+    }
+
+    totals.into_values().collect()
+}
+
+/// Writes `stats` to `path` as CSV, or as a JSON array if `path` ends with
+/// `.json`.
+pub fn export<P: AsRef<Path>>(stats: &[Stats], path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    let contents = if path.extension().is_some_and(|ext| ext == "json") {
+        let mut json = serde_json::to_string_pretty(
+            &stats.iter().map(stats_value).collect::<Vec<_>>(),
+        )
+        .map_err(Error::Serialize)?;
+        json.push('\n');
+
+        json
+    } else {
+        to_csv(stats)
+    };
+
+    util::create_file(path)?
+        .write_all(contents.as_bytes())
+        .map_err(|ioe| Error::FileWrite(path.to_owned(), ioe))
+}
+
+/// `stats` as a JSON object, with its crash rate.
+pub fn stats_value(stats: &Stats) -> serde_json::Value {
+    let mut value = serde_json::json!(stats);
+    value["crash_rate"] = stats.crash_rate().into();
+
+    value
+}
+
+pub fn to_csv(stats: &[Stats]) -> String {
+    let mut csv = String::from(
+        "username,period,playtime_secs,sessions,crashes,crash_rate\n",
+    );
+    for s in stats {
+        // Writing to a `String` never fails.
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{:.3}",
+            csv_field(&s.username),
+            s.period,
+            s.playtime_secs,
+            s.sessions,
+            s.crashes,
+            s.crash_rate(),
+        );
+    }
+
+    csv
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// `timestamp`'s date, as `YYYY-MM-DD`.
+fn date(timestamp: u64) -> String {
+    // See `civil_from_days` in
+    // <https://howardhinnant.github.io/date_algorithms.html>.
+    let z = (timestamp / SECS_PER_DAY) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
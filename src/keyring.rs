@@ -8,8 +8,15 @@ const APP_ID: &str = "app_id";
 const APP_ID_VALUE: &str = "shticker_book_unwritten";
 const SECRET_ITEM_LABEL: &str = "Toontown Credentials";
 const SECRET_ITEM_ATTRIBUTE: &str = "user";
-
-pub(super) fn get_saved_password(
+/// A separate app ID, so that TOTP secrets are never mistaken for
+/// passwords.
+const TOTP_APP_ID_VALUE: &str = "shticker_book_unwritten_totp";
+const TOTP_SECRET_ITEM_LABEL: &str = "Toontown Two-Factor Secret";
+const ALIAS_ATTRIBUTE: &str = "alias";
+/// Comma-separated.
+const GROUPS_ATTRIBUTE: &str = "groups";
+
+pub fn get_saved_password(
     config: &Config,
     username: &str,
 ) -> Result<Option<String>, Error> {
@@ -18,37 +25,40 @@ pub(super) fn get_saved_password(
     }
 
     let secret_service = SecretService::connect(EncryptionType::Dh)
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     let collection = secret_service
         .get_default_collection()
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     collection
         .ensure_unlocked()
-        .map_err(Error::PasswordUnlock)?;
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
 
     let mut results = collection
         .search_items(HashMap::from([
             (SECRET_ITEM_ATTRIBUTE, username),
             (APP_ID, APP_ID_VALUE),
         ]))
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     let Some(item) = results.pop() else {
         return Ok(None);
     };
 
-    item.ensure_unlocked().map_err(Error::PasswordUnlock)?;
+    item.ensure_unlocked()
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
 
-    let secret = item.get_secret().map_err(Error::PasswordGet)?;
+    let secret = item
+        .get_secret()
+        .map_err(|e| Error::PasswordGet(Box::new(e)))?;
 
     Ok(Some(
         String::from_utf8(secret).map_err(Error::PasswordUtf8)?,
     ))
 }
 
-pub(super) fn save_password<P: AsRef<Path>>(
+pub fn save_password<P: AsRef<Path>>(
     config: &mut Config,
     _config_path: P,
     username: String,
@@ -59,55 +69,57 @@ pub(super) fn save_password<P: AsRef<Path>>(
     }
 
     let secret_service = SecretService::connect(EncryptionType::Dh)
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     let collection = secret_service
         .get_default_collection()
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     collection
         .ensure_unlocked()
-        .map_err(Error::PasswordUnlock)?;
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
 
+    // Labelled just like `save_account_metadata` would, so that this
+    // replaces the password that's already saved, if any.
+    let groups = config.groups(&username).join(",");
     collection
         .create_item(
             SECRET_ITEM_LABEL,
-            HashMap::from([
-                (SECRET_ITEM_ATTRIBUTE, username.as_str()),
-                (APP_ID, APP_ID_VALUE),
-            ]),
+            password_attributes(&username, config.alias(&username), &groups),
             password.as_bytes(),
             true, // replace
             "text/plain",
         )
-        .map_err(Error::PasswordSave)?;
+        .map_err(|e| Error::PasswordSave(Box::new(e)))?;
 
     Ok(())
 }
 
-pub(super) fn stored_accounts() -> Result<Vec<String>, Error> {
+pub fn stored_accounts() -> Result<Vec<String>, Error> {
     let secret_service = SecretService::connect(EncryptionType::Dh)
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     let collection = secret_service
         .get_default_collection()
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     collection
         .ensure_unlocked()
-        .map_err(Error::PasswordUnlock)?;
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
 
     let results = collection
         .search_items(HashMap::from([(APP_ID, APP_ID_VALUE)]))
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     results
         .into_iter()
         .map(|item| {
-            item.ensure_unlocked().map_err(Error::PasswordUnlock)?;
+            item.ensure_unlocked()
+                .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
 
-            let attributes =
-                item.get_attributes().map_err(Error::PasswordGet)?;
+            let attributes = item
+                .get_attributes()
+                .map_err(|e| Error::PasswordGet(Box::new(e)))?;
 
             let username = attributes.get(SECRET_ITEM_ATTRIBUTE).cloned();
 
@@ -117,53 +129,207 @@ pub(super) fn stored_accounts() -> Result<Vec<String>, Error> {
         .collect()
 }
 
-pub(super) fn account_exists(username: &str) -> Result<bool, Error> {
+pub fn account_exists(username: &str) -> Result<bool, Error> {
     let secret_service = SecretService::connect(EncryptionType::Dh)
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     let collection = secret_service
         .get_default_collection()
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     collection
         .ensure_unlocked()
-        .map_err(Error::PasswordUnlock)?;
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
 
     let results = collection
         .search_items(HashMap::from([
             (SECRET_ITEM_ATTRIBUTE, username),
             (APP_ID, APP_ID_VALUE),
         ]))
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     Ok(!results.is_empty())
 }
 
-pub(super) fn forget_account(username: &str) -> Result<(), Error> {
+pub fn forget_account(username: &str) -> Result<(), Error> {
     let secret_service = SecretService::connect(EncryptionType::Dh)
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     let collection = secret_service
         .get_default_collection()
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     collection
         .ensure_unlocked()
-        .map_err(Error::PasswordUnlock)?;
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
 
     let mut results = collection
         .search_items(HashMap::from([
             (SECRET_ITEM_ATTRIBUTE, username),
             (APP_ID, APP_ID_VALUE),
         ]))
-        .map_err(Error::SessionStoreConnect)?;
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
 
     let Some(item) = results.pop() else {
         return Ok(());
     };
 
-    item.ensure_unlocked().map_err(Error::PasswordUnlock)?;
-    item.delete().map_err(Error::DeleteSecretItem)?;
+    item.ensure_unlocked()
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
+    item.delete()
+        .map_err(|e| Error::DeleteSecretItem(Box::new(e)))?;
+
+    Ok(())
+}
+
+/// Keeps the saved password of `username` (if any) labelled with its
+/// alias & groups, so that they stay with it in the keyring. The config is
+/// what they're actually read from (see `Config::alias`).
+pub fn save_account_metadata(
+    username: &str,
+    alias: Option<&str>,
+    groups: &[&str],
+) -> Result<(), Error> {
+    let secret_service = SecretService::connect(EncryptionType::Dh)
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    let collection = secret_service
+        .get_default_collection()
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    collection
+        .ensure_unlocked()
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
+
+    let results = collection
+        .search_items(HashMap::from([
+            (SECRET_ITEM_ATTRIBUTE, username),
+            (APP_ID, APP_ID_VALUE),
+        ]))
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    let groups = groups.join(",");
+    let attributes = password_attributes(username, alias, &groups);
+    for item in results {
+        item.ensure_unlocked()
+            .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
+        item.set_attributes(attributes.clone())
+            .map_err(|e| Error::PasswordSave(Box::new(e)))?;
+    }
+
+    Ok(())
+}
+
+/// `groups` is comma-separated.
+fn password_attributes<'a>(
+    username: &'a str,
+    alias: Option<&'a str>,
+    groups: &'a str,
+) -> HashMap<&'a str, &'a str> {
+    let mut attributes = HashMap::from([
+        (SECRET_ITEM_ATTRIBUTE, username),
+        (APP_ID, APP_ID_VALUE),
+    ]);
+    if let Some(alias) = alias {
+        attributes.insert(ALIAS_ATTRIBUTE, alias);
+    }
+    if !groups.is_empty() {
+        attributes.insert(GROUPS_ATTRIBUTE, groups);
+    }
+
+    attributes
+}
+
+/// Unlike passwords, TOTP secrets are saved regardless of `store_passwords`,
+/// because they are only ever saved on request.
+pub fn get_totp_secret(username: &str) -> Result<Option<String>, Error> {
+    let secret_service = SecretService::connect(EncryptionType::Dh)
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    let collection = secret_service
+        .get_default_collection()
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    collection
+        .ensure_unlocked()
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
+
+    let mut results = collection
+        .search_items(HashMap::from([
+            (SECRET_ITEM_ATTRIBUTE, username),
+            (APP_ID, TOTP_APP_ID_VALUE),
+        ]))
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    let Some(item) = results.pop() else {
+        return Ok(None);
+    };
+
+    item.ensure_unlocked()
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
+
+    let secret = item
+        .get_secret()
+        .map_err(|e| Error::PasswordGet(Box::new(e)))?;
+
+    Ok(Some(
+        String::from_utf8(secret).map_err(Error::PasswordUtf8)?,
+    ))
+}
+
+pub fn save_totp_secret(username: &str, secret: &str) -> Result<(), Error> {
+    let secret_service = SecretService::connect(EncryptionType::Dh)
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    let collection = secret_service
+        .get_default_collection()
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    collection
+        .ensure_unlocked()
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
+
+    collection
+        .create_item(
+            TOTP_SECRET_ITEM_LABEL,
+            HashMap::from([
+                (SECRET_ITEM_ATTRIBUTE, username),
+                (APP_ID, TOTP_APP_ID_VALUE),
+            ]),
+            secret.as_bytes(),
+            true, // replace
+            "text/plain",
+        )
+        .map_err(|e| Error::PasswordSave(Box::new(e)))?;
+
+    Ok(())
+}
+
+pub fn forget_totp_secret(username: &str) -> Result<(), Error> {
+    let secret_service = SecretService::connect(EncryptionType::Dh)
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    let collection = secret_service
+        .get_default_collection()
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    collection
+        .ensure_unlocked()
+        .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
+
+    let results = collection
+        .search_items(HashMap::from([
+            (SECRET_ITEM_ATTRIBUTE, username),
+            (APP_ID, TOTP_APP_ID_VALUE),
+        ]))
+        .map_err(|e| Error::SessionStoreConnect(Box::new(e)))?;
+
+    for item in results {
+        item.ensure_unlocked()
+            .map_err(|e| Error::PasswordUnlock(Box::new(e)))?;
+        item.delete()
+            .map_err(|e| Error::DeleteSecretItem(Box::new(e)))?;
+    }
 
     Ok(())
 }
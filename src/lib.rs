@@ -0,0 +1,38 @@
+//! The core of shticker_book_unwritten, usable without the CLI: reading the
+//! config, parsing the manifest, updating the game files, and logging into &
+//! launching the game.
+//!
+//! On Linux, the `secret-store` feature makes passwords be saved in the
+//! desktop's secret service (see the `keyring` module) rather than in the
+//! config.
+
+#![forbid(unsafe_code)]
+#![deny(deprecated)]
+
+pub mod config;
+pub mod decompress;
+pub mod diff;
+pub mod error;
+pub mod hash;
+pub mod hash_index;
+pub mod history;
+pub mod keyring;
+pub mod login;
+pub mod login_api;
+pub mod logs;
+pub mod manifest;
+pub mod observer;
+pub mod output;
+pub mod password_command;
+pub mod patch;
+pub mod supervisor;
+pub mod totp;
+pub mod update;
+mod util;
+
+pub use config::Config;
+pub use error::Error;
+pub use login::{LoginClient, LoginOutcome, PlayCredentials};
+pub use manifest::Manifest;
+pub use observer::{Event, Observer};
+pub use update::Updater;
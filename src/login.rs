@@ -1,19 +1,77 @@
 #[cfg(all(target_os = "linux", feature = "secret-store"))]
-use crate::keyring::{get_saved_password, save_password};
-use crate::{config::Config, error::Error};
+use crate::keyring::{get_saved_password, get_totp_secret, save_password};
+use crate::{
+    config::{self, Config, LaunchCheck},
+    error::Error,
+    login_api::{Limits, Negotiation, Request, Response, Step},
+    logs::{self, PendingLog},
+    observer::{Event, LoginStatus, Observer, Silent, TerminalObserver},
+    output::{self, OutputFormat},
+    password_command, totp,
+    update::Updater,
+    util,
+};
 use reqwest::{blocking as rb, header};
 use serde::Serialize;
 use std::{
-    collections::BTreeMap,
-    ffi::OsStr,
-    io::{self, Write},
+    collections::{BTreeMap, VecDeque},
+    io,
+    num::NonZeroUsize,
     path::Path,
-    process, thread,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
     time::{Duration, Instant},
 };
 
-const LOGIN_API_URI: &str =
-    "https://www.toontownrewritten.com/api/login?format=json";
+/// What the login API hands out once a login succeeds, which is everything
+/// needed to launch the game.
+#[derive(Clone, Debug)]
+pub struct PlayCredentials {
+    pub cookie: String,
+    pub gameserver: String,
+}
+
+#[derive(Clone, Debug)]
+pub enum LoginOutcome {
+    Success(PlayCredentials),
+    /// The login API refused the login, with the contained explanation.
+    Failed(String),
+    /// Two-factor authentication was cancelled, or the login was cancelled
+    /// via `LoginClient::cancel_on`.
+    Cancelled,
+    /// See `LoginClient::timeout` & `LoginClient::max_queue_wait`.
+    TimedOut,
+}
+
+/// What to do once logged in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum LaunchMode {
+    /// Launch the game.
+    #[default]
+    Launch,
+    /// Print the play credentials as environment variables (as shell
+    /// exports, or as a JSON object), without launching anything.
+    PrintEnv(OutputFormat),
+    /// Run the contained shell command instead of the game, with the play
+    /// credentials set in its environment. See `exec`.
+    Exec(String),
+}
+
+/// Talks to the login API on behalf of a single account at a time.
+pub struct LoginClient<'a> {
+    client: &'a rb::Client,
+    api_uri: &'a str,
+    observer: &'a dyn Observer,
+    limits: Limits,
+    cancel: Option<&'a AtomicBool>,
+}
+
+pub const PLAYCOOKIE_ENV_VAR: &str = "TTR_PLAYCOOKIE";
+pub const GAMESERVER_ENV_VAR: &str = "TTR_GAMESERVER";
 
 #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
 fn get_saved_password(
@@ -21,22 +79,47 @@ fn get_saved_password(
     username: &str,
 ) -> Result<Option<String>, Error> {
     Ok(if config.store_passwords {
-        config
-            .accounts
-            .get(username)
-            .and_then(|val| {
-                if let serde_json::Value::String(p) = val {
-                    Some(p)
-                } else {
-                    None
-                }
-            })
-            .cloned()
+        config.saved_password(username).map(str::to_owned)
     } else {
         None
     })
 }
 
+/// Returns a function that gets `username`'s saved TOTP secret, so that it is
+/// only looked up if a two-factor token is actually asked for.
+#[cfg(not(all(target_os = "linux", feature = "secret-store")))]
+fn saved_totp_secret(
+    config: &Config,
+    username: &str,
+) -> impl Fn() -> Option<String> + Send + Sync + use<> {
+    let secret = config.totp_secret(username).map(str::to_owned);
+
+    move || secret.clone()
+}
+
+/// Returns a function that gets `username`'s saved TOTP secret, so that the
+/// secret store is only touched if a two-factor token is actually asked for.
+/// If the secret can't be gotten, then it's treated as not saved, & the token
+/// will have to be typed in.
+#[cfg(all(target_os = "linux", feature = "secret-store"))]
+fn saved_totp_secret(
+    _config: &Config,
+    username: &str,
+) -> impl Fn() -> Option<String> + Send + Sync + use<> {
+    let username = username.to_owned();
+
+    move || {
+        get_totp_secret(&username).unwrap_or_else(|e| {
+            eprintln!(
+                "Couldn't get the saved TOTP secret for {username}, so a \
+                 two-factor code will have to be typed in: {e}"
+            );
+
+            None
+        })
+    }
+}
+
 #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
 fn save_password<P: AsRef<Path>>(
     config: &mut Config,
@@ -50,352 +133,906 @@ fn save_password<P: AsRef<Path>>(
     Ok(())
 }
 
+/// Splits a `login` command line at its first `--exec` argument, returning
+/// what comes before it & the command to run, which is everything after it
+/// exactly as it was typed (quotes, runs of whitespace & all), save for any
+/// surrounding whitespace.
+pub fn split_exec(line: &str) -> (&str, Option<&str>) {
+    let mut arg_start = None;
+    for (i, c) in line.char_indices().chain([(line.len(), ' ')]) {
+        if !c.is_whitespace() {
+            arg_start.get_or_insert(i);
+        } else if let Some(start) = arg_start.take()
+            && &line[start..i] == "--exec"
+        {
+            return (&line[..start], Some(line[i..].trim()));
+        }
+    }
+
+    (line, None)
+}
+
+/// Logs in with each of the accounts in `argv` (or with one read from stdin,
+/// if there are none), which may be referred to by alias or by group (see
+/// `Config::resolve_accounts`), & then does whatever `mode` says to. `argv`
+/// may also contain `-n`/`--no-save` or `--print-env` (for `--exec`, see
+/// `split_exec`). Returns what was done (i.e. `mode`, unless `argv` said
+/// otherwise).
+#[allow(clippy::too_many_arguments)]
 pub fn login<'a, P: AsRef<Path>, A: Iterator<Item = &'a str>>(
     config: &mut Config,
     config_path: P,
     client: &rb::Client,
     quiet: bool,
+    output: OutputFormat,
+    observer: &dyn Observer,
     argv: A,
+    mut mode: LaunchMode,
     children: &mut Vec<(String, process::Child, Instant)>,
-) -> Result<(), Error> {
+) -> Result<LaunchMode, Error> {
     let (mut usernames, mut no_save) = (Vec::new(), !config.store_passwords);
     for arg in argv {
         match arg {
             "-n" | "--no-save" => no_save = true,
+            "--print-env" => mode = LaunchMode::PrintEnv(output),
             _ => usernames.push(arg),
         }
     }
+    // Anything else printed to stdout would end up mixed in with the
+    // exports.
+    let print_env = matches!(mode, LaunchMode::PrintEnv(_));
+    let quiet = quiet || print_env;
+    let quiet_observer = TerminalObserver::new(true);
+    let observer: &dyn Observer = if print_env && !output.is_json() {
+        &quiet_observer
+    } else {
+        observer
+    };
+
+    if mode == LaunchMode::Launch {
+        let others_running = children
+            .iter_mut()
+            .any(|(_, child, _)| matches!(child.try_wait(), Ok(None)));
+        if !check_install(
+            config,
+            client,
+            quiet,
+            observer,
+            !output.is_json(),
+            others_running,
+        )? {
+            return Ok(mode);
+        }
+    }
 
     let mut username_buf = String::new();
 
     if usernames.is_empty() {
-        print!("Username: ");
-        io::stdout().flush().map_err(Error::Stdout)?;
+        // Prompts go to stderr, like all of the others here, so that stdout
+        // stays clean for `--print-env`.
+        eprint!("Username: ");
         username_buf.reserve(0x10);
         io::stdin()
             .read_line(&mut username_buf)
             .map_err(Error::Stdin)?;
         username_buf.truncate(username_buf.trim_end().len());
 
-        let password = if let Some(password) =
-            get_saved_password(config, &username_buf)?
-        {
-            if !quiet {
-                println!("Using saved password...");
-            }
+        usernames.push(&username_buf);
+    }
+    let usernames = match config.resolve_accounts(usernames) {
+        Ok(usernames) => usernames,
+        Err(e) => {
+            eprintln!("{e}");
 
-            password
-        } else {
-            print!("Password for {username_buf}: ");
-            io::stdout().flush().map_err(Error::Stdout)?;
+            return Ok(mode);
+        }
+    };
+    let usernames: Vec<_> = usernames.iter().map(String::as_str).collect();
 
-            rpassword::read_password().map_err(Error::PasswordRead)?
-        };
+    if usernames.len() > 1 {
+        login_concurrently(
+            config,
+            config_path,
+            client,
+            quiet,
+            observer,
+            no_save,
+            &mode,
+            &usernames,
+            children,
+        )?;
+
+        return Ok(mode);
+    }
+
+    for username in usernames {
+        let password = get_password(config, quiet, username)?;
 
         if let Some(c) = handle_name_and_pw(
             config,
-            config_path,
+            config_path.as_ref(),
             client,
             quiet,
+            observer,
             no_save,
-            username_buf,
+            &mode,
+            username.to_owned(),
             password,
         )? {
             children.push(c);
         }
+    }
+
+    Ok(mode)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn handle_name_and_pw<P: AsRef<Path>>(
+    config: &mut Config,
+    config_path: P,
+    client: &rb::Client,
+    quiet: bool,
+    observer: &dyn Observer,
+    no_save: bool,
+    mode: &LaunchMode,
+    username: String,
+    password: String,
+) -> Result<Option<(String, process::Child, Instant)>, Error> {
+    let login_client = LoginClient::new(client)
+        .api_uri(&config.login_api_uri)
+        .observer(observer)
+        .timeout(config.login_timeout.map(Duration::from_secs))
+        .max_queue_wait(config.max_queue_wait.map(Duration::from_secs));
+    let outcome = authenticate(
+        &login_client,
+        &username,
+        &password,
+        saved_totp_secret(config, &username),
+        quiet,
+        prompt_for_2fa,
+    )?;
+    let LoginOutcome::Success(credentials) = outcome else {
+        return Ok(None);
+    };
+
+    finish_login(
+        config,
+        config_path,
+        quiet,
+        observer,
+        no_save,
+        mode,
+        username,
+        password,
+        &credentials,
+    )
+}
+
+/// Logs in as `username` again, & does whatever `mode` says to, e.g. to
+/// relaunch an instance that crashed. Nothing is asked for, since there may
+/// be no one there to answer: the password has to come from a password
+/// command or be saved, & any two-factor token has to be generated from a
+/// TOTP secret. An outdated install is only updated first (if
+/// `config.launch_check` says to) if there are no `others_running`.
+#[allow(clippy::too_many_arguments)]
+pub fn relaunch<P: AsRef<Path>>(
+    config: &mut Config,
+    config_path: P,
+    client: &rb::Client,
+    quiet: bool,
+    observer: &dyn Observer,
+    username: &str,
+    mode: &LaunchMode,
+    others_running: bool,
+) -> Result<Option<(String, process::Child, Instant)>, Error> {
+    let unattended = |reason: &str| {
+        Error::UnattendedLogin(username.to_owned(), reason.to_owned())
+    };
+
+    if *mode == LaunchMode::Launch
+        && !check_install(
+            config,
+            client,
+            quiet,
+            observer,
+            false,
+            others_running,
+        )?
+    {
+        return Ok(None);
+    }
+
+    let password = get_unattended_password(config, quiet, username)?
+        .ok_or_else(|| unattended("the password isn't saved"))?;
+    let totp_secret = saved_totp_secret(config, username);
+    let login_client = LoginClient::new(client)
+        .api_uri(&config.login_api_uri)
+        .observer(observer)
+        .timeout(config.login_timeout.map(Duration::from_secs))
+        .max_queue_wait(config.max_queue_wait.map(Duration::from_secs));
+    let outcome = authenticate(
+        &login_client,
+        username,
+        &password,
+        totp_secret,
+        quiet,
+        |_| Ok(None),
+    )?;
+
+    let credentials = match outcome {
+        LoginOutcome::Success(credentials) => credentials,
+        LoginOutcome::Failed(banner) => return Err(unattended(&banner)),
+        LoginOutcome::Cancelled => {
+            return Err(unattended("a two-factor token has to be typed in"));
+        }
+        LoginOutcome::TimedOut => {
+            return Err(unattended("the login timed out"));
+        }
+    };
+
+    finish_login(
+        config,
+        config_path,
+        quiet,
+        observer,
+        true,
+        mode,
+        username.to_owned(),
+        password,
+        &credentials,
+    )
+}
+
+/// Checks the install against the manifest right before launching the game,
+/// & if it's out of date, does whatever `config.launch_check` says to.
+/// `interactive` is whether there's anyone there to ask, & `others_running`
+/// is whether any instances of the game are already running, in which case
+/// updating in place isn't safe, so it's never done. Returns `false` if the
+/// game shouldn't be launched after all.
+///
+/// The check is only a courtesy, so failing to do it (e.g. because the
+/// manifest couldn't be downloaded) is warned about, but isn't an error.
+pub fn check_install(
+    config: &Config,
+    client: &rb::Client,
+    quiet: bool,
+    observer: &dyn Observer,
+    interactive: bool,
+    others_running: bool,
+) -> Result<bool, Error> {
+    if config.launch_check == LaunchCheck::Never {
+        return Ok(true);
+    }
+
+    let outdated = match Updater::new(config, client)
+        .max_tries(NonZeroUsize::MIN)
+        .check()
+    {
+        Ok(outdated) => outdated,
+        Err(e) => {
+            eprintln!("Couldn't check whether the game is up to date: {e}");
+
+            return Ok(true);
+        }
+    };
+    if outdated.is_empty() {
+        return Ok(true);
+    }
+    observer.on_event(&Event::InstallOutdated { files: &outdated });
+
+    let can_update = !others_running;
+    let update = match config.launch_check {
+        LaunchCheck::Update => can_update,
+        LaunchCheck::Ask if interactive => {
+            match prompt_for_outdated(can_update)? {
+                Some(update) => update,
+                None => return Ok(false),
+            }
+        }
+        _ => false,
+    };
+    if update {
+        Updater::new(config, client)
+            .observer(observer)
+            .update(false)?;
+    } else if !quiet {
+        if !can_update {
+            eprintln!("Other instances are running, so not updating.");
+        }
+        eprintln!("Launching anyway...");
+    }
+
+    Ok(true)
+}
+
+/// Asks whether to update (if `can_update`), launch anyway, or abort.
+/// Returns whether to update, or `None` to abort.
+fn prompt_for_outdated(can_update: bool) -> Result<Option<bool>, Error> {
+    let choices = if can_update {
+        "[u]pdate, [l]aunch anyway, or [a]bort?"
     } else {
-        for username in usernames {
-            if let Some(password) = get_saved_password(config, username)? {
-                if !quiet {
-                    println!("Using saved password...");
-                }
+        "Other instances are running, so updating isn't safe. [l]aunch \
+         anyway, or [a]bort?"
+    };
+    let mut answer = String::with_capacity(0x10);
+    loop {
+        eprint!("{choices}\n> ");
+        answer.clear();
+        if io::stdin().read_line(&mut answer).map_err(Error::Stdin)? == 0 {
+            return Ok(None);
+        }
+        answer.make_ascii_lowercase();
+
+        match answer.trim() {
+            "u" | "update" if can_update => return Ok(Some(true)),
+            "l" | "launch" => return Ok(Some(false)),
+            "a" | "abort" => return Ok(None),
+            _ => (),
+        }
+    }
+}
+
+/// What a login thread of `login_concurrently` has to say.
+enum Message {
+    Status(usize, LoginStatus),
+    /// Needs a two-factor token for the prompt, to be sent back.
+    Prompt(usize, String, mpsc::Sender<Result<Option<String>, Error>>),
+    Done(usize, Result<LoginOutcome, Error>),
+}
+
+/// Passes the events of a single account's login on to `login_concurrently`.
+struct StatusObserver {
+    index: usize,
+    tx: mpsc::Sender<Message>,
+}
 
-                if let Some(c) = handle_name_and_pw(
+impl Observer for StatusObserver {
+    fn on_event(&self, event: &Event) {
+        let status = match *event {
+            Event::QueuePosition { eta, position } => {
+                LoginStatus::Queued { eta, position }
+            }
+            Event::TwoFactorRequired { .. } => LoginStatus::TwoFactorRequired,
+            Event::LoggedIn => LoginStatus::LoggedIn,
+            Event::LoginFailed { banner } => LoginStatus::Failed {
+                reason: banner.to_owned(),
+            },
+            Event::LoginTimedOut => LoginStatus::TimedOut,
+            _ => return,
+        };
+
+        let _ = self.tx.send(Message::Status(self.index, status));
+    }
+}
+
+/// Like doing `handle_name_and_pw` for each of `usernames`, but with every
+/// login negotiated at once, so that no account has to wait for another's
+/// time in the queue. Passwords & TOTP secrets are all gotten beforehand,
+/// & any two-factor tokens that have to be typed in are asked for one at a
+/// time. Each game is launched as soon as its login succeeds, but no sooner
+/// than `config.launch_stagger` seconds after the last one.
+#[allow(clippy::too_many_arguments)]
+fn login_concurrently<P: AsRef<Path>>(
+    config: &mut Config,
+    config_path: P,
+    client: &rb::Client,
+    quiet: bool,
+    observer: &dyn Observer,
+    no_save: bool,
+    mode: &LaunchMode,
+    usernames: &[&str],
+    children: &mut Vec<(String, process::Child, Instant)>,
+) -> Result<(), Error> {
+    let mut secrets = Vec::with_capacity(usernames.len());
+    for &username in usernames {
+        secrets.push((
+            get_password(config, quiet, username)?,
+            saved_totp_secret(config, username),
+        ));
+    }
+
+    // Cloned, so that `config` can still be mutated while the logins are
+    // going.
+    let api_uri = config.login_api_uri.clone();
+    let timeout = config.login_timeout.map(Duration::from_secs);
+    let max_queue_wait = config.max_queue_wait.map(Duration::from_secs);
+    let stagger = Duration::from_secs(config.launch_stagger.unwrap_or(0));
+
+    let mut statuses: Vec<_> = usernames
+        .iter()
+        .map(|&username| (username.to_owned(), LoginStatus::LoggingIn))
+        .collect();
+    let (tx, rx) = mpsc::channel();
+    let mut first_err = None;
+
+    thread::scope(|scope| {
+        for (index, (&username, (password, totp_secret))) in
+            usernames.iter().zip(&secrets).enumerate()
+        {
+            let tx = tx.clone();
+            let api_uri = &api_uri;
+            scope.spawn(move || {
+                let status_observer = StatusObserver {
+                    index,
+                    tx: tx.clone(),
+                };
+                let login_client = LoginClient::new(client)
+                    .api_uri(api_uri)
+                    .observer(&status_observer)
+                    .timeout(timeout)
+                    .max_queue_wait(max_queue_wait);
+                let outcome = authenticate(
+                    &login_client,
+                    username,
+                    password,
+                    totp_secret,
+                    true,
+                    |banner| {
+                        let (reply_tx, reply_rx) = mpsc::channel();
+                        if tx
+                            .send(Message::Prompt(
+                                index,
+                                banner.to_owned(),
+                                reply_tx,
+                            ))
+                            .is_err()
+                        {
+                            return Ok(None);
+                        }
+
+                        reply_rx.recv().unwrap_or(Ok(None))
+                    },
+                );
+
+                let _ = tx.send(Message::Done(index, outcome));
+            });
+        }
+        drop(tx);
+
+        let mut in_progress = usernames.len();
+        let mut ready: VecDeque<(usize, PlayCredentials)> = VecDeque::new();
+        let mut next_launch = Instant::now();
+        loop {
+            let now = Instant::now();
+            if now >= next_launch
+                && let Some((index, credentials)) = ready.pop_front()
+            {
+                let (password, _) = &secrets[index];
+                match finish_login(
                     config,
                     config_path.as_ref(),
-                    client,
                     quiet,
+                    observer,
                     no_save,
-                    username.to_owned(),
-                    password,
-                )? {
-                    children.push(c);
+                    mode,
+                    usernames[index].to_owned(),
+                    password.clone(),
+                    &credentials,
+                ) {
+                    Ok(Some(c)) => children.push(c),
+                    Ok(None) => (),
+                    Err(e) => {
+                        eprintln!("{}: {e}", usernames[index]);
+                        first_err.get_or_insert(e);
+                    }
                 }
+                next_launch = Instant::now() + stagger;
+
+                continue;
+            }
+            if in_progress == 0 && ready.is_empty() {
+                break;
+            }
+
+            let message = if ready.is_empty() {
+                rx.recv().ok()
             } else {
-                print!("Password for {username}: ");
-                io::stdout().flush().map_err(Error::Stdout)?;
+                match rx.recv_timeout(next_launch - now) {
+                    Ok(message) => Some(message),
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => None,
+                }
+            };
+            let Some(message) = message else {
+                // Every login is done, so just wait to launch the rest.
+                in_progress = 0;
+                thread::sleep(next_launch.saturating_duration_since(now));
 
-                if let Some(c) = handle_name_and_pw(
-                    config,
-                    config_path.as_ref(),
-                    client,
-                    quiet,
-                    no_save,
-                    username.to_owned(),
-                    rpassword::read_password().map_err(Error::PasswordRead)?,
-                )? {
-                    children.push(c);
+                continue;
+            };
+
+            let (index, status) = match message {
+                Message::Status(index, status) => (index, status),
+                Message::Prompt(index, banner, reply_tx) => {
+                    let _ = reply_tx.send(prompt_for_2fa(&format!(
+                        "{}: {banner}",
+                        usernames[index],
+                    )));
+
+                    continue;
+                }
+                Message::Done(index, outcome) => {
+                    in_progress -= 1;
+                    match outcome {
+                        Ok(LoginOutcome::Success(credentials)) => {
+                            ready.push_back((index, credentials));
+
+                            continue;
+                        }
+                        Ok(LoginOutcome::Cancelled) => {
+                            (index, LoginStatus::Cancelled)
+                        }
+                        // Already reported via `StatusObserver`.
+                        Ok(
+                            LoginOutcome::Failed(_) | LoginOutcome::TimedOut,
+                        ) => continue,
+                        Err(e) => {
+                            let status = LoginStatus::Failed {
+                                reason: e.to_string(),
+                            };
+                            first_err.get_or_insert(e);
+
+                            (index, status)
+                        }
+                    }
                 }
+            };
+            if statuses[index].1 != status {
+                statuses[index].1 = status;
+                observer.on_event(&Event::LoginStatuses {
+                    accounts: &statuses,
+                    changed: index,
+                });
             }
         }
+    });
+
+    first_err.map_or(Ok(()), Err)
+}
+
+/// Logs in, generating a two-factor token from the secret that `totp_secret`
+/// returns if one is needed & there is a secret. Otherwise (or if the
+/// generated token seems to have been rejected), a token is asked for with
+/// `prompt`. `totp_secret` is called at most once, & only if a token is
+/// needed.
+fn authenticate<
+    S: Fn() -> Option<String>,
+    F: FnMut(&str) -> Result<Option<String>, Error>,
+>(
+    login_client: &LoginClient,
+    username: &str,
+    password: &str,
+    totp_secret: S,
+    quiet: bool,
+    mut prompt: F,
+) -> Result<LoginOutcome, Error> {
+    let mut secret = None;
+    let mut totp_used = false;
+    let outcome = login_client.login(username, password, |banner| {
+        generate_or_prompt_for_2fa(
+            secret.get_or_insert_with(&totp_secret).as_deref(),
+            &mut totp_used,
+            quiet,
+            banner,
+            &mut prompt,
+        )
+    })?;
+    if totp_used && matches!(outcome, LoginOutcome::Failed(_)) {
+        eprintln!(
+            "The generated two-factor code for {username} may have been \
+             rejected, so trying again with one typed in by hand..."
+        );
+
+        return login_client.login(username, password, prompt);
     }
 
-    Ok(())
+    Ok(outcome)
 }
 
-fn handle_name_and_pw<P: AsRef<Path>>(
+/// Saves the password (if it should be), & then does whatever `mode` says to
+/// with `credentials`.
+#[allow(clippy::too_many_arguments)]
+fn finish_login<P: AsRef<Path>>(
     config: &mut Config,
     config_path: P,
-    client: &rb::Client,
     quiet: bool,
+    observer: &dyn Observer,
     no_save: bool,
+    mode: &LaunchMode,
     username: String,
     password: String,
+    credentials: &PlayCredentials,
 ) -> Result<Option<(String, process::Child, Instant)>, Error> {
-    let mut params = BTreeMap::new();
-    params.insert("username", username.as_str());
-    params.insert("password", password.as_str());
-    if let Some(response_json) = handle_login_negotiation(
-        client,
-        quiet,
-        post_to_login_api(client, &params)?,
-    )? {
-        if !no_save {
-            let new_account = get_saved_password(config, &username)?.is_none();
-            save_password(config, config_path, username.clone(), password)?;
-            if !quiet && new_account {
-                #[cfg(not(all(
-                    target_os = "linux",
-                    feature = "secret-store"
-                )))]
-                println!("New account saved in config!");
-                #[cfg(all(target_os = "linux", feature = "secret-store"))]
-                println!("New account saved in default keyring!");
-            }
+    // Passwords that come from a password command are never saved, & neither
+    // are passwords typed in because the password command failed, since the
+    // account is supposed to be managed by the password manager.
+    if !no_save && config.password_command(&username).is_none() {
+        let new_account = get_saved_password(config, &username)?.is_none();
+        save_password(config, config_path, username.clone(), password)?;
+        if !quiet && new_account {
+            #[cfg(not(all(target_os = "linux", feature = "secret-store")))]
+            println!("New account saved in config!");
+            #[cfg(all(target_os = "linux", feature = "secret-store"))]
+            println!("New account saved in default keyring!");
         }
+    }
 
-        let play_cookie = response_json
-            .get("cookie")
-            .and_then(|val| {
-                if let serde_json::Value::String(c) = val {
-                    Some(c)
-                } else {
-                    None
-                }
-            })
-            .ok_or(Error::BadLoginResponse(
-                "Expected \"cookie\" key with String value",
-            ))?;
-        let game_server = response_json
-            .get("gameserver")
-            .and_then(|val| {
-                if let serde_json::Value::String(gs) = val {
-                    Some(gs)
-                } else {
-                    None
-                }
-            })
-            .ok_or(Error::BadLoginResponse(
-                "Expected \"gameserver\" key with String value",
-            ))?;
-
-        let ret = launch(config, quiet, play_cookie, game_server)
-            .map(|c| Some((username, c, Instant::now())));
-        if !quiet && ret.is_ok() {
-            println!("Game launched successfully!");
+    match mode {
+        LaunchMode::Launch => launch(config, observer, &username, credentials)
+            .map(|c| Some((username, c, Instant::now()))),
+        LaunchMode::PrintEnv(output) => {
+            print_env(*output, &username, credentials);
+
+            Ok(None)
+        }
+        LaunchMode::Exec(command) => {
+            exec(config, observer, &username, credentials, command)
+                .map(|c| Some((username, c, Instant::now())))
         }
+    }
+}
 
-        ret
-    } else {
-        Ok(None)
+/// Gets `username`'s password by running its password command if it has
+/// one, or else from wherever passwords are saved. Failing that (or if the
+/// password command fails), asks the user for it.
+fn get_password(
+    config: &Config,
+    quiet: bool,
+    username: &str,
+) -> Result<String, Error> {
+    if let Some(password) = get_unattended_password(config, quiet, username)? {
+        return Ok(password);
     }
+
+    eprint!("Password for {username}: ");
+
+    rpassword::read_password().map_err(Error::PasswordRead)
 }
 
-fn handle_login_negotiation(
-    client: &rb::Client,
+/// Gets `username`'s password from their password command, or from the
+/// saved passwords, without asking for it.
+fn get_unattended_password(
+    config: &Config,
     quiet: bool,
-    mut response_json: serde_json::Value,
-) -> Result<Option<serde_json::Value>, Error> {
-    loop {
-        let success = response_json
-            .get("success")
-            .and_then(|val| match val {
-                serde_json::Value::String(s) => Some(s.as_str()),
-                serde_json::Value::Bool(b) => {
-                    if *b {
-                        Some("true")
-                    } else {
-                        Some("false")
-                    }
-                }
-                _ => None,
-            })
-            .ok_or(Error::BadLoginResponse(
-                "Expected \"success\" key with a String or Boolean value",
-            ))?;
-
-        match success {
-            "true" => {
+    username: &str,
+) -> Result<Option<String>, Error> {
+    if let Some(command) = config.password_command(username) {
+        match password_command::run(
+            command,
+            username,
+            Duration::from_secs(config.password_command_timeout),
+        )? {
+            password_command::Outcome::Password(password) => {
                 if !quiet {
-                    println!("Authentication success!");
+                    println!("Using password from password command...");
                 }
 
-                return Ok(Some(response_json));
-            }
-            "delayed" => {
-                response_json = enqueue(client, quiet, &response_json)?;
+                return Ok(Some(password));
             }
-            "partial" => {
-                response_json =
-                    if let Some(rj) = do_2fa(client, &response_json)? {
-                        rj
-                    } else {
-                        return Ok(None);
-                    }
-            }
-            "false" => {
-                println!(
-                    "Login failed: {}",
-                    response_json
-                        .get("banner")
-                        .and_then(|val| {
-                            if let serde_json::Value::String(s) = val {
-                                Some(s)
-                            } else {
-                                None
-                            }
-                        })
-                        .ok_or(Error::BadLoginResponse(
-                            "Expected \"banner\" key with String value",
-                        ))?,
-                );
+            password_command::Outcome::Failed(status) => eprintln!(
+                "The password command for {username} failed ({status}), so \
+                 asking for the password instead."
+            ),
+        }
+    } else if let Some(password) = get_saved_password(config, username)? {
+        if !quiet {
+            println!("Using saved password...");
+        }
 
-                return Ok(None);
-            }
-            _ => {
-                return Err(Error::UnexpectedSuccessValue(success.to_owned()));
+        return Ok(Some(password));
+    }
+
+    Ok(None)
+}
+
+/// Generates a two-factor authentication token from `totp_secret`, unless
+/// there is no secret, or one was already generated (in which case it must
+/// have been rejected). Otherwise, asks for one with `prompt`.
+fn generate_or_prompt_for_2fa<
+    F: FnMut(&str) -> Result<Option<String>, Error>,
+>(
+    totp_secret: Option<&str>,
+    totp_used: &mut bool,
+    quiet: bool,
+    banner: &str,
+    prompt: &mut F,
+) -> Result<Option<String>, Error> {
+    match totp_secret {
+        Some(secret) if !*totp_used => {
+            *totp_used = true;
+            if !quiet {
+                println!("Using generated two-factor code...");
             }
+
+            totp::code(secret).map(Some)
+        }
+        Some(_) => {
+            eprintln!("The generated two-factor code was rejected.");
+
+            prompt(banner)
         }
+        None => prompt(banner),
     }
 }
 
-/// Return value is `Ok(None)` if cancelled by user.
-fn do_2fa(
-    client: &rb::Client,
-    response_json: &serde_json::Value,
-) -> Result<Option<serde_json::Value>, Error> {
-    let auth_token = response_json
-        .get("responseToken")
-        .and_then(|val| {
-            if let serde_json::Value::String(s) = val {
-                Some(s)
-            } else {
-                None
-            }
-        })
-        .ok_or(Error::BadLoginResponse(
-            "Expected \"responseToken\" key with String value",
-        ))?;
-
-    print!(
-        "{}\n(...or type \"cancel\" to cancel):\n> ",
-        response_json
-            .get("banner")
-            .and_then(|val| if let serde_json::Value::String(s) = val {
-                Some(s)
-            } else {
-                None
-            })
-            .ok_or(Error::BadLoginResponse(
-                "Expected \"banner\" key with String value",
-            ))?,
-    );
-    io::stdout().flush().map_err(Error::Stdout)?;
+/// Asks the user for a two-factor authentication token on stdin. Return value
+/// is `Ok(None)` if cancelled by user.
+fn prompt_for_2fa(banner: &str) -> Result<Option<String>, Error> {
+    eprint!("{banner}\n(...or type \"cancel\" to cancel):\n> ");
     let mut app_token = String::with_capacity(0x10);
     io::stdin()
         .read_line(&mut app_token)
         .map_err(Error::Stdin)?;
     app_token.truncate(app_token.trim_end().len());
 
-    if app_token == "cancel" {
-        Ok(None)
+    Ok(if app_token == "cancel" {
+        None
     } else {
-        let mut params = BTreeMap::new();
-        params.insert("appToken", app_token.as_str());
-        params.insert("authToken", auth_token);
+        Some(app_token)
+    })
+}
 
-        post_to_login_api(client, &params).map(Some)
+impl<'a> LoginClient<'a> {
+    pub fn new(client: &'a rb::Client) -> Self {
+        Self {
+            client,
+            api_uri: config::DEFAULT_LOGIN_API_URI,
+            observer: &Silent,
+            limits: Limits::default(),
+            cancel: None,
+        }
     }
-}
 
-fn enqueue(
-    client: &rb::Client,
-    quiet: bool,
-    response_json: &serde_json::Value,
-) -> Result<serde_json::Value, Error> {
-    let eta = response_json
-        .get("eta")
-        .and_then(|val| match val {
-            serde_json::Value::String(s) => s.parse().ok(),
-            serde_json::Value::Number(n) => n.as_f64(),
-            _ => None,
-        })
-        .ok_or(Error::BadLoginResponse(
-            "Expected \"eta\" key with a String or Number value",
-        ))?;
-    if !quiet {
-        println!(
-            "Waiting in queue... ETA: {eta}, position in line: {}",
-            response_json
-                .get("position")
-                .and_then(|val| match val {
-                    serde_json::Value::String(s) => s.parse().ok(),
-                    serde_json::Value::Number(n) => n.as_u64(),
-                    _ => None,
-                })
-                .ok_or(Error::BadLoginResponse(
-                    "Expected \"position\" key with a String or unsigned \
-                     Number value",
-                ))?,
-        );
+    /// Uses the login API at `api_uri` instead of TTR's own.
+    pub fn api_uri(mut self, api_uri: &'a str) -> Self {
+        self.api_uri = api_uri;
+
+        self
     }
 
-    let queue_token = response_json
-        .get("queueToken")
-        .and_then(|val| {
-            if let serde_json::Value::String(s) = val {
-                Some(s)
-            } else {
-                None
+    /// Reports progress (e.g. queue position) to `observer`.
+    pub fn observer(mut self, observer: &'a dyn Observer) -> Self {
+        self.observer = observer;
+
+        self
+    }
+
+    /// Gives up with `LoginOutcome::TimedOut` if logging in takes longer
+    /// than `timeout` altogether.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.limits.timeout = timeout;
+
+        self
+    }
+
+    /// Gives up with `LoginOutcome::TimedOut` if more than `max_queue_wait`
+    /// is spent waiting in the login queue.
+    pub fn max_queue_wait(mut self, max_queue_wait: Option<Duration>) -> Self {
+        self.limits.max_queue_wait = max_queue_wait;
+
+        self
+    }
+
+    /// Gives up with `LoginOutcome::Cancelled` as soon as `cancel` is set,
+    /// e.g. from another thread.
+    pub fn cancel_on(mut self, cancel: &'a AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+
+        self
+    }
+
+    /// Logs into the account, waiting in the login queue if necessary.
+    /// If the account uses two-factor authentication, then `two_factor` is
+    /// called with the login API's prompt, and should return the token, or
+    /// `None` to cancel the login.
+    pub fn login<F>(
+        &self,
+        username: &str,
+        password: &str,
+        mut two_factor: F,
+    ) -> Result<LoginOutcome, Error>
+    where
+        F: FnMut(&str) -> Result<Option<String>, Error>,
+    {
+        let mut negotiation = Negotiation::new(self.limits, Instant::now());
+
+        loop {
+            if self.cancelled() {
+                negotiation.cancel();
             }
-        })
-        .ok_or(Error::BadLoginResponse(
-            "Expected \"queueToken\" key with a String value",
-        ))?;
-    // Be a tad less aggressive if the server is overloaded
-    let sleep_time = if eta < 0.25 {
-        25
-    } else if eta < 5.0 {
-        500
-    } else {
-        1_500
-    };
 
-    thread::sleep(Duration::from_millis(sleep_time));
+            match negotiation.next(Instant::now()) {
+                Step::Post { request, delay } => {
+                    if !self.sleep(delay) {
+                        negotiation.cancel();
+
+                        continue;
+                    }
+
+                    let mut params = BTreeMap::new();
+                    match &request {
+                        Request::Credentials => {
+                            params.insert("username", username);
+                            params.insert("password", password);
+                        }
+                        Request::QueueToken(queue_token) => {
+                            params.insert("queueToken", queue_token);
+                        }
+                        Request::TwoFactor {
+                            app_token,
+                            auth_token,
+                        } => {
+                            params.insert("appToken", app_token);
+                            params.insert("authToken", auth_token);
+                        }
+                    }
+
+                    let response = Response::from_json(&post_to_login_api(
+                        self.client,
+                        self.api_uri,
+                        &params,
+                    )?)?;
+                    self.report(&response);
+                    negotiation.on_response(response, Instant::now());
+                }
+                Step::AskForToken { banner } => {
+                    negotiation.on_token(two_factor(&banner)?);
+                }
+                Step::Done(outcome) => {
+                    if let LoginOutcome::TimedOut = outcome {
+                        self.observer.on_event(&Event::LoginTimedOut);
+                    }
+
+                    return Ok(outcome);
+                }
+            }
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.is_some_and(|c| c.load(Ordering::Relaxed))
+    }
 
-    let mut params = BTreeMap::new();
-    params.insert("queueToken", queue_token);
+    /// Returns `false` if cancelled while sleeping.
+    fn sleep(&self, duration: Duration) -> bool {
+        const SLICE: Duration = Duration::from_millis(25);
+
+        let deadline = Instant::now() + duration;
+        loop {
+            if self.cancelled() {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep(SLICE.min(deadline - now));
+        }
+    }
 
-    post_to_login_api(client, &params)
+    fn report(&self, response: &Response) {
+        self.observer.on_event(&match response {
+            Response::Success { .. } => Event::LoggedIn,
+            Response::Delayed { eta, position, .. } => Event::QueuePosition {
+                eta: *eta,
+                position: *position,
+            },
+            Response::Partial { banner, .. } => {
+                Event::TwoFactorRequired { banner }
+            }
+            Response::Failed { banner } => Event::LoginFailed { banner },
+        });
+    }
 }
 
 fn post_to_login_api<K: Ord + Serialize, V: Serialize>(
     client: &rb::Client,
+    api_uri: &str,
     params: &BTreeMap<K, V>,
 ) -> Result<serde_json::Value, Error> {
     serde_json::from_str(
         &client
-            .post(LOGIN_API_URI)
+            .post(api_uri)
             .header(header::ACCEPT, "text/plain")
             .form(&params)
             .send()
@@ -406,49 +1043,171 @@ fn post_to_login_api<K: Ord + Serialize, V: Serialize>(
     .map_err(Error::Deserialize)
 }
 
-fn launch<S: AsRef<OsStr>, T: AsRef<OsStr>>(
+/// Launches the game from `config.install_dir`, logged in as `username` with
+/// `credentials`, as `username`'s launch spec (see `Config::launch_spec`)
+/// says to. The game's output is logged (see `logs`), unless
+/// `config.max_logs` is `0`.
+pub fn launch(
     config: &Config,
-    quiet: bool,
-    play_cookie: S,
-    game_server: T,
+    observer: &dyn Observer,
+    username: &str,
+    credentials: &PlayCredentials,
 ) -> Result<process::Child, Error> {
-    if !quiet {
-        println!("Launching the game...");
-    }
-
-    #[cfg(target_os = "linux")]
-    let command_text = "./TTREngine";
-    #[cfg(windows)]
-    let command_text = {
-        #[cfg(target_arch = "x86")]
-        const EXE_NAME: &str = "TTREngine.exe";
-        #[cfg(target_arch = "x86_64")]
-        const EXE_NAME: &str = "TTREngine64.exe";
-
-        // `.current_dir(&config.install_dir)` doesn't seem to work like it
-        // does on Linux, so this is just a (naïve) way of making real sure
-        // that we are pointing at the right executable.
-        let mut command_buf = config.install_dir.clone();
-        command_buf.push(EXE_NAME);
-
-        command_buf
+    let spec = config.launch_spec(username)?;
+    // An absolute path, because whether or not `.current_dir` affects how
+    // the executable itself is found differs between platforms.
+    let exe_path = std::path::absolute(config.game_executable_path())
+        .map_err(|ioe| {
+            Error::UnknownIo(
+                format!("resolving {:?}", config.game_executable),
+                ioe,
+            )
+        })?;
+    let wrapper = spec.wrapper.as_deref().unwrap_or_default();
+
+    let mut command = if let [program, wrapper_args @ ..] = wrapper {
+        let mut command = process::Command::new(program);
+        command.args(wrapper_args).arg(exe_path);
+
+        command
+    } else {
+        process::Command::new(exe_path)
+    };
+    let working_dir = match &spec.working_dir {
+        Some(working_dir) => {
+            let working_dir = config.install_dir.join(working_dir);
+            // Otherwise, spawning would fail in a way that looks like the
+            // wrapper or the game is missing.
+            if !working_dir.is_dir() {
+                return Err(Error::InvalidConfig(format!(
+                    "The launch `working_dir` for {username} isn't a \
+                     directory: {working_dir:?}",
+                )));
+            }
+
+            working_dir
+        }
+        None => config.install_dir.clone(),
+    };
+    command
+        .args(&config.launch_args)
+        .args(&spec.args)
+        .current_dir(working_dir);
+    for var in &spec.env_remove {
+        command.env_remove(var);
+    }
+    command
+        .envs(&spec.env)
+        .env(PLAYCOOKIE_ENV_VAR, &credentials.cookie)
+        .env(GAMESERVER_ENV_VAR, &credentials.gameserver)
+        .stdin(process::Stdio::null());
+
+    let pending_log = if config.max_logs > 0 {
+        let (pending_log, file) = PendingLog::create(config, username)?;
+        let file_clone = file.try_clone().map_err(|ioe| {
+            Error::UnknownIo("duplicating a log file handle".to_owned(), ioe)
+        })?;
+        command.stdout(file).stderr(file_clone);
+
+        Some(pending_log)
+    } else {
+        command
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null());
+
+        None
     };
-    #[cfg(target_os = "macos")]
-    let command_text = {
-        // `.current_dir` is also borked on macOS.
-        let mut command_buf = config.install_dir.clone();
-        command_buf.push("Toontown Rewritten");
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(ioe) => {
+            if let Some(pending_log) = pending_log {
+                pending_log.discard();
+            }
 
-        command_buf
+            return Err(match wrapper.first() {
+                Some(program) => {
+                    Error::LaunchWrapperSpawn(program.clone(), ioe)
+                }
+                None => Error::ThreadSpawn(ioe),
+            });
+        }
     };
+    let log = pending_log
+        .map(|pending_log| pending_log.launched(child.id()))
+        .transpose()?;
+    if let Some(log) = &log
+        && config.max_log_size > 0
+    {
+        logs::watch(log.clone(), child.id(), config.max_log_size);
+    }
 
-    process::Command::new(command_text)
+    observer.on_event(&Event::GameLaunched {
+        username,
+        pid: child.id(),
+        log: log.as_deref(),
+    });
+
+    Ok(child)
+}
+
+/// Runs `command` with the system shell, in `config.install_dir`, in place
+/// of the game: e.g. a wrapper script that launches the game in a container
+/// or a Wine prefix. The command gets `credentials` in the same environment
+/// variables as the game would, & `username` in `SBU_USERNAME`.
+pub fn exec(
+    config: &Config,
+    observer: &dyn Observer,
+    username: &str,
+    credentials: &PlayCredentials,
+    command: &str,
+) -> Result<process::Child, Error> {
+    let child = util::shell(command)
         .current_dir(&config.install_dir)
-        .env("TTR_PLAYCOOKIE", play_cookie)
-        .env("TTR_GAMESERVER", game_server)
+        .env(PLAYCOOKIE_ENV_VAR, &credentials.cookie)
+        .env(GAMESERVER_ENV_VAR, &credentials.gameserver)
+        .env(password_command::USERNAME_ENV_VAR, username)
         .stdin(process::Stdio::null())
-        .stdout(process::Stdio::null())
-        .stderr(process::Stdio::null())
         .spawn()
-        .map_err(Error::ThreadSpawn)
+        .map_err(|ioe| Error::ExecSpawn(command.to_owned(), ioe))?;
+
+    observer.on_event(&Event::GameLaunched {
+        username,
+        pid: child.id(),
+        log: None,
+    });
+
+    Ok(child)
+}
+
+/// Writes `credentials` to stdout as environment variables: as `export`s
+/// that a POSIX shell can `eval`, or as a single JSON object.
+pub fn print_env(
+    output: OutputFormat,
+    username: &str,
+    credentials: &PlayCredentials,
+) {
+    if output.is_json() {
+        output::emit(serde_json::json!({
+            "event": "login_env",
+            "username": username,
+            "env": {
+                PLAYCOOKIE_ENV_VAR: credentials.cookie,
+                GAMESERVER_ENV_VAR: credentials.gameserver,
+            },
+        }));
+    } else {
+        println!(
+            "export {PLAYCOOKIE_ENV_VAR}={}",
+            shell_quote(&credentials.cookie),
+        );
+        println!(
+            "export {GAMESERVER_ENV_VAR}={}",
+            shell_quote(&credentials.gameserver),
+        );
+    }
+}
+
+/// Single-quotes `s` for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
 }
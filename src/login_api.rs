@@ -0,0 +1,331 @@
+//! The login API's responses, & the state machine that negotiates a login
+//! out of them.
+//!
+//! Nothing in here does any I/O: `Negotiation` says what to send next (via
+//! `Negotiation::next`), & is told what came back (via
+//! `Negotiation::on_response` & `Negotiation::on_token`). `LoginClient` is
+//! what actually talks to the login API.
+
+use crate::{
+    error::Error,
+    login::{LoginOutcome, PlayCredentials},
+};
+use std::time::{Duration, Instant};
+
+/// A single response from the login API.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Response {
+    /// `"success": "true"`
+    Success { cookie: String, gameserver: String },
+    /// `"success": "delayed"`, i.e. waiting in the login queue. `eta` is in
+    /// seconds.
+    Delayed {
+        eta: f64,
+        position: u64,
+        queue_token: String,
+    },
+    /// `"success": "partial"`, i.e. a two-factor token is needed.
+    Partial {
+        banner: String,
+        response_token: String,
+    },
+    /// `"success": "false"`
+    Failed { banner: String },
+}
+
+/// A request that `Negotiation` wants sent to the login API. The username
+/// & password aren't kept in the negotiation, so `Credentials` doesn't
+/// carry them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    Credentials,
+    QueueToken(String),
+    TwoFactor {
+        app_token: String,
+        auth_token: String,
+    },
+}
+
+/// What needs to happen next for a `Negotiation` to make progress.
+#[derive(Clone, Debug)]
+pub enum Step {
+    /// Wait for `delay`, then send `request` & pass the response to
+    /// `Negotiation::on_response`.
+    Post {
+        request: Request,
+        delay: Duration,
+    },
+    /// Get a two-factor token for the prompt `banner` from somewhere, & pass
+    /// it (or `None`, to cancel) to `Negotiation::on_token`.
+    AskForToken {
+        banner: String,
+    },
+    Done(LoginOutcome),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// How long the whole negotiation may take.
+    pub timeout: Option<Duration>,
+    /// How long may be spent waiting in the login queue, in one go.
+    pub max_queue_wait: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+enum State {
+    /// Nothing has been sent yet.
+    Start,
+    /// A request was sent, & its response is needed.
+    Posted,
+    Queued {
+        queue_token: String,
+        eta: f64,
+    },
+    NeedsToken {
+        banner: String,
+        response_token: String,
+    },
+    HasToken {
+        app_token: String,
+        auth_token: String,
+    },
+    Done(LoginOutcome),
+}
+
+#[derive(Clone, Debug)]
+pub struct Negotiation {
+    state: State,
+    limits: Limits,
+    started: Instant,
+    /// When the current stint in the login queue started.
+    queued_since: Option<Instant>,
+}
+
+impl Response {
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, Error> {
+        let success = match json.get("success") {
+            Some(serde_json::Value::String(s)) => s.as_str(),
+            Some(serde_json::Value::Bool(true)) => "true",
+            Some(serde_json::Value::Bool(false)) => "false",
+            _ => {
+                return Err(Error::BadLoginResponse(
+                    "Expected \"success\" key with a String or Boolean value"
+                        .to_owned(),
+                ));
+            }
+        };
+
+        match success {
+            "true" => Ok(Self::Success {
+                cookie: get_string(json, success, "cookie")?,
+                gameserver: get_string(json, success, "gameserver")?,
+            }),
+            "delayed" => Ok(Self::Delayed {
+                eta: get_number(
+                    json,
+                    success,
+                    "eta",
+                    serde_json::Number::as_f64,
+                )?,
+                position: get_number(
+                    json,
+                    success,
+                    "position",
+                    serde_json::Number::as_u64,
+                )?,
+                queue_token: get_string(json, success, "queueToken")?,
+            }),
+            "partial" => Ok(Self::Partial {
+                banner: get_string(json, success, "banner")?,
+                response_token: get_string(json, success, "responseToken")?,
+            }),
+            "false" => Ok(Self::Failed {
+                banner: get_string(json, success, "banner")?,
+            }),
+            _ => Err(Error::UnexpectedSuccessValue(success.to_owned())),
+        }
+    }
+}
+
+impl Negotiation {
+    pub fn new(limits: Limits, now: Instant) -> Self {
+        Self {
+            state: State::Start,
+            limits,
+            started: now,
+            queued_since: None,
+        }
+    }
+
+    /// The outcome, if the negotiation is over.
+    pub fn outcome(&self) -> Option<&LoginOutcome> {
+        if let State::Done(outcome) = &self.state {
+            Some(outcome)
+        } else {
+            None
+        }
+    }
+
+    /// Says what to do next, as of `now`. A `Step::Post` must be followed by
+    /// a call to `on_response`, & a `Step::AskForToken` by a call to
+    /// `on_token`.
+    pub fn next(&mut self, now: Instant) -> Step {
+        if self.outcome().is_none()
+            && let Some(timeout) = self.limits.timeout
+            && now.saturating_duration_since(self.started) >= timeout
+        {
+            self.state = State::Done(LoginOutcome::TimedOut);
+        }
+
+        let step = match &mut self.state {
+            State::Start => Step::Post {
+                request: Request::Credentials,
+                delay: Duration::ZERO,
+            },
+            State::Queued { queue_token, eta } => Step::Post {
+                request: Request::QueueToken(std::mem::take(queue_token)),
+                delay: queue_delay(*eta),
+            },
+            State::HasToken {
+                app_token,
+                auth_token,
+            } => Step::Post {
+                request: Request::TwoFactor {
+                    app_token: std::mem::take(app_token),
+                    auth_token: std::mem::take(auth_token),
+                },
+                delay: Duration::ZERO,
+            },
+            // These are waiting on something other than `next`, so they stay
+            // put.
+            State::NeedsToken { banner, .. } => {
+                return Step::AskForToken {
+                    banner: banner.clone(),
+                };
+            }
+            State::Done(outcome) => return Step::Done(outcome.clone()),
+            State::Posted => {
+                return Step::Done(LoginOutcome::Failed(
+                    "No response was given to the last request".to_owned(),
+                ));
+            }
+        };
+        self.state = State::Posted;
+
+        step
+    }
+
+    /// Takes in the login API's response to the last `Step::Post`, as of
+    /// `now`.
+    pub fn on_response(&mut self, response: Response, now: Instant) {
+        if self.outcome().is_some() {
+            return;
+        }
+
+        if !matches!(response, Response::Delayed { .. }) {
+            self.queued_since = None;
+        }
+
+        self.state = match response {
+            Response::Success { cookie, gameserver } => {
+                State::Done(LoginOutcome::Success(PlayCredentials {
+                    cookie,
+                    gameserver,
+                }))
+            }
+            Response::Failed { banner } => {
+                State::Done(LoginOutcome::Failed(banner))
+            }
+            Response::Delayed {
+                eta, queue_token, ..
+            } => {
+                let queued_since = *self.queued_since.get_or_insert(now);
+                match self.limits.max_queue_wait {
+                    Some(max_wait)
+                        if now.saturating_duration_since(queued_since)
+                            >= max_wait =>
+                    {
+                        State::Done(LoginOutcome::TimedOut)
+                    }
+                    _ => State::Queued { queue_token, eta },
+                }
+            }
+            Response::Partial {
+                banner,
+                response_token,
+            } => State::NeedsToken {
+                banner,
+                response_token,
+            },
+        };
+    }
+
+    /// Takes in the two-factor token asked for by the last
+    /// `Step::AskForToken`. `None` cancels the login.
+    pub fn on_token(&mut self, app_token: Option<String>) {
+        let State::NeedsToken { response_token, .. } = &mut self.state else {
+            return;
+        };
+
+        self.state = match app_token {
+            Some(app_token) => State::HasToken {
+                app_token,
+                auth_token: std::mem::take(response_token),
+            },
+            None => State::Done(LoginOutcome::Cancelled),
+        };
+    }
+
+    /// Gives up on the login, unless it's already over.
+    pub fn cancel(&mut self) {
+        if self.outcome().is_none() {
+            self.state = State::Done(LoginOutcome::Cancelled);
+        }
+    }
+}
+
+/// How long to wait before asking the queue for an update. A tad less
+/// aggressive if the server is overloaded.
+fn queue_delay(eta: f64) -> Duration {
+    Duration::from_millis(if eta < 0.25 {
+        25
+    } else if eta < 5.0 {
+        500
+    } else {
+        1_500
+    })
+}
+
+fn get_string(
+    json: &serde_json::Value,
+    success: &str,
+    key: &str,
+) -> Result<String, Error> {
+    match json.get(key) {
+        Some(serde_json::Value::String(s)) => Ok(s.clone()),
+        _ => Err(Error::BadLoginResponse(format!(
+            "Expected {key:?} key with a String value in a {success:?} \
+             response",
+        ))),
+    }
+}
+
+/// Gets a number that may be given as either a Number or a String.
+fn get_number<T: std::str::FromStr>(
+    json: &serde_json::Value,
+    success: &str,
+    key: &str,
+    from_number: fn(&serde_json::Number) -> Option<T>,
+) -> Result<T, Error> {
+    match json.get(key) {
+        Some(serde_json::Value::String(s)) => s.parse().ok(),
+        Some(serde_json::Value::Number(n)) => from_number(n),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        Error::BadLoginResponse(format!(
+            "Expected {key:?} key with a String or Number value in a \
+             {success:?} response",
+        ))
+    })
+}
@@ -0,0 +1,348 @@
+//! Log files of the game's output (stdout & stderr), one per instance, named
+//! `<username>-<PID>-<timestamp>.log` (with the timestamp in seconds since
+//! the Unix epoch), so that there's something to look at when the game
+//! crashes. Once a log file grows past `Config::max_log_size`, what's in it
+//! is moved to `<username>-<PID>-<timestamp>.log.old`, replacing whatever
+//! was moved there before.
+
+use crate::{config::Config, error::Error, util};
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const EXTENSION: &str = "log";
+/// For log files whose game hasn't been launched yet, & so whose PID isn't
+/// known.
+const PENDING_EXTENSION: &str = "log.pending";
+/// For the output from before a log file was last rotated.
+const ROTATED_EXTENSION: &str = "log.old";
+/// How often `watch` checks the size of a log file.
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How much of the end of a log file `tail` looks at.
+const MAX_TAIL_BYTES: u64 = 0x10000;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A log file in the logs directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub username: String,
+    pub pid: u32,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// A log file that the game's output can be sent to, before the game has
+/// been launched.
+#[derive(Debug)]
+pub struct PendingLog {
+    path: PathBuf,
+    username: String,
+    timestamp: u64,
+}
+
+impl LogFile {
+    /// Parses a path named like the ones that `PendingLog::launched` gives.
+    fn parse(path: PathBuf) -> Option<Self> {
+        let stem = path
+            .file_name()?
+            .to_str()?
+            .strip_suffix(EXTENSION)?
+            .strip_suffix('.')?;
+        let mut parts = stem.rsplitn(3, '-');
+        let timestamp = parts.next()?.parse().ok()?;
+        let pid = parts.next()?.parse().ok()?;
+        let username = parts.next()?.to_owned();
+
+        Some(Self {
+            path,
+            username,
+            pid,
+            timestamp,
+        })
+    }
+}
+
+impl PendingLog {
+    /// Creates an empty log file for `username` in `config`'s logs
+    /// directory (see `Config::logs_dir`), after pruning old log files
+    /// (see `prune`). Returns the file for the game's output to be sent to.
+    pub fn create(
+        config: &Config,
+        username: &str,
+    ) -> Result<(Self, File), Error> {
+        let dir = config.logs_dir();
+        fs::create_dir_all(&dir)
+            .map_err(|ioe| Error::Mkdir(dir.clone(), ioe))?;
+        // Leaves room for the new one.
+        prune(config, 1);
+
+        // This process's PID & a counter keep the name unique, even when
+        // the same account is launched more than once in the same second.
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let username = file_name_safe(username);
+        let timestamp = now();
+        let path = dir.join(format!(
+            "{username}-{timestamp}-{}-{}.{PENDING_EXTENSION}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+        // Appended to, so that the game keeps writing from the start of the
+        // file after it's been truncated by `rotate`.
+        let file = File::options()
+            .append(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|ioe| {
+            Error::UnknownIo(format!("creating {path:?}"), ioe)
+        })?;
+
+        Ok((
+            Self {
+                path,
+                username,
+                timestamp,
+            },
+            file,
+        ))
+    }
+
+    /// Names the log file after `pid`, now that the game has been launched.
+    pub fn launched(self, pid: u32) -> Result<PathBuf, Error> {
+        let path = self.path.with_file_name(format!(
+            "{}-{pid}-{}.{EXTENSION}",
+            self.username, self.timestamp,
+        ));
+        fs::rename(&self.path, &path)
+            .map_err(|_| Error::FileRename(self.path.clone(), path.clone()))?;
+
+        Ok(path)
+    }
+
+    /// Removes the log file, e.g. because the game couldn't be launched.
+    pub fn discard(self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// All of the log files in `config`'s logs directory, oldest first.
+pub fn list(config: &Config) -> Result<Vec<LogFile>, Error> {
+    let dir = config.logs_dir();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(ioe) if ioe.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(ioe) => {
+            return Err(Error::UnknownIo(format!("reading {dir:?}"), ioe));
+        }
+    };
+
+    let mut logs = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|ioe| {
+            Error::UnknownIo(format!("reading {dir:?}"), ioe)
+        })?;
+        if let Some(log) = LogFile::parse(entry.path()) {
+            logs.push(log);
+        }
+    }
+    logs.sort_by_key(|log| (log.timestamp, log.pid));
+
+    Ok(logs)
+}
+
+/// The newest log file of the instance with the PID or username `instance`,
+/// whether or not it's still running. PIDs take precedence over usernames.
+pub fn find(
+    config: &Config,
+    instance: &str,
+) -> Result<Option<LogFile>, Error> {
+    let logs = list(config)?;
+    let by_pid = instance
+        .parse::<u32>()
+        .ok()
+        .and_then(|pid| logs.iter().rev().find(|log| log.pid == pid));
+
+    Ok(by_pid
+        .or_else(|| {
+            let username = file_name_safe(instance);
+            logs.iter().rev().find(|log| log.username == username)
+        })
+        .cloned())
+}
+
+/// Up to the last `lines` lines of the log file at `path`, including ones
+/// from before it was last rotated.
+pub fn tail<P: AsRef<Path>>(
+    path: P,
+    lines: usize,
+) -> Result<Vec<String>, Error> {
+    let mut tail = tail_file(path.as_ref(), lines)?;
+    let rotated = rotated_path(path.as_ref());
+    if tail.len() < lines && rotated.exists() {
+        let mut older = tail_file(&rotated, lines - tail.len())?;
+        older.append(&mut tail);
+        tail = older;
+    }
+
+    Ok(tail)
+}
+
+fn tail_file(path: &Path, lines: usize) -> Result<Vec<String>, Error> {
+    let mut file = util::open_file(path)?;
+    let read_err = |ioe| Error::FileRead(path.to_owned(), ioe);
+
+    let len = file.metadata().map_err(read_err)?.len();
+    let start = len.saturating_sub(MAX_TAIL_BYTES);
+    file.seek(SeekFrom::Start(start)).map_err(read_err)?;
+    let mut buf = Vec::with_capacity((len - start) as usize);
+    file.read_to_end(&mut buf).map_err(read_err)?;
+
+    let text = String::from_utf8_lossy(&buf);
+    let mut all_lines: Vec<_> = text.lines().collect();
+    // The first line is probably only part of one.
+    if start > 0 && all_lines.len() > 1 {
+        all_lines.remove(0);
+    }
+
+    Ok(all_lines[all_lines.len().saturating_sub(lines)..]
+        .iter()
+        .map(|&line| line.to_owned())
+        .collect())
+}
+
+/// Deletes log files older than `config.max_log_age` days, & then the
+/// oldest ones until there are at most `config.max_logs - reserve` left.
+/// Log files of instances that are still running are never deleted, & nor
+/// are pending ones, unless the process that created them has since died.
+/// This is best-effort, so errors are ignored.
+pub fn prune(config: &Config, reserve: usize) {
+    prune_pending(config);
+
+    let Ok(logs) = list(config) else {
+        return;
+    };
+
+    let cutoff = config
+        .max_log_age
+        .map(|days| now().saturating_sub(days.saturating_mul(SECS_PER_DAY)));
+    let keep = config.max_logs.saturating_sub(reserve);
+    let mut excess = logs.len().saturating_sub(keep);
+    for log in &logs {
+        let too_old = cutoff.is_some_and(|cutoff| log.timestamp < cutoff);
+        if (excess > 0 || too_old) && !util::process_is_running(log.pid) {
+            let _ = fs::remove_file(&log.path);
+            let _ = fs::remove_file(rotated_path(&log.path));
+            excess = excess.saturating_sub(1);
+        }
+    }
+}
+
+/// Deletes pending log files (see `PendingLog`) that will never be launched
+/// or discarded, because the process that created them is gone.
+fn prune_pending(config: &Config) {
+    let Ok(entries) = fs::read_dir(config.logs_dir()) else {
+        return;
+    };
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if let Some(creator) = pending_creator(&path)
+            && !util::process_is_running(creator)
+        {
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+/// The PID of the process that created the pending log file at `path`, if
+/// it's named like the ones that `PendingLog::create` gives.
+fn pending_creator(path: &Path) -> Option<u32> {
+    let stem = path
+        .file_name()?
+        .to_str()?
+        .strip_suffix(PENDING_EXTENSION)?
+        .strip_suffix('.')?;
+
+    stem.rsplit('-').nth(1)?.parse().ok()
+}
+
+/// If the log file at `path` is bigger than `max_size` bytes, moves what's
+/// in it to the side (see the module-level docs), & empties it. Returns
+/// whether it was rotated.
+///
+/// The game keeps writing to the same file all along, so this copies &
+/// truncates it, rather than renaming it. Anything written between the two
+/// is lost.
+pub fn rotate<P: AsRef<Path>>(path: P, max_size: u64) -> Result<bool, Error> {
+    let path = path.as_ref();
+    let len = fs::metadata(path)
+        .map_err(|ioe| Error::FileRead(path.to_owned(), ioe))?
+        .len();
+    if len <= max_size {
+        return Ok(false);
+    }
+
+    let rotated = rotated_path(path);
+    fs::copy(path, &rotated).map_err(|ioe| {
+        Error::UnknownIo(format!("copying {path:?} to {rotated:?}"), ioe)
+    })?;
+    File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_len(0))
+        .map_err(|ioe| Error::FileWrite(path.to_owned(), ioe))?;
+
+    Ok(true)
+}
+
+/// Keeps the log file at `path` from growing past `max_size` bytes (see
+/// `rotate`), for as long as the process with the ID `pid` is running, &
+/// this one is too. Like `prune`, this is best-effort, so errors are
+/// ignored.
+pub fn watch(path: PathBuf, pid: u32, max_size: u64) {
+    let _ = thread::Builder::new()
+        .name(format!("log rotation for {pid}"))
+        .spawn(move || {
+            while util::process_is_running(pid) {
+                // Unless the log file has gone missing.
+                if let Err(Error::FileRead(..)) = rotate(&path, max_size) {
+                    break;
+                }
+                thread::sleep(ROTATION_CHECK_INTERVAL);
+            }
+        });
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    path.with_extension(ROTATED_EXTENSION)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+/// Replaces anything that might not be allowed in a file name.
+fn file_name_safe(username: &str) -> String {
+    username
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '@')
+            {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
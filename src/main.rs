@@ -3,32 +3,45 @@
 
 mod accounts;
 mod command;
-mod config;
-mod error;
-mod keyring;
-mod login;
-mod patch;
-mod update;
-mod util;
 
 use clap::{
     Arg, ArgAction, Command,
     builder::{ArgPredicate, ValueParser},
     crate_authors, crate_description, crate_name, crate_version, value_parser,
 };
-use error::Error;
 use reqwest::blocking as rb;
-use std::{num::NonZeroUsize, process};
+use shticker_book_unwritten::{
+    config, diff,
+    error::Error,
+    login::LaunchMode,
+    observer::{Observer, TerminalObserver},
+    output::{self, JsonObserver, OutputFormat},
+    update::Updater,
+};
+use std::{num::NonZeroUsize, path::PathBuf, process};
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("{e}");
+    let arg_matches = cli().get_matches();
+    let output = arg_matches
+        .get_one::<String>("output")
+        .and_then(|o| OutputFormat::from_arg(o))
+        .unwrap_or(OutputFormat::Human);
+
+    if let Err(e) = run(&arg_matches, output) {
+        if output.is_json() {
+            eprintln!(
+                "{}",
+                serde_json::json!({ "error": output::error_value(&e) })
+            );
+        } else {
+            eprintln!("{e}");
+        }
 
         process::exit(e.return_code())
     }
 }
 
-fn run() -> Result<(), Error> {
+fn cli() -> Command {
     #[cfg(target_os = "linux")]
     const CONFIG_LONG_HELP: &str = concat!(
         "Configuration JSON file to use. Defaults to \"$XDG_CONFIG_HOME\"/",
@@ -51,7 +64,7 @@ fn run() -> Result<(), Error> {
         "/config.json",
     );
 
-    let arg_matches = Command::new(crate_name!())
+    Command::new(crate_name!())
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
@@ -81,6 +94,22 @@ fn run() -> Result<(), Error> {
                 .conflicts_with("config")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("profile")
+                .short('p')
+                .long("profile")
+                .value_name("PROFILE")
+                .help("Use the named profile from the config.")
+                .long_help(
+                    "Overrides values in the config with those of the named \
+                     profile (found under \"profiles\" in the config), e.g. \
+                     to use a test server. The profile will not be written \
+                     to the config.",
+                )
+                .num_args(1)
+                .conflicts_with("no-config")
+                .action(ArgAction::Set),
+        )
         .arg(
             Arg::new("install-dir")
                 .short('i')
@@ -140,7 +169,9 @@ fn run() -> Result<(), Error> {
                     "If this option is supplied, then after (possibly) \
                      auto-updating, the game will be launched with this \
                      username. To login with multiple accounts, specify this \
-                     option more than once. The corresponding password(s) \
+                     option more than once. Accounts may also be referred to \
+                     by their alias, or all at once as @group. The \
+                     corresponding password(s) \
                      will be prompted for as normal if they aren't saved. \
                      Then, if the login(s) succeed, command mode is entered \
                      (assuming `-d` is not supplied).",
@@ -148,6 +179,47 @@ fn run() -> Result<(), Error> {
                 .num_args(1..)
                 .action(ArgAction::Append),
         )
+        .arg(
+            Arg::new("print-env")
+                .long("print-env")
+                .help(
+                    "Print the play cookie & gameserver for `-u` instead of \
+                     launching the game.",
+                )
+                .long_help(
+                    "Instead of launching the game after logging in with \
+                     `-u`, print TTR_PLAYCOOKIE & TTR_GAMESERVER as shell \
+                     exports (e.g. for `eval`), or as a JSON object if \
+                     `-o json` is supplied, so that the game can be launched \
+                     some other way. Implies `-q`.",
+                )
+                .num_args(0)
+                .requires("username")
+                .conflicts_with("exec")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("exec")
+                .short('e')
+                .long("exec")
+                .value_name("COMMAND")
+                .help(
+                    "Run COMMAND instead of the game for `-u`, with the play \
+                     cookie & gameserver in its environment.",
+                )
+                .long_help(
+                    "Instead of launching the game after logging in with \
+                     `-u`, run COMMAND with the system shell, in the \
+                     installation directory, with TTR_PLAYCOOKIE & \
+                     TTR_GAMESERVER (& SBU_USERNAME) set just as the game \
+                     would get them. Useful for launching the game through \
+                     a wrapper, a container, Wine, etc. The command is \
+                     tracked as a game instance.",
+                )
+                .num_args(1)
+                .requires("username")
+                .action(ArgAction::Set),
+        )
         .arg(
             Arg::new("detach")
                 .short('d')
@@ -213,9 +285,71 @@ fn run() -> Result<(), Error> {
                 .action(ArgAction::SetTrue)
                 .conflicts_with("no-auto-update"),
         )
-        .get_matches();
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FORMAT")
+                .help("Output format: either human (the default) or json.")
+                .long_help(
+                    "Output format: either human (the default) or json. In \
+                     json mode, human-readable output is suppressed (as if \
+                     `-q` were supplied), and update progress & results, \
+                     account listings, and instance listings are instead \
+                     written to stdout as JSON objects, one per line. Errors \
+                     are written to stderr as JSON objects.",
+                )
+                .num_args(1)
+                .value_parser(["human", "json"])
+                .default_value("human")
+                .action(ArgAction::Set),
+        )
+        .subcommand(
+            Command::new("mkpatch")
+                .about(
+                    "Create a bsdiff (BSDIFF40) patch that turns <OLD> into \
+                     <NEW>, and write it to <OUT>.",
+                )
+                .long_about(
+                    "Creates a bsdiff (BSDIFF40) patch that turns <OLD> into \
+                     <NEW>, and writes it to <OUT>, without touching the \
+                     config or the game installation. The patch is \
+                     uncompressed as a whole, just like the patches that the \
+                     manifest points to are once they are extracted. Both \
+                     files are read fully into memory.",
+                )
+                .arg(
+                    Arg::new("old")
+                        .value_name("OLD")
+                        .help("The original file.")
+                        .required(true)
+                        .value_parser(ValueParser::path_buf()),
+                )
+                .arg(
+                    Arg::new("new")
+                        .value_name("NEW")
+                        .help("The updated file.")
+                        .required(true)
+                        .value_parser(ValueParser::path_buf()),
+                )
+                .arg(
+                    Arg::new("out")
+                        .value_name("OUT")
+                        .help("Where to write the patch.")
+                        .required(true)
+                        .value_parser(ValueParser::path_buf()),
+                ),
+        )
+}
 
-    let quiet = arg_matches.get_one("quiet").copied().unwrap_or(false);
+fn run(
+    arg_matches: &clap::ArgMatches,
+    output: OutputFormat,
+) -> Result<(), Error> {
+    let print_env = arg_matches.get_one("print-env").copied().unwrap_or(false);
+    let quiet = arg_matches.get_one("quiet").copied().unwrap_or(false)
+        || output.is_json()
+        || print_env;
     let max_tries =
         if let Some(tries) = arg_matches.get_one::<NonZeroUsize>("tries") {
             *tries
@@ -223,14 +357,26 @@ fn run() -> Result<(), Error> {
             NonZeroUsize::new(5).unwrap()
         };
 
+    if let Some(("mkpatch", mkpatch_matches)) = arg_matches.subcommand() {
+        return mkpatch(mkpatch_matches, quiet, output);
+    }
+
     let (mut config, config_path) = config::get_config(
         arg_matches.get_one("no-config").copied().unwrap_or(false),
         arg_matches.get_one("config").cloned(),
         arg_matches.get_one("install-dir").cloned(),
         arg_matches.get_one("cache-dir").cloned(),
+        arg_matches.get_one::<String>("profile").map(String::as_str),
         quiet,
     )?;
 
+    let terminal_observer = TerminalObserver::new(quiet);
+    let observer: &dyn Observer = if output.is_json() {
+        &JsonObserver
+    } else {
+        &terminal_observer
+    };
+
     let client = rb::ClientBuilder::new()
         .build()
         .map_err(Error::HttpClientCreate)?;
@@ -240,13 +386,12 @@ fn run() -> Result<(), Error> {
         .copied()
         .unwrap_or(false)
     {
-        update::update(
-            &config,
-            &client,
-            quiet,
-            max_tries,
-            arg_matches.get_one("dry-update").copied().unwrap_or(false),
-        )?;
+        Updater::new(&config, &client)
+            .max_tries(max_tries)
+            .observer(observer)
+            .update(
+                arg_matches.get_one("dry-update").copied().unwrap_or(false),
+            )?;
 
         if !quiet {
             println!();
@@ -258,10 +403,48 @@ fn run() -> Result<(), Error> {
         &config_path,
         &client,
         quiet,
+        output,
+        observer,
         arg_matches
             .get_many::<String>("username")
             .map(|it| it.map(String::as_str)),
+        if print_env {
+            LaunchMode::PrintEnv(output)
+        } else if let Some(command) = arg_matches.get_one::<String>("exec") {
+            LaunchMode::Exec(command.clone())
+        } else {
+            LaunchMode::Launch
+        },
         arg_matches.get_one("detach").copied().unwrap_or(false),
         max_tries,
     )
 }
+
+fn mkpatch(
+    arg_matches: &clap::ArgMatches,
+    quiet: bool,
+    output: OutputFormat,
+) -> Result<(), Error> {
+    let path = |id| arg_matches.get_one::<PathBuf>(id).unwrap();
+    let (old, new, out) = (path("old"), path("new"), path("out"));
+
+    if !quiet {
+        println!("Diffing {} against {}...", new.display(), old.display());
+    }
+
+    diff::diff_files(old, new, out)?;
+
+    if !quiet {
+        println!("Patch written to {}", out.display());
+    }
+    output::emit_if_json(output, || {
+        serde_json::json!({
+            "event": "patch_created",
+            "old": old,
+            "new": new,
+            "patch": out,
+        })
+    });
+
+    Ok(())
+}
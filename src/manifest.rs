@@ -0,0 +1,239 @@
+//! Parsing of the patch manifest, which lists every game file along with its
+//! hashes, its download, & any patches that can be applied to older versions
+//! of it.
+
+use crate::{
+    decompress::Compression,
+    error::Error,
+    hash::{Hash, HashAlgorithm},
+    util,
+};
+
+#[cfg(target_os = "linux")]
+pub const OS_AND_ARCH: &str = "linux2";
+#[cfg(target_os = "macos")]
+pub const OS_AND_ARCH: &str = "darwin";
+#[cfg(all(windows, target_arch = "x86_64"))]
+pub const OS_AND_ARCH: &str = "win64";
+#[cfg(all(windows, target_arch = "x86"))]
+pub const OS_AND_ARCH: &str = "win32";
+
+#[derive(Debug)]
+pub struct Manifest {
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug)]
+pub struct ManifestFile {
+    pub name: String,
+    /// The OS & architecture combinations that this file is used on.
+    pub only: Vec<String>,
+    /// Name of the compressed version of this file on the CDN.
+    pub dl: String,
+    /// Taken from the optional `"compression"` field, or else guessed from
+    /// `dl`.
+    pub compression: Compression,
+    /// Taken from `"compSha256"` if present, or else `"compHash"` (SHA-1).
+    pub comp_hash: Hash,
+    /// Taken from `"sha256"` if present, or else `"hash"` (SHA-1).
+    pub hash: Hash,
+    pub patches: Vec<ManifestPatch>,
+}
+
+#[derive(Debug)]
+pub struct ManifestPatch {
+    /// Hash of the old version of the file that this patch applies to. This
+    /// may be either SHA-1 or SHA-256, depending on its length.
+    pub from_hash: Hash,
+    /// Name of the compressed patch on the CDN.
+    pub filename: String,
+    /// Taken from the optional `"compression"` field, or else guessed from
+    /// `filename`.
+    pub compression: Compression,
+    /// Taken from `"compPatchSha256"` if present, or else `"compPatchHash"`
+    /// (SHA-1).
+    pub comp_patch_hash: Hash,
+    /// Taken from `"patchSha256"` if present, or else `"patchHash"` (SHA-1).
+    pub patch_hash: Hash,
+}
+
+impl Manifest {
+    pub fn from_json(value: serde_json::Value) -> Result<Self, Error> {
+        let serde_json::Value::Object(manifest_map) = value else {
+            return Err(Error::BadManifestFormat(
+                "Top-level value is not an Object".to_owned(),
+            ));
+        };
+
+        manifest_map
+            .into_iter()
+            .map(|(name, file_obj)| ManifestFile::from_json(name, file_obj))
+            .collect::<Result<_, _>>()
+            .map(|files| Self { files })
+    }
+}
+
+impl ManifestFile {
+    fn from_json(
+        name: String,
+        file_obj: serde_json::Value,
+    ) -> Result<Self, Error> {
+        let serde_json::Value::Object(file_map) = file_obj else {
+            return Err(Error::BadManifestFormat(
+                "Expected Object at 2nd-to-top level".to_owned(),
+            ));
+        };
+
+        let serde_json::Value::Array(supported_archs) =
+            file_map.get("only").ok_or_else(|| {
+                Error::BadManifestFormat("Missing the \"only\" key".to_owned())
+            })?
+        else {
+            return Err(Error::BadManifestFormat(
+                "Expected \"only\"'s value to be an Array".to_owned(),
+            ));
+        };
+        let only = supported_archs
+            .iter()
+            .map(|arch_val| match arch_val {
+                serde_json::Value::String(s) => Ok(s.clone()),
+                _ => Err(Error::BadManifestFormat(
+                    "Expected OS & architecture values to be Strings"
+                        .to_owned(),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let patches = match file_map.get("patches") {
+            None => Vec::new(),
+            Some(serde_json::Value::Object(patches_map)) => patches_map
+                .iter()
+                .map(|(from_hash_str, patch_obj)| {
+                    ManifestPatch::from_json(from_hash_str, patch_obj)
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => {
+                return Err(Error::BadManifestFormat(format!(
+                    "Expected \"patches\" key to have an Object value in the \
+                     \"{name}\" Object",
+                )));
+            }
+        };
+
+        let dl = get_str(&file_map, "dl")?.to_owned();
+        util::check_relative_path(&name)?;
+        util::check_relative_path(&dl)?;
+
+        Ok(Self {
+            compression: get_compression(&file_map, &dl)?,
+            dl,
+            comp_hash: get_hash(&file_map, "compSha256", "compHash")?,
+            hash: get_hash(&file_map, "sha256", "hash")?,
+            name,
+            only,
+            patches,
+        })
+    }
+
+    pub fn supported_by_this_arch(&self) -> bool {
+        self.only.iter().any(|arch| arch == OS_AND_ARCH)
+    }
+
+    /// The hash algorithms needed to check a local copy of this file against
+    /// both `hash` & the hashes that the patches apply to. The algorithm of
+    /// `hash` always comes first.
+    pub fn hash_algorithms(&self) -> Vec<HashAlgorithm> {
+        let mut algs = vec![self.hash.algorithm()];
+        for patch in &self.patches {
+            let alg = patch.from_hash.algorithm();
+            if !algs.contains(&alg) {
+                algs.push(alg);
+            }
+        }
+
+        algs
+    }
+
+    /// Finds a patch that can be applied to the version of this file whose
+    /// hashes are `local_hashes`, if there is one.
+    pub fn patch_from(&self, local_hashes: &[Hash]) -> Option<&ManifestPatch> {
+        self.patches
+            .iter()
+            .find(|p| local_hashes.contains(&p.from_hash))
+    }
+}
+
+impl ManifestPatch {
+    fn from_json(
+        from_hash_str: &str,
+        patch_obj: &serde_json::Value,
+    ) -> Result<Self, Error> {
+        let serde_json::Value::Object(patch_map) = patch_obj else {
+            return Err(Error::BadManifestFormat(
+                "Expected \"patches\" to be objects".to_owned(),
+            ));
+        };
+
+        let filename = get_str(patch_map, "filename")?.to_owned();
+        util::check_relative_path(&filename)?;
+
+        Ok(Self {
+            from_hash: Hash::from_hex(from_hash_str)
+                .map_err(Error::BadManifestFormat)?,
+            compression: get_compression(patch_map, &filename)?,
+            filename,
+            comp_patch_hash: get_hash(
+                patch_map,
+                "compPatchSha256",
+                "compPatchHash",
+            )?,
+            patch_hash: get_hash(patch_map, "patchSha256", "patchHash")?,
+        })
+    }
+}
+
+fn get_str<'a>(
+    map: &'a serde_json::Map<String, serde_json::Value>,
+    key: &str,
+) -> Result<&'a str, Error> {
+    match map.get(key) {
+        Some(serde_json::Value::String(s)) => Ok(s),
+        Some(_) => Err(Error::BadManifestFormat(format!(
+            "Expected \"{key}\" to be a String",
+        ))),
+        None => Err(Error::BadManifestFormat(format!("Expected \"{key}\""))),
+    }
+}
+
+fn get_compression(
+    map: &serde_json::Map<String, serde_json::Value>,
+    file_name: &str,
+) -> Result<Compression, Error> {
+    match map.get("compression") {
+        None => Ok(Compression::from_file_name(file_name)),
+        Some(serde_json::Value::String(s)) => Compression::from_name(s)
+            .ok_or_else(|| {
+                Error::BadManifestFormat(format!(
+                    "Unsupported compression format: {s:?}",
+                ))
+            }),
+        Some(_) => Err(Error::BadManifestFormat(
+            "Expected \"compression\" to be a String".to_owned(),
+        )),
+    }
+}
+
+/// Gets the hash stored under `sha256_key` if there is one, or else the
+/// SHA-1 hash stored under `sha1_key`.
+fn get_hash(
+    map: &serde_json::Map<String, serde_json::Value>,
+    sha256_key: &str,
+    sha1_key: &str,
+) -> Result<Hash, Error> {
+    if map.contains_key(sha256_key) {
+        Hash::from_hex_as(HashAlgorithm::Sha256, get_str(map, sha256_key)?)
+    } else {
+        Hash::from_hex_as(HashAlgorithm::Sha1, get_str(map, sha1_key)?)
+    }
+    .map_err(Error::BadManifestFormat)
+}
@@ -0,0 +1,428 @@
+//! Progress reporting for updating & logging in.
+//!
+//! Everything that happens during an update or a login is reported to an
+//! `Observer` as an `Event`. The CLI uses `TerminalObserver` for its usual
+//! human-readable output, and `output::JsonObserver` for `--output json`;
+//! other frontends can implement `Observer` themselves.
+
+use crate::{
+    error::Error,
+    hash::{Hash, HashAlgorithm},
+};
+use std::{fmt, num::NonZeroUsize, path::Path};
+
+/// What happened to a single file from the manifest during an update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileStatus {
+    /// Not supported by this OS & architecture, so it was skipped.
+    Unsupported,
+    UpToDate,
+    /// Doesn't exist locally.
+    Missing,
+    /// Exists locally, but doesn't match the manifest.
+    Outdated {
+        patch_available: bool,
+    },
+    Patched,
+    Downloaded,
+}
+
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum Event<'a> {
+    ManifestDownloading {
+        attempt: usize,
+        max_tries: NonZeroUsize,
+    },
+    ManifestFetched {
+        files: usize,
+    },
+    /// About to check the file that is `index`th (starting from 1) out of
+    /// `total` files in the manifest.
+    FileChecking {
+        file: &'a str,
+        index: usize,
+        total: usize,
+    },
+    /// A local file is being hashed, to compare it against the manifest.
+    FileHashing {
+        file: &'a str,
+        algorithm: HashAlgorithm,
+    },
+    HashMatched {
+        file: &'a str,
+        algorithm: HashAlgorithm,
+    },
+    /// `file` is either a local file that is out of date, or a freshly
+    /// downloaded file that is corrupt (& that will be downloaded again).
+    HashMismatch {
+        file: &'a str,
+        local: &'a Hash,
+        expected: &'a Hash,
+    },
+    FileStatus {
+        file: &'a str,
+        status: FileStatus,
+    },
+    /// Checking that none of the files that an update plan touches have
+    /// changed since the plan was made.
+    PlanVerifying,
+    /// About to patch the file that is `index`th (starting from 1) out of
+    /// `total` files to update.
+    Patching {
+        file: &'a str,
+        index: usize,
+        total: usize,
+    },
+    PatchApplied {
+        file: &'a str,
+    },
+    /// About to download the file that is `index`th (starting from 1) out of
+    /// `total` files to update, without patching.
+    FileDownloading {
+        file: &'a str,
+        index: usize,
+        total: usize,
+    },
+    /// A single attempt at downloading `file` (which may be compressed, or
+    /// may be a patch) is starting.
+    Download {
+        file: &'a str,
+        attempt: usize,
+        max_tries: NonZeroUsize,
+    },
+    /// `bytes` bytes of `file` have been downloaded so far. `total` is known
+    /// only if the server said how large the file is.
+    DownloadProgress {
+        file: &'a str,
+        bytes: u64,
+        total: Option<u64>,
+    },
+    Extracting {
+        file: &'a str,
+    },
+    /// A network operation failed, & will be retried if `attempt` is less
+    /// than `max_tries`. `file` is `None` when fetching the manifest.
+    Retry {
+        file: Option<&'a str>,
+        attempt: usize,
+        max_tries: NonZeroUsize,
+        error: &'a Error,
+    },
+    /// The game executable didn't have its executable bit set, so it was set.
+    MadeExecutable {
+        file: &'a str,
+    },
+    UpdateFinished {
+        dry: bool,
+        staged: bool,
+        needs_update: &'a [String],
+    },
+    StagedUpdateApplying {
+        files: usize,
+    },
+    StagedFileMoved {
+        file: &'a str,
+    },
+    StagedUpdateApplied,
+    /// Right before launching the game, these files turned out not to match
+    /// the manifest.
+    InstallOutdated {
+        files: &'a [String],
+    },
+    /// Waiting in the login queue. `eta` is in seconds.
+    QueuePosition {
+        eta: f64,
+        position: u64,
+    },
+    /// The account uses two-factor authentication, & the login API is asking
+    /// for a token with `banner`.
+    TwoFactorRequired {
+        banner: &'a str,
+    },
+    LoggedIn,
+    /// The login API refused the login, with `banner` as its explanation.
+    LoginFailed {
+        banner: &'a str,
+    },
+    /// The login took too long, or too long was spent in the login queue.
+    LoginTimedOut,
+    GameLaunched {
+        username: &'a str,
+        pid: u32,
+        /// Where the game's output is being logged, if anywhere.
+        log: Option<&'a Path>,
+    },
+    /// When logging into several accounts at once, the status of
+    /// `accounts[changed]` changed. Replaces the other login events, which
+    /// don't say which account they're about.
+    LoginStatuses {
+        accounts: &'a [(String, LoginStatus)],
+        changed: usize,
+    },
+}
+
+/// How one of several logins that are being done at once is going.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoginStatus {
+    LoggingIn,
+    /// `eta` is in seconds.
+    Queued {
+        eta: f64,
+        position: u64,
+    },
+    TwoFactorRequired,
+    LoggedIn,
+    /// The login API refused the login (or something went wrong), with
+    /// `reason` as the explanation.
+    Failed {
+        reason: String,
+    },
+    TimedOut,
+    Cancelled,
+}
+
+pub trait Observer {
+    fn on_event(&self, event: &Event);
+}
+
+/// Ignores all events.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Silent;
+
+/// Writes human-readable progress to stdout (& to stderr, for failures).
+/// If `quiet` is `true`, then only failures are written.
+#[derive(Clone, Copy, Debug)]
+pub struct TerminalObserver {
+    quiet: bool,
+}
+
+impl FileStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unsupported => "unsupported",
+            Self::UpToDate => "up_to_date",
+            Self::Missing => "missing",
+            Self::Outdated { .. } => "outdated",
+            Self::Patched => "patched",
+            Self::Downloaded => "downloaded",
+        }
+    }
+}
+
+impl LoginStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::LoggingIn => "logging_in",
+            Self::Queued { .. } => "queued",
+            Self::TwoFactorRequired => "two_factor_required",
+            Self::LoggedIn => "logged_in",
+            Self::Failed { .. } => "failed",
+            Self::TimedOut => "timed_out",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// Whether or not this is bad news.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::Failed { .. } | Self::TimedOut)
+    }
+}
+
+impl fmt::Display for LoginStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::LoggingIn => f.write_str("logging in"),
+            Self::Queued { eta, position } => {
+                write!(f, "#{position} in queue (ETA: {eta})")
+            }
+            Self::TwoFactorRequired => f.write_str("needs a two-factor token"),
+            Self::LoggedIn => f.write_str("logged in"),
+            Self::Failed { reason } => write!(f, "failed ({reason})"),
+            Self::TimedOut => f.write_str("timed out"),
+            Self::Cancelled => f.write_str("cancelled"),
+        }
+    }
+}
+
+impl Observer for Silent {
+    fn on_event(&self, _: &Event) {}
+}
+
+impl TerminalObserver {
+    pub fn new(quiet: bool) -> Self {
+        Self { quiet }
+    }
+}
+
+impl Observer for TerminalObserver {
+    fn on_event(&self, event: &Event) {
+        // Failures are worth mentioning even when quiet.
+        match *event {
+            Event::Retry {
+                file,
+                attempt,
+                max_tries,
+                error,
+            } => {
+                eprintln!(
+                    "{}{error}{}",
+                    if file.is_some() { "        " } else { "" },
+                    if attempt < max_tries.get() {
+                        "; retrying..."
+                    } else {
+                        "; no more attempts remaining!"
+                    },
+                );
+
+                return;
+            }
+            Event::LoginFailed { banner } => {
+                eprintln!("Login failed: {banner}");
+
+                return;
+            }
+            Event::LoginTimedOut => {
+                eprintln!("Login timed out.");
+
+                return;
+            }
+            Event::InstallOutdated { files } => {
+                eprintln!(
+                    "The game isn't up to date! Not matching the manifest: {}",
+                    files.join(", "),
+                );
+
+                return;
+            }
+            Event::LoginStatuses { accounts, changed }
+                if self.quiet
+                    && let Some((username, status)) =
+                        accounts.get(changed)
+                    && status.is_failure() =>
+            {
+                eprintln!("{username}: {status}");
+
+                return;
+            }
+            _ => (),
+        }
+
+        if self.quiet {
+            return;
+        }
+
+        match *event {
+            Event::ManifestDownloading { attempt, max_tries } => {
+                println!(
+                    "Downloading manifest [attempt {attempt}/{max_tries}]..."
+                );
+            }
+            Event::ManifestFetched { .. } => {
+                println!("Downloaded manifest successfully!");
+            }
+            Event::FileChecking { file, index, total } => {
+                println!(
+                    "[{index:2}/{total}] Checking for updates for {file}"
+                );
+            }
+            Event::FileHashing { algorithm, .. } => {
+                println!("        Checking {algorithm} hash...");
+            }
+            Event::HashMatched { algorithm, .. } => {
+                println!("        {algorithm} hash matches!");
+            }
+            Event::HashMismatch {
+                local, expected, ..
+            } => {
+                println!(
+                    "        {} hash mismatch:\n          Local:    \
+                     {local}\n          Manifest: {expected}",
+                    expected.algorithm(),
+                );
+            }
+            Event::FileStatus { file, status } => match status {
+                FileStatus::Unsupported => println!(
+                    "        Not supported by this OS & architecture, \
+                     skipping..."
+                ),
+                FileStatus::UpToDate => (),
+                FileStatus::Missing => println!(
+                    "        File doesn't exist; needs to be downloaded from \
+                     scratch."
+                ),
+                FileStatus::Outdated {
+                    patch_available: true,
+                } => println!("        Found a patch!"),
+                FileStatus::Outdated {
+                    patch_available: false,
+                } => println!(
+                    "        No patches found; needs to be downloaded from \
+                     scratch."
+                ),
+                FileStatus::Patched => {
+                    println!("        File patched successfully!");
+                }
+                FileStatus::Downloaded => {
+                    println!("        {file} all done downloading!");
+                }
+            },
+            Event::PlanVerifying => {
+                println!("Making sure that the plan is still up to date...");
+            }
+            Event::Patching { file, index, total } => {
+                println!("[{index:2}/{total}] Patching {file}");
+            }
+            Event::PatchApplied { .. } => println!("        Patch applied!"),
+            Event::FileDownloading { file, index, total } => {
+                println!(
+                    "[{index:2}/{total}] Downloading {file} from scratch"
+                );
+            }
+            Event::Download {
+                file,
+                attempt,
+                max_tries,
+            } => {
+                println!(
+                    "        Downloading {file} [attempt {attempt}/{max_tries}]"
+                );
+            }
+            Event::Extracting { .. } => println!("        Extracting..."),
+            Event::MadeExecutable { file } => {
+                println!("{file} is now executable!");
+            }
+            Event::UpdateFinished { dry: true, .. } => println!(
+                "This is a dry run, so no updates were downloaded nor applied."
+            ),
+            Event::StagedUpdateApplying { .. } => {
+                println!("Applying staged update...");
+            }
+            Event::StagedFileMoved { file } => {
+                println!("        Moved {file} into place");
+            }
+            Event::StagedUpdateApplied => {
+                println!("Staged update applied successfully!");
+            }
+            Event::QueuePosition { eta, position } => println!(
+                "Waiting in queue... ETA: {eta}, position in line: {position}"
+            ),
+            Event::LoggedIn => println!("Authentication success!"),
+            Event::GameLaunched { .. } => {
+                println!("Game launched successfully!");
+            }
+            Event::LoginStatuses { accounts, .. } => {
+                let mut line = String::new();
+                for (i, (username, status)) in accounts.iter().enumerate() {
+                    if i > 0 {
+                        line.push_str(" | ");
+                    }
+                    line.push_str(&format!("{username}: {status}"));
+                }
+
+                println!("{line}");
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Machine-readable output, for when `--output json` is supplied.
+//!
+//! In JSON mode, all of the usual human-readable output is suppressed (as if
+//! `--quiet` were supplied), and is replaced by JSON objects written to
+//! stdout, one object per line.
+
+use crate::{
+    error::Error,
+    observer::{Event, FileStatus, LoginStatus, Observer},
+};
+use serde_json::json;
+
+/// Writes events to stdout as JSON objects, one per line. Events that are
+/// only interesting to humans (e.g. download progress) are left out.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonObserver;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "human" => Some(Self::Human),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub fn is_json(self) -> bool {
+        self == Self::Json
+    }
+}
+
+/// Writes `value` to stdout as a single line of JSON.
+pub fn emit(value: serde_json::Value) {
+    println!("{value}");
+}
+
+/// Like `emit`, but only does anything in JSON mode.
+pub fn emit_if_json<F: FnOnce() -> serde_json::Value>(
+    output: OutputFormat,
+    f: F,
+) {
+    if output.is_json() {
+        emit(f());
+    }
+}
+
+/// Prints `text` (e.g. help text) as is, or as the `"text"` of an `event`
+/// event in JSON mode.
+pub fn print_text(output: OutputFormat, event: &str, text: &str) {
+    if output.is_json() {
+        emit(json!({ "event": event, "text": text }));
+    } else {
+        print!("{text}");
+    }
+}
+
+/// Reports that a command couldn't be carried out as it was typed in (e.g.
+/// it was unrecognized, or an argument was missing): as a `command_error`
+/// event in JSON mode, & as plain `message` otherwise.
+pub fn command_error(output: OutputFormat, message: &str) {
+    if output.is_json() {
+        emit(json!({ "event": "command_error", "message": message }));
+    } else {
+        println!("{message}");
+    }
+}
+
+impl Observer for JsonObserver {
+    fn on_event(&self, event: &Event) {
+        if let Some(value) = event_value(event) {
+            emit(value);
+        }
+    }
+}
+
+pub fn event_value(event: &Event) -> Option<serde_json::Value> {
+    Some(match *event {
+        Event::ManifestFetched { files } => {
+            json!({ "event": "manifest_fetched", "files": files })
+        }
+        Event::FileChecking { file, index, total } => json!({
+            "event": "file_checking",
+            "file": file,
+            "index": index,
+            "total": total,
+        }),
+        Event::HashMismatch {
+            file,
+            local,
+            expected,
+        } => json!({
+            "event": "hash_mismatch",
+            "file": file,
+            "algorithm": expected.algorithm().name(),
+            "local": local.to_string(),
+            "expected": expected.to_string(),
+        }),
+        Event::FileStatus { file, status } => {
+            let mut obj = json!({
+                "event": "file_status",
+                "file": file,
+                "status": status.as_str(),
+            });
+            if let FileStatus::Outdated { patch_available } = status {
+                obj["patch_available"] = patch_available.into();
+            }
+
+            obj
+        }
+        Event::PatchApplied { file } => {
+            json!({ "event": "patch_applied", "file": file })
+        }
+        Event::Download {
+            file,
+            attempt,
+            max_tries,
+        } => json!({
+            "event": "download",
+            "file": file,
+            "attempt": attempt,
+            "max_tries": max_tries,
+        }),
+        Event::Retry {
+            file,
+            attempt,
+            max_tries,
+            error,
+        } => {
+            let mut obj = json!({
+                "event": "retry",
+                "attempt": attempt,
+                "max_tries": max_tries,
+                "error": error_value(error),
+            });
+            if let Some(file) = file {
+                obj["file"] = file.into();
+            }
+
+            obj
+        }
+        Event::UpdateFinished {
+            dry,
+            staged,
+            needs_update,
+        } => json!({
+            "event": "update_finished",
+            "dry": dry,
+            "staged": staged,
+            "needs_update": needs_update,
+        }),
+        Event::StagedUpdateApplied => {
+            json!({ "event": "staged_update_applied" })
+        }
+        Event::InstallOutdated { files } => {
+            json!({ "event": "install_outdated", "files": files })
+        }
+        Event::QueuePosition { eta, position } => json!({
+            "event": "queue_position",
+            "eta": eta,
+            "position": position,
+        }),
+        Event::TwoFactorRequired { banner } => {
+            json!({ "event": "two_factor_required", "banner": banner })
+        }
+        Event::LoggedIn => json!({ "event": "logged_in" }),
+        Event::LoginFailed { banner } => {
+            json!({ "event": "login_failed", "banner": banner })
+        }
+        Event::LoginTimedOut => json!({ "event": "login_timed_out" }),
+        Event::GameLaunched { username, pid, log } => json!({
+            "event": "game_launched",
+            "username": username,
+            "pid": pid,
+            "log": log,
+        }),
+        Event::LoginStatuses { accounts, changed } => json!({
+            "event": "login_statuses",
+            "changed": accounts.get(changed).map(|(username, _)| username),
+            "accounts": accounts
+                .iter()
+                .map(|(username, status)| {
+                    let mut obj = json!({
+                        "username": username,
+                        "status": status.as_str(),
+                    });
+                    match status {
+                        LoginStatus::Queued { eta, position } => {
+                            obj["eta"] = (*eta).into();
+                            obj["position"] = (*position).into();
+                        }
+                        LoginStatus::Failed { reason } => {
+                            obj["reason"] = reason.as_str().into();
+                        }
+                        _ => (),
+                    }
+
+                    obj
+                })
+                .collect::<Vec<_>>(),
+        }),
+        _ => return None,
+    })
+}
+
+pub fn error_value(e: &Error) -> serde_json::Value {
+    serde_json::json!({
+        "code": e.return_code(),
+        "message": e.to_string(),
+    })
+}
@@ -0,0 +1,119 @@
+//! Getting passwords out of an external password manager (`pass`, the
+//! 1Password CLI, Bitwarden's CLI, etc.), by running a user-supplied command
+//! & reading the password from its stdout.
+
+use crate::{error::Error, util::shell};
+use std::{
+    io::Read,
+    process::{ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const USERNAME_PLACEHOLDER: &str = "{username}";
+/// Set for the command, for commands that would rather not use
+/// `{username}`.
+pub const USERNAME_ENV_VAR: &str = "SBU_USERNAME";
+const POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+#[derive(Debug)]
+pub enum Outcome {
+    Password(String),
+    /// The command exited unsuccessfully, so the password should be gotten
+    /// some other way.
+    Failed(ExitStatus),
+}
+
+/// Runs `command` with the system shell (`sh -c` or `cmd /C`), after
+/// replacing every `{username}` in it with `username`. The first line of
+/// whatever the command writes to stdout is the password; stdin & stderr
+/// are left alone, so that the password manager can ask for its own
+/// passphrase if it needs to. The command is killed if it's still running
+/// after `timeout`.
+pub fn run(
+    command: &str,
+    username: &str,
+    timeout: Duration,
+) -> Result<Outcome, Error> {
+    if command.contains(USERNAME_PLACEHOLDER)
+        && !username.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '@')
+        })
+    {
+        return Err(Error::PasswordCommandOutput(
+            command.to_owned(),
+            format!(
+                "{username:?} can't be safely put into a shell command; use \
+                 ${USERNAME_ENV_VAR} instead",
+            ),
+        ));
+    }
+
+    let mut child = shell(&command.replace(USERNAME_PLACEHOLDER, username))
+        .env(USERNAME_ENV_VAR, username)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|ioe| Error::PasswordCommandSpawn(command.to_owned(), ioe))?;
+
+    // Read on another thread, so that a command with lots of output can't
+    // block forever on a full pipe while this thread waits for it to exit.
+    let mut stdout = child.stdout.take().unwrap_or_else(|| unreachable!());
+    let reader = thread::spawn(move || {
+        let mut output = Vec::with_capacity(0x40);
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+
+                return Err(Error::PasswordCommandTimeout(
+                    command.to_owned(),
+                    timeout.as_secs(),
+                ));
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(ioe) => {
+                return Err(Error::PasswordCommandSpawn(
+                    command.to_owned(),
+                    ioe,
+                ));
+            }
+        }
+    };
+
+    if !status.success() {
+        return Ok(Outcome::Failed(status));
+    }
+
+    let output = reader
+        .join()
+        .map_err(|_| {
+            Error::PasswordCommandOutput(
+                command.to_owned(),
+                "its output could not be read".to_owned(),
+            )
+        })?
+        .map_err(|ioe| Error::PasswordCommandSpawn(command.to_owned(), ioe))?;
+    let output = String::from_utf8(output).map_err(|_| {
+        Error::PasswordCommandOutput(
+            command.to_owned(),
+            "it printed something that isn't valid UTF-8".to_owned(),
+        )
+    })?;
+    let password = output.lines().next().unwrap_or_default();
+    if password.is_empty() {
+        return Err(Error::PasswordCommandOutput(
+            command.to_owned(),
+            "it printed an empty password".to_owned(),
+        ));
+    }
+
+    Ok(Outcome::Password(password.to_owned()))
+}
@@ -36,18 +36,38 @@
 //!  */
 //! ```
 
-use crate::{error::Error, util};
-use bzip2::read::BzDecoder as BzReadDecoder;
+use crate::{
+    decompress,
+    error::Error,
+    hash::{Hash, HashAlgorithm},
+    util,
+};
 use std::{
     self,
     ffi::{OsStr, OsString},
-    io::{Seek, SeekFrom, prelude::*},
+    fs::File,
+    io::{self, BufReader, BufWriter, Seek, SeekFrom, prelude::*},
     path::Path,
 };
 
-pub fn patch_file<P: AsRef<Path>, Q: AsRef<Path>>(
+/// Patches are applied in chunks of at most this many bytes, so that neither
+/// the old file nor the new file ever has to be held in memory all at once.
+const CHUNK_SIZE: usize = 0x1_0000;
+
+/// Applies the patch at `patch_file_path` to `old_file_path`, writing the
+/// result to `target_file_path`. The old & target paths may be the same, in
+/// which case the file is patched in place. Patches that claim to produce more
+/// than `max_output_size` bytes are rejected.
+///
+/// The new file is written to a temporary file first, & only takes the place
+/// of `target_file_path` once it's known to have the expected `hash`, so a
+/// bad patch never leaves a bad file behind.
+pub fn patch_file<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
     patch_file_path: P,
-    target_file_path: Q,
+    old_file_path: Q,
+    target_file_path: R,
+    max_output_size: u64,
+    hash: &Hash,
 ) -> Result<(), Error> {
     let target_file_osstr: &OsStr = target_file_path.as_ref().as_ref();
     let mut temp_file_path =
@@ -55,174 +75,377 @@ pub fn patch_file<P: AsRef<Path>, Q: AsRef<Path>>(
     temp_file_path.push(target_file_path.as_ref());
     temp_file_path.push(".tmp");
 
-    bsdiff_patch(patch_file_path, &target_file_path, &temp_file_path)?;
+    let patched = bsdiff_patch(
+        patch_file_path,
+        old_file_path,
+        &temp_file_path,
+        max_output_size,
+        hash.algorithm(),
+    )
+    .and_then(|patched_hash| {
+        if patched_hash == *hash {
+            Ok(())
+        } else {
+            Err(Error::HashMismatch(
+                target_file_path.as_ref().to_path_buf(),
+                hash.clone(),
+            ))
+        }
+    });
+    if let Err(e) = patched {
+        let _ = std::fs::remove_file(&temp_file_path);
+
+        return Err(e);
+    }
 
     std::fs::rename(&temp_file_path, &target_file_path).map_err(|_| {
         Error::FileRename(
             temp_file_path.into(),
             target_file_path.as_ref().to_path_buf(),
         )
-    })?;
-
-    Ok(())
+    })
 }
 
 fn bsdiff_patch<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
     patch_file_path: P,
     old_file_path: Q,
     new_file_path: R,
-) -> Result<(), Error> {
-    let new = apply_patch(patch_file_path, old_file_path)?;
+    max_output_size: u64,
+    hash_alg: HashAlgorithm,
+) -> Result<Hash, Error> {
+    let write_err =
+        |ioe| Error::FileWrite(new_file_path.as_ref().to_path_buf(), ioe);
 
-    // Write the new file
-    let mut fd = util::create_file(&new_file_path)?;
-    fd.write_all(&new[..]).map_err(|ioe| {
-        Error::FileWrite(new_file_path.as_ref().to_path_buf(), ioe)
+    // Write the new file as it's produced
+    let mut fd = BufWriter::new(util::create_new_file(&new_file_path)?);
+    let mut hasher = hash_alg.hasher();
+    apply_patch(patch_file_path, old_file_path, max_output_size, |chunk| {
+        hasher.update(chunk);
+        fd.write_all(chunk).map_err(write_err)
     })?;
+    fd.flush().map_err(write_err)?;
 
-    Ok(())
+    Ok(hasher.finalize())
 }
 
-fn apply_patch<P: AsRef<Path>, Q: AsRef<Path>>(
+/// Feeds the new file to `write`, one chunk at a time.
+fn apply_patch<P, Q, W>(
     patch_file_path: P,
     old_file_path: Q,
-) -> Result<Vec<u8>, Error> {
-    let header = {
-        // Open patch file
-        let mut f = util::open_file(&patch_file_path)?;
-
-        /*
-         * File format:
-         *
-         *   | offset | len | data
-         * --+--------+-----+----------------------
-         *   | 0      | 8   | "BSDIFF40"
-         *   | 8      | 8   | X
-         *   | 16     | 8   | Y
-         *   | 24     | 8   | sizeof(new_file)
-         *   | 32     | X   | bzip2(control block)
-         *   | 32+X   | Y   | bzip2(diff block)
-         *   | 32+X+Y | ??? | bzip2(extra block)
-         *
-         * With control block a set of triples (x, y, z) meaning "add x bytes
-         * from old_file to x bytes from the diff block; copy y bytes from the
-         * extra block; seek forwards in old_file by z bytes".
-         */
-
-        // Read header
-        let mut header = [0u8; 32];
-        f.read_exact(&mut header).map_err(|ioe| {
-            Error::FileRead(patch_file_path.as_ref().to_path_buf(), ioe)
-        })?;
+    max_output_size: u64,
+    mut write: W,
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    W: FnMut(&[u8]) -> Result<(), Error>,
+{
+    let bad_patch =
+        |s: String| Error::BadPatch(patch_file_path.as_ref().to_path_buf(), s);
 
-        header
+    // Running out of patch, or finding garbage where compressed data should
+    // be, means that the patch itself is bad, rather than the disk.
+    let read_err = |ioe: io::Error| {
+        if ioe.kind() == io::ErrorKind::UnexpectedEof {
+            bad_patch("Header is truncated".to_owned())
+        } else {
+            Error::FileRead(patch_file_path.as_ref().to_path_buf(), ioe)
+        }
     };
+    let decode_err = |ioe: io::Error| match ioe.kind() {
+        io::ErrorKind::UnexpectedEof => {
+            bad_patch("Blocks end before the new file does".to_owned())
+        }
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => {
+            bad_patch(format!("Blocks couldn't be decompressed: {ioe}"))
+        }
+        _ => Error::Decode(patch_file_path.as_ref().to_path_buf(), ioe),
+    };
+
+    // Open patch file
+    let mut f = BufReader::new(util::open_file(&patch_file_path)?);
 
-    // Check for appropriate magic
-    if header[..8] != b"BSDIFF40"[..] {
+    /*
+     * There are two supported file formats. The original format of bsdiff 4.3:
+     *
+     *   | offset | len | data
+     * --+--------+-----+----------------------
+     *   | 0      | 8   | "BSDIFF40"
+     *   | 8      | 8   | X
+     *   | 16     | 8   | Y
+     *   | 24     | 8   | sizeof(new_file)
+     *   | 32     | X   | bzip2(control block)
+     *   | 32+X   | Y   | bzip2(diff block)
+     *   | 32+X+Y | ??? | bzip2(extra block)
+     *
+     * With control block a set of triples (x, y, z) meaning "add x bytes
+     * from old_file to x bytes from the diff block; copy y bytes from the
+     * extra block; seek forwards in old_file by z bytes".
+     *
+     * And the single-stream format of Matthew Endsley's fork of bsdiff:
+     *
+     *   | offset | len | data
+     * --+--------+-----+----------------------
+     *   | 0      | 16  | "ENDSLEY/BSDIFF43"
+     *   | 16     | 8   | sizeof(new_file)
+     *   | 24     | ??? | bzip2(blocks)
+     *
+     * Where each triple is immediately followed by the x bytes of diff data
+     * & then the y bytes of extra data that it refers to.
+     */
+
+    // Read header
+    let mut magic = [0u8; 16];
+    f.read_exact(&mut magic).map_err(read_err)?;
+
+    let (newsize, mut blocks) = if magic[..8] == b"BSDIFF40"[..] {
+        let mut header = [0u8; 32];
+        header[..16].copy_from_slice(&magic);
+        f.read_exact(&mut header[16..]).map_err(read_err)?;
+
+        // Read lengths from header
+        let bzctrllen = offtin(&header[8..]);
+        let bzdatalen = offtin(&header[16..]);
+        let newsize = offtin(&header[24..]);
+        if bzctrllen < 0 || bzdatalen < 0 || newsize < 0 {
+            return Err(Error::BadPatchSize);
+        }
+        let (bzctrllen, bzdatalen) = (bzctrllen as u64, bzdatalen as u64);
+        let extra_start =
+            (32 + bzctrllen).checked_add(bzdatalen).ok_or_else(|| {
+                bad_patch(format!(
+                    "Control & diff block lengths ({bzctrllen} & \
+                     {bzdatalen}) are too large",
+                ))
+            })?;
+
+        // Open patch file in the right places with libbzip2 (or whichever
+        // decompressor the blocks call for)
+        let open_block = |start| {
+            let mut pf = util::open_file(&patch_file_path)?;
+            pf.seek(SeekFrom::Start(start)).map_err(|ioe| {
+                Error::Seek(patch_file_path.as_ref().to_path_buf(), ioe)
+            })?;
+
+            decompress::sniffing_decoder(BufReader::new(pf))
+                .map_err(decode_err)
+        };
+
+        (
+            newsize as u64,
+            Blocks::Separate {
+                ctrl: open_block(32)?,
+                diff: open_block(32 + bzctrllen)?,
+                extra: open_block(extra_start)?,
+            },
+        )
+    } else if magic == *b"ENDSLEY/BSDIFF43" {
+        let mut buf = [0u8; 8];
+        f.read_exact(&mut buf).map_err(read_err)?;
+        let newsize = offtin(&buf);
+        if newsize < 0 {
+            return Err(Error::BadPatchSize);
+        }
+
+        (
+            newsize as u64,
+            Blocks::Interleaved(
+                decompress::sniffing_decoder(f).map_err(decode_err)?,
+            ),
+        )
+    } else {
         return Err(Error::BadPatchVersion);
-    }
+    };
 
-    // Read lengths from header
-    let bzctrllen = offtin(&header[8..]);
-    let bzdatalen = offtin(&header[16..]);
-    let newsize = offtin(&header[24..]);
-    if bzctrllen < 0 || bzdatalen < 0 || newsize < 0 {
-        return Err(Error::BadPatchSize);
+    if newsize > max_output_size {
+        return Err(Error::PatchTooLarge(
+            patch_file_path.as_ref().to_path_buf(),
+            newsize,
+            max_output_size,
+        ));
     }
 
-    // Open patch file in the right places with libbzip2
-    let mut cpf = util::open_file(&patch_file_path)?;
-    cpf.seek(SeekFrom::Start(32)).map_err(|ioe| {
-        Error::Seek(patch_file_path.as_ref().to_path_buf(), ioe)
-    })?;
-    let mut cpfbz2 = BzReadDecoder::new(cpf);
-    let mut dpf = util::open_file(&patch_file_path)?;
-    dpf.seek(SeekFrom::Start((32 + bzctrllen) as u64))
-        .map_err(|ioe| {
-            Error::Seek(patch_file_path.as_ref().to_path_buf(), ioe)
-        })?;
-    let mut dpfbz2 = BzReadDecoder::new(dpf);
-    let mut epf = util::open_file(&patch_file_path)?;
-    epf.seek(SeekFrom::Start((32 + bzctrllen + bzdatalen) as u64))
-        .map_err(|ioe| {
-            Error::Seek(patch_file_path.as_ref().to_path_buf(), ioe)
-        })?;
-    let mut epfbz2 = BzReadDecoder::new(epf);
-
-    let mut fd = util::open_file(&old_file_path)?;
-    let oldsize = fd.seek(SeekFrom::End(0)).map_err(|ioe| {
-        Error::Seek(old_file_path.as_ref().to_path_buf(), ioe)
-    })? as i64;
-    let mut old = Vec::with_capacity(oldsize as usize);
-    old.resize_with(oldsize as usize, Default::default);
-    fd.seek(SeekFrom::Start(0)).map_err(|ioe| {
-        Error::Seek(old_file_path.as_ref().to_path_buf(), ioe)
-    })?;
-    fd.read_exact(&mut old[..]).map_err(|ioe| {
-        Error::FileRead(old_file_path.as_ref().to_path_buf(), ioe)
-    })?;
-
-    let mut new = Vec::with_capacity(newsize as usize);
-    new.resize_with(newsize as usize, Default::default);
+    let mut old = OldFile::open(old_file_path)?;
 
     // Start the actual patching
     let mut buf = [0u8; 8];
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    let mut old_chunk = vec![0u8; CHUNK_SIZE];
     let mut ctrl = [0i64; 3];
     let mut oldpos = 0i64;
-    let mut newpos = 0i64;
+    let mut newpos = 0u64;
     while newpos < newsize {
         // Read control data
         for ctrl_off in &mut ctrl {
-            cpfbz2.read_exact(&mut buf).map_err(|ioe| {
-                Error::Decode(patch_file_path.as_ref().to_path_buf(), ioe)
-            })?;
+            blocks.ctrl().read_exact(&mut buf).map_err(decode_err)?;
             *ctrl_off = offtin(&buf);
         }
+        let [diff_len, extra_len, seek_len] = ctrl;
 
         // Sanity check
-        if newpos + ctrl[0] > newsize {
-            return Err(Error::PatchSanityCheckFail(0));
+        let diff_end = u64::try_from(diff_len)
+            .ok()
+            .and_then(|len| newpos.checked_add(len))
+            .filter(|&end| end <= newsize)
+            .ok_or_else(|| {
+                bad_patch(format!(
+                    "Diff block would write {diff_len} bytes at offset \
+                     {newpos} of a {newsize}-byte file",
+                ))
+            })?;
+
+        while newpos < diff_end {
+            let n = (diff_end - newpos).min(CHUNK_SIZE as u64) as usize;
+            let chunk = &mut chunk[..n];
+
+            // Read diff string
+            blocks.diff().read_exact(chunk).map_err(decode_err)?;
+
+            // Add old data to diff string
+            old.add_to(oldpos, chunk, &mut old_chunk)?;
+            write(chunk)?;
+
+            // Adjust pointers
+            newpos += n as u64;
+            oldpos = oldpos.checked_add(n as i64).ok_or_else(|| {
+                bad_patch(format!(
+                    "Old file offset overflowed at offset {newpos}"
+                ))
+            })?;
         }
 
-        // Read diff string
-        dpfbz2
-            .read_exact(&mut new[newpos as usize..(newpos + ctrl[0]) as usize])
-            .map_err(|ioe| {
-                Error::Decode(patch_file_path.as_ref().to_path_buf(), ioe)
+        // Sanity check
+        let extra_end = u64::try_from(extra_len)
+            .ok()
+            .and_then(|len| newpos.checked_add(len))
+            .filter(|&end| end <= newsize)
+            .ok_or_else(|| {
+                bad_patch(format!(
+                    "Extra block would write {extra_len} bytes at offset \
+                     {newpos} of a {newsize}-byte file",
+                ))
             })?;
 
-        // Add old data to diff string
-        for i in 0..ctrl[0] {
-            if (oldpos + i >= 0) && (oldpos + i < oldsize) {
-                let new_index = (newpos + i) as usize;
-                let old_index = (oldpos + i) as usize;
-                new[new_index] = new[new_index].wrapping_add(old[old_index]);
-            }
+        while newpos < extra_end {
+            let n = (extra_end - newpos).min(CHUNK_SIZE as u64) as usize;
+            let chunk = &mut chunk[..n];
+
+            // Read extra string
+            blocks.extra().read_exact(chunk).map_err(decode_err)?;
+            write(chunk)?;
+
+            newpos += n as u64;
         }
 
         // Adjust pointers
-        newpos += ctrl[0];
-        oldpos += ctrl[0];
+        oldpos = oldpos.checked_add(seek_len).ok_or_else(|| {
+            bad_patch(format!("Old file offset overflowed at offset {newpos}"))
+        })?;
+    }
 
-        // Sanity check
-        if newpos + ctrl[1] > newsize {
-            return Err(Error::PatchSanityCheckFail(1));
+    Ok(())
+}
+
+/// The decompressed control, diff, & extra blocks of a patch.
+enum Blocks<'a> {
+    /// `BSDIFF40`, which compresses each block separately.
+    Separate {
+        ctrl: Box<dyn Read + 'a>,
+        diff: Box<dyn Read + 'a>,
+        extra: Box<dyn Read + 'a>,
+    },
+    /// `ENDSLEY/BSDIFF43`, which interleaves the blocks in a single stream,
+    /// in the same order that they're read in.
+    Interleaved(Box<dyn Read + 'a>),
+}
+
+impl Blocks<'_> {
+    fn ctrl(&mut self) -> &mut dyn Read {
+        match self {
+            Self::Separate { ctrl, .. } => ctrl,
+            Self::Interleaved(r) => r,
         }
+    }
 
-        // Read extra string
-        epfbz2
-            .read_exact(&mut new[newpos as usize..(newpos + ctrl[1]) as usize])
-            .map_err(|ioe| {
-                Error::Decode(patch_file_path.as_ref().to_path_buf(), ioe)
-            })?;
+    fn diff(&mut self) -> &mut dyn Read {
+        match self {
+            Self::Separate { diff, .. } => diff,
+            Self::Interleaved(r) => r,
+        }
+    }
 
-        // Adjust pointers
-        newpos += ctrl[1];
-        oldpos += ctrl[2];
+    fn extra(&mut self) -> &mut dyn Read {
+        match self {
+            Self::Separate { extra, .. } => extra,
+            Self::Interleaved(r) => r,
+        }
+    }
+}
+
+/// The file being patched, which is read on demand rather than all at once.
+struct OldFile<P: AsRef<Path>> {
+    path: P,
+    reader: BufReader<File>,
+    /// Current position of `reader`.
+    pos: u64,
+    size: u64,
+}
+
+impl<P: AsRef<Path>> OldFile<P> {
+    fn open(path: P) -> Result<Self, Error> {
+        let mut f = util::open_file(&path)?;
+        let size = f
+            .seek(SeekFrom::End(0))
+            .map_err(|ioe| Error::Seek(path.as_ref().to_path_buf(), ioe))?;
+        f.seek(SeekFrom::Start(0))
+            .map_err(|ioe| Error::Seek(path.as_ref().to_path_buf(), ioe))?;
+
+        Ok(Self {
+            path,
+            reader: BufReader::new(f),
+            pos: 0,
+            size,
+        })
     }
 
-    Ok(new)
+    /// Adds the bytes of the old file starting at `oldpos` to `chunk`,
+    /// bytewise. Bytes outside of the old file are treated as if they were
+    /// zero. `scratch` must be at least as long as `chunk`.
+    fn add_to(
+        &mut self,
+        oldpos: i64,
+        chunk: &mut [u8],
+        scratch: &mut [u8],
+    ) -> Result<(), Error> {
+        let start = oldpos.max(0) as u64;
+        let end = oldpos.saturating_add(chunk.len() as i64).clamp(0, i64::MAX)
+            as u64;
+        let end = end.min(self.size);
+        if start >= end {
+            return Ok(());
+        }
+
+        if start != self.pos {
+            // Relative seeks keep whatever is already buffered, if possible.
+            self.reader
+                .seek_relative(start.wrapping_sub(self.pos) as i64)
+                .map_err(|ioe| {
+                    Error::Seek(self.path.as_ref().to_path_buf(), ioe)
+                })?;
+        }
+        let old_bytes = &mut scratch[..(end - start) as usize];
+        self.reader.read_exact(old_bytes).map_err(|ioe| {
+            Error::FileRead(self.path.as_ref().to_path_buf(), ioe)
+        })?;
+        self.pos = end;
+
+        let offset = (start as i64 - oldpos) as usize;
+        for (new_byte, old_byte) in chunk[offset..].iter_mut().zip(old_bytes) {
+            *new_byte = new_byte.wrapping_add(*old_byte);
+        }
+
+        Ok(())
+    }
 }
 
 fn offtin(buf: &[u8]) -> i64 {
@@ -0,0 +1,261 @@
+//! Relaunching game instances that crash, for long unattended sessions.
+//!
+//! A `Supervisor` only keeps track of instances & their restart policies, &
+//! decides what to do when they exit; actually logging in again &
+//! relaunching is up to whoever is holding onto the instances (see
+//! `login::relaunch`). Instances that exit normally, or that are closed on
+//! purpose (& so are `forget`ten first), are never relaunched.
+
+use crate::{config::RestartPolicy, login::LaunchMode};
+use std::{
+    collections::BTreeMap,
+    process::ExitStatus,
+    time::{Duration, Instant},
+};
+
+/// Keeps track of instances & their restart policies, & of the relaunches
+/// that are due.
+#[derive(Debug, Default)]
+pub struct Supervisor {
+    /// By PID.
+    watched: BTreeMap<u32, Watched>,
+    pending: Vec<PendingRestart>,
+    /// By username.
+    history: BTreeMap<String, Vec<Restart>>,
+}
+
+#[derive(Clone, Debug)]
+struct Watched {
+    username: String,
+    /// `None` if the instance isn't supervised.
+    policy: Option<RestartPolicy>,
+    mode: LaunchMode,
+}
+
+/// An account whose instance is to be relaunched once `due`.
+#[derive(Clone, Debug)]
+pub struct PendingRestart {
+    pub username: String,
+    pub policy: RestartPolicy,
+    pub mode: LaunchMode,
+    pub due: Instant,
+}
+
+/// A crash of a supervised instance (or a failure to relaunch one).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Restart {
+    /// The PID of the instance that crashed, or `None` if this is about a
+    /// relaunch that failed.
+    pub pid: Option<u32>,
+    /// `None` if the instance was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub at: Instant,
+    /// The PID of the instance that replaced the one that crashed, once
+    /// there is one.
+    pub relaunched_pid: Option<u32>,
+}
+
+/// What a `Supervisor` decided to do about an instance that exited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// The instance wasn't supervised.
+    Unsupervised,
+    /// The instance exited normally, so there's no need to relaunch it.
+    Exited,
+    /// The instance will be relaunched in `delay`.
+    Restart { delay: Duration },
+    /// The instance has crashed too many times within its policy's window,
+    /// so it's being left alone.
+    GaveUp,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts keeping track of the instance with PID `pid`, which was
+    /// launched for `username` as `mode` says. It's only supervised if it
+    /// has a `policy`.
+    pub fn watch(
+        &mut self,
+        pid: u32,
+        username: String,
+        mode: LaunchMode,
+        policy: Option<RestartPolicy>,
+    ) {
+        self.watched.insert(
+            pid,
+            Watched {
+                username,
+                policy,
+                mode,
+            },
+        );
+    }
+
+    /// Stops keeping track of the instance with PID `pid`, e.g. because
+    /// it's being closed on purpose.
+    pub fn forget(&mut self, pid: u32) {
+        self.watched.remove(&pid);
+    }
+
+    /// Starts (or, if `policy` is `None`, stops) supervising the instance
+    /// with PID `pid`. Returns `false` if it isn't being kept track of.
+    pub fn set_policy(
+        &mut self,
+        pid: u32,
+        policy: Option<RestartPolicy>,
+    ) -> bool {
+        let Some(watched) = self.watched.get_mut(&pid) else {
+            return false;
+        };
+        watched.policy = policy;
+
+        true
+    }
+
+    pub fn is_supervised(&self, pid: u32) -> bool {
+        self.watched
+            .get(&pid)
+            .is_some_and(|watched| watched.policy.is_some())
+    }
+
+    /// Whether there is anything to keep an eye on.
+    pub fn is_active(&self) -> bool {
+        self.watched
+            .values()
+            .any(|watched| watched.policy.is_some())
+            || !self.pending.is_empty()
+    }
+
+    /// Decides what to do about the instance with PID `pid` having exited
+    /// with `status`, at `now`.
+    pub fn exited(
+        &mut self,
+        pid: u32,
+        status: ExitStatus,
+        now: Instant,
+    ) -> Decision {
+        let Some(watched) = self.watched.remove(&pid) else {
+            return Decision::Unsupervised;
+        };
+        let Some(policy) = watched.policy else {
+            return Decision::Unsupervised;
+        };
+        if status.success() {
+            return Decision::Exited;
+        }
+
+        self.crashed(
+            watched.username,
+            policy,
+            watched.mode,
+            Some(pid),
+            status.code(),
+            now,
+        )
+    }
+
+    /// Like `exited`, but for when relaunching `pending` failed.
+    pub fn relaunch_failed(
+        &mut self,
+        pending: PendingRestart,
+        now: Instant,
+    ) -> Decision {
+        let PendingRestart {
+            username,
+            policy,
+            mode,
+            ..
+        } = pending;
+
+        self.crashed(username, policy, mode, None, None, now)
+    }
+
+    fn crashed(
+        &mut self,
+        username: String,
+        policy: RestartPolicy,
+        mode: LaunchMode,
+        pid: Option<u32>,
+        exit_code: Option<i32>,
+        now: Instant,
+    ) -> Decision {
+        let window = Duration::from_secs(policy.window);
+        let history = self.history.entry(username.clone()).or_default();
+        let recent = history
+            .iter()
+            .filter(|restart| {
+                now.saturating_duration_since(restart.at) < window
+            })
+            .count();
+        history.push(Restart {
+            pid,
+            exit_code,
+            at: now,
+            relaunched_pid: None,
+        });
+        if recent >= policy.max_restarts as usize {
+            return Decision::GaveUp;
+        }
+
+        // Doubles with each recent crash, saturating rather than wrapping
+        // around to no delay at all after enough of them.
+        let factor = u32::try_from(recent)
+            .ok()
+            .and_then(|recent| 1u64.checked_shl(recent))
+            .unwrap_or(u64::MAX);
+        let delay = Duration::from_secs(
+            policy
+                .backoff
+                .saturating_mul(factor)
+                .min(policy.max_backoff),
+        );
+        self.pending.push(PendingRestart {
+            username,
+            policy,
+            mode,
+            due: now + delay,
+        });
+
+        Decision::Restart { delay }
+    }
+
+    /// Takes the relaunches that are due at `now`, in the order that they
+    /// became due.
+    pub fn due(&mut self, now: Instant) -> Vec<PendingRestart> {
+        let (mut due, pending) = self
+            .pending
+            .drain(..)
+            .partition::<Vec<_>, _>(|pending| pending.due <= now);
+        self.pending = pending;
+        due.sort_by_key(|pending| pending.due);
+
+        due
+    }
+
+    /// The relaunches that aren't due yet.
+    pub fn pending(&self) -> &[PendingRestart] {
+        &self.pending
+    }
+
+    /// `pending` was relaunched as the instance with PID `pid`, which is
+    /// now supervised in its stead.
+    pub fn relaunched(&mut self, pending: PendingRestart, pid: u32) {
+        if let Some(last) = self
+            .history
+            .get_mut(&pending.username)
+            .and_then(|history| history.last_mut())
+        {
+            last.relaunched_pid = Some(pid);
+        }
+
+        self.watch(pid, pending.username, pending.mode, Some(pending.policy));
+    }
+
+    /// Every crash of `username`'s supervised instances, oldest first.
+    pub fn history(&self, username: &str) -> &[Restart] {
+        self.history.get(username).map_or(&[], Vec::as_slice)
+    }
+}
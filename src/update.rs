@@ -1,8 +1,17 @@
-use crate::{config::Config, error::Error, patch, util};
-use bzip2::write::BzDecoder as BzWriteDecoder;
+use crate::{
+    config::Config,
+    decompress::Compression,
+    error::Error,
+    hash::{self, Hash, HashAlgorithm},
+    hash_index::HashIndex,
+    manifest::{self, Manifest},
+    observer::{Event, FileStatus, Observer, Silent},
+    patch, util,
+};
 use reqwest::blocking as rb;
-use sha1::{Digest, Sha1};
+use serde::{Deserialize, Serialize};
 use std::{
+    ffi::{OsStr, OsString},
     fs::{self, File},
     io::{self, prelude::*},
     num::NonZeroUsize,
@@ -10,479 +19,742 @@ use std::{
 };
 
 pub const BUFFER_SIZE: usize = 0x20_00;
-#[cfg(target_os = "linux")]
-pub const OS_AND_ARCH: &str = "linux2";
-#[cfg(target_os = "macos")]
-pub const OS_AND_ARCH: &str = "darwin";
-#[cfg(all(windows, target_arch = "x86_64"))]
-pub const OS_AND_ARCH: &str = "win64";
-#[cfg(all(windows, target_arch = "x86"))]
-pub const OS_AND_ARCH: &str = "win32";
-
-pub fn update(
-    config: &Config,
-    client: &rb::Client,
-    quiet: bool,
-    max_tries: NonZeroUsize,
-    dry: bool,
-) -> Result<(), Error> {
-    ensure_dir(&config.install_dir)?;
-    if !dry {
-        ensure_dir(&config.cache_dir)?;
+const STAGING_DIR_NAME: &str = "staging";
+
+/// A description of exactly what an update will do to each file, along with
+/// the hashes that each decision was based on. A plan can be written to a
+/// file, reviewed, & then applied later (possibly on another machine).
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Plan {
+    /// The OS & architecture that this plan was made for.
+    pub os_and_arch: String,
+    pub files: Vec<PlannedFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlannedFile {
+    pub file: String,
+    /// Hash of the local file that this plan was based on, or `None` if the
+    /// file didn't exist.
+    pub local_hash: Option<String>,
+    /// Hash that the file will have once the action has been carried out.
+    pub hash: String,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// The file is already up to date.
+    Skip,
+    Patch {
+        patch: String,
+        compression: Compression,
+        comp_patch_hash: String,
+        patch_hash: String,
+    },
+    Download {
+        dl: String,
+        compression: Compression,
+        comp_hash: String,
+    },
+}
+
+impl Plan {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        serde_json::from_reader(io::BufReader::new(util::open_file(path)?))
+            .map_err(Error::Deserialize)
     }
 
-    let serde_json::Value::Object(manifest_map) =
-        get_manifest(config, client, quiet, max_tries)?
-    else {
-        return Err(Error::BadManifestFormat(
-            "Top-level value is not an Object".to_owned(),
-        ));
-    };
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut plan_file = util::create_file(&path)?;
+        serde_json::to_writer_pretty(&mut plan_file, self)
+            .map_err(Error::Serialize)?;
+        plan_file
+            .write_all(b"\n")
+            .map_err(|ioe| Error::FileWrite(path.as_ref().to_path_buf(), ioe))
+    }
 
-    if !quiet {
-        println!("Downloaded manifest successfully!");
+    /// Names of the files that this plan will write to.
+    pub fn files_to_update(&self) -> impl Iterator<Item = &str> {
+        self.files
+            .iter()
+            .filter(|pf| pf.action != Action::Skip)
+            .map(|pf| pf.file.as_str())
     }
+}
 
-    let mut install_dir = config.install_dir.clone();
-    for (i, (file_name, file_obj)) in manifest_map.iter().enumerate() {
-        if !quiet {
-            println!(
-                "[{:2}/{}] Checking for updates for {file_name}",
-                i + 1,
-                manifest_map.len(),
-            );
-        }
+/// An update whose files have been fully downloaded, verified, and patched,
+/// but which have not yet been moved into the installation directory.
+#[derive(Debug)]
+pub struct StagedUpdate {
+    staging_dir: PathBuf,
+    files: Vec<String>,
+}
 
-        let serde_json::Value::Object(file_map) = file_obj else {
-            return Err(Error::BadManifestFormat(
-                "Expected Object at 2nd-to-top level".to_owned(),
-            ));
-        };
+impl StagedUpdate {
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
 
-        let serde_json::Value::Array(supported_archs) =
-            file_map.get("only").ok_or_else(|| {
-                Error::BadManifestFormat("Missing the \"only\" key".to_owned())
-            })?
-        else {
-            return Err(Error::BadManifestFormat(
-                "Expected \"only\"'s value to be an Array".to_owned(),
-            ));
-        };
-        let mut supported_by_this_arch = false;
-        for arch_val in supported_archs {
-            match arch_val {
-                serde_json::Value::String(s) => {
-                    if OS_AND_ARCH == s {
-                        supported_by_this_arch = true;
-
-                        break;
-                    }
-                }
-                _ => {
-                    return Err(Error::BadManifestFormat(
-                        "Expected OS & architecture values to be Strings"
-                            .to_owned(),
-                    ));
-                }
-            }
-        }
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
 
-        if !supported_by_this_arch {
-            if !quiet {
-                println!(
-                    "        Not supported by this OS & architecture, \
-                     skipping..."
-                );
-            }
+    /// Swaps the staged files into `config.install_dir`. This should only be
+    /// called when no game instances are running.
+    pub fn apply(
+        self,
+        config: &Config,
+        observer: &dyn Observer,
+    ) -> Result<(), Error> {
+        observer.on_event(&Event::StagedUpdateApplying {
+            files: self.files.len(),
+        });
+
+        for file_name in &self.files {
+            move_file(
+                util::safe_join(&self.staging_dir, file_name)?,
+                util::safe_join(&config.install_dir, file_name)?,
+            )?;
 
-            continue;
+            observer.on_event(&Event::StagedFileMoved { file: file_name });
         }
 
-        if !quiet {
-            println!("        Checking to see if file already exists...");
-        }
+        make_executable(config, observer)?;
 
-        install_dir.push(file_name);
+        observer.on_event(&Event::StagedUpdateApplied);
 
-        let already_existing_file = match File::open(&install_dir) {
-            Ok(f) => Some(f),
-            Err(ioe) => match ioe.kind() {
-                io::ErrorKind::NotFound => {
-                    if !quiet {
-                        if dry {
-                            println!(
-                                "        File doesn't exist! Suppressing \
-                                 download because this is a dry run."
-                            );
-                            install_dir.pop();
-
-                            continue;
-                        }
-
-                        println!(
-                            "        File doesn't exist; downloading from \
-                             scratch..."
-                        );
-                    }
-
-                    let mut file_buf = [0u8; BUFFER_SIZE];
-                    let compressed_file_name = file_map
-                        .get("dl")
-                        .ok_or_else(|| {
-                            Error::BadManifestFormat(
-                                "Expected \"dl\"".to_owned(),
-                            )
-                        })
-                        .and_then(|val| match val {
-                            serde_json::Value::String(s) => Ok(s),
-                            _ => Err(Error::BadManifestFormat(
-                                "Expected \"dl\" to be a String".to_owned(),
-                            )),
-                        })?;
-                    let compressed_sha = file_map
-                        .get("compHash")
-                        .ok_or_else(|| {
-                            Error::BadManifestFormat(
-                                "Expected \"compHash\"".to_owned(),
-                            )
-                        })
-                        .and_then(|val| match val {
-                            serde_json::Value::String(s) => {
-                                sha_from_hash_str(s)
-                            }
-                            _ => Err(Error::BadManifestFormat(
-                                "Expected \"compHash\" to be a String"
-                                    .to_owned(),
-                            )),
-                        })?;
-                    let decompressed_sha = file_map
-                        .get("hash")
-                        .ok_or_else(|| {
-                            Error::BadManifestFormat(
-                                "Expected \"hash\"".to_owned(),
-                            )
-                        })
-                        .and_then(|val| match val {
-                            serde_json::Value::String(s) => {
-                                sha_from_hash_str(s)
-                            }
-                            _ => Err(Error::BadManifestFormat(
-                                "Expected \"hash\" to be a String".to_owned(),
-                            )),
-                        })?;
-
-                    download_file(
-                        false,
-                        &mut file_buf,
-                        config,
-                        client,
-                        quiet,
-                        max_tries,
-                        compressed_file_name,
-                        file_name,
-                        &compressed_sha,
-                        &decompressed_sha,
-                    )?;
-
-                    None
-                }
-                io::ErrorKind::PermissionDenied => {
-                    return Err(Error::PermissionDenied(
-                        format!("opening {install_dir:?}"),
-                        ioe,
-                    ));
-                }
-                _ => {
-                    return Err(Error::UnknownIo(
-                        format!("opening {install_dir:?}"),
-                        ioe,
-                    ));
-                }
-            },
-        };
-        if let Some(f) = already_existing_file {
-            update_existing_file(
-                config,
-                client,
-                quiet,
-                dry,
-                max_tries,
-                f,
-                file_map,
-                file_name,
-                &install_dir,
-            )?;
+        Ok(())
+    }
+}
+
+/// Checks for, downloads, & applies updates to the game files in
+/// `config.install_dir`.
+///
+/// By default, an `Updater` tries network operations up to 5 times, and
+/// doesn't report progress anywhere.
+pub struct Updater<'a> {
+    config: &'a Config,
+    client: &'a rb::Client,
+    max_tries: NonZeroUsize,
+    observer: &'a dyn Observer,
+}
+
+impl<'a> Updater<'a> {
+    pub fn new(config: &'a Config, client: &'a rb::Client) -> Self {
+        Self {
+            config,
+            client,
+            max_tries: NonZeroUsize::new(5).unwrap(),
+            observer: &Silent,
         }
+    }
 
-        install_dir.pop();
+    /// Number of times to try each network operation before giving up.
+    pub fn max_tries(mut self, max_tries: NonZeroUsize) -> Self {
+        self.max_tries = max_tries;
+
+        self
     }
 
-    #[cfg(unix)]
-    {
-        if dry {
-            return Ok(());
-        }
+    /// Reports progress to `observer`.
+    pub fn observer(mut self, observer: &'a dyn Observer) -> Self {
+        self.observer = observer;
 
-        use std::os::unix::fs::PermissionsExt;
+        self
+    }
 
-        #[cfg(target_os = "linux")]
-        const EXE_NAME: &str = "TTREngine";
-        #[cfg(target_os = "macos")]
-        const EXE_NAME: &str = "Toontown Rewritten";
+    /// Brings `config.install_dir` up to date, or, if `dry` is `true`, only
+    /// checks which files need updating.
+    pub fn update(&self, dry: bool) -> Result<(), Error> {
+        update(self.config, self.client, self.observer, self.max_tries, dry)
+    }
 
-        if !quiet {
-            println!("Making sure {EXE_NAME} is executable...");
-        }
+    /// Like `update`, but never writes to `config.install_dir`. Instead,
+    /// updated files are written to a staging directory inside of
+    /// `config.cache_dir`, so that this is safe to do while the game is
+    /// running.
+    pub fn stage(&self) -> Result<StagedUpdate, Error> {
+        stage_update(self.config, self.client, self.observer, self.max_tries)
+    }
 
-        install_dir.push(EXE_NAME);
-        let mut ttrengine_perms = fs::metadata(&install_dir)
-            .map_err(|ioe| match ioe.kind() {
-                io::ErrorKind::NotFound => Error::MissingFile(EXE_NAME),
-                io::ErrorKind::PermissionDenied => Error::PermissionDenied(
-                    format!("obtaining metadata for {install_dir:?}"),
-                    ioe,
-                ),
-                _ => Error::UnknownIo(
-                    format!("obtaining metadata for {install_dir:?}"),
-                    ioe,
-                ),
-            })?
-            .permissions();
-        let ttrengine_mode = ttrengine_perms.mode();
-        if (ttrengine_mode & 0o100) == 0 {
-            if !quiet {
-                println!(
-                    "{EXE_NAME} isn't executable; setting executable bit..."
-                );
-            }
+    /// Checks the files in `config.install_dir` against the manifest,
+    /// without downloading nor writing anything other than the manifest
+    /// itself.
+    pub fn plan(&self) -> Result<Plan, Error> {
+        plan(
+            self.config,
+            self.client,
+            self.observer,
+            self.max_tries,
+            false,
+        )
+    }
 
-            ttrengine_perms.set_mode(ttrengine_mode | 0o700);
-            fs::set_permissions(&install_dir, ttrengine_perms)
-                .map_err(|ioe| Error::PermissionsSet(install_dir, ioe))?;
+    /// Quickly checks the files in `config.install_dir` against the
+    /// manifest, without hashing the files that haven't changed since they
+    /// were last hashed. Returns the names of the files that need updating.
+    pub fn check(&self) -> Result<Vec<String>, Error> {
+        check(self.config, self.client, self.observer, self.max_tries)
+    }
 
-            if !quiet {
-                println!("{EXE_NAME} is now executable!");
-            }
-        } else if !quiet {
-            println!("{EXE_NAME} is already executable!");
-        }
+    /// Carries out `plan`, writing directly into `config.install_dir`.
+    pub fn apply_plan(&self, plan: &Plan) -> Result<(), Error> {
+        apply_plan(
+            self.config,
+            self.client,
+            self.observer,
+            self.max_tries,
+            plan,
+            false,
+        )
     }
 
-    Ok(())
+    /// Like `apply_plan`, but writes into a staging directory, like `stage`
+    /// does.
+    pub fn stage_plan(&self, plan: &Plan) -> Result<StagedUpdate, Error> {
+        stage_plan(
+            self.config,
+            self.client,
+            self.observer,
+            self.max_tries,
+            plan,
+            false,
+        )
+    }
 }
 
-#[allow(clippy::too_many_arguments)]
-fn update_existing_file<S: AsRef<str>, P: AsRef<Path>>(
+fn update(
     config: &Config,
     client: &rb::Client,
-    quiet: bool,
-    dry: bool,
+    observer: &dyn Observer,
     max_tries: NonZeroUsize,
-    mut already_existing_file: File,
-    file_map: &serde_json::Map<String, serde_json::Value>,
-    file_name: S,
-    full_file_path: P,
+    dry: bool,
 ) -> Result<(), Error> {
-    if !quiet {
-        println!("        File exists; checking SHA-1 hash...");
+    let plan = plan(config, client, observer, max_tries, dry)?;
+
+    if dry {
+        let needs_update: Vec<_> =
+            plan.files_to_update().map(str::to_owned).collect();
+        observer.on_event(&Event::UpdateFinished {
+            dry: true,
+            staged: false,
+            needs_update: &needs_update,
+        });
+
+        Ok(())
+    } else {
+        apply_plan(config, client, observer, max_tries, &plan, true)
     }
+}
 
-    let mut file_buf = [0u8; BUFFER_SIZE];
-    let initial_sha = sha_of_reader(&mut already_existing_file, &mut file_buf)
-        .map_err(|ioe| {
-            Error::FileRead(full_file_path.as_ref().to_path_buf(), ioe)
-        })?;
+fn stage_update(
+    config: &Config,
+    client: &rb::Client,
+    observer: &dyn Observer,
+    max_tries: NonZeroUsize,
+) -> Result<StagedUpdate, Error> {
+    let plan = plan(config, client, observer, max_tries, false)?;
 
-    let manifest_sha = sha_from_hash_str(match file_map.get("hash") {
-        Some(serde_json::Value::String(s)) => s,
-        Some(_) => {
-            return Err(Error::BadManifestFormat(
-                "Value of \"hash\" was not a String".to_owned(),
-            ));
-        }
-        _ => {
-            return Err(Error::BadManifestFormat(
-                "\"hash\" key missing".to_owned(),
-            ));
-        }
-    })?;
+    stage_plan(config, client, observer, max_tries, &plan, true)
+}
 
-    if initial_sha == manifest_sha {
-        if !quiet {
-            println!("        SHA-1 hash matches!");
-        }
+/// Unless `dry` is `true`, the hashes that this comes up with are saved in
+/// the hash index for next time.
+fn plan(
+    config: &Config,
+    client: &rb::Client,
+    observer: &dyn Observer,
+    max_tries: NonZeroUsize,
+    dry: bool,
+) -> Result<Plan, Error> {
+    ensure_dir(&config.install_dir)?;
 
-        return Ok(());
-    }
+    let manifest = Manifest::from_json(get_manifest(
+        config, client, observer, max_tries,
+    )?)?;
 
-    if !quiet {
-        print!("        SHA-1 hash mismatch:\n          Local:    ");
-        for b in &initial_sha {
-            print!("{b:02x}");
-        }
-        print!("\n          Manifest: ");
-        for b in &manifest_sha {
-            print!("{b:02x}");
-        }
-        println!("\n        Checking for a patch...");
-    }
-
-    let patches_map = file_map
-        .get("patches")
-        .and_then(|val| match val {
-            serde_json::Value::Object(m) => Some(m),
-            _ => None,
-        })
-        .ok_or_else(|| {
-            Error::BadManifestFormat(format!(
-                "Expected \"patches\" key with Object value to be in the \
-                 \"{}\" Object",
-                file_name.as_ref(),
-            ))
-        })?;
+    observer.on_event(&Event::ManifestFetched {
+        files: manifest.files.len(),
+    });
+
+    let mut index = HashIndex::load(config);
+    let mut file_buf = [0u8; BUFFER_SIZE];
+    let mut planned_files = Vec::with_capacity(manifest.files.len());
+    for (i, file) in manifest.files.iter().enumerate() {
+        observer.on_event(&Event::FileChecking {
+            file: &file.name,
+            index: i + 1,
+            total: manifest.files.len(),
+        });
+
+        if !file.supported_by_this_arch() {
+            observer.on_event(&Event::FileStatus {
+                file: &file.name,
+                status: FileStatus::Unsupported,
+            });
 
-    let mut did_patch = false;
-    for (manifest_sha_str, patch_obj) in patches_map {
-        if sha_from_hash_str(manifest_sha_str)? != initial_sha {
             continue;
         }
 
-        let serde_json::Value::Object(patch_map) = patch_obj else {
-            return Err(Error::BadManifestFormat(
-                "Expected \"patches\" to be objects".to_owned(),
-            ));
+        // Never trusting the index here, but keeping it up to date.
+        let local_hashes = indexed_local_hashes(
+            &mut index,
+            false,
+            util::safe_join(&config.install_dir, &file.name)?,
+            &mut file_buf,
+            &file.hash_algorithms(),
+            &file.name,
+            observer,
+        )?;
+        // The local hash that the plan's action is based on.
+        let mut local_hash = local_hashes.as_ref().map(|hs| hs[0].clone());
+        let (status, action) = match &local_hashes {
+            None => (
+                FileStatus::Missing,
+                Action::Download {
+                    dl: file.dl.clone(),
+                    compression: file.compression,
+                    comp_hash: file.comp_hash.to_string(),
+                },
+            ),
+            Some(hashes) if hashes[0] == file.hash => {
+                observer.on_event(&Event::HashMatched {
+                    file: &file.name,
+                    algorithm: file.hash.algorithm(),
+                });
+
+                (FileStatus::UpToDate, Action::Skip)
+            }
+            Some(hashes) => {
+                observer.on_event(&Event::HashMismatch {
+                    file: &file.name,
+                    local: &hashes[0],
+                    expected: &file.hash,
+                });
+
+                if let Some(patch) = file.patch_from(hashes) {
+                    local_hash = Some(patch.from_hash.clone());
+
+                    (
+                        FileStatus::Outdated {
+                            patch_available: true,
+                        },
+                        Action::Patch {
+                            patch: patch.filename.clone(),
+                            compression: patch.compression,
+                            comp_patch_hash: patch.comp_patch_hash.to_string(),
+                            patch_hash: patch.patch_hash.to_string(),
+                        },
+                    )
+                } else {
+                    (
+                        FileStatus::Outdated {
+                            patch_available: false,
+                        },
+                        Action::Download {
+                            dl: file.dl.clone(),
+                            compression: file.compression,
+                            comp_hash: file.comp_hash.to_string(),
+                        },
+                    )
+                }
+            }
         };
 
-        let patch_file_name = patch_map
-            .get("filename")
-            .and_then(|val| match val {
-                serde_json::Value::String(s) => Some(s),
-                _ => None,
-            })
-            .ok_or_else(|| {
-                Error::BadManifestFormat(
-                    "Expected \"filename\" key in patch Object".to_owned(),
-                )
-            })?;
+        observer.on_event(&Event::FileStatus {
+            file: &file.name,
+            status,
+        });
+        planned_files.push(PlannedFile {
+            file: file.name.clone(),
+            local_hash: local_hash.as_ref().map(Hash::to_string),
+            hash: file.hash.to_string(),
+            action,
+        });
+    }
 
-        if !quiet {
-            if dry {
-                println!(
-                    "        Found a patch! Suppressed downloading patch \
-                     because this is a dry run."
-                );
+    if !dry {
+        index.save();
+    }
 
-                return Ok(());
-            }
+    Ok(Plan {
+        os_and_arch: manifest::OS_AND_ARCH.to_owned(),
+        files: planned_files,
+    })
+}
 
-            println!("        Found a patch! Downloading it...");
-        }
+fn check(
+    config: &Config,
+    client: &rb::Client,
+    observer: &dyn Observer,
+    max_tries: NonZeroUsize,
+) -> Result<Vec<String>, Error> {
+    let manifest = Manifest::from_json(get_manifest(
+        config, client, observer, max_tries,
+    )?)?;
 
-        let mut extracted_patch_file_name =
-            String::with_capacity(patch_file_name.len() + ".extracted".len());
-        extracted_patch_file_name += patch_file_name;
-        extracted_patch_file_name += ".extracted";
-        let extracted_patch_path = download_file(
+    observer.on_event(&Event::ManifestFetched {
+        files: manifest.files.len(),
+    });
+
+    let mut index = HashIndex::load(config);
+    let mut file_buf = [0u8; BUFFER_SIZE];
+    let mut outdated = Vec::new();
+    for file in manifest.files.iter().filter(|f| f.supported_by_this_arch()) {
+        let local_hashes = indexed_local_hashes(
+            &mut index,
             true,
+            util::safe_join(&config.install_dir, &file.name)?,
             &mut file_buf,
-            config,
-            client,
-            quiet,
-            max_tries,
-            patch_file_name,
-            &extracted_patch_file_name,
-            &patch_map
-                .get("compPatchHash")
-                .ok_or_else(|| {
-                    Error::BadManifestFormat(
-                        "Expected \"compPatchHash\"".to_owned(),
-                    )
-                })
-                .and_then(|val| match val {
-                    serde_json::Value::String(s) => sha_from_hash_str(s),
-                    _ => Err(Error::BadManifestFormat(
-                        "Expected \"compPatchHash\" to be a String".to_owned(),
-                    )),
-                })?,
-            &patch_map
-                .get("patchHash")
-                .ok_or_else(|| {
-                    Error::BadManifestFormat(
-                        "Expected \"patchHash\"".to_owned(),
-                    )
-                })
-                .and_then(|val| match val {
-                    serde_json::Value::String(s) => sha_from_hash_str(s),
-                    _ => Err(Error::BadManifestFormat(
-                        "Expected \"patchHash\" to be a String".to_owned(),
-                    )),
-                })?,
+            &[file.hash.algorithm()],
+            &file.name,
+            observer,
         )?;
-
-        if !quiet {
-            println!("        Applying patch...");
+        if local_hashes.is_none_or(|hashes| hashes[0] != file.hash) {
+            outdated.push(file.name.clone());
         }
+    }
+
+    index.save();
+
+    Ok(outdated)
+}
+
+fn apply_plan(
+    config: &Config,
+    client: &rb::Client,
+    observer: &dyn Observer,
+    max_tries: NonZeroUsize,
+    plan: &Plan,
+    fresh: bool,
+) -> Result<(), Error> {
+    let files = execute_plan(
+        config,
+        client,
+        observer,
+        max_tries,
+        plan,
+        fresh,
+        &config.install_dir,
+    )?;
+
+    make_executable(config, observer)?;
+
+    observer.on_event(&Event::UpdateFinished {
+        dry: false,
+        staged: false,
+        needs_update: &files,
+    });
+
+    Ok(())
+}
+
+fn stage_plan(
+    config: &Config,
+    client: &rb::Client,
+    observer: &dyn Observer,
+    max_tries: NonZeroUsize,
+    plan: &Plan,
+    fresh: bool,
+) -> Result<StagedUpdate, Error> {
+    let staging_dir = config.cache_dir.join(STAGING_DIR_NAME);
+    // Anything left over from a previous staging is stale by now.
+    if let Err(ioe) = fs::remove_dir_all(&staging_dir)
+        && ioe.kind() != io::ErrorKind::NotFound
+    {
+        return Err(Error::RemoveFile(staging_dir, ioe));
+    }
+    ensure_dir(&staging_dir)?;
+
+    let files = execute_plan(
+        config,
+        client,
+        observer,
+        max_tries,
+        plan,
+        fresh,
+        &staging_dir,
+    )?;
+
+    observer.on_event(&Event::UpdateFinished {
+        dry: false,
+        staged: true,
+        needs_update: &files,
+    });
+
+    Ok(StagedUpdate { staging_dir, files })
+}
+
+/// Reads files from `config.install_dir`, and writes the updated versions to
+/// `dest_dir`. Unless the plan was `fresh`ly made (i.e. its hashes were only
+/// just taken), refuses to do anything at all if any of the files that the
+/// plan would touch no longer have the hashes that the plan was based on.
+/// Returns the names of the files that were written.
+fn execute_plan(
+    config: &Config,
+    client: &rb::Client,
+    observer: &dyn Observer,
+    max_tries: NonZeroUsize,
+    plan: &Plan,
+    fresh: bool,
+    dest_dir: &Path,
+) -> Result<Vec<String>, Error> {
+    if plan.os_and_arch != manifest::OS_AND_ARCH {
+        return Err(Error::BadPlan(format!(
+            "Plan was made for {}, but this is {}",
+            plan.os_and_arch,
+            manifest::OS_AND_ARCH,
+        )));
+    }
 
-        patch::patch_file(&extracted_patch_path, full_file_path)?;
+    ensure_dir(&config.install_dir)?;
+    ensure_dir(&config.cache_dir)?;
 
-        if !quiet {
-            println!("        File patched successfully!");
+    let mut file_buf = [0u8; BUFFER_SIZE];
+
+    let to_update = plan.files_to_update().count();
+    if !fresh && to_update > 0 {
+        observer.on_event(&Event::PlanVerifying);
+    }
+    for planned_file in &plan.files {
+        if fresh || planned_file.action == Action::Skip {
+            continue;
         }
 
-        did_patch = true;
+        let full_file_path =
+            util::safe_join(&config.install_dir, &planned_file.file)?;
+        let expected_hash = planned_file
+            .local_hash
+            .as_ref()
+            .map(plan_hash)
+            .transpose()?;
+        let algs: &[_] = match &expected_hash {
+            Some(h) => &[h.algorithm()],
+            None => &[],
+        };
+        let actual_hash = local_hashes(
+            &full_file_path,
+            &mut file_buf,
+            algs,
+            &planned_file.file,
+            &Silent,
+        )?
+        .map(|hs| hs.into_iter().next());
+        if actual_hash != expected_hash.map(Some) {
+            return Err(Error::PlanOutdated(full_file_path));
+        }
+    }
 
-        break;
+    let mut written_files = Vec::with_capacity(to_update);
+    for (i, planned_file) in plan
+        .files
+        .iter()
+        .filter(|pf| pf.action != Action::Skip)
+        .enumerate()
+    {
+        let file_name = &planned_file.file;
+        let hash = plan_hash(&planned_file.hash)?;
+        match &planned_file.action {
+            Action::Skip => continue,
+            Action::Patch {
+                patch,
+                compression,
+                comp_patch_hash,
+                patch_hash,
+            } => {
+                observer.on_event(&Event::Patching {
+                    file: file_name,
+                    index: i + 1,
+                    total: to_update,
+                });
+
+                let mut extracted_patch_file_name =
+                    String::with_capacity(patch.len() + ".extracted".len());
+                extracted_patch_file_name += patch;
+                extracted_patch_file_name += ".extracted";
+                let extracted_patch_path = download_file(
+                    &config.cache_dir,
+                    &mut file_buf,
+                    config,
+                    client,
+                    observer,
+                    max_tries,
+                    *compression,
+                    patch,
+                    &extracted_patch_file_name,
+                    &plan_hash(comp_patch_hash)?,
+                    &plan_hash(patch_hash)?,
+                )?;
+
+                let dest_path = util::safe_join(dest_dir, file_name)?;
+                patch::patch_file(
+                    &extracted_patch_path,
+                    util::safe_join(&config.install_dir, file_name)?,
+                    &dest_path,
+                    config.max_patch_output_size,
+                    &hash,
+                )?;
+
+                observer.on_event(&Event::PatchApplied { file: file_name });
+
+                observer.on_event(&Event::FileStatus {
+                    file: file_name,
+                    status: FileStatus::Patched,
+                });
+            }
+            Action::Download {
+                dl,
+                compression,
+                comp_hash,
+            } => {
+                observer.on_event(&Event::FileDownloading {
+                    file: file_name,
+                    index: i + 1,
+                    total: to_update,
+                });
+
+                download_file(
+                    dest_dir,
+                    &mut file_buf,
+                    config,
+                    client,
+                    observer,
+                    max_tries,
+                    *compression,
+                    dl,
+                    file_name,
+                    &plan_hash(comp_hash)?,
+                    &hash,
+                )?;
+                observer.on_event(&Event::FileStatus {
+                    file: file_name,
+                    status: FileStatus::Downloaded,
+                });
+            }
+        }
+
+        written_files.push(file_name.clone());
     }
 
-    if !did_patch {
-        if !quiet {
-            if dry {
-                println!(
-                    "        No patches found! Suppressing download because \
-                     this is a dry run."
-                );
+    Ok(written_files)
+}
 
-                return Ok(());
+fn plan_hash<S: AsRef<str>>(hash_str: S) -> Result<Hash, Error> {
+    Hash::from_hex(hash_str.as_ref()).map_err(Error::BadPlan)
+}
+
+/// Hashes the file at `path` using each of `algs`, returning the hashes in
+/// the same order. Returns `None` if there is no file at `path`.
+fn local_hashes<P: AsRef<Path>>(
+    path: P,
+    buf: &mut [u8],
+    algs: &[HashAlgorithm],
+    file_name: &str,
+    observer: &dyn Observer,
+) -> Result<Option<Vec<Hash>>, Error> {
+    match File::open(&path) {
+        Ok(mut f) => {
+            if let Some(&algorithm) = algs.first() {
+                observer.on_event(&Event::FileHashing {
+                    file: file_name,
+                    algorithm,
+                });
             }
 
-            println!("        No patches found; downloading from scratch...");
+            hash::hash_reader(&mut f, buf, algs)
+                .map(Some)
+                .map_err(|ioe| {
+                    Error::FileRead(path.as_ref().to_path_buf(), ioe)
+                })
         }
+        Err(ioe) => match ioe.kind() {
+            io::ErrorKind::NotFound => Ok(None),
+            io::ErrorKind::PermissionDenied => Err(Error::PermissionDenied(
+                format!("opening {:?}", path.as_ref()),
+                ioe,
+            )),
+            _ => Err(Error::UnknownIo(
+                format!("opening {:?}", path.as_ref()),
+                ioe,
+            )),
+        },
+    }
+}
 
-        let compressed_file_name = file_map
-            .get("dl")
-            .ok_or_else(|| {
-                Error::BadManifestFormat("Expected \"dl\"".to_owned())
-            })
-            .and_then(|val| match val {
-                serde_json::Value::String(s) => Ok(s),
-                _ => Err(Error::BadManifestFormat(
-                    "Expected \"dl\" to be a String".to_owned(),
-                )),
-            })?;
-        let compressed_sha = file_map
-            .get("compHash")
-            .ok_or_else(|| {
-                Error::BadManifestFormat("Expected \"compHash\"".to_owned())
-            })
-            .and_then(|val| match val {
-                serde_json::Value::String(s) => sha_from_hash_str(s),
-                _ => Err(Error::BadManifestFormat(
-                    "Expected \"compHash\" to be a String".to_owned(),
-                )),
-            })?;
+/// Like `local_hashes`, but also keeps `index` up to date, & if `use_index`
+/// is `true`, then doesn't bother hashing the file if `index` already knows
+/// its hashes.
+#[allow(clippy::too_many_arguments)]
+fn indexed_local_hashes<P: AsRef<Path>>(
+    index: &mut HashIndex,
+    use_index: bool,
+    path: P,
+    buf: &mut [u8],
+    algs: &[HashAlgorithm],
+    file_name: &str,
+    observer: &dyn Observer,
+) -> Result<Option<Vec<Hash>>, Error> {
+    // From before hashing, so that the file being modified while it's being
+    // hashed can't leave a stale entry in the index.
+    let metadata = fs::metadata(&path).ok();
+    if use_index
+        && let Some(metadata) = &metadata
+        && let Some(hashes) = index.get(file_name, metadata, algs)
+    {
+        return Ok(Some(hashes));
+    }
 
-        download_file(
-            false,
-            &mut file_buf,
-            config,
-            client,
-            quiet,
-            max_tries,
-            compressed_file_name,
-            file_name,
-            &compressed_sha,
-            &manifest_sha,
-        )?;
+    let hashes = local_hashes(path, buf, algs, file_name, observer)?;
+    match (&metadata, &hashes) {
+        (Some(metadata), Some(hashes)) => {
+            index.insert(file_name, metadata, hashes);
+        }
+        _ => index.remove(file_name),
+    }
+
+    Ok(hashes)
+}
+
+fn make_executable(
+    config: &Config,
+    observer: &dyn Observer,
+) -> Result<(), Error> {
+    #[cfg(not(unix))]
+    let _ = (config, observer);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let exe_path = config.game_executable_path();
+        let mut ttrengine_perms = fs::metadata(&exe_path)
+            .map_err(|ioe| match ioe.kind() {
+                io::ErrorKind::NotFound => {
+                    Error::MissingFile(exe_path.clone())
+                }
+                io::ErrorKind::PermissionDenied => Error::PermissionDenied(
+                    format!("obtaining metadata for {exe_path:?}"),
+                    ioe,
+                ),
+                _ => Error::UnknownIo(
+                    format!("obtaining metadata for {exe_path:?}"),
+                    ioe,
+                ),
+            })?
+            .permissions();
+        let ttrengine_mode = ttrengine_perms.mode();
+        if (ttrengine_mode & 0o100) == 0 {
+            ttrengine_perms.set_mode(ttrengine_mode | 0o700);
+            fs::set_permissions(&exe_path, ttrengine_perms)
+                .map_err(|ioe| Error::PermissionsSet(exe_path, ioe))?;
+
+            observer.on_event(&Event::MadeExecutable {
+                file: &config.game_executable.to_string_lossy(),
+            });
+        }
     }
 
     Ok(())
@@ -491,27 +763,26 @@ fn update_existing_file<S: AsRef<str>, P: AsRef<Path>>(
 fn get_manifest(
     config: &Config,
     client: &rb::Client,
-    quiet: bool,
+    observer: &dyn Observer,
     max_tries: NonZeroUsize,
 ) -> Result<serde_json::Value, Error> {
     let mut last_err = None;
 
     for i in 1..=max_tries.get() {
         let mut handle_retry = |e| {
-            eprintln!(
-                "{e}{}",
-                if i < max_tries.get() {
-                    "; retrying..."
-                } else {
-                    "; no more attempts remaining!"
-                },
-            );
+            observer.on_event(&Event::Retry {
+                file: None,
+                attempt: i,
+                max_tries,
+                error: &e,
+            });
             last_err = Some(e);
         };
 
-        if !quiet {
-            println!("Downloading manifest [attempt {i}/{max_tries}]...");
-        }
+        observer.on_event(&Event::ManifestDownloading {
+            attempt: i,
+            max_tries,
+        });
 
         let manifest_resp = match client
             .get(&config.manifest_uri)
@@ -551,78 +822,34 @@ fn get_manifest(
     Err(last_err.unwrap_or_else(|| unreachable!()))
 }
 
-fn sha_of_reader<R: Read>(
-    r: &mut R,
-    buf: &mut [u8],
-) -> Result<[u8; 20], io::Error> {
-    let mut sha = Sha1::default();
-    let mut n = buf.len();
-    while n == buf.len() {
-        n = r.read(buf)?;
-        sha.update(&buf[..n]);
-    }
-
-    Ok(sha.finalize().into())
-}
-
-fn sha_of_file_by_path<P: AsRef<Path>>(
+fn hash_of_file_by_path<P: AsRef<Path>>(
     path: P,
     buf: &mut [u8],
-) -> Result<[u8; 20], Error> {
+    alg: HashAlgorithm,
+) -> Result<Hash, Error> {
     let mut file = util::open_file(&path)?;
 
-    let mut sha = Sha1::default();
-    let mut n = buf.len();
-    while n == buf.len() {
-        n = file.read(buf).map_err(|ioe| {
-            Error::FileRead(path.as_ref().to_path_buf(), ioe)
-        })?;
-        sha.update(&buf[..n]);
-    }
-
-    Ok(sha.finalize().into())
+    hash::hash_reader(&mut file, buf, &[alg])
+        .map(|hs| hs.into_iter().next().unwrap())
+        .map_err(|ioe| Error::FileRead(path.as_ref().to_path_buf(), ioe))
 }
 
-fn sha_from_hash_str<S: AsRef<str>>(hash_str: S) -> Result<[u8; 20], Error> {
-    let mut manifest_sha = [0u8; 20];
-    for (i, &b) in hash_str.as_ref().as_bytes().iter().enumerate() {
-        let nibble_val = match b {
-            b if b.is_ascii_digit() => b - b'0',
-            b'a' | b'A' => 0x0a,
-            b'b' | b'B' => 0x0b,
-            b'c' | b'C' => 0x0c,
-            b'd' | b'D' => 0x0d,
-            b'e' | b'E' => 0x0e,
-            b'f' | b'F' => 0x0f,
-            _ => {
-                return Err(Error::BadManifestFormat(format!(
-                    "Unexpected character in SHA-1 hash string: {:?}",
-                    b as char,
-                )));
-            }
-        };
-
-        manifest_sha[i / 2] |= nibble_val << if i % 2 == 0 { 4 } else { 0 };
-    }
-
-    Ok(manifest_sha)
-}
-
-/// Downloads to the cache if `to_cache`, otherwise downloads to the main
+/// Downloads into `dest_dir`, which is typically either the cache or the main
 /// installation directory. Returns the full path to the downloaded file on
 /// success.
 #[allow(clippy::too_many_arguments)]
 fn download_file<S: AsRef<str>, T: AsRef<str>>(
-    to_cache: bool,
+    dest_dir: &Path,
     buf: &mut [u8],
     config: &Config,
     client: &rb::Client,
-    quiet: bool,
+    observer: &dyn Observer,
     max_tries: NonZeroUsize,
+    compression: Compression,
     compressed_file_name: S,
     decompressed_file_name: T,
-    compressed_sha: &[u8; 20],
-    decompressed_sha: &[u8; 20],
+    compressed_hash: &Hash,
+    decompressed_hash: &Hash,
 ) -> Result<PathBuf, Error> {
     let mut dl_uri = String::with_capacity(
         config.cdn_uri.len() + compressed_file_name.as_ref().len(),
@@ -630,48 +857,29 @@ fn download_file<S: AsRef<str>, T: AsRef<str>>(
     dl_uri += &config.cdn_uri;
     dl_uri += compressed_file_name.as_ref();
 
-    let compressed_file_path = {
-        let mut pb = if to_cache {
-            config.cache_dir.clone()
-        } else {
-            config.install_dir.clone()
-        };
-        pb.push(compressed_file_name.as_ref());
-
-        pb
-    };
-    let decompressed_file_path = {
-        let mut pb = if to_cache {
-            config.cache_dir.clone()
-        } else {
-            config.install_dir.clone()
-        };
-        pb.push(decompressed_file_name.as_ref());
-
-        pb
-    };
+    let compressed_file_path =
+        util::safe_join(dest_dir, compressed_file_name.as_ref())?;
+    let decompressed_file_path =
+        util::safe_join(dest_dir, decompressed_file_name.as_ref())?;
 
     let mut last_err = None;
 
     for i in 1..=max_tries.get() {
         let mut handle_retry = |e| {
-            eprintln!(
-                "        {e}{}",
-                if i < max_tries.get() {
-                    "; retrying..."
-                } else {
-                    "; no more attempts remaining!"
-                },
-            );
+            observer.on_event(&Event::Retry {
+                file: Some(compressed_file_name.as_ref()),
+                attempt: i,
+                max_tries,
+                error: &e,
+            });
             last_err = Some(e);
         };
 
-        if !quiet {
-            println!(
-                "        Downloading {} [attempt {i}/{max_tries}]",
-                compressed_file_name.as_ref(),
-            );
-        }
+        observer.on_event(&Event::Download {
+            file: compressed_file_name.as_ref(),
+            attempt: i,
+            max_tries,
+        });
 
         let mut dl_resp =
             match client.get(&dl_uri).send().map_err(Error::DownloadRequest) {
@@ -689,76 +897,76 @@ fn download_file<S: AsRef<str>, T: AsRef<str>>(
         }
 
         {
-            let mut dled_file = util::create_file(&compressed_file_path)?;
+            let total = dl_resp.content_length();
+            let mut dled_file = ProgressWriter {
+                inner: util::create_file(&compressed_file_path)?,
+                observer,
+                file: compressed_file_name.as_ref(),
+                bytes: 0,
+                total,
+            };
             dl_resp.copy_to(&mut dled_file).map_err(|re| {
                 Error::CopyIntoFile(compressed_file_path.clone(), re)
             })?;
         }
 
-        if !quiet {
-            println!(
-                "        Checking SHA-1 hash of {}",
-                compressed_file_name.as_ref(),
-            );
-        }
-
-        let dled_sha = sha_of_file_by_path(&compressed_file_path, buf)?;
-        if &dled_sha != compressed_sha {
-            if !quiet {
-                eprint!("        SHA-1 hash mismatch:\n          Local:    ");
-                for b in &dled_sha {
-                    eprint!("{b:02x}");
-                }
-                eprint!("\n          Manifest: ");
-                for b in compressed_sha {
-                    eprint!("{b:02x}");
-                }
-                eprintln!("\n        Redownloading...");
-            }
-
+        let dled_hash = hash_of_file_by_path(
+            &compressed_file_path,
+            buf,
+            compressed_hash.algorithm(),
+        )?;
+        if &dled_hash != compressed_hash {
+            observer.on_event(&Event::HashMismatch {
+                file: compressed_file_name.as_ref(),
+                local: &dled_hash,
+                expected: compressed_hash,
+            });
             last_err = Some(Error::HashMismatch(
                 compressed_file_path.clone(),
-                *compressed_sha,
+                compressed_hash.clone(),
             ));
 
             continue;
         }
 
-        if !quiet {
-            println!("        SHA-1 hash matches! Extracting...");
-        }
-
-        decompress_file(buf, &compressed_file_path, &decompressed_file_path)?;
-
-        if !quiet {
-            println!("        Checking SHA-1 hash of extracted file...");
-        }
-
-        let extracted_sha = sha_of_file_by_path(&decompressed_file_path, buf)?;
-        if &extracted_sha != decompressed_sha {
-            if !quiet {
-                eprint!("        SHA-1 hash mismatch:\n          Local:    ");
-                for b in &extracted_sha {
-                    eprint!("{b:02x}");
-                }
-                eprint!("\n          Manifest: ");
-                for b in decompressed_sha {
-                    eprint!("{b:02x}");
-                }
-                eprintln!("\n        Redownloading...");
-            }
+        observer.on_event(&Event::HashMatched {
+            file: compressed_file_name.as_ref(),
+            algorithm: compressed_hash.algorithm(),
+        });
+        observer.on_event(&Event::Extracting {
+            file: compressed_file_name.as_ref(),
+        });
+
+        decompress_file(
+            buf,
+            compression,
+            &compressed_file_path,
+            &decompressed_file_path,
+        )?;
 
+        let extracted_hash = hash_of_file_by_path(
+            &decompressed_file_path,
+            buf,
+            decompressed_hash.algorithm(),
+        )?;
+        if &extracted_hash != decompressed_hash {
+            observer.on_event(&Event::HashMismatch {
+                file: decompressed_file_name.as_ref(),
+                local: &extracted_hash,
+                expected: decompressed_hash,
+            });
             last_err = Some(Error::HashMismatch(
                 decompressed_file_path.clone(),
-                *decompressed_sha,
+                decompressed_hash.clone(),
             ));
 
             continue;
         }
 
-        if !quiet {
-            println!("        SHA-1 hash matches!");
-        }
+        observer.on_event(&Event::HashMatched {
+            file: decompressed_file_name.as_ref(),
+            algorithm: decompressed_hash.algorithm(),
+        });
 
         last_err = None;
 
@@ -769,46 +977,72 @@ fn download_file<S: AsRef<str>, T: AsRef<str>>(
         return Err(e);
     }
 
-    if !quiet {
-        println!("        Deleting compressed version...");
-    }
-
     fs::remove_file(&compressed_file_path)
         .map_err(|ioe| Error::RemoveFile(compressed_file_path, ioe))?;
 
-    if !quiet {
-        println!(
-            "        {} all done downloading!",
-            decompressed_file_name.as_ref(),
-        );
+    Ok(decompressed_file_path)
+}
+
+/// Reports `Event::DownloadProgress` as bytes are written to `inner`.
+struct ProgressWriter<'a, W> {
+    inner: W,
+    observer: &'a dyn Observer,
+    file: &'a str,
+    bytes: u64,
+    total: Option<u64>,
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n as u64;
+        self.observer.on_event(&Event::DownloadProgress {
+            file: self.file,
+            bytes: self.bytes,
+            total: self.total,
+        });
+
+        Ok(n)
     }
 
-    Ok(decompressed_file_path)
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 fn decompress_file<P: AsRef<Path>>(
     buf: &mut [u8],
+    compression: Compression,
     compressed_path: P,
     decompress_path: P,
 ) -> Result<(), Error> {
-    let decompressed_file = util::create_file(&decompress_path)?;
-    let mut decoder = BzWriteDecoder::new(decompressed_file);
-
-    let mut compressed_file = util::open_file(&compressed_path)?;
+    let mut decompressed_file = util::create_file(&decompress_path)?;
 
-    let mut n = buf.len();
-    while n == buf.len() {
-        n = compressed_file.read(buf).map_err(|ioe| {
-            Error::FileRead(compressed_path.as_ref().to_path_buf(), ioe)
+    let compressed_file = util::open_file(&compressed_path)?;
+    let mut decoder = compression
+        .decoder(io::BufReader::new(compressed_file))
+        .map_err(|ioe| {
+            Error::Decode(compressed_path.as_ref().to_path_buf(), ioe)
         })?;
-        decoder.write_all(&buf[..n]).map_err(|ioe| {
-            Error::Decode(decompress_path.as_ref().to_path_buf(), ioe)
+
+    loop {
+        let n = match decoder.read(buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ioe) if ioe.kind() == io::ErrorKind::Interrupted => continue,
+            Err(ioe) => {
+                return Err(Error::Decode(
+                    compressed_path.as_ref().to_path_buf(),
+                    ioe,
+                ));
+            }
+        };
+        decompressed_file.write_all(&buf[..n]).map_err(|ioe| {
+            Error::FileWrite(decompress_path.as_ref().to_path_buf(), ioe)
         })?;
     }
 
-    decoder.finish().map(|_| ()).map_err(|ioe| {
-        Error::Decode(decompress_path.as_ref().to_path_buf(), ioe)
-    })
+    Ok(())
 }
 
 fn ensure_dir<P: AsRef<Path>>(path: P) -> Result<(), Error> {
@@ -834,3 +1068,39 @@ fn ensure_dir<P: AsRef<Path>>(path: P) -> Result<(), Error> {
         },
     }
 }
+
+/// Like `fs::rename`, but falls back to copying when `from` & `to` are on
+/// different filesystems (e.g. the cache is on a different disk than the
+/// installation).
+fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    from: P,
+    to: Q,
+) -> Result<(), Error> {
+    if fs::rename(&from, &to).is_ok() {
+        return Ok(());
+    }
+
+    let to_osstr: &OsStr = to.as_ref().as_ref();
+    let mut temp_path = OsString::with_capacity(to_osstr.len() + ".tmp".len());
+    temp_path.push(to_osstr);
+    temp_path.push(".tmp");
+
+    // Not `fs::copy`, which would write through a symlink at `temp_path`.
+    let mut from_file = util::open_file(&from)?;
+    let mut temp_file = util::create_new_file(&temp_path)?;
+    io::copy(&mut from_file, &mut temp_file)
+        .map_err(|ioe| Error::FileWrite(temp_path.clone().into(), ioe))?;
+    let permissions = from_file
+        .metadata()
+        .map_err(|ioe| Error::FileRead(from.as_ref().to_path_buf(), ioe))?
+        .permissions();
+    temp_file
+        .set_permissions(permissions)
+        .map_err(|ioe| Error::PermissionsSet(temp_path.clone().into(), ioe))?;
+    drop(temp_file);
+    fs::rename(&temp_path, &to).map_err(|_| {
+        Error::FileRename(temp_path.into(), to.as_ref().to_path_buf())
+    })?;
+    fs::remove_file(&from)
+        .map_err(|ioe| Error::RemoveFile(from.as_ref().to_path_buf(), ioe))
+}
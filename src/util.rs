@@ -1,5 +1,10 @@
 use crate::error::Error;
-use std::{fs::File, io, path::Path};
+use std::{
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
 
 pub fn open_file<P: AsRef<Path>>(path: P) -> Result<File, Error> {
     File::open(&path).map_err(|ioe| match ioe.kind() {
@@ -20,3 +25,149 @@ pub fn create_file<P: AsRef<Path>>(path: P) -> Result<File, Error> {
         _ => Error::UnknownIo(format!("creating {:?}", path.as_ref()), ioe),
     })
 }
+
+/// Creates a file at `path` to write to, like `create_file`, but replaces
+/// whatever is already there rather than opening it, so that a symlink at
+/// `path` (e.g. a planted `<file>.tmp` next to one of the install's files)
+/// is never written through. Because the file must be newly created, this
+/// holds even if a symlink appears after the old file is removed.
+pub fn create_new_file<P: AsRef<Path>>(path: P) -> Result<File, Error> {
+    match fs::remove_file(&path) {
+        Ok(()) => (),
+        Err(ioe) if ioe.kind() == io::ErrorKind::NotFound => (),
+        Err(ioe) => {
+            return Err(Error::RemoveFile(path.as_ref().to_path_buf(), ioe));
+        }
+    }
+
+    File::options()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|ioe| match ioe.kind() {
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied(
+                format!("creating {:?}", path.as_ref()),
+                ioe,
+            ),
+            _ => {
+                Error::UnknownIo(format!("creating {:?}", path.as_ref()), ioe)
+            }
+        })
+}
+
+/// Makes sure that `name`, which comes from a manifest or an update plan, is
+/// a plain relative path that can't escape whatever directory it gets joined
+/// onto.
+pub fn check_relative_path(name: &str) -> Result<(), Error> {
+    let mut has_normal_component = false;
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(_) => has_normal_component = true,
+            Component::CurDir => (),
+            Component::ParentDir => {
+                return Err(Error::UnsafePath(
+                    name.to_owned(),
+                    "refers to a parent directory",
+                ));
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(Error::UnsafePath(
+                    name.to_owned(),
+                    "is an absolute path",
+                ));
+            }
+        }
+    }
+
+    if has_normal_component {
+        Ok(())
+    } else {
+        Err(Error::UnsafePath(name.to_owned(), "doesn't name a file"))
+    }
+}
+
+/// Joins `name` onto `base` like `Path::join`, but only after checking it
+/// with `check_relative_path`, & making sure that none of the resulting
+/// path's components below `base` are symlinks. This way, a file name from a
+/// manifest can never be used to read or write outside of `base`.
+pub fn safe_join<P: AsRef<Path>>(
+    base: P,
+    name: &str,
+) -> Result<PathBuf, Error> {
+    check_relative_path(name)?;
+
+    let mut path = base.as_ref().to_path_buf();
+    for component in Path::new(name).components() {
+        let Component::Normal(c) = component else {
+            continue;
+        };
+        path.push(c);
+
+        match fs::symlink_metadata(&path) {
+            Ok(md) if md.file_type().is_symlink() => {
+                return Err(Error::UnsafePath(
+                    name.to_owned(),
+                    "would be accessed through a symlink",
+                ));
+            }
+            Ok(_) => (),
+            // Nothing below here exists yet, so there can't be any symlinks.
+            Err(ioe) if ioe.kind() == io::ErrorKind::NotFound => break,
+            Err(ioe) => {
+                return Err(Error::UnknownIo(
+                    format!("getting metadata for {path:?}"),
+                    ioe,
+                ));
+            }
+        }
+    }
+
+    Ok(base.as_ref().join(name))
+}
+
+/// A command that runs `command_line` with the system shell (`sh -c` or
+/// `cmd /C`).
+#[cfg(not(windows))]
+pub fn shell(command_line: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(command_line);
+
+    command
+}
+
+#[cfg(windows)]
+pub fn shell(command_line: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(command_line);
+
+    command
+}
+
+/// Whether a process with the ID `pid` exists, as far as can be told. This
+/// errs on the side of saying that it does.
+#[cfg(target_os = "linux")]
+pub fn process_is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn process_is_running(pid: u32) -> bool {
+    Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+#[cfg(windows)]
+pub fn process_is_running(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/NH", "/FO", "CSV", "/FI"])
+        .arg(format!("PID eq {pid}"))
+        .output()
+        .map_or(true, |output| {
+            String::from_utf8_lossy(&output.stdout)
+                .contains(&format!("\"{pid}\""))
+        })
+}
@@ -0,0 +1,199 @@
+mod common;
+
+use common::{MockServer, TempDir};
+use serde_json::json;
+use shticker_book_unwritten::{Error, config::Config};
+
+fn config(server: &MockServer, dir: &TempDir) -> Config {
+    let mut config = server.config(dir);
+    config.accounts = serde_json::from_value(json!({
+        "a_very_long_username": {
+            "password": "hunter2",
+            "alias": "long",
+            "groups": ["team"],
+            "tags": ["main"],
+        },
+        "another_long_username": {
+            "alias": "other",
+            "groups": ["team", "alts"],
+        },
+        "plain": "hunter3",
+        "nobody": null,
+    }))
+    .unwrap();
+    config.validate().unwrap();
+
+    config
+}
+
+#[test]
+fn aliases_and_groups_are_resolved() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let config = config(&server, &dir);
+
+    assert_eq!(
+        config
+            .resolve_accounts(["other", "@team", "plain", "newcomer"])
+            .unwrap(),
+        [
+            "another_long_username",
+            "a_very_long_username",
+            "plain",
+            "newcomer",
+        ],
+    );
+    assert_eq!(
+        config.resolve_accounts(["@alts", "long"]).unwrap(),
+        ["another_long_username", "a_very_long_username"],
+    );
+    let err = config.resolve_accounts(["@nope"]).unwrap_err();
+    assert!(
+        matches!(&err, Error::UnknownGroup(g) if g == "nope"),
+        "{err:?}"
+    );
+    assert_eq!(config.tags("a_very_long_username"), ["main"]);
+    assert_eq!(
+        config.all_groups().into_iter().collect::<Vec<_>>(),
+        [
+            ("alts", vec!["another_long_username"]),
+            (
+                "team",
+                vec!["a_very_long_username", "another_long_username"]
+            ),
+        ],
+    );
+}
+
+#[test]
+fn changing_aliases_and_groups() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let mut config = config(&server, &dir);
+
+    // Taken by another account (as its alias or username), or malformed.
+    for alias in ["long", "plain", "@team", ""] {
+        let err = config.set_alias("nobody", Some(alias.to_owned()));
+        assert!(matches!(err, Err(Error::InvalidConfig(_))), "{err:?}");
+    }
+    config.set_alias("plain", Some("p".to_owned())).unwrap();
+    config.set_alias("a_very_long_username", None).unwrap();
+    assert!(config.add_to_group("plain", "alts").unwrap());
+    assert!(!config.add_to_group("plain", "alts").unwrap());
+    assert!(config.add_to_group("plain", "@alts").is_err());
+    assert!(config.remove_from_group("another_long_username", "alts"));
+    assert!(!config.remove_from_group("nobody", "alts"));
+
+    // Survives being written & read back.
+    let config_path = dir.join("config.json");
+    shticker_book_unwritten::config::commit_config(&config, &config_path)
+        .unwrap();
+    let config = Config::load(&config_path).unwrap();
+    assert_eq!(config.alias("plain"), Some("p"));
+    // Still has its password.
+    assert_eq!(config.saved_password("plain"), Some("hunter3"));
+    assert_eq!(config.alias("a_very_long_username"), None);
+    assert_eq!(config.resolve_accounts(["@alts"]).unwrap(), ["plain"]);
+    assert_eq!(config.resolve_accounts(["long"]).unwrap(), ["long"]);
+}
+
+#[test]
+fn invalid_aliases_and_groups_are_rejected() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+
+    for accounts in [
+        json!({ "a": { "alias": "b" }, "b": null }),
+        json!({ "a": { "alias": "c" }, "b": { "alias": "c" } }),
+        json!({ "a": { "alias": "@c" } }),
+        json!({ "a": { "alias": 1 } }),
+        json!({ "a": { "groups": "team" } }),
+        json!({ "a": { "groups": ["@team"] } }),
+        json!({ "a": { "tags": [1] } }),
+    ] {
+        let mut config = server.config(&dir);
+        config.accounts = serde_json::from_value(accounts).unwrap();
+
+        let err = config.validate().unwrap_err();
+
+        assert!(matches!(err, Error::InvalidConfig(_)), "{err:?}");
+    }
+}
+
+/// A group is logged into from command mode, & its instances are killed by
+/// alias & by group.
+#[cfg(all(unix, not(all(target_os = "linux", feature = "secret-store"))))]
+#[test]
+fn command_mode_accepts_aliases_and_groups() {
+    use shticker_book_unwritten::Updater;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        process::{Command, Stdio},
+    };
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, b"#!/bin/sh\nexec sleep 60\n");
+    for _ in 0..3 {
+        server.push_login_response(json!({
+            "success": "true",
+            "cookie": "COOKIE",
+            "gameserver": "gameserver.example:7198",
+        }));
+    }
+    let mut config = config(&server, &dir);
+    config.store_passwords = true;
+    config.add_account("another_long_username".to_owned(), "pw".to_owned());
+    config.add_account("plain".to_owned(), "hunter3".to_owned());
+    Updater::new(&config, &common::client())
+        .update(false)
+        .unwrap();
+    let config_path = dir.join("config.json");
+    shticker_book_unwritten::config::commit_config(&config, &config_path)
+        .unwrap();
+
+    let mut child =
+        Command::new(env!("CARGO_BIN_EXE_shticker_book_unwritten"))
+            .arg("-c")
+            .arg(&config_path)
+            .args(["-n", "-o", "json", "-u", "@team", "plain"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"kill long\ninstances\nkill @team\nkill plain\nquit\n")
+        .unwrap();
+    let events: Vec<serde_json::Value> =
+        BufReader::new(child.stdout.take().unwrap())
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+    assert!(child.wait().unwrap().success());
+
+    let mut launched: Vec<_> = events
+        .iter()
+        .filter(|e| e["event"] == "game_launched")
+        .map(|e| e["username"].as_str().unwrap())
+        .collect();
+    launched.sort_unstable();
+    assert_eq!(
+        launched,
+        ["a_very_long_username", "another_long_username", "plain"],
+    );
+    let listing = events.iter().find(|e| e.get("instances").is_some());
+    let mut running: Vec<_> = listing.unwrap()["instances"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|instance| instance["username"].as_str().unwrap())
+        .collect();
+    running.sort_unstable();
+    assert_eq!(running, ["another_long_username", "plain"]);
+    assert_eq!(server.pending_login_responses(), 0);
+    let sessions = shticker_book_unwritten::history::load(&config).unwrap();
+    assert_eq!(sessions.len(), 3);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::{MockServer, TempDir};
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Command, Stdio},
+};
+
+/// Runs command mode with JSON output, typing in `commands`, & returns
+/// everything that was written to stdout.
+fn run_commands(commands: &[u8]) -> Vec<serde_json::Value> {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let config = server.config(&dir);
+    let config_path = dir.join("config.json");
+    shticker_book_unwritten::config::commit_config(&config, &config_path)
+        .unwrap();
+
+    let mut child =
+        Command::new(env!("CARGO_BIN_EXE_shticker_book_unwritten"))
+            .arg("-c")
+            .arg(&config_path)
+            .args(["-n", "-o", "json"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+    child.stdin.take().unwrap().write_all(commands).unwrap();
+    let events = BufReader::new(child.stdout.take().unwrap())
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert!(child.wait().unwrap().success());
+
+    events
+}
+
+#[test]
+fn mistyped_commands_are_json_events() {
+    let events = run_commands(
+        b"frobnicate\nkill\nupdate plan\nupdate --bogus\nlogs 1 many\n\
+          stats fortnight\nstats export\naccounts frobnicate\n\
+          accounts group add\nquit\n",
+    );
+
+    let messages: Vec<_> = events
+        .iter()
+        .map(|e| {
+            assert_eq!(e["event"], "command_error", "{e}");
+
+            e["message"].as_str().unwrap()
+        })
+        .collect();
+    assert_eq!(messages.len(), 9, "{messages:?}");
+    assert!(messages[0].starts_with("Unrecognized command."));
+    assert_eq!(messages[1], "Expected the <instance> argument!");
+    assert_eq!(messages[2], "Expected the <plan_file> argument!");
+    assert_eq!(messages[3], "Unexpected argument: --bogus");
+    assert_eq!(messages[4], "Expected [lines] to be a number!");
+    assert_eq!(messages[5], "Expected day or week!");
+    assert_eq!(messages[6], "Expected the <file> argument!");
+    assert!(messages[7].starts_with("Unrecognized accounts subcommand."));
+    assert!(messages[8].starts_with("Expected a group."));
+}
+
+#[test]
+fn help_is_a_json_event() {
+    let events = run_commands(b"help\naccounts help\nabout\nquit\n");
+
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0]["event"], "help");
+    assert!(events[0]["text"].as_str().unwrap().contains("quit, exit"));
+    assert_eq!(events[1]["event"], "help");
+    assert!(events[1]["text"].as_str().unwrap().contains("forget"));
+    assert_eq!(events[2]["event"], "about");
+}
@@ -0,0 +1,606 @@
+//! Shared harness for the integration tests: scratch directories, a mock
+//! CDN & login API served over HTTP from inside of the test process, & an
+//! `Observer` that records every event that it sees.
+//!
+//! Each test binary only uses some of this, hence the `allow`.
+
+#![allow(dead_code)]
+
+use bzip2::write::BzEncoder;
+use serde_json::json;
+use shticker_book_unwritten::{
+    Config, Event, Observer,
+    decompress::Compression,
+    hash::{self, Hash, HashAlgorithm},
+    manifest, output,
+};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+pub const MANIFEST_PATH: &str = "/manifest.txt";
+pub const CDN_PATH: &str = "/cdn/";
+pub const LOGIN_PATH: &str = "/login";
+/// Used on every platform, rather than each platform's default.
+pub const GAME_EXECUTABLE: &str = "TTREngine";
+
+/// A directory under the system's temporary directory, which is removed
+/// (along with everything in it) when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "sbu-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, p: P) -> PathBuf {
+        self.path.join(p)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A single request that the mock server received.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// The decoded `application/x-www-form-urlencoded` body, if any.
+    pub form: BTreeMap<String, String>,
+}
+
+/// An HTTP server on localhost that plays the part of both the CDN (serving
+/// a manifest that is regenerated every time that a file is published) &
+/// the login API (replying with whatever responses it has been scripted
+/// with, in order).
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    scratch: TempDir,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct State {
+    manifest: serde_json::Map<String, serde_json::Value>,
+    /// Every version of each published file, oldest first.
+    versions: HashMap<String, Vec<Vec<u8>>>,
+    /// Keyed by request path.
+    files: HashMap<String, Vec<u8>>,
+    /// Served instead of `files`, once each & in order, before falling back
+    /// to `files`. Keyed by request path.
+    overrides: HashMap<String, VecDeque<(u16, Vec<u8>)>>,
+    login_responses: VecDeque<serde_json::Value>,
+    requests: Vec<Request>,
+    /// How newly-published files & patches (& the blocks inside of the
+    /// patches) are compressed, & whether the manifest says so with a
+    /// `"compression"` field, rather than with the file extension.
+    compression: Option<(Compression, bool)>,
+}
+
+impl MockServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let state = Arc::clone(&state);
+            let shutdown = Arc::clone(&shutdown);
+
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let state = Arc::clone(&state);
+                    thread::spawn(move || handle_connection(stream, &state));
+                }
+            })
+        };
+
+        Self {
+            addr,
+            state,
+            scratch: TempDir::new(),
+            shutdown,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn manifest_uri(&self) -> String {
+        format!("http://{}{MANIFEST_PATH}", self.addr)
+    }
+
+    pub fn cdn_uri(&self) -> String {
+        format!("http://{}{CDN_PATH}", self.addr)
+    }
+
+    pub fn login_api_uri(&self) -> String {
+        format!("http://{}{LOGIN_PATH}", self.addr)
+    }
+
+    /// A config that installs into `dir` & talks only to this server.
+    pub fn config(&self, dir: &TempDir) -> Config {
+        serde_json::from_value(json!({
+            "install_dir": dir.join("install"),
+            "cache_dir": dir.join("cache"),
+            "manifest_uri": self.manifest_uri(),
+            "cdn_uri": self.cdn_uri(),
+            "login_api_uri": self.login_api_uri(),
+            "game_executable": GAME_EXECUTABLE,
+            "store_passwords": false,
+            "accounts": {},
+        }))
+        .unwrap()
+    }
+
+    /// Publishes `contents` as the newest version of `name`, bzip2'd (unless
+    /// `compress_with` says otherwise), for this OS & architecture. If older
+    /// versions of `name` were published, then BSDIFF40 patches from each of
+    /// them are published too.
+    pub fn publish(&self, name: &str, contents: &[u8]) {
+        self.publish_for(name, contents, &[manifest::OS_AND_ARCH]);
+    }
+
+    /// Like `publish`, but only for the OS & architecture combinations in
+    /// `only`.
+    pub fn publish_for(&self, name: &str, contents: &[u8], only: &[&str]) {
+        let mut state = self.state.lock().unwrap();
+
+        let (compression, by_field) =
+            state.compression.unwrap_or((Compression::Bzip2, false));
+        let extension = if by_field {
+            "packed"
+        } else {
+            match compression {
+                Compression::Bzip2 => "bz2",
+                Compression::Zstd => "zst",
+                Compression::Xz => "xz",
+            }
+        };
+
+        let dl = format!("{name}.{extension}");
+        let compressed = compress(contents, compression);
+        let mut entry = json!({
+            "dl": dl,
+            "only": only,
+            "hash": sha1_hex(contents),
+            "compHash": sha1_hex(&compressed),
+            "patches": {},
+        });
+        if by_field {
+            entry["compression"] = json!(compression);
+        }
+        state.files.insert(format!("{CDN_PATH}{dl}"), compressed);
+
+        let old_versions =
+            state.versions.get(name).cloned().unwrap_or_default();
+        for old in old_versions.iter().filter(|old| *old != contents) {
+            let old_hash = sha1_hex(old);
+            let patch =
+                recompress_blocks(&self.bsdiff(old, contents), compression);
+            let compressed_patch = compress(&patch, compression);
+            let filename =
+                format!("{name}.{}.patch.{extension}", &old_hash[..8]);
+
+            entry["patches"][&old_hash] = json!({
+                "filename": filename,
+                "patchHash": sha1_hex(&patch),
+                "compPatchHash": sha1_hex(&compressed_patch),
+            });
+            if by_field {
+                entry["patches"][&old_hash]["compression"] =
+                    json!(compression);
+            }
+            state
+                .files
+                .insert(format!("{CDN_PATH}{filename}"), compressed_patch);
+        }
+
+        state.manifest.insert(name.to_owned(), entry);
+        state
+            .versions
+            .entry(name.to_owned())
+            .or_default()
+            .push(contents.to_vec());
+    }
+
+    /// Changes the manifest, as it stands, with `f`. Publishing a file again
+    /// replaces its entry, edits & all.
+    pub fn edit_manifest<
+        F: FnOnce(&mut serde_json::Map<String, serde_json::Value>),
+    >(
+        &self,
+        f: F,
+    ) {
+        f(&mut self.state.lock().unwrap().manifest);
+    }
+
+    /// Compresses everything published from now on with `compression`,
+    /// which the manifest gives in a `"compression"` field if `by_field`,
+    /// & with the file extension otherwise.
+    pub fn compress_with(&self, compression: Compression, by_field: bool) {
+        self.state.lock().unwrap().compression = Some((compression, by_field));
+    }
+
+    /// The next `times` requests for the CDN file `file_name` (which is e.g.
+    /// a `dl` or a patch's `filename`) get a body that doesn't match its
+    /// hash.
+    pub fn corrupt_next(&self, file_name: &str, times: usize) {
+        self.override_next(
+            &format!("{CDN_PATH}{file_name}"),
+            times,
+            200,
+            &bzip2(b"not what you were looking for"),
+        );
+    }
+
+    /// The next `times` requests for `path` get a 503.
+    pub fn fail_next(&self, path: &str, times: usize) {
+        self.override_next(path, times, 503, b"");
+    }
+
+    fn override_next(
+        &self,
+        path: &str,
+        times: usize,
+        status: u16,
+        body: &[u8],
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let queue = state.overrides.entry(path.to_owned()).or_default();
+        for _ in 0..times {
+            queue.push_back((status, body.to_vec()));
+        }
+    }
+
+    /// Queues up a response for the login API to give. Responses are given
+    /// in the order that they were queued, one per POST.
+    pub fn push_login_response(&self, response: serde_json::Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .login_responses
+            .push_back(response);
+    }
+
+    /// How many login API responses have yet to be given.
+    pub fn pending_login_responses(&self) -> usize {
+        self.state.lock().unwrap().login_responses.len()
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// The requests that were made for `path`.
+    pub fn requests_for(&self, path: &str) -> Vec<Request> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.path == path)
+            .collect()
+    }
+
+    /// How many times the CDN file `file_name` was requested.
+    pub fn downloads_of(&self, file_name: &str) -> usize {
+        self.requests_for(&format!("{CDN_PATH}{file_name}")).len()
+    }
+
+    fn bsdiff(&self, old: &[u8], new: &[u8]) -> Vec<u8> {
+        let old_path = self.scratch.join("old");
+        let new_path = self.scratch.join("new");
+        let patch_path = self.scratch.join("patch");
+        fs::write(&old_path, old).unwrap();
+        fs::write(&new_path, new).unwrap();
+        shticker_book_unwritten::diff::diff_files(
+            &old_path,
+            &new_path,
+            &patch_path,
+        )
+        .unwrap();
+
+        fs::read(&patch_path).unwrap()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the listener up, so that it notices.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<State>) {
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return;
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() {
+            return;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse().unwrap_or(0);
+        }
+    }
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let (status, content_type, body) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(Request {
+            method: method.clone(),
+            path: path.clone(),
+            form: parse_form(&String::from_utf8_lossy(&body)),
+        });
+
+        respond(&mut state, &method, &path)
+    };
+
+    let mut stream = &stream;
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {status} {}\r\nContent-Type: {content_type}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        if status < 400 { "OK" } else { "Error" },
+        body.len(),
+    );
+    let _ = stream.write_all(&body);
+    let _ = stream.flush();
+}
+
+fn respond(
+    state: &mut State,
+    method: &str,
+    path: &str,
+) -> (u16, &'static str, Vec<u8>) {
+    const OCTETS: &str = "application/octet-stream";
+    const JSON: &str = "application/json";
+
+    if let Some((status, body)) =
+        state.overrides.get_mut(path).and_then(VecDeque::pop_front)
+    {
+        return (status, OCTETS, body);
+    }
+
+    match (method, path) {
+        ("GET", MANIFEST_PATH) => {
+            (200, JSON, serde_json::to_vec(&state.manifest).unwrap())
+        }
+        ("POST", LOGIN_PATH) => match state.login_responses.pop_front() {
+            Some(resp) => (200, JSON, serde_json::to_vec(&resp).unwrap()),
+            None => (500, JSON, b"{}".to_vec()),
+        },
+        ("GET", _) => match state.files.get(path) {
+            Some(body) => (200, OCTETS, body.clone()),
+            None => (404, OCTETS, Vec::new()),
+        },
+        _ => (405, OCTETS, Vec::new()),
+    }
+}
+
+fn parse_form(body: &str) -> BTreeMap<String, String> {
+    body.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 2;
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8(decoded).unwrap()
+}
+
+pub fn bzip2(data: &[u8]) -> Vec<u8> {
+    let mut encoder = BzEncoder::new(Vec::new(), bzip2::Compression::best());
+    encoder.write_all(data).unwrap();
+
+    encoder.finish().unwrap()
+}
+
+pub fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+    match compression {
+        Compression::Bzip2 => bzip2(data),
+        Compression::Zstd => ruzstd::encoding::compress_to_vec(
+            data,
+            ruzstd::encoding::CompressionLevel::Fastest,
+        ),
+        Compression::Xz => {
+            let mut writer = lzma_rust2::XzWriter::new(
+                Vec::new(),
+                lzma_rust2::XzOptions::default(),
+            )
+            .unwrap();
+            writer.write_all(data).unwrap();
+
+            writer.finish().unwrap()
+        }
+    }
+}
+
+/// Recompresses the control, diff, & extra blocks of the BSDIFF40 `patch`
+/// (which are bzip2'd) with `compression`.
+pub fn recompress_blocks(patch: &[u8], compression: Compression) -> Vec<u8> {
+    let len_at = |i: usize| {
+        u64::from_le_bytes(patch[i..i + 8].try_into().unwrap()) as usize
+    };
+    assert_eq!(&patch[..8], b"BSDIFF40");
+    let (ctrl_len, diff_len) = (len_at(8), len_at(16));
+    let blocks = [
+        &patch[32..32 + ctrl_len],
+        &patch[32 + ctrl_len..32 + ctrl_len + diff_len],
+        &patch[32 + ctrl_len + diff_len..],
+    ]
+    .map(|block| {
+        let mut decompressed = Vec::new();
+        bzip2::read::BzDecoder::new(block)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+
+        compress(&decompressed, compression)
+    });
+
+    let mut recompressed = patch[..32].to_vec();
+    recompressed[8..16]
+        .copy_from_slice(&(blocks[0].len() as u64).to_le_bytes());
+    recompressed[16..24]
+        .copy_from_slice(&(blocks[1].len() as u64).to_le_bytes());
+    for block in blocks {
+        recompressed.extend_from_slice(&block);
+    }
+
+    recompressed
+}
+
+pub fn sha1(data: &[u8]) -> Hash {
+    digest(data, HashAlgorithm::Sha1)
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1(data).to_string()
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    digest(data, HashAlgorithm::Sha256).to_string()
+}
+
+fn digest(data: &[u8], alg: HashAlgorithm) -> Hash {
+    let mut buf = [0; 0x100];
+
+    hash::hash_reader(&mut &data[..], &mut buf, &[alg])
+        .unwrap()
+        .remove(0)
+}
+
+/// A small xorshift PRNG, so that failures are reproducible.
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        self.0
+    }
+
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// Records every event, in the same JSON form that `--output json` uses.
+#[derive(Default)]
+pub struct Recorder {
+    events: Mutex<Vec<serde_json::Value>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<serde_json::Value> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// The recorded events whose `"event"` is `name`.
+    pub fn named(&self, name: &str) -> Vec<serde_json::Value> {
+        self.events()
+            .into_iter()
+            .filter(|e| e["event"] == name)
+            .collect()
+    }
+}
+
+impl Observer for Recorder {
+    fn on_event(&self, event: &Event) {
+        if let Some(value) = output::event_value(event) {
+            self.events.lock().unwrap().push(value);
+        }
+    }
+}
+
+pub fn client() -> reqwest::blocking::Client {
+    reqwest::blocking::Client::new()
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use common::{GAME_EXECUTABLE, MockServer, Recorder, TempDir};
+use serde_json::json;
+use shticker_book_unwritten::{
+    Error, Manifest, Updater,
+    decompress::{self, Compression},
+    patch,
+};
+use std::{fs, io::Read};
+
+const FORMATS: [Compression; 3] =
+    [Compression::Bzip2, Compression::Zstd, Compression::Xz];
+
+/// Something that compresses well, followed by something that doesn't.
+fn sample() -> Vec<u8> {
+    let mut data = b"phase file ".repeat(0x400);
+    let mut x = 0x2545_f491_u32;
+    data.extend((0..0x4000).map(|_| {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+
+        x as u8
+    }));
+
+    data
+}
+
+fn decode(decoder: Box<dyn Read + '_>) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    { decoder }.read_to_end(&mut decoded).unwrap();
+
+    decoded
+}
+
+#[test]
+fn round_trips() {
+    for data in [Vec::new(), b"x".to_vec(), sample()] {
+        for compression in FORMATS {
+            let compressed = common::compress(&data, compression);
+
+            assert_eq!(
+                Compression::from_magic(&compressed),
+                Some(compression),
+            );
+            assert_eq!(
+                decode(compression.decoder(&compressed[..]).unwrap()),
+                data,
+                "{compression:?}",
+            );
+            assert_eq!(
+                decode(decompress::sniffing_decoder(&compressed[..]).unwrap()),
+                data,
+                "{compression:?}",
+            );
+        }
+    }
+}
+
+#[test]
+fn formats_are_chosen_by_name_extension_or_magic() {
+    assert_eq!(Compression::from_name("bzip2"), Some(Compression::Bzip2));
+    assert_eq!(Compression::from_name("zstd"), Some(Compression::Zstd));
+    assert_eq!(Compression::from_name("xz"), Some(Compression::Xz));
+    assert_eq!(Compression::from_name("lz4"), None);
+    assert_eq!(Compression::from_file_name("a.bz2"), Compression::Bzip2);
+    assert_eq!(Compression::from_file_name("a.zst"), Compression::Zstd);
+    assert_eq!(Compression::from_file_name("a.xz"), Compression::Xz);
+    // The official CDN's default.
+    assert_eq!(Compression::from_file_name("a.lz4"), Compression::Bzip2);
+    assert_eq!(Compression::from_magic(b"\x04\x22\x4d\x18"), None);
+    assert_eq!(Compression::from_magic(b""), None);
+
+    // Unrecognized data is taken to be bzip2, which it isn't.
+    let mut garbage = decompress::sniffing_decoder(&b"not compressed"[..])
+        .expect("bzip2 is assumed");
+    assert!(garbage.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn unknown_formats_in_the_manifest_are_rejected() {
+    for (file, patch) in [("lz4", "bzip2"), ("bzip2", "lz4")] {
+        let manifest = json!({
+            "phase_3.mf": {
+                "dl": "phase_3.mf.lz4",
+                "compression": file,
+                "only": ["linux2"],
+                "hash": common::sha1_hex(b""),
+                "compHash": common::sha1_hex(b""),
+                "patches": {
+                    common::sha1_hex(b"old"): {
+                        "filename": "phase_3.mf.patch.lz4",
+                        "compression": patch,
+                        "patchHash": common::sha1_hex(b""),
+                        "compPatchHash": common::sha1_hex(b""),
+                    },
+                },
+            },
+        });
+
+        let err = Manifest::from_json(manifest).unwrap_err();
+
+        let Error::BadManifestFormat(msg) = err else {
+            panic!("{err:?}");
+        };
+        assert!(msg.contains("lz4"), "{msg}");
+    }
+}
+
+/// BSDIFF40 patches whose blocks are compressed with something other than
+/// bzip2 are applied all the same.
+#[test]
+fn patch_blocks_in_each_format() {
+    let dir = TempDir::new();
+    let old = sample();
+    let mut new = old.clone();
+    new[0x100..0x200].fill(b'!');
+    new.extend_from_slice(b"some more");
+    let (old_path, new_path) = (dir.join("old"), dir.join("new"));
+    fs::write(&old_path, &old).unwrap();
+    fs::write(&new_path, &new).unwrap();
+    let bzip2_patch_path = dir.join("patch.bz2");
+    shticker_book_unwritten::diff::diff_files(
+        &old_path,
+        &new_path,
+        &bzip2_patch_path,
+    )
+    .unwrap();
+    let bzip2_patch = fs::read(&bzip2_patch_path).unwrap();
+
+    for compression in FORMATS {
+        let patch_path = dir.join("patch");
+        let patch = common::recompress_blocks(&bzip2_patch, compression);
+        fs::write(&patch_path, &patch).unwrap();
+        let out_path = dir.join("out");
+
+        patch::patch_file(
+            &patch_path,
+            &old_path,
+            &out_path,
+            u64::MAX,
+            &common::sha1(&new),
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(&out_path).unwrap(), new, "{compression:?}");
+    }
+}
+
+/// Whole files & patches are downloaded & decompressed in each format, which
+/// is given either by the file extension or by the `"compression"` field.
+#[test]
+fn updates_in_each_format() {
+    const GAME_V1: &[u8] = b"#!/bin/sh\n# version 1 of the game\n";
+    const GAME_V2: &[u8] = b"#!/bin/sh\n# version 2 of the game, now longer\n";
+
+    for compression in FORMATS {
+        for by_field in [false, true] {
+            let server = MockServer::start();
+            let dir = TempDir::new();
+            server.compress_with(compression, by_field);
+            server.publish(GAME_EXECUTABLE, GAME_V1);
+            server.publish("phase_3.mf", &sample());
+            let config = server.config(&dir);
+            let client = common::client();
+            Updater::new(&config, &client).update(false).unwrap();
+            server.publish(GAME_EXECUTABLE, GAME_V2);
+            let recorder = Recorder::new();
+
+            Updater::new(&config, &client)
+                .observer(&recorder)
+                .update(false)
+                .unwrap();
+
+            let install = &config.install_dir;
+            let case = format!("{compression:?}, by field: {by_field}");
+            assert_eq!(
+                fs::read(install.join(GAME_EXECUTABLE)).unwrap(),
+                GAME_V2,
+                "{case}",
+            );
+            assert_eq!(
+                fs::read(install.join("phase_3.mf")).unwrap(),
+                sample(),
+                "{case}",
+            );
+            assert_eq!(recorder.named("patch_applied").len(), 1, "{case}");
+        }
+    }
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::{Rng, TempDir};
+use shticker_book_unwritten::{diff, patch};
+use std::fs;
+
+/// `data`, with a few random bytes changed, inserted, & removed.
+fn mutate(rng: &mut Rng, data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    for _ in 0..rng.below(8) + 1 {
+        let at = rng.below(data.len() as u64 + 1) as usize;
+        let len = rng.below(0x40) as usize + 1;
+        match rng.below(3) {
+            0 => {
+                let end = (at + len).min(data.len());
+                let bytes = rng.bytes(end - at);
+                data[at..end].copy_from_slice(&bytes);
+            }
+            1 => {
+                data.splice(at..at, rng.bytes(len));
+            }
+            _ => {
+                data.drain(at..(at + len).min(data.len()));
+            }
+        }
+    }
+
+    data
+}
+
+/// Diffs `old` against `new`, & checks that the patch turns `old` back into
+/// exactly `new`.
+fn assert_round_trips(dir: &TempDir, old: &[u8], new: &[u8]) {
+    let (old_path, new_path, patch_path, out_path) = (
+        dir.join("old"),
+        dir.join("new"),
+        dir.join("patch"),
+        dir.join("out"),
+    );
+    fs::write(&old_path, old).unwrap();
+    fs::write(&new_path, new).unwrap();
+
+    diff::diff_files(&old_path, &new_path, &patch_path).unwrap();
+    patch::patch_file(
+        &patch_path,
+        &old_path,
+        &out_path,
+        new.len() as u64,
+        &common::sha1(new),
+    )
+    .unwrap();
+
+    assert!(
+        fs::read(&out_path).unwrap() == new,
+        "{} bytes to {} bytes",
+        old.len(),
+        new.len(),
+    );
+}
+
+#[test]
+fn empty_files() {
+    let dir = TempDir::new();
+
+    assert_round_trips(&dir, b"", b"");
+    assert_round_trips(&dir, b"", b"new");
+    assert_round_trips(&dir, b"old", b"");
+    assert_round_trips(&dir, b"", &Rng(1).bytes(0x1_0000));
+    assert_round_trips(&dir, &Rng(2).bytes(0x1_0000), b"");
+}
+
+#[test]
+fn identical_files() {
+    let dir = TempDir::new();
+
+    for data in [
+        b"x".to_vec(),
+        b"same".repeat(0x1000),
+        Rng(3).bytes(0x2_0000),
+    ] {
+        assert_round_trips(&dir, &data, &data);
+    }
+}
+
+#[test]
+fn random_files() {
+    let dir = TempDir::new();
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+    for _ in 0..16 {
+        let old_len = rng.below(0x2000) as usize;
+        let new_len = rng.below(0x2000) as usize;
+        let (old, new) = (rng.bytes(old_len), rng.bytes(new_len));
+
+        assert_round_trips(&dir, &old, &new);
+    }
+}
+
+#[test]
+fn random_mutations() {
+    let dir = TempDir::new();
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+    for len in [1, 0x10, 0x400, 0x1_0000, 0x2_1234] {
+        let old = rng.bytes(len);
+        for _ in 0..4 {
+            let new = mutate(&mut rng, &old);
+
+            assert_round_trips(&dir, &old, &new);
+        }
+    }
+}
+
+/// Files that look more like what the game ships: mostly repetitive &
+/// structured, with data that moves around & grows between versions.
+#[test]
+fn realistic_files() {
+    let dir = TempDir::new();
+    let mut rng = Rng(0xdead_beef_cafe_f00d);
+    let mut old: Vec<u8> = (0..0x2000u32)
+        .flat_map(|i| format!("model_{i:04}.bam\t{}\n", i * 37).into_bytes())
+        .collect();
+    old.extend(rng.bytes(0x8000));
+
+    let mut new = old.clone();
+    // Entries renumbered, a block moved, & something appended.
+    for byte in new.iter_mut().take(0x4000) {
+        if byte.is_ascii_digit() && rng.below(16) == 0 {
+            *byte = b'0' + rng.below(10) as u8;
+        }
+    }
+    new.copy_within(0x8000..0xc000, 0x1_0000);
+    new.extend(b"new_model.bam\t0\n".repeat(0x100));
+
+    assert_round_trips(&dir, &old, &new);
+    assert_round_trips(&dir, &new, &old);
+}
@@ -0,0 +1,217 @@
+mod common;
+
+use common::{MockServer, TempDir};
+use shticker_book_unwritten::history::{
+    self, Outcome, Period, Session, Stats,
+};
+use std::fs;
+
+/// 2024-01-01, a Monday.
+const MONDAY: u64 = 1_704_067_200;
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+fn session(
+    username: &str,
+    start: u64,
+    secs: u64,
+    outcome: Outcome,
+) -> Session {
+    Session {
+        username: username.to_owned(),
+        pid: 1234,
+        start,
+        end: start + secs,
+        outcome,
+        exit_code: match outcome {
+            Outcome::Exited => Some(0),
+            Outcome::Crashed => Some(1),
+            Outcome::Killed | Outcome::Detached => None,
+        },
+    }
+}
+
+fn stats(
+    username: &str,
+    period: &str,
+    playtime_secs: u64,
+    sessions: u64,
+    crashes: u64,
+) -> Stats {
+    Stats {
+        username: username.to_owned(),
+        period: period.to_owned(),
+        playtime_secs,
+        sessions,
+        crashes,
+    }
+}
+
+#[test]
+fn playtime_per_day_and_week() {
+    let sessions = [
+        // From 23:00 on Monday to 01:00 on Tuesday.
+        session("toon", MONDAY + 23 * HOUR, 2 * HOUR, Outcome::Exited),
+        session("toon", MONDAY + DAY + 5 * HOUR, HOUR, Outcome::Crashed),
+        // From Sunday into the next week.
+        session(
+            "toon",
+            MONDAY + 6 * DAY + 23 * HOUR,
+            2 * HOUR,
+            Outcome::Killed,
+        ),
+        session("other", MONDAY, 30 * 60, Outcome::Detached),
+    ];
+
+    assert_eq!(
+        history::stats(&sessions, Period::Day),
+        [
+            stats("other", "2024-01-01", 30 * 60, 1, 0),
+            stats("toon", "2024-01-01", HOUR, 1, 0),
+            stats("toon", "2024-01-02", 2 * HOUR, 1, 1),
+            stats("toon", "2024-01-07", HOUR, 1, 0),
+            stats("toon", "2024-01-08", HOUR, 0, 0),
+        ],
+    );
+    assert_eq!(
+        history::stats(&sessions, Period::Week),
+        [
+            stats("other", "2024-01-01", 30 * 60, 1, 0),
+            stats("toon", "2024-01-01", 4 * HOUR, 3, 1),
+            stats("toon", "2024-01-08", HOUR, 0, 0),
+        ],
+    );
+    let totals = history::totals(&sessions);
+    assert_eq!(
+        totals,
+        [
+            stats("other", "", 30 * 60, 1, 0),
+            stats("toon", "", 5 * HOUR, 3, 1),
+        ],
+    );
+    assert!((totals[1].crash_rate() - 1.0 / 3.0).abs() < 1e-9);
+    assert_eq!(totals[0].crash_rate(), 0.0);
+}
+
+#[test]
+fn recorded_sessions_are_kept() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let config = server.config(&dir);
+    let first = session("toon", MONDAY, HOUR, Outcome::Crashed);
+    let second = session("other", MONDAY + DAY, HOUR, Outcome::Exited);
+
+    assert!(history::load(&config).unwrap().is_empty());
+    history::record(&config, &first).unwrap();
+    // E.g. from the launcher being killed while writing.
+    let path = config.history_path();
+    let mut contents = fs::read_to_string(&path).unwrap();
+    contents.push_str("{\"username\":\"tru");
+    contents.push('\n');
+    fs::write(&path, contents).unwrap();
+    history::record(&config, &second).unwrap();
+
+    assert_eq!(path, dir.join("cache").join("history.jsonl"));
+    assert_eq!(history::load(&config).unwrap(), [first, second]);
+}
+
+#[test]
+fn exporting() {
+    let dir = TempDir::new();
+    let sessions = [
+        session("a, \"b\"", MONDAY, HOUR, Outcome::Crashed),
+        session("a, \"b\"", MONDAY + HOUR, HOUR, Outcome::Exited),
+    ];
+    let stats = history::stats(&sessions, Period::Day);
+
+    history::export(&stats, dir.join("stats.csv")).unwrap();
+    history::export(&stats, dir.join("stats.json")).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(dir.join("stats.csv")).unwrap(),
+        "username,period,playtime_secs,sessions,crashes,crash_rate\n\
+         \"a, \"\"b\"\"\",2024-01-01,7200,2,1,0.500\n",
+    );
+    let json: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(dir.join("stats.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([{
+            "username": "a, \"b\"",
+            "period": "2024-01-01",
+            "playtime_secs": 7200,
+            "sessions": 2,
+            "crashes": 1,
+            "crash_rate": 0.5,
+        }]),
+    );
+}
+
+/// Sessions are recorded from command mode, & `stats` shows them.
+#[cfg(all(unix, not(all(target_os = "linux", feature = "secret-store"))))]
+#[test]
+fn stats_command() {
+    use serde_json::json;
+    use shticker_book_unwritten::{Updater, config};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        process::{Command, Stdio},
+    };
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, b"#!/bin/sh\nexec sleep 60\n");
+    server.push_login_response(json!({
+        "success": "true",
+        "cookie": "COOKIE",
+        "gameserver": "gameserver.example:7198",
+    }));
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    Updater::new(&config, &common::client())
+        .update(false)
+        .unwrap();
+    let config_path = dir.join("config.json");
+    config::commit_config(&config, &config_path).unwrap();
+
+    let mut child =
+        Command::new(env!("CARGO_BIN_EXE_shticker_book_unwritten"))
+            .arg("-c")
+            .arg(&config_path)
+            .args(["-n", "-o", "json", "-u", "toon"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+    let csv_path = dir.join("stats.csv");
+    let commands = format!(
+        "kill toon\nstats week\nstats export {} week\nquit\n",
+        csv_path.display(),
+    );
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(commands.as_bytes())
+        .unwrap();
+    let events: Vec<serde_json::Value> =
+        BufReader::new(child.stdout.take().unwrap())
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+    assert!(child.wait().unwrap().success());
+
+    let sessions = history::load(&config).unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].username, "toon");
+    assert_eq!(sessions[0].outcome, Outcome::Killed);
+    let shown = events.iter().find(|e| e.get("totals").is_some()).unwrap();
+    assert_eq!(shown["period"], "week");
+    assert_eq!(shown["totals"][0]["username"], "toon");
+    assert_eq!(shown["totals"][0]["sessions"], 1);
+    assert_eq!(shown["stats"][0]["sessions"], 1);
+    assert!(fs::read_to_string(csv_path).unwrap().contains("\ntoon,"));
+}
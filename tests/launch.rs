@@ -0,0 +1,167 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{GAME_EXECUTABLE, MockServer, Recorder, TempDir};
+use serde_json::json;
+use shticker_book_unwritten::{
+    Config, Error, PlayCredentials, Updater, config::LaunchSpec, login,
+};
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+/// Writes down how it was launched, in whatever directory it was launched
+/// in.
+const FAKE_GAME: &[u8] = b"#!/bin/sh\n\
+    printf '%s\\n' \"$TTR_PLAYCOOKIE\" \"$FOO\" \"${GONE-unset}\" \
+    \"${HOME-unset}\" \"$*\" > launched.txt\n";
+
+fn credentials() -> PlayCredentials {
+    PlayCredentials {
+        cookie: "COOKIE".to_owned(),
+        gameserver: "gameserver.example:7198".to_owned(),
+    }
+}
+
+/// A config with the fake game installed.
+fn installed(server: &MockServer, dir: &TempDir) -> Config {
+    server.publish(GAME_EXECUTABLE, FAKE_GAME);
+    let config = server.config(dir);
+    Updater::new(&config, &common::client())
+        .update(false)
+        .unwrap();
+
+    config
+}
+
+/// Launches as `username`, & waits for the game to exit.
+fn launch(config: &Config, username: &str) -> Result<(), Error> {
+    let mut child =
+        login::launch(config, &Recorder::new(), username, &credentials())?;
+    assert!(child.wait().unwrap().success());
+
+    Ok(())
+}
+
+fn read(path: impl AsRef<Path>) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn global_and_per_account_specs() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let mut config = installed(&server, &dir);
+    let wrapper = dir.join("wrapper.sh");
+    fs::write(
+        &wrapper,
+        "#!/bin/sh\nprintf '%s\\n' \"$*\" > wrapped.txt\nshift\nexec \"$@\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::create_dir(config.install_dir.join("wd")).unwrap();
+    config.launch_args = vec!["--launch-arg".to_owned()];
+    config.launch = serde_json::from_value(json!({
+        "wrapper": [wrapper, "--wrapper-arg"],
+        "env": { "FOO": "global", "GONE": "here" },
+        "args": ["--global-arg"],
+    }))
+    .unwrap();
+    config.accounts = serde_json::from_value(json!({
+        "toon": {
+            "password": "hunter2",
+            "launch": {
+                "env": { "FOO": "toon" },
+                "env_remove": ["GONE", "HOME"],
+                "working_dir": "wd",
+                "args": ["--toon-arg"],
+            },
+        },
+        "unwrapped": { "launch": { "wrapper": [] } },
+    }))
+    .unwrap();
+    config.validate().unwrap();
+    let exe = std::path::absolute(config.game_executable_path()).unwrap();
+    let wd = config.install_dir.join("wd");
+
+    launch(&config, "toon").unwrap();
+
+    assert_eq!(
+        read(wd.join("wrapped.txt")),
+        format!(
+            "--wrapper-arg {} --launch-arg --global-arg --toon-arg\n",
+            exe.display(),
+        ),
+    );
+    assert_eq!(
+        read(wd.join("launched.txt")),
+        "COOKIE\ntoon\nunset\nunset\n--launch-arg --global-arg --toon-arg\n",
+    );
+
+    launch(&config, "unwrapped").unwrap();
+
+    assert!(!config.install_dir.join("wrapped.txt").exists());
+    let launched = read(config.install_dir.join("launched.txt"));
+    let lines: Vec<_> = launched.lines().collect();
+    assert_eq!(lines[..3], ["COOKIE", "global", "here"]);
+    assert_eq!(lines[4], "--launch-arg --global-arg");
+
+    // Accounts without a spec of their own get the global one.
+    assert_eq!(config.launch_spec("someone else").unwrap(), config.launch);
+}
+
+#[test]
+fn missing_wrapper() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let mut config = installed(&server, &dir);
+    config.launch.wrapper =
+        Some(vec!["shticker-book-unwritten-no-such-wrapper".to_owned()]);
+
+    let err = launch(&config, "toon").unwrap_err();
+
+    assert!(
+        matches!(
+            &err,
+            Error::LaunchWrapperSpawn(wrapper, _)
+                if wrapper == "shticker-book-unwritten-no-such-wrapper",
+        ),
+        "{err:?}",
+    );
+}
+
+#[test]
+fn missing_working_dir() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let mut config = installed(&server, &dir);
+    config.launch.working_dir = Some("nowhere".into());
+
+    let err = launch(&config, "toon").unwrap_err();
+
+    assert!(matches!(err, Error::InvalidConfig(_)), "{err:?}");
+}
+
+#[test]
+fn invalid_specs_are_rejected() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+
+    for (launch, account_launch) in [
+        (json!({ "wrapper": [""] }), json!({})),
+        (json!({ "env": { "A=B": "C" } }), json!({})),
+        (json!({}), json!({ "env_remove": [""] })),
+        (json!({}), json!({ "wraper": ["gamemoderun"] })),
+        (json!({}), json!({ "args": "--foo" })),
+    ] {
+        let mut config = server.config(&dir);
+        config.accounts = serde_json::from_value(json!({
+            "toon": { "launch": account_launch },
+        }))
+        .unwrap();
+        config.launch = serde_json::from_value::<LaunchSpec>(launch).unwrap();
+
+        let err = config.validate().unwrap_err();
+
+        assert!(matches!(err, Error::InvalidConfig(_)), "{err:?}");
+    }
+}
@@ -0,0 +1,765 @@
+mod common;
+
+use common::{LOGIN_PATH, MockServer, Recorder};
+use serde_json::json;
+use shticker_book_unwritten::{
+    Error, LoginClient, LoginOutcome, PlayCredentials,
+};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+fn success() -> serde_json::Value {
+    json!({
+        "success": "true",
+        "cookie": "COOKIE",
+        "gameserver": "gameserver.example:7198",
+    })
+}
+
+fn login<F>(
+    server: &MockServer,
+    observer: &Recorder,
+    two_factor: F,
+) -> Result<LoginOutcome, Error>
+where
+    F: FnMut(&str) -> Result<Option<String>, Error>,
+{
+    let client = common::client();
+    let api_uri = server.login_api_uri();
+
+    LoginClient::new(&client)
+        .api_uri(&api_uri)
+        .observer(observer)
+        .login("toon", "hunter2", two_factor)
+}
+
+fn no_two_factor(_: &str) -> Result<Option<String>, Error> {
+    panic!("Wasn't expecting to be asked for a token");
+}
+
+fn expect_success(outcome: LoginOutcome) -> PlayCredentials {
+    match outcome {
+        LoginOutcome::Success(creds) => creds,
+        other => panic!("Expected success, got {other:?}"),
+    }
+}
+
+#[test]
+fn immediate_success() {
+    let server = MockServer::start();
+    server.push_login_response(success());
+    let recorder = Recorder::new();
+
+    let creds =
+        expect_success(login(&server, &recorder, no_two_factor).unwrap());
+
+    assert_eq!(creds.cookie, "COOKIE");
+    assert_eq!(creds.gameserver, "gameserver.example:7198");
+    let posts = server.requests_for(LOGIN_PATH);
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].method, "POST");
+    assert_eq!(posts[0].form["username"], "toon");
+    assert_eq!(posts[0].form["password"], "hunter2");
+    assert_eq!(recorder.named("logged_in").len(), 1);
+}
+
+#[test]
+fn boolean_success_values_are_accepted() {
+    let server = MockServer::start();
+    let mut resp = success();
+    resp["success"] = true.into();
+    server.push_login_response(resp);
+
+    expect_success(login(&server, &Recorder::new(), no_two_factor).unwrap());
+}
+
+#[test]
+fn queueing() {
+    let server = MockServer::start();
+    server.push_login_response(json!({
+        "success": "delayed",
+        "eta": "0",
+        "position": "2",
+        "queueToken": "QUEUE-1",
+    }));
+    server.push_login_response(json!({
+        "success": "delayed",
+        "eta": 0,
+        "position": 1,
+        "queueToken": "QUEUE-2",
+    }));
+    server.push_login_response(success());
+    let recorder = Recorder::new();
+
+    expect_success(login(&server, &recorder, no_two_factor).unwrap());
+
+    let posts = server.requests_for(LOGIN_PATH);
+    assert_eq!(posts.len(), 3);
+    assert_eq!(posts[1].form["queueToken"], "QUEUE-1");
+    assert_eq!(posts[2].form["queueToken"], "QUEUE-2");
+    assert!(!posts[1].form.contains_key("password"));
+    let positions: Vec<_> = recorder
+        .named("queue_position")
+        .iter()
+        .map(|e| e["position"].as_u64().unwrap())
+        .collect();
+    assert_eq!(positions, [2, 1]);
+}
+
+#[test]
+fn giving_up_on_the_queue() {
+    let server = MockServer::start();
+    server.push_login_response(json!({
+        "success": "delayed",
+        "eta": "0",
+        "position": "500",
+        "queueToken": "QUEUE-1",
+    }));
+    let recorder = Recorder::new();
+    let client = common::client();
+    let api_uri = server.login_api_uri();
+
+    let outcome = LoginClient::new(&client)
+        .api_uri(&api_uri)
+        .observer(&recorder)
+        .max_queue_wait(Some(Duration::ZERO))
+        .login("toon", "hunter2", no_two_factor)
+        .unwrap();
+
+    assert!(matches!(outcome, LoginOutcome::TimedOut), "{outcome:?}");
+    assert_eq!(server.requests_for(LOGIN_PATH).len(), 1);
+    assert_eq!(recorder.named("login_timed_out").len(), 1);
+}
+
+#[test]
+fn cancelling_while_queued() {
+    let server = MockServer::start();
+    server.push_login_response(json!({
+        "success": "delayed",
+        "eta": "600",
+        "position": "500",
+        "queueToken": "QUEUE-1",
+    }));
+    let client = common::client();
+    let api_uri = server.login_api_uri();
+    let cancel = AtomicBool::new(false);
+
+    let start = Instant::now();
+    let outcome = thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            cancel.store(true, Ordering::Relaxed);
+        });
+
+        LoginClient::new(&client)
+            .api_uri(&api_uri)
+            .cancel_on(&cancel)
+            .login("toon", "hunter2", no_two_factor)
+            .unwrap()
+    });
+
+    assert!(matches!(outcome, LoginOutcome::Cancelled), "{outcome:?}");
+    assert!(start.elapsed() < Duration::from_millis(1_000));
+    assert_eq!(server.requests_for(LOGIN_PATH).len(), 1);
+}
+
+#[test]
+fn two_factor() {
+    let server = MockServer::start();
+    server.push_login_response(json!({
+        "success": "partial",
+        "responseToken": "AUTH",
+        "banner": "Enter the token from your authenticator app",
+    }));
+    server.push_login_response(success());
+    let recorder = Recorder::new();
+    let mut banners = Vec::new();
+
+    expect_success(
+        login(&server, &recorder, |banner| {
+            banners.push(banner.to_owned());
+
+            Ok(Some("123456".to_owned()))
+        })
+        .unwrap(),
+    );
+
+    assert_eq!(banners, ["Enter the token from your authenticator app"]);
+    let posts = server.requests_for(LOGIN_PATH);
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[1].form["appToken"], "123456");
+    assert_eq!(posts[1].form["authToken"], "AUTH");
+    assert_eq!(recorder.named("two_factor_required").len(), 1);
+}
+
+#[test]
+fn two_factor_then_queueing() {
+    let server = MockServer::start();
+    server.push_login_response(json!({
+        "success": "partial",
+        "responseToken": "AUTH",
+        "banner": "Token?",
+    }));
+    server.push_login_response(json!({
+        "success": "delayed",
+        "eta": "0",
+        "position": "1",
+        "queueToken": "QUEUE",
+    }));
+    server.push_login_response(success());
+
+    expect_success(
+        login(&server, &Recorder::new(), |_| Ok(Some("654321".to_owned())))
+            .unwrap(),
+    );
+
+    let posts = server.requests_for(LOGIN_PATH);
+    assert_eq!(posts.len(), 3);
+    assert_eq!(posts[2].form["queueToken"], "QUEUE");
+}
+
+#[test]
+fn two_factor_cancelled() {
+    let server = MockServer::start();
+    server.push_login_response(json!({
+        "success": "partial",
+        "responseToken": "AUTH",
+        "banner": "Token?",
+    }));
+    server.push_login_response(success());
+
+    let outcome = login(&server, &Recorder::new(), |_| Ok(None)).unwrap();
+
+    assert!(matches!(outcome, LoginOutcome::Cancelled), "{outcome:?}");
+    assert_eq!(server.requests_for(LOGIN_PATH).len(), 1);
+    assert_eq!(server.pending_login_responses(), 1);
+}
+
+#[test]
+fn failed_login() {
+    let server = MockServer::start();
+    server.push_login_response(json!({
+        "success": "false",
+        "banner": "Incorrect username and/or password.",
+    }));
+    let recorder = Recorder::new();
+
+    let outcome = login(&server, &recorder, no_two_factor).unwrap();
+
+    let LoginOutcome::Failed(banner) = outcome else {
+        panic!("Expected failure, got {outcome:?}");
+    };
+    assert_eq!(banner, "Incorrect username and/or password.");
+    assert_eq!(recorder.named("login_failed")[0]["banner"], banner);
+}
+
+#[test]
+fn malformed_responses_are_errors() {
+    let server = MockServer::start();
+    server.push_login_response(json!({ "success": "true" }));
+    server.push_login_response(json!({ "success": "maybe" }));
+
+    let err = login(&server, &Recorder::new(), no_two_factor).unwrap_err();
+    assert!(matches!(err, Error::BadLoginResponse(_)), "{err:?}");
+
+    let err = login(&server, &Recorder::new(), no_two_factor).unwrap_err();
+    assert!(matches!(err, Error::UnexpectedSuccessValue(_)), "{err:?}");
+}
+
+/// Installs a fake game (a shell script that writes down how it was
+/// launched), logs in, & launches it.
+#[cfg(unix)]
+#[test]
+fn install_login_and_launch() {
+    use common::TempDir;
+    use shticker_book_unwritten::{Updater, login};
+    use std::fs;
+
+    const FAKE_GAME: &[u8] = b"#!/bin/sh\n\
+        printf '%s\\n' \"$TTR_PLAYCOOKIE\" \"$TTR_GAMESERVER\" \"$*\" \
+        > launched.txt\n";
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, FAKE_GAME);
+    server.push_login_response(json!({
+        "success": "delayed",
+        "eta": "0",
+        "position": "1",
+        "queueToken": "QUEUE",
+    }));
+    server.push_login_response(success());
+    let mut config = server.config(&dir);
+    config.launch_args = vec!["--foo".to_owned(), "bar".to_owned()];
+    let client = common::client();
+    let recorder = Recorder::new();
+
+    Updater::new(&config, &client)
+        .observer(&recorder)
+        .update(false)
+        .unwrap();
+    let creds = expect_success(
+        LoginClient::new(&client)
+            .api_uri(&config.login_api_uri)
+            .observer(&recorder)
+            .login("toon", "hunter2", no_two_factor)
+            .unwrap(),
+    );
+    let mut child = login::launch(&config, &recorder, "toon", &creds).unwrap();
+    assert!(child.wait().unwrap().success());
+
+    let launched =
+        fs::read_to_string(config.install_dir.join("launched.txt")).unwrap();
+    assert_eq!(launched, "COOKIE\ngameserver.example:7198\n--foo bar\n");
+    let launches = recorder.named("game_launched");
+    assert_eq!(launches.len(), 1);
+    assert_eq!(launches[0]["username"], "toon");
+    assert_eq!(launches[0]["pid"], child.id());
+}
+
+/// Logs in through the same path as the `login` command, with a saved
+/// password & TOTP secret, so that nothing needs to be typed in.
+///
+/// This & the other tests that log in with saved passwords don't exist with
+/// the `secret-store` feature, which saves passwords & TOTP secrets on the
+/// keyring, rather than in the config.
+#[cfg(all(unix, not(all(target_os = "linux", feature = "secret-store"))))]
+#[test]
+fn unattended_login_with_totp() {
+    use common::TempDir;
+    use shticker_book_unwritten::{
+        Updater,
+        login::{self, LaunchMode},
+        output::OutputFormat,
+        totp,
+    };
+
+    const SECRET: &str = "JBSWY3DPEHPK3PXP";
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, b"#!/bin/sh\n");
+    server.push_login_response(json!({
+        "success": "partial",
+        "responseToken": "AUTH",
+        "banner": "Token?",
+    }));
+    server.push_login_response(success());
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    config.set_totp_secret("toon", Some(SECRET.to_owned()));
+    let config_path = dir.join("config.json");
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+
+    let before = totp::code(SECRET).unwrap();
+    let mut children: Vec<(String, std::process::Child, Instant)> = Vec::new();
+    login::login(
+        &mut config,
+        &config_path,
+        &client,
+        true,
+        OutputFormat::Human,
+        &Recorder::new(),
+        ["toon"].into_iter(),
+        LaunchMode::Launch,
+        &mut children,
+    )
+    .unwrap();
+    let after = totp::code(SECRET).unwrap();
+
+    assert_eq!(children.len(), 1);
+    children[0].1.wait().unwrap();
+    let posts = server.requests_for(LOGIN_PATH);
+    assert_eq!(posts[0].form["password"], "hunter2");
+    let app_token = &posts[1].form["appToken"];
+    assert!(*app_token == before || *app_token == after, "{app_token}");
+    assert_eq!(posts[1].form["authToken"], "AUTH");
+    // Re-saving the password must not lose the secret.
+    let saved = shticker_book_unwritten::Config::load(&config_path).unwrap();
+    assert_eq!(saved.saved_password("toon"), Some("hunter2"));
+    assert_eq!(saved.totp_secret("toon"), Some(SECRET));
+}
+
+/// `login <username> --exec <command...>` runs the command (exactly as typed)
+/// instead of the game, & tracks it as an instance.
+#[cfg(all(unix, not(all(target_os = "linux", feature = "secret-store"))))]
+#[test]
+fn login_then_exec() {
+    use common::TempDir;
+    use shticker_book_unwritten::{
+        login::{self, LaunchMode},
+        output::OutputFormat,
+    };
+    use std::fs;
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.push_login_response(success());
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    fs::create_dir_all(&config.install_dir).unwrap();
+    let recorder = Recorder::new();
+
+    let (args, command) = login::split_exec(
+        "toon --exec echo \"$TTR_PLAYCOOKIE  $TTR_GAMESERVER $SBU_USERNAME\" \
+         > exec.txt",
+    );
+    assert_eq!(args, "toon ");
+    let mut children = Vec::new();
+    login::login(
+        &mut config,
+        dir.join("config.json"),
+        &common::client(),
+        true,
+        OutputFormat::Human,
+        &recorder,
+        args.split_whitespace(),
+        LaunchMode::Exec(command.unwrap().to_owned()),
+        &mut children,
+    )
+    .unwrap();
+
+    assert_eq!(children.len(), 1);
+    assert_eq!(children[0].0, "toon");
+    assert!(children[0].1.wait().unwrap().success());
+    let written =
+        fs::read_to_string(config.install_dir.join("exec.txt")).unwrap();
+    assert_eq!(written, "COOKIE  gameserver.example:7198 toon\n");
+    assert_eq!(recorder.named("game_launched").len(), 1);
+}
+
+/// `--print-env` prints exports that a shell can `eval`, even when the
+/// credentials have quotes in them, & launches nothing.
+#[cfg(all(unix, not(all(target_os = "linux", feature = "secret-store"))))]
+#[test]
+fn print_env_from_the_command_line() {
+    use common::TempDir;
+    use shticker_book_unwritten::config;
+    use std::process::Command;
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    let config_path = dir.join("config.json");
+    config::commit_config(&config, &config_path).unwrap();
+    let run = |args: &[&str]| {
+        let output =
+            Command::new(env!("CARGO_BIN_EXE_shticker_book_unwritten"))
+                .arg("-c")
+                .arg(&config_path)
+                .args(["-n", "-d", "-u", "toon", "--print-env"])
+                .args(args)
+                .output()
+                .unwrap();
+        assert!(output.status.success(), "{output:?}");
+
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    server.push_login_response(json!({
+        "success": "true",
+        "cookie": "it's a cookie",
+        "gameserver": "gameserver.example:7198",
+    }));
+    let exports = run(&[]);
+    assert_eq!(
+        exports,
+        "export TTR_PLAYCOOKIE='it'\\''s a cookie'\n\
+         export TTR_GAMESERVER='gameserver.example:7198'\n",
+    );
+    let evaled = Command::new("sh")
+        .arg("-c")
+        .arg(
+            "eval \"$1\"; \
+             printf '%s|%s' \"$TTR_PLAYCOOKIE\" \"$TTR_GAMESERVER\"",
+        )
+        .arg("sh")
+        .arg(&exports)
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(evaled.stdout).unwrap(),
+        "it's a cookie|gameserver.example:7198",
+    );
+
+    server.push_login_response(success());
+    let json_lines = run(&["-o", "json"]);
+    let env_events: Vec<serde_json::Value> = json_lines
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .filter(|event: &serde_json::Value| event["event"] == "login_env")
+        .collect();
+    assert_eq!(
+        env_events,
+        [json!({
+            "event": "login_env",
+            "username": "toon",
+            "env": {
+                "TTR_PLAYCOOKIE": "COOKIE",
+                "TTR_GAMESERVER": "gameserver.example:7198",
+            },
+        })],
+    );
+    assert!(!config.install_dir.join(common::GAME_EXECUTABLE).exists());
+}
+
+/// Prompting for a two-factor token in `--print-env` mode leaves stdout with
+/// nothing but the exports.
+#[cfg(all(unix, not(all(target_os = "linux", feature = "secret-store"))))]
+#[test]
+fn print_env_prompts_go_to_stderr() {
+    use common::TempDir;
+    use shticker_book_unwritten::config;
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    let config_path = dir.join("config.json");
+    config::commit_config(&config, &config_path).unwrap();
+    server.push_login_response(json!({
+        "success": "partial",
+        "responseToken": "AUTH",
+        "banner": "Enter the token from your authenticator app",
+    }));
+    server.push_login_response(success());
+
+    let mut child =
+        Command::new(env!("CARGO_BIN_EXE_shticker_book_unwritten"))
+            .arg("-c")
+            .arg(&config_path)
+            .args(["-n", "-d", "-u", "toon", "--print-env"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+    child.stdin.take().unwrap().write_all(b"123456\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "export TTR_PLAYCOOKIE='COOKIE'\n\
+         export TTR_GAMESERVER='gameserver.example:7198'\n",
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Enter the token from your authenticator app"),
+        "{stderr}",
+    );
+    assert_eq!(
+        server.requests_for(LOGIN_PATH)[1].form["appToken"],
+        "123456"
+    );
+}
+
+/// Logging into several accounts at once launches each game, spaced out by
+/// the stagger, & reports every account's status. Which account ends up
+/// waiting in the queue depends on which login gets to the server first.
+#[cfg(all(unix, not(all(target_os = "linux", feature = "secret-store"))))]
+#[test]
+fn logging_into_several_accounts_at_once() {
+    use common::TempDir;
+    use shticker_book_unwritten::{
+        Updater,
+        login::{self, LaunchMode},
+        output::OutputFormat,
+    };
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, b"#!/bin/sh\n");
+    server.push_login_response(json!({
+        "success": "delayed",
+        "eta": "0",
+        "position": "1",
+        "queueToken": "QUEUE",
+    }));
+    server.push_login_response(success());
+    server.push_login_response(success());
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    config.add_account("other".to_owned(), "hunter3".to_owned());
+    config.launch_stagger = Some(1);
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+    let recorder = Recorder::new();
+
+    let mut children = Vec::new();
+    login::login(
+        &mut config,
+        dir.join("config.json"),
+        &client,
+        true,
+        OutputFormat::Human,
+        &recorder,
+        ["toon", "other"].into_iter(),
+        LaunchMode::Launch,
+        &mut children,
+    )
+    .unwrap();
+
+    assert_eq!(server.pending_login_responses(), 0);
+    let mut launched: Vec<_> = children
+        .iter()
+        .map(|(username, ..)| username.as_str())
+        .collect();
+    launched.sort_unstable();
+    assert_eq!(launched, ["other", "toon"]);
+    assert!(
+        children[1].2.duration_since(children[0].2) >= Duration::from_secs(1)
+    );
+    for (_, child, _) in &mut children {
+        child.wait().unwrap();
+    }
+    let statuses = recorder.named("login_statuses");
+    assert!(statuses.iter().any(|e| {
+        e["accounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|account| account["status"] == "queued")
+    }));
+    let last = &statuses.last().unwrap()["accounts"];
+    assert_eq!(last[0]["username"], "toon");
+    assert_eq!(last[1]["username"], "other");
+    assert_eq!(last[0]["status"], "logged_in");
+    assert_eq!(last[1]["status"], "logged_in");
+    // The usual login events are replaced.
+    assert!(recorder.named("queue_position").is_empty());
+}
+
+/// One account failing to log in doesn't stop the others from launching.
+#[cfg(all(unix, not(all(target_os = "linux", feature = "secret-store"))))]
+#[test]
+fn one_of_several_logins_failing() {
+    use common::TempDir;
+    use shticker_book_unwritten::{
+        Updater,
+        login::{self, LaunchMode},
+        output::OutputFormat,
+    };
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, b"#!/bin/sh\n");
+    server.push_login_response(json!({
+        "success": "false",
+        "banner": "Incorrect password.",
+    }));
+    server.push_login_response(success());
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    config.add_account("other".to_owned(), "hunter3".to_owned());
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+    let recorder = Recorder::new();
+
+    let mut children = Vec::new();
+    login::login(
+        &mut config,
+        dir.join("config.json"),
+        &client,
+        true,
+        OutputFormat::Human,
+        &recorder,
+        ["toon", "other"].into_iter(),
+        LaunchMode::Launch,
+        &mut children,
+    )
+    .unwrap();
+
+    assert_eq!(children.len(), 1);
+    children[0].1.wait().unwrap();
+    let last = recorder.named("login_statuses").pop().unwrap();
+    let mut statuses: Vec<_> = last["accounts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|account| account["status"].as_str().unwrap().to_owned())
+        .collect();
+    statuses.sort_unstable();
+    assert_eq!(statuses, ["failed", "logged_in"]);
+    assert!(
+        last["accounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|account| account["reason"] == "Incorrect password.")
+    );
+}
+
+/// What happens when the install is out of date right before launching,
+/// under each `launch_check` policy.
+#[test]
+fn outdated_install_before_launching() {
+    use common::{GAME_EXECUTABLE, MANIFEST_PATH, TempDir};
+    use shticker_book_unwritten::{Updater, config::LaunchCheck, login};
+    use std::fs;
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, b"#!/bin/sh\n# v1\n");
+    let mut config = server.config(&dir);
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+    let exe_path = config.install_dir.join(GAME_EXECUTABLE);
+    let recorder = Recorder::new();
+    let check = |config: &_, interactive, others_running| {
+        login::check_install(
+            config,
+            &client,
+            true,
+            &recorder,
+            interactive,
+            others_running,
+        )
+        .unwrap()
+    };
+
+    assert!(check(&config, false, false));
+    assert!(recorder.named("install_outdated").is_empty());
+    server.publish(GAME_EXECUTABLE, b"#!/bin/sh\n# v2\n");
+
+    // Without anyone there to ask, asking is the same as warning.
+    for policy in [LaunchCheck::Ask, LaunchCheck::Warn] {
+        config.launch_check = policy;
+        assert!(check(&config, false, false));
+    }
+    let outdated = recorder.named("install_outdated");
+    assert_eq!(outdated.len(), 2);
+    assert_eq!(outdated[0]["files"], json!([GAME_EXECUTABLE]));
+
+    config.launch_check = LaunchCheck::Never;
+    let manifest_requests = server.requests_for(MANIFEST_PATH).len();
+    assert!(check(&config, true, false));
+    assert_eq!(server.requests_for(MANIFEST_PATH).len(), manifest_requests);
+
+    // Not while other instances are running.
+    config.launch_check = LaunchCheck::Update;
+    assert!(check(&config, false, true));
+    assert_eq!(fs::read(&exe_path).unwrap(), b"#!/bin/sh\n# v1\n");
+    assert!(check(&config, false, false));
+    assert_eq!(fs::read(&exe_path).unwrap(), b"#!/bin/sh\n# v2\n");
+    assert_eq!(recorder.named("install_outdated").len(), 4);
+
+    // The check failing doesn't stop anything from launching.
+    config.manifest_uri = format!("{}/nothing-here", server.login_api_uri());
+    assert!(check(&config, false, false));
+}
@@ -0,0 +1,361 @@
+//! Every transition of the login negotiation, driven by canned responses
+//! rather than by a server.
+
+use serde_json::json;
+use shticker_book_unwritten::{
+    Error, LoginOutcome,
+    login_api::{Limits, Negotiation, Request, Response, Step},
+};
+use std::time::{Duration, Instant};
+
+fn success() -> Response {
+    Response::Success {
+        cookie: "COOKIE".to_owned(),
+        gameserver: "GS".to_owned(),
+    }
+}
+
+fn delayed(eta: f64, queue_token: &str) -> Response {
+    Response::Delayed {
+        eta,
+        position: 1,
+        queue_token: queue_token.to_owned(),
+    }
+}
+
+fn partial() -> Response {
+    Response::Partial {
+        banner: "Token?".to_owned(),
+        response_token: "AUTH".to_owned(),
+    }
+}
+
+fn expect_post(step: Step) -> (Request, Duration) {
+    match step {
+        Step::Post { request, delay } => (request, delay),
+        other => panic!("Expected a post, got {other:?}"),
+    }
+}
+
+fn expect_done(step: Step) -> LoginOutcome {
+    match step {
+        Step::Done(outcome) => outcome,
+        other => panic!("Expected to be done, got {other:?}"),
+    }
+}
+
+/// A negotiation that has already sent the credentials.
+fn started(limits: Limits, now: Instant) -> Negotiation {
+    let mut negotiation = Negotiation::new(limits, now);
+    assert_eq!(
+        expect_post(negotiation.next(now)),
+        (Request::Credentials, Duration::ZERO),
+    );
+
+    negotiation
+}
+
+#[test]
+fn credentials_then_success() {
+    let now = Instant::now();
+    let mut negotiation = started(Limits::default(), now);
+    assert!(negotiation.outcome().is_none());
+
+    negotiation.on_response(success(), now);
+
+    let LoginOutcome::Success(creds) = expect_done(negotiation.next(now))
+    else {
+        panic!("Expected success");
+    };
+    assert_eq!(
+        (creds.cookie.as_str(), creds.gameserver.as_str()),
+        ("COOKIE", "GS")
+    );
+    // Stays done.
+    assert!(matches!(negotiation.next(now), Step::Done(_)));
+    assert!(negotiation.outcome().is_some());
+}
+
+#[test]
+fn credentials_then_failure() {
+    let now = Instant::now();
+    let mut negotiation = started(Limits::default(), now);
+
+    negotiation.on_response(
+        Response::Failed {
+            banner: "Nope".to_owned(),
+        },
+        now,
+    );
+
+    assert!(matches!(
+        expect_done(negotiation.next(now)),
+        LoginOutcome::Failed(banner) if banner == "Nope",
+    ));
+}
+
+#[test]
+fn queue_tokens_are_posted_back_with_backoff() {
+    let now = Instant::now();
+    let mut negotiation = started(Limits::default(), now);
+
+    for (eta, expected_delay) in [(0.0, 25), (1.0, 500), (60.0, 1_500)] {
+        negotiation.on_response(delayed(eta, &format!("Q{eta}")), now);
+        assert_eq!(
+            expect_post(negotiation.next(now)),
+            (
+                Request::QueueToken(format!("Q{eta}")),
+                Duration::from_millis(expected_delay),
+            ),
+        );
+    }
+
+    negotiation.on_response(success(), now);
+    assert!(matches!(
+        expect_done(negotiation.next(now)),
+        LoginOutcome::Success(_),
+    ));
+}
+
+#[test]
+fn two_factor_token_is_posted_with_auth_token() {
+    let now = Instant::now();
+    let mut negotiation = started(Limits::default(), now);
+
+    negotiation.on_response(partial(), now);
+    let Step::AskForToken { banner } = negotiation.next(now) else {
+        panic!("Expected to be asked for a token");
+    };
+    assert_eq!(banner, "Token?");
+    // Asks again if no token is given yet.
+    assert!(matches!(negotiation.next(now), Step::AskForToken { .. }));
+
+    negotiation.on_token(Some("123456".to_owned()));
+    assert_eq!(
+        expect_post(negotiation.next(now)),
+        (
+            Request::TwoFactor {
+                app_token: "123456".to_owned(),
+                auth_token: "AUTH".to_owned(),
+            },
+            Duration::ZERO,
+        ),
+    );
+
+    // A rejected token gets another prompt.
+    negotiation.on_response(partial(), now);
+    assert!(matches!(negotiation.next(now), Step::AskForToken { .. }));
+}
+
+#[test]
+fn declining_to_give_a_token_cancels() {
+    let now = Instant::now();
+    let mut negotiation = started(Limits::default(), now);
+    negotiation.on_response(partial(), now);
+    negotiation.next(now);
+
+    negotiation.on_token(None);
+
+    assert!(matches!(
+        expect_done(negotiation.next(now)),
+        LoginOutcome::Cancelled,
+    ));
+}
+
+#[test]
+fn cancelling_from_any_state() {
+    let now = Instant::now();
+    let queued = |n: &mut Negotiation| n.on_response(delayed(0.0, "Q"), now);
+    let needs_token = |n: &mut Negotiation| n.on_response(partial(), now);
+
+    for setup in [&queued as &dyn Fn(&mut Negotiation), &needs_token] {
+        let mut negotiation = started(Limits::default(), now);
+        setup(&mut negotiation);
+
+        negotiation.cancel();
+
+        assert!(matches!(
+            expect_done(negotiation.next(now)),
+            LoginOutcome::Cancelled,
+        ));
+    }
+
+    let mut negotiation = Negotiation::new(Limits::default(), now);
+    negotiation.cancel();
+    assert!(matches!(
+        expect_done(negotiation.next(now)),
+        LoginOutcome::Cancelled,
+    ));
+
+    // ...But an outcome that was already reached is kept.
+    let mut negotiation = started(Limits::default(), now);
+    negotiation.on_response(success(), now);
+    negotiation.cancel();
+    assert!(matches!(
+        expect_done(negotiation.next(now)),
+        LoginOutcome::Success(_),
+    ));
+}
+
+#[test]
+fn overall_timeout() {
+    let start = Instant::now();
+    let limits = Limits {
+        timeout: Some(Duration::from_secs(10)),
+        max_queue_wait: None,
+    };
+    let mut negotiation = started(limits, start);
+
+    negotiation.on_response(delayed(0.0, "Q"), start + Duration::from_secs(5));
+    expect_post(negotiation.next(start + Duration::from_secs(5)));
+    negotiation
+        .on_response(delayed(0.0, "Q"), start + Duration::from_secs(10));
+
+    assert!(matches!(
+        expect_done(negotiation.next(start + Duration::from_secs(10))),
+        LoginOutcome::TimedOut,
+    ));
+}
+
+#[test]
+fn timeout_applies_while_waiting_for_a_token() {
+    let start = Instant::now();
+    let limits = Limits {
+        timeout: Some(Duration::from_secs(10)),
+        max_queue_wait: None,
+    };
+    let mut negotiation = started(limits, start);
+    negotiation.on_response(partial(), start);
+
+    assert!(matches!(
+        expect_done(negotiation.next(start + Duration::from_secs(11))),
+        LoginOutcome::TimedOut,
+    ));
+}
+
+#[test]
+fn max_queue_wait() {
+    let start = Instant::now();
+    let limits = Limits {
+        timeout: None,
+        max_queue_wait: Some(Duration::from_secs(30)),
+    };
+    let at = |secs| start + Duration::from_secs(secs);
+    let mut negotiation = started(limits, start);
+
+    negotiation.on_response(delayed(0.0, "Q"), at(1));
+    expect_post(negotiation.next(at(1)));
+    negotiation.on_response(delayed(0.0, "Q"), at(20));
+    expect_post(negotiation.next(at(20)));
+    // Leaving the queue (for two-factor authentication, here) resets the
+    // clock.
+    negotiation.on_response(partial(), at(25));
+    negotiation.next(at(25));
+    negotiation.on_token(Some("123456".to_owned()));
+    expect_post(negotiation.next(at(25)));
+    negotiation.on_response(delayed(0.0, "Q"), at(40));
+    expect_post(negotiation.next(at(40)));
+    negotiation.on_response(delayed(0.0, "Q"), at(69));
+    expect_post(negotiation.next(at(69)));
+    negotiation.on_response(delayed(0.0, "Q"), at(70));
+
+    assert!(matches!(
+        expect_done(negotiation.next(at(70))),
+        LoginOutcome::TimedOut,
+    ));
+}
+
+#[test]
+fn responses_are_parsed() {
+    for (json, expected) in [
+        (
+            json!({ "success": "true", "cookie": "C", "gameserver": "G" }),
+            Response::Success {
+                cookie: "C".to_owned(),
+                gameserver: "G".to_owned(),
+            },
+        ),
+        (
+            json!({ "success": true, "cookie": "C", "gameserver": "G" }),
+            Response::Success {
+                cookie: "C".to_owned(),
+                gameserver: "G".to_owned(),
+            },
+        ),
+        (
+            json!({
+                "success": "delayed",
+                "eta": "1.5",
+                "position": "7",
+                "queueToken": "Q",
+            }),
+            Response::Delayed {
+                eta: 1.5,
+                position: 7,
+                queue_token: "Q".to_owned(),
+            },
+        ),
+        (
+            json!({
+                "success": "delayed",
+                "eta": 3,
+                "position": 8,
+                "queueToken": "Q",
+            }),
+            Response::Delayed {
+                eta: 3.0,
+                position: 8,
+                queue_token: "Q".to_owned(),
+            },
+        ),
+        (
+            json!({
+                "success": "partial",
+                "banner": "B",
+                "responseToken": "R",
+            }),
+            Response::Partial {
+                banner: "B".to_owned(),
+                response_token: "R".to_owned(),
+            },
+        ),
+        (
+            json!({ "success": "false", "banner": "B" }),
+            Response::Failed {
+                banner: "B".to_owned(),
+            },
+        ),
+        (
+            json!({ "success": false, "banner": "B" }),
+            Response::Failed {
+                banner: "B".to_owned(),
+            },
+        ),
+    ] {
+        assert_eq!(Response::from_json(&json).unwrap(), expected, "{json}");
+    }
+}
+
+#[test]
+fn malformed_responses_are_rejected() {
+    for json in [
+        json!({}),
+        json!({ "success": 1 }),
+        json!({ "success": "true", "cookie": "C" }),
+        json!({ "success": "delayed", "eta": "soon", "position": 1,
+                "queueToken": "Q" }),
+        json!({ "success": "delayed", "eta": 1, "position": -1,
+                "queueToken": "Q" }),
+        json!({ "success": "partial", "banner": "B" }),
+        json!({ "success": "false" }),
+    ] {
+        let err = Response::from_json(&json).unwrap_err();
+        assert!(matches!(err, Error::BadLoginResponse(_)), "{json}: {err:?}");
+    }
+
+    let err = Response::from_json(&json!({ "success": "maybe" })).unwrap_err();
+    assert!(
+        matches!(&err, Error::UnexpectedSuccessValue(s) if s == "maybe"),
+        "{err:?}",
+    );
+}
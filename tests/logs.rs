@@ -0,0 +1,326 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{GAME_EXECUTABLE, MockServer, Recorder, TempDir};
+use shticker_book_unwritten::{
+    Config, PlayCredentials, Updater, config, login, logs,
+};
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Writes to both stdout & stderr.
+const NOISY_GAME: &[u8] = b"#!/bin/sh\n\
+    echo \"starting $TTR_GAMESERVER\"\n\
+    echo 'something went wrong' >&2\n\
+    echo 'exiting'\n";
+
+fn installed(server: &MockServer, dir: &TempDir) -> Config {
+    server.publish(GAME_EXECUTABLE, NOISY_GAME);
+    let config = server.config(dir);
+    Updater::new(&config, &common::client())
+        .update(false)
+        .unwrap();
+
+    config
+}
+
+/// Launches as `username`, waits for the game to exit, & returns its PID.
+fn launch(config: &Config, recorder: &Recorder, username: &str) -> u32 {
+    let credentials = PlayCredentials {
+        cookie: "COOKIE".to_owned(),
+        gameserver: "gameserver.example:7198".to_owned(),
+    };
+    let mut child =
+        login::launch(config, recorder, username, &credentials).unwrap();
+    assert!(child.wait().unwrap().success());
+
+    child.id()
+}
+
+#[test]
+fn output_is_logged_per_instance() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let config = installed(&server, &dir);
+    let recorder = Recorder::new();
+
+    let pid = launch(&config, &recorder, "toon");
+
+    let log = logs::find(&config, "toon").unwrap().unwrap();
+    assert_eq!(log, logs::find(&config, &pid.to_string()).unwrap().unwrap());
+    assert_eq!((log.username.as_str(), log.pid), ("toon", pid));
+    assert_eq!(log.path.parent().unwrap(), dir.join("cache").join("logs"));
+    assert_eq!(
+        log.path.file_name().unwrap().to_str().unwrap(),
+        format!("toon-{pid}-{}.log", log.timestamp),
+    );
+    assert_eq!(
+        logs::tail(&log.path, 20).unwrap(),
+        [
+            "starting gameserver.example:7198",
+            "something went wrong",
+            "exiting",
+        ],
+    );
+    assert_eq!(logs::tail(&log.path, 1).unwrap(), ["exiting"]);
+    let launches = recorder.named("game_launched");
+    assert_eq!(launches[0]["log"], log.path.to_str().unwrap());
+    assert!(logs::find(&config, "someone else").unwrap().is_none());
+}
+
+#[test]
+fn usernames_are_made_safe_for_file_names() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let config = installed(&server, &dir);
+
+    let pid = launch(&config, &Recorder::new(), "../a-b c");
+
+    let log = logs::find(&config, "../a-b c").unwrap().unwrap();
+    assert_eq!((log.username.as_str(), log.pid), (".._a-b_c", pid));
+    assert_eq!(log.path.parent().unwrap(), dir.join("cache").join("logs"));
+}
+
+#[test]
+fn old_logs_are_pruned() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let mut config = installed(&server, &dir);
+    config.max_logs = 3;
+    config.max_log_age = Some(30);
+    let logs_dir = config.logs_dir();
+    fs::create_dir_all(&logs_dir).unwrap();
+    // From 1970, so it's too old, & from a PID that's higher than any that
+    // a running process could have.
+    fs::write(logs_dir.join("toon-999999999-1000.log"), "").unwrap();
+    // Not a log file, so it's left alone.
+    fs::write(logs_dir.join("notes.txt"), "").unwrap();
+
+    let pids: Vec<_> = (0..5)
+        .map(|_| launch(&config, &Recorder::new(), "toon"))
+        .collect();
+
+    let remaining: Vec<_> = logs::list(&config)
+        .unwrap()
+        .into_iter()
+        .map(|log| log.pid)
+        .collect();
+    assert_eq!(remaining, pids[2..]);
+    assert!(logs_dir.join("notes.txt").exists());
+}
+
+/// Even the oldest log file is kept for as long as its instance is running.
+#[test]
+fn logs_of_running_instances_are_not_pruned() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let mut config = installed(&server, &dir);
+    config.max_logs = 2;
+    config.max_log_age = Some(30);
+    let logs_dir = config.logs_dir();
+    fs::create_dir_all(&logs_dir).unwrap();
+    // This test's own process stands in for a running instance.
+    let running = std::process::id();
+    let running_log = logs_dir.join(format!("toon-{running}-1000.log"));
+    fs::write(&running_log, "still going").unwrap();
+
+    let pids: Vec<_> = (0..3)
+        .map(|_| launch(&config, &Recorder::new(), "toon"))
+        .collect();
+
+    let remaining: Vec<_> = logs::list(&config)
+        .unwrap()
+        .into_iter()
+        .map(|log| log.pid)
+        .collect();
+    assert_eq!(remaining, [running, pids[2]]);
+    assert_eq!(fs::read_to_string(&running_log).unwrap(), "still going");
+}
+
+/// Launching the same account more than once in the same second gives each
+/// instance a log file of its own.
+#[test]
+fn pending_logs_do_not_collide() {
+    let dir = TempDir::new();
+    let mut config = MockServer::start().config(&dir);
+    config.max_logs = 10;
+
+    let (first, mut first_file) =
+        logs::PendingLog::create(&config, "toon").unwrap();
+    let (second, mut second_file) =
+        logs::PendingLog::create(&config, "toon").unwrap();
+    first_file.write_all(b"first").unwrap();
+    second_file.write_all(b"second").unwrap();
+    let first = first.launched(1_000_001).unwrap();
+    let second = second.launched(1_000_002).unwrap();
+
+    assert_eq!(fs::read_to_string(first).unwrap(), "first");
+    assert_eq!(fs::read_to_string(second).unwrap(), "second");
+}
+
+/// Pending log files are left behind if the launcher is killed before the
+/// game is launched, so they're pruned once their launcher is gone.
+#[test]
+fn stale_pending_logs_are_pruned() {
+    let dir = TempDir::new();
+    let config = MockServer::start().config(&dir);
+    let logs_dir = config.logs_dir();
+    fs::create_dir_all(&logs_dir).unwrap();
+    let stale = logs_dir.join("toon-1000-999999999-0.log.pending");
+    fs::write(&stale, "").unwrap();
+
+    let (pending, _) = logs::PendingLog::create(&config, "toon").unwrap();
+    logs::prune(&config, 0);
+
+    assert!(!stale.exists());
+    // This process is still running, so its own pending log file is kept.
+    assert!(pending.launched(1_000_001).is_ok());
+}
+
+#[test]
+fn logs_are_rotated_past_the_max_size() {
+    let dir = TempDir::new();
+    let path = dir.join("toon-1-1000.log");
+    let mut file = fs::File::options()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b"small\n").unwrap();
+
+    assert!(!logs::rotate(&path, 10).unwrap());
+
+    file.write_all(b"and now too big\n").unwrap();
+    assert!(logs::rotate(&path, 10).unwrap());
+    assert_eq!(fs::read(&path).unwrap(), b"");
+    assert_eq!(
+        fs::read_to_string(dir.join("toon-1-1000.log.old")).unwrap(),
+        "small\nand now too big\n",
+    );
+
+    // Still appended to, from the start.
+    file.write_all(b"after\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+    // Including what was rotated away.
+    assert_eq!(logs::tail(&path, 2).unwrap(), ["and now too big", "after"],);
+}
+
+/// A game that's still running has its log file rotated as it goes.
+#[test]
+fn running_games_have_their_logs_rotated() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    // Floods its log, & then waits for it to be rotated.
+    server.publish(
+        GAME_EXECUTABLE,
+        b"#!/bin/sh\n\
+          i=0\n\
+          while [ $i -lt 1000 ]; do echo \"line $i\"; i=$((i + 1)); done\n\
+          i=0\n\
+          while ! ls \"$LOGS\"/toon-$$-*.log.old > /dev/null 2>&1 \
+              && [ $i -lt 100 ]; do\n\
+              sleep 0.1; i=$((i + 1))\n\
+          done\n\
+          echo 'after rotation'\n",
+    );
+    let mut config = server.config(&dir);
+    Updater::new(&config, &common::client())
+        .update(false)
+        .unwrap();
+    config.max_log_size = 0x1000;
+    config
+        .launch
+        .env
+        .insert("LOGS".to_owned(), config.logs_dir().display().to_string());
+
+    launch(&config, &Recorder::new(), "toon");
+
+    // Whether it's rotated during the flood or after it depends on timing.
+    let log = logs::find(&config, "toon").unwrap().unwrap();
+    let current = fs::read_to_string(&log.path).unwrap();
+    assert!(!current.starts_with("line 0\n"), "{current}");
+    assert!(current.ends_with("after rotation\n"), "{current}");
+    let rotated =
+        fs::read_to_string(log.path.with_extension("log.old")).unwrap();
+    assert!(rotated.lines().all(|line| line.starts_with("line ")));
+}
+
+#[test]
+fn logging_can_be_turned_off() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let mut config = installed(&server, &dir);
+    config.max_logs = 0;
+    let recorder = Recorder::new();
+
+    launch(&config, &recorder, "toon");
+
+    assert!(!config.logs_dir().exists());
+    assert!(recorder.named("game_launched")[0]["log"].is_null());
+}
+
+#[test]
+fn tail_of_a_large_log() {
+    let dir = TempDir::new();
+    let path = dir.join("big.log");
+    let mut file = fs::File::create(&path).unwrap();
+    for i in 0..100_000 {
+        writeln!(file, "line {i}").unwrap();
+    }
+
+    assert_eq!(
+        logs::tail(&path, 3).unwrap(),
+        ["line 99997", "line 99998", "line 99999"],
+    );
+    let many = logs::tail(&path, 1_000_000).unwrap();
+    assert!(many.len() < 100_000);
+    assert!(many[0].starts_with("line "));
+    assert_eq!(many.last().unwrap(), "line 99999");
+}
+
+#[test]
+fn logs_command() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    let config = installed(&server, &dir);
+    let config_path = dir.join("config.json");
+    config::commit_config(&config, &config_path).unwrap();
+    let pid = launch(&config, &Recorder::new(), "toon");
+
+    let mut child =
+        Command::new(env!("CARGO_BIN_EXE_shticker_book_unwritten"))
+            .arg("-c")
+            .arg(&config_path)
+            .args(["-n", "-o", "json"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"logs toon 2\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let shown: serde_json::Value = serde_json::from_str(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(shown["username"], "toon");
+    assert_eq!(shown["pid"], pid);
+    assert_eq!(
+        shown["lines"],
+        serde_json::json!(["something went wrong", "exiting"]),
+    );
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use common::{sha1_hex, sha256_hex};
+use serde_json::json;
+use shticker_book_unwritten::{
+    Error, Manifest,
+    hash::{Hash, HashAlgorithm},
+    manifest::{ManifestFile, OS_AND_ARCH},
+};
+
+fn hash(hex: &str) -> Hash {
+    Hash::from_hex(hex).unwrap()
+}
+
+fn only_file(manifest: serde_json::Value) -> ManifestFile {
+    let mut files = Manifest::from_json(manifest).unwrap().files;
+    assert_eq!(files.len(), 1);
+
+    files.remove(0)
+}
+
+#[test]
+fn sha256_is_preferred_over_sha1() {
+    let file = only_file(json!({
+        "phase_3.mf": {
+            "dl": "phase_3.mf.bz2",
+            "only": [OS_AND_ARCH],
+            "hash": sha1_hex(b"sha-1 says this"),
+            "sha256": sha256_hex(b"sha-256 says this"),
+            "compHash": sha1_hex(b"compressed, sha-1 says this"),
+            "compSha256": sha256_hex(b"compressed, sha-256 says this"),
+            "patches": {
+                sha1_hex(b"old"): {
+                    "filename": "phase_3.mf.patch.bz2",
+                    "patchHash": sha1_hex(b"patch"),
+                    "patchSha256": sha256_hex(b"patch"),
+                    "compPatchHash": sha1_hex(b"compressed patch"),
+                    "compPatchSha256": sha256_hex(b"compressed patch"),
+                },
+            },
+        },
+    }));
+
+    assert_eq!(file.hash, hash(&sha256_hex(b"sha-256 says this")));
+    assert_eq!(
+        file.comp_hash,
+        hash(&sha256_hex(b"compressed, sha-256 says this")),
+    );
+    let patch = &file.patches[0];
+    assert_eq!(patch.patch_hash, hash(&sha256_hex(b"patch")));
+    assert_eq!(
+        patch.comp_patch_hash,
+        hash(&sha256_hex(b"compressed patch")),
+    );
+}
+
+#[test]
+fn sha1_is_used_without_sha256() {
+    let file = only_file(json!({
+        "phase_3.mf": {
+            "dl": "phase_3.mf.bz2",
+            "only": [OS_AND_ARCH],
+            "hash": sha1_hex(b"new"),
+            "compHash": sha1_hex(b"compressed"),
+        },
+    }));
+
+    assert_eq!(file.hash, hash(&sha1_hex(b"new")));
+    assert_eq!(file.hash.algorithm(), HashAlgorithm::Sha1);
+    assert_eq!(file.comp_hash, hash(&sha1_hex(b"compressed")));
+    assert!(file.patches.is_empty());
+}
+
+/// Patches may be keyed by the SHA-1 or the SHA-256 hash of the version that
+/// they apply to, even within the same file's entry.
+#[test]
+fn patches_with_mixed_length_from_hashes() {
+    let file = only_file(json!({
+        "phase_3.mf": {
+            "dl": "phase_3.mf.bz2",
+            "only": [OS_AND_ARCH],
+            "hash": sha1_hex(b"new"),
+            "sha256": sha256_hex(b"new"),
+            "compHash": sha1_hex(b"compressed"),
+            "patches": {
+                sha1_hex(b"v1"): {
+                    "filename": "phase_3.mf.v1.patch.bz2",
+                    "patchHash": sha1_hex(b"patch from v1"),
+                    "compPatchHash": sha1_hex(b"compressed patch from v1"),
+                },
+                sha256_hex(b"v2"): {
+                    "filename": "phase_3.mf.v2.patch.bz2",
+                    "patchHash": sha1_hex(b"patch from v2"),
+                    "compPatchHash": sha1_hex(b"compressed patch from v2"),
+                },
+            },
+        },
+    }));
+
+    assert_eq!(
+        file.hash_algorithms(),
+        [HashAlgorithm::Sha256, HashAlgorithm::Sha1],
+    );
+    let patch_from = |version: &[u8]| {
+        let local = [hash(&sha256_hex(version)), hash(&sha1_hex(version))];
+
+        file.patch_from(&local).map(|patch| patch.filename.as_str())
+    };
+    assert_eq!(patch_from(b"v1"), Some("phase_3.mf.v1.patch.bz2"));
+    assert_eq!(patch_from(b"v2"), Some("phase_3.mf.v2.patch.bz2"));
+    assert_eq!(patch_from(b"v3"), None);
+    // Only the SHA-1 hash of v2 is known, which isn't what it's keyed by.
+    assert!(file.patch_from(&[hash(&sha1_hex(b"v2"))]).is_none());
+}
+
+#[test]
+fn hashes_of_the_wrong_length_are_rejected() {
+    let sha1 = sha1_hex(b"x");
+    let sha256 = sha256_hex(b"x");
+
+    assert_eq!(hash(&sha1).algorithm(), HashAlgorithm::Sha1);
+    assert_eq!(hash(&sha256).algorithm(), HashAlgorithm::Sha256);
+    assert_eq!(hash(&sha1.to_uppercase()), hash(&sha1));
+    for hex in [
+        "",
+        &sha1[..39],
+        &format!("{sha1}0"),
+        &sha256[..63],
+        &format!("{sha256}0"),
+        &sha256[..48],
+    ] {
+        assert!(Hash::from_hex(hex).is_err(), "{hex:?}");
+    }
+    assert!(Hash::from_hex(&format!("{}g", &sha1[..39])).is_err());
+    assert!(Hash::from_hex_as(HashAlgorithm::Sha1, &sha256).is_err());
+    assert!(Hash::from_hex_as(HashAlgorithm::Sha256, &sha1).is_err());
+
+    // Wherever they are in the manifest.
+    for (key, value) in [
+        ("hash", &sha1[..39]),
+        ("sha256", &sha1),
+        ("compSha256", &sha256[..63]),
+    ] {
+        let mut entry = json!({
+            "dl": "phase_3.mf.bz2",
+            "only": [OS_AND_ARCH],
+            "hash": sha1,
+            "compHash": sha1,
+        });
+        entry[key] = json!(value);
+
+        let err =
+            Manifest::from_json(json!({ "phase_3.mf": entry })).unwrap_err();
+
+        assert!(matches!(err, Error::BadManifestFormat(_)), "{err:?}");
+    }
+    let err = Manifest::from_json(json!({
+        "phase_3.mf": {
+            "dl": "phase_3.mf.bz2",
+            "only": [OS_AND_ARCH],
+            "hash": sha1,
+            "compHash": sha1,
+            "patches": {
+                &sha256[..50]: {
+                    "filename": "phase_3.mf.patch.bz2",
+                    "patchHash": sha1,
+                    "compPatchHash": sha1,
+                },
+            },
+        },
+    }))
+    .unwrap_err();
+    assert!(matches!(err, Error::BadManifestFormat(_)), "{err:?}");
+}
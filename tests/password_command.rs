@@ -0,0 +1,82 @@
+#![cfg(unix)]
+
+use shticker_book_unwritten::{
+    Error,
+    password_command::{self, Outcome},
+};
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn password(outcome: Outcome) -> String {
+    match outcome {
+        Outcome::Password(p) => p,
+        other => panic!("Expected a password, got {other:?}"),
+    }
+}
+
+#[test]
+fn first_line_of_stdout_is_the_password() {
+    let outcome = password_command::run(
+        "printf 'hunter2\\nurl: example.com\\n'",
+        "toon",
+        TIMEOUT,
+    )
+    .unwrap();
+
+    assert_eq!(password(outcome), "hunter2");
+}
+
+#[test]
+fn username_is_substituted_and_exported() {
+    let outcome = password_command::run(
+        "echo \"{username}:$SBU_USERNAME\"",
+        "some.toon",
+        TIMEOUT,
+    )
+    .unwrap();
+
+    assert_eq!(password(outcome), "some.toon:some.toon");
+}
+
+#[test]
+fn unsafe_usernames_are_not_substituted() {
+    let err = password_command::run("echo {username}", "a;rm -rf ~", TIMEOUT)
+        .unwrap_err();
+    assert!(matches!(err, Error::PasswordCommandOutput(..)), "{err:?}");
+
+    // ...But are fine when only the environment variable is used.
+    let outcome =
+        password_command::run("echo \"$SBU_USERNAME\"", "a;b", TIMEOUT)
+            .unwrap();
+    assert_eq!(password(outcome), "a;b");
+}
+
+#[test]
+fn nonzero_exit_means_ask_instead() {
+    let outcome =
+        password_command::run("echo oops; exit 3", "toon", TIMEOUT).unwrap();
+
+    let Outcome::Failed(status) = outcome else {
+        panic!("Expected failure, got {outcome:?}");
+    };
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn empty_output_is_an_error() {
+    let err = password_command::run("true", "toon", TIMEOUT).unwrap_err();
+
+    assert!(matches!(err, Error::PasswordCommandOutput(..)), "{err:?}");
+}
+
+#[test]
+fn slow_commands_are_killed() {
+    let start = Instant::now();
+    let err =
+        password_command::run("exec sleep 30", "toon", Duration::from_secs(1))
+            .unwrap_err();
+
+    assert!(matches!(err, Error::PasswordCommandTimeout(..)), "{err:?}");
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
@@ -0,0 +1,391 @@
+mod common;
+
+use common::{Rng, TempDir};
+use shticker_book_unwritten::{Error, decompress, diff, patch};
+use std::{
+    fs,
+    io::Read,
+    panic,
+    path::{Path, PathBuf},
+};
+
+/// What the patches in `tests/fixtures/malformed_patches` are applied to.
+const OLD: &[u8] = b"hello old world\n\
+                     hello old world\n\
+                     hello old world\n\
+                     hello old world\n";
+
+/// What the patches in `tests/fixtures/malformed_patches` would produce, if
+/// they weren't malformed.
+const NEW: &[u8] = b"hello new world\n\
+                     hello new world\n\
+                     hello new world\n\
+                     hello new world\n\
+                     more\n";
+
+fn fixture_dir(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// The name & contents of each patch in `fixture_dir(name)`.
+fn fixtures(name: &str) -> Vec<(String, Vec<u8>)> {
+    let dir = fixture_dir(name);
+    let mut fixtures: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into();
+
+            (name, fs::read(&path).unwrap())
+        })
+        .filter(|(name, _): &(String, _)| name.ends_with(".patch"))
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "no fixtures in {}", dir.display());
+
+    fixtures
+}
+
+/// Each patch in the corpus is truncated or hostile in its own way, & is
+/// rejected as such, rather than panicking or being taken for an I/O error.
+#[test]
+fn malformed_patches_are_rejected() {
+    let dir = TempDir::new();
+    let old_path = dir.join("old");
+    fs::write(&old_path, OLD).unwrap();
+
+    for (name, contents) in fixtures("malformed_patches") {
+        let patch_path = dir.join(&name);
+        fs::write(&patch_path, &contents).unwrap();
+        let new_path = dir.join("new");
+
+        let res = panic::catch_unwind(|| {
+            patch::patch_file(
+                &patch_path,
+                &old_path,
+                &new_path,
+                1 << 20,
+                &common::sha1(NEW),
+            )
+        });
+
+        let err = match res {
+            Ok(Err(err)) => err,
+            Ok(Ok(())) => panic!("{name} was applied"),
+            Err(_) => panic!("{name} panicked"),
+        };
+        assert!(
+            matches!(
+                err,
+                Error::BadPatch(..)
+                    | Error::PatchTooLarge(..)
+                    | Error::BadPatchSize,
+            ),
+            "{name}: {err:?}",
+        );
+        assert!(!new_path.exists(), "{name}");
+        assert!(!dir.join("new.tmp").exists(), "{name}");
+    }
+}
+
+/// The in-memory bspatch that `patch::patch_file` replaced, which reads the
+/// whole patch & old file up front, for the streaming one to be checked
+/// against. It trusts its input, so it's only given well-formed patches.
+fn reference_patch(patch: &[u8], old: &[u8]) -> Vec<u8> {
+    let decode = |block: &[u8]| {
+        let mut decoded = Vec::new();
+        decompress::sniffing_decoder(block)
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+
+        decoded
+    };
+    let offtin = |buf: &[u8]| {
+        let y = i64::from_le_bytes(buf[..8].try_into().unwrap());
+
+        if y < 0 { -(y & i64::MAX) } else { y }
+    };
+
+    let (newsize, ctrl, diff, extra, interleaved) =
+        if patch.starts_with(b"BSDIFF40") {
+            let ctrl_len = offtin(&patch[8..]) as usize;
+            let diff_len = offtin(&patch[16..]) as usize;
+            let diff_start = 32 + ctrl_len;
+            let extra_start = diff_start + diff_len;
+
+            (
+                offtin(&patch[24..]) as usize,
+                decode(&patch[32..diff_start]),
+                decode(&patch[diff_start..extra_start]),
+                decode(&patch[extra_start..]),
+                false,
+            )
+        } else {
+            assert!(patch.starts_with(b"ENDSLEY/BSDIFF43"));
+
+            let blocks = decode(&patch[24..]);
+            (
+                offtin(&patch[16..]) as usize,
+                blocks,
+                Vec::new(),
+                Vec::new(),
+                true,
+            )
+        };
+
+    let mut new = vec![0u8; newsize];
+    let (mut ctrl_pos, mut diff_pos, mut extra_pos) = (0, 0, 0);
+    let mut oldpos = 0i64;
+    let mut newpos = 0;
+    while newpos < newsize {
+        let triple: Vec<_> =
+            (0..3).map(|i| offtin(&ctrl[ctrl_pos + 8 * i..])).collect();
+        ctrl_pos += 24;
+        let (diff_len, extra_len) = (triple[0] as usize, triple[1] as usize);
+
+        let diff = if interleaved {
+            diff_pos = ctrl_pos;
+            ctrl_pos += diff_len;
+            &ctrl
+        } else {
+            &diff
+        };
+        for i in 0..diff_len {
+            new[newpos + i] = diff[diff_pos + i];
+            if let Some(old_byte) = usize::try_from(oldpos + i as i64)
+                .ok()
+                .and_then(|old_index| old.get(old_index))
+            {
+                new[newpos + i] = new[newpos + i].wrapping_add(*old_byte);
+            }
+        }
+        diff_pos += diff_len;
+        newpos += diff_len;
+        oldpos += diff_len as i64;
+
+        let extra = if interleaved {
+            extra_pos = ctrl_pos;
+            ctrl_pos += extra_len;
+            &ctrl
+        } else {
+            &extra
+        };
+        new[newpos..newpos + extra_len]
+            .copy_from_slice(&extra[extra_pos..extra_pos + extra_len]);
+        extra_pos += extra_len;
+        newpos += extra_len;
+        oldpos += triple[2];
+    }
+
+    new
+}
+
+/// Builds a patch, in both formats, out of random (but well-formed) control
+/// triples that write `newsize` bytes altogether, & that seek all over, both
+/// within & well outside of an old file of `oldsize` bytes.
+fn random_patches(
+    rng: &mut Rng,
+    oldsize: usize,
+    newsize: usize,
+) -> [Vec<u8>; 2] {
+    let off = |x: i64| {
+        let mut buf = x.unsigned_abs().to_le_bytes();
+        if x < 0 {
+            buf[7] |= 0x80;
+        }
+
+        buf
+    };
+
+    let (mut ctrl, mut diff, mut extra, mut interleaved) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let mut left = newsize;
+    while left > 0 {
+        // Sometimes bigger than a chunk, so that chunks get split up.
+        let max_len = if rng.below(4) == 0 { 0x2_0000 } else { 0x800 };
+        let diff_len = rng.below(max_len).min(left as u64) as usize;
+        left -= diff_len;
+        let extra_len = rng.below(max_len).min(left as u64) as usize;
+        left -= extra_len;
+        let span = 2 * oldsize as i64 + 0x100;
+        let seek = rng.below(2 * span as u64) as i64 - span;
+
+        let diff_bytes: Vec<_> = (0..diff_len)
+            .map(|_| {
+                if rng.below(8) == 0 {
+                    rng.next() as u8
+                } else {
+                    0
+                }
+            })
+            .collect();
+        let extra_bytes = rng.bytes(extra_len);
+        let triple =
+            [off(diff_len as i64), off(extra_len as i64), off(seek)].concat();
+
+        ctrl.extend_from_slice(&triple);
+        diff.extend_from_slice(&diff_bytes);
+        extra.extend_from_slice(&extra_bytes);
+        interleaved.extend_from_slice(&triple);
+        interleaved.extend_from_slice(&diff_bytes);
+        interleaved.extend_from_slice(&extra_bytes);
+    }
+
+    let (ctrl, diff) = (common::bzip2(&ctrl), common::bzip2(&diff));
+    let bsdiff40 = [
+        &b"BSDIFF40"[..],
+        &off(ctrl.len() as i64),
+        &off(diff.len() as i64),
+        &off(newsize as i64),
+        &ctrl,
+        &diff,
+        &common::bzip2(&extra),
+    ]
+    .concat();
+    let bsdiff43 = [
+        &b"ENDSLEY/BSDIFF43"[..],
+        &off(newsize as i64),
+        &common::bzip2(&interleaved),
+    ]
+    .concat();
+
+    [bsdiff40, bsdiff43]
+}
+
+/// Applies `patch` to `old` with both the streaming implementation & the
+/// reference one, & checks that they agree.
+fn assert_same_as_reference(dir: &TempDir, patch: &[u8], old: &[u8]) {
+    let expected = reference_patch(patch, old);
+    let (patch_path, old_path, new_path) =
+        (dir.join("patch"), dir.join("old"), dir.join("new"));
+    fs::write(&patch_path, patch).unwrap();
+    fs::write(&old_path, old).unwrap();
+
+    patch::patch_file(
+        &patch_path,
+        &old_path,
+        &new_path,
+        u64::MAX,
+        &common::sha1(&expected),
+    )
+    .unwrap();
+
+    assert_eq!(fs::read(&new_path).unwrap(), expected);
+}
+
+#[test]
+fn streaming_agrees_with_in_memory_on_random_patches() {
+    let dir = TempDir::new();
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+    for (oldsize, newsize) in [
+        (0, 0),
+        (0, 0x1000),
+        (0x1000, 0),
+        (0x100, 0x100),
+        (0x1_0000, 0x1_0000),
+        (0x1_0001, 0x2_ffff),
+        (0x3_0000, 0x1_8000),
+    ] {
+        for _ in 0..4 {
+            let old = rng.bytes(oldsize);
+            for patch in random_patches(&mut rng, oldsize, newsize) {
+                assert_same_as_reference(&dir, &patch, &old);
+            }
+        }
+    }
+}
+
+/// Patches as `bsdiff` itself would make them, between versions of a file
+/// that differ in the ways that game files tend to.
+#[test]
+fn streaming_agrees_with_in_memory_on_realistic_patches() {
+    let dir = TempDir::new();
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut old = b"phase file header\n".repeat(0x1000);
+    old.extend(rng.bytes(0x2_0000));
+    let mut new = old.clone();
+    // Changed in place, moved, inserted into, & cut short.
+    new[0x100..0x180].fill(b'!');
+    new.copy_within(0x1_0000..0x1_8000, 0x2_0000);
+    new.splice(0x8000..0x8000, rng.bytes(0x3000));
+    new.truncate(new.len() - 0x1234);
+    let (old_path, new_path, patch_path) =
+        (dir.join("v1"), dir.join("v2"), dir.join("v1-v2.patch"));
+    fs::write(&old_path, &old).unwrap();
+    fs::write(&new_path, &new).unwrap();
+
+    diff::diff_files(&old_path, &new_path, &patch_path).unwrap();
+
+    let patch = fs::read(&patch_path).unwrap();
+    assert_eq!(reference_patch(&patch, &old), new);
+    assert_same_as_reference(&dir, &patch, &old);
+}
+
+/// Patches made by something other than `diff::diff_files`, in each format,
+/// give exactly the output that they were made to give.
+#[test]
+fn known_answers() {
+    let dir = TempDir::new();
+    let fixture_dir = fixture_dir("known_answers");
+    let old_path = fixture_dir.join("old");
+
+    for (name, _) in fixtures("known_answers") {
+        let patch_path = fixture_dir.join(&name);
+        let expected = fs::read(patch_path.with_extension("new")).unwrap();
+        let new_path = dir.join("new");
+
+        patch::patch_file(
+            &patch_path,
+            &old_path,
+            &new_path,
+            expected.len() as u64,
+            &common::sha1(&expected),
+        )
+        .unwrap_or_else(|e| panic!("{name}: {e:?}"));
+
+        assert_eq!(fs::read(&new_path).unwrap(), expected, "{name}");
+    }
+}
+
+#[test]
+fn unknown_magic_is_rejected() {
+    let dir = TempDir::new();
+    let old_path = dir.join("old");
+    fs::write(&old_path, OLD).unwrap();
+    let bsdiff40 =
+        fs::read(fixture_dir("known_answers").join("bsdiff40_basic.patch"))
+            .unwrap();
+
+    for magic in [
+        &b"BSDIFF41"[..],
+        b"bsdiff40",
+        b"ENDSLEY/BSDIFF44",
+        b"ENDSLEY/",
+        b"\0\0\0\0\0\0\0\0",
+    ] {
+        let mut patch = bsdiff40.clone();
+        patch[..magic.len()].copy_from_slice(magic);
+        let patch_path = dir.join("patch");
+        fs::write(&patch_path, &patch).unwrap();
+
+        let err = patch::patch_file(
+            &patch_path,
+            &old_path,
+            dir.join("new"),
+            u64::MAX,
+            &common::sha1(NEW),
+        )
+        .unwrap_err();
+
+        assert!(
+            matches!(err, Error::BadPatchVersion),
+            "{}: {err:?}",
+            String::from_utf8_lossy(magic),
+        );
+        assert!(!dir.join("new").exists());
+    }
+}
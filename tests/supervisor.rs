@@ -0,0 +1,503 @@
+#![cfg(unix)]
+// The tests that log in need passwords saved in the config (see
+// `tests/login.rs`), so much of this is only used without `secret-store`.
+#![cfg_attr(
+    all(target_os = "linux", feature = "secret-store"),
+    allow(dead_code, unused_imports)
+)]
+
+mod common;
+
+use common::{MockServer, Recorder, TempDir};
+use serde_json::json;
+use shticker_book_unwritten::{
+    Error, Updater,
+    config::RestartPolicy,
+    login::{self, LaunchMode},
+    supervisor::{Decision, Supervisor},
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::process::ExitStatusExt,
+    process::{Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+const POLICY: RestartPolicy = RestartPolicy {
+    max_restarts: 3,
+    window: 60,
+    backoff: 10,
+    max_backoff: 25,
+};
+
+fn exit_code(code: i32) -> ExitStatus {
+    ExitStatus::from_raw(code << 8)
+}
+
+fn success() -> serde_json::Value {
+    json!({
+        "success": "true",
+        "cookie": "COOKIE",
+        "gameserver": "gameserver.example:7198",
+    })
+}
+
+/// Crashes `pid`, & relaunches it as `pid + 1` once it's due.
+fn crash(supervisor: &mut Supervisor, pid: u32, now: Instant) -> Decision {
+    let decision = supervisor.exited(pid, exit_code(1), now);
+    if let Decision::Restart { delay } = decision {
+        let mut due = supervisor.due(now + delay);
+        assert_eq!(due.len(), 1);
+        supervisor.relaunched(due.pop().unwrap(), pid + 1);
+    }
+
+    decision
+}
+
+#[test]
+fn crashes_are_relaunched_with_backoff() {
+    let mut supervisor = Supervisor::new();
+    let start = Instant::now();
+    supervisor.watch(1, "toon".to_owned(), LaunchMode::Launch, Some(POLICY));
+
+    let decision = supervisor.exited(1, exit_code(1), start);
+
+    assert_eq!(
+        decision,
+        Decision::Restart {
+            delay: Duration::from_secs(10),
+        },
+    );
+    assert!(supervisor.due(start).is_empty());
+    let due = supervisor.due(start + Duration::from_secs(10));
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].username, "toon");
+    assert_eq!(due[0].mode, LaunchMode::Launch);
+    supervisor.relaunched(due.into_iter().next().unwrap(), 2);
+    assert!(supervisor.is_supervised(2));
+    assert_eq!(supervisor.history("toon").len(), 1);
+    assert_eq!(supervisor.history("toon")[0].pid, Some(1));
+    assert_eq!(supervisor.history("toon")[0].exit_code, Some(1));
+    assert_eq!(supervisor.history("toon")[0].relaunched_pid, Some(2));
+
+    // Doubling, up to `max_backoff`.
+    assert_eq!(
+        crash(&mut supervisor, 2, start + Duration::from_secs(11)),
+        Decision::Restart {
+            delay: Duration::from_secs(20),
+        },
+    );
+    assert_eq!(
+        crash(&mut supervisor, 3, start + Duration::from_secs(32)),
+        Decision::Restart {
+            delay: Duration::from_secs(25),
+        },
+    );
+    assert_eq!(
+        crash(&mut supervisor, 4, start + Duration::from_secs(58)),
+        Decision::GaveUp,
+    );
+    assert!(!supervisor.is_active());
+    assert_eq!(supervisor.history("toon").len(), 4);
+}
+
+#[test]
+fn old_crashes_fall_out_of_the_window() {
+    let mut supervisor = Supervisor::new();
+    let start = Instant::now();
+    supervisor.watch(1, "toon".to_owned(), LaunchMode::Launch, Some(POLICY));
+
+    for pid in 1..=3 {
+        assert!(matches!(
+            crash(&mut supervisor, pid, start),
+            Decision::Restart { .. },
+        ));
+    }
+    let decision = crash(&mut supervisor, 4, start + Duration::from_secs(60));
+
+    assert_eq!(
+        decision,
+        Decision::Restart {
+            delay: Duration::from_secs(10),
+        },
+    );
+}
+
+#[test]
+fn only_supervised_crashes_are_relaunched() {
+    let mut supervisor = Supervisor::new();
+    let now = Instant::now();
+    supervisor.watch(1, "a".to_owned(), LaunchMode::Launch, Some(POLICY));
+    supervisor.watch(2, "b".to_owned(), LaunchMode::Launch, None);
+    supervisor.watch(3, "c".to_owned(), LaunchMode::Launch, Some(POLICY));
+    supervisor.watch(4, "d".to_owned(), LaunchMode::Launch, None);
+    assert!(supervisor.set_policy(4, Some(POLICY)));
+    assert!(!supervisor.set_policy(5, Some(POLICY)));
+
+    assert_eq!(supervisor.exited(1, exit_code(0), now), Decision::Exited);
+    assert_eq!(
+        supervisor.exited(2, exit_code(1), now),
+        Decision::Unsupervised,
+    );
+    // E.g. closed with the `kill` command.
+    supervisor.forget(3);
+    assert_eq!(
+        supervisor.exited(3, ExitStatus::from_raw(9), now),
+        Decision::Unsupervised,
+    );
+    assert!(matches!(
+        supervisor.exited(4, ExitStatus::from_raw(9), now),
+        Decision::Restart { .. },
+    ));
+    assert_eq!(supervisor.history("d")[0].exit_code, None);
+    assert!(supervisor.history("a").is_empty());
+    assert_eq!(supervisor.pending().len(), 1);
+}
+
+#[test]
+fn failed_relaunches_count_as_crashes() {
+    let mut supervisor = Supervisor::new();
+    let start = Instant::now();
+    let mode = LaunchMode::Exec("true".to_owned());
+    supervisor.watch(1, "toon".to_owned(), mode.clone(), Some(POLICY));
+    supervisor.exited(1, exit_code(1), start);
+
+    let mut decisions = Vec::new();
+    for secs in 1..60 {
+        let now = start + Duration::from_secs(secs);
+        if let Some(pending) = supervisor.due(now).pop() {
+            assert_eq!(pending.mode, mode);
+            decisions.push(supervisor.relaunch_failed(pending, now));
+        }
+    }
+
+    assert_eq!(
+        decisions,
+        [
+            Decision::Restart {
+                delay: Duration::from_secs(20),
+            },
+            Decision::Restart {
+                delay: Duration::from_secs(25),
+            },
+            Decision::GaveUp,
+        ],
+    );
+    assert!(
+        supervisor.history("toon")[1..]
+            .iter()
+            .all(|restart| restart.pid.is_none())
+    );
+}
+
+/// However many times an instance has crashed recently, it's never relaunched
+/// with less of a delay than the time before.
+#[test]
+fn backoff_saturates() {
+    let policy = RestartPolicy {
+        max_restarts: 100,
+        window: 3_600,
+        backoff: 1,
+        max_backoff: 300,
+    };
+    let mut supervisor = Supervisor::new();
+    let now = Instant::now();
+    supervisor.watch(1, "toon".to_owned(), LaunchMode::Launch, Some(policy));
+
+    let delays: Vec<_> = (1..=80)
+        .map(|pid| match crash(&mut supervisor, pid, now) {
+            Decision::Restart { delay } => delay.as_secs(),
+            decision => panic!("{decision:?} after {pid} crashes"),
+        })
+        .collect();
+
+    assert_eq!(delays[..4], [1, 2, 4, 8]);
+    assert!(delays.is_sorted(), "{delays:?}");
+    assert!(delays[9..].iter().all(|&delay| delay == 300), "{delays:?}");
+}
+
+#[cfg(not(all(target_os = "linux", feature = "secret-store")))]
+#[test]
+fn relaunching_needs_no_one_there() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, b"#!/bin/sh\n");
+    server.push_login_response(success());
+    server.push_login_response(json!({
+        "success": "partial",
+        "responseToken": "AUTH",
+        "banner": "Token?",
+    }));
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    let config_path = dir.join("config.json");
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+    let recorder = Recorder::new();
+
+    let (username, mut child, _) = login::relaunch(
+        &mut config,
+        &config_path,
+        &client,
+        true,
+        &recorder,
+        "toon",
+        &LaunchMode::Launch,
+        false,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(username, "toon");
+    assert!(child.wait().unwrap().success());
+    assert_eq!(recorder.named("game_launched").len(), 1);
+
+    // No token can be typed in.
+    let err = login::relaunch(
+        &mut config,
+        &config_path,
+        &client,
+        true,
+        &recorder,
+        "toon",
+        &LaunchMode::Launch,
+        false,
+    )
+    .unwrap_err();
+    assert!(
+        matches!(&err, Error::UnattendedLogin(name, _) if name == "toon"),
+        "{err:?}",
+    );
+
+    // No password can be typed in.
+    let err = login::relaunch(
+        &mut config,
+        &config_path,
+        &client,
+        true,
+        &recorder,
+        "someone else",
+        &LaunchMode::Launch,
+        false,
+    )
+    .unwrap_err();
+    assert!(matches!(err, Error::UnattendedLogin(_, _)), "{err:?}");
+    assert_eq!(server.pending_login_responses(), 0);
+}
+
+/// Relaunching updates an outdated install first, like launching does, but
+/// only if nothing else is running.
+#[cfg(not(all(target_os = "linux", feature = "secret-store")))]
+#[test]
+fn relaunching_updates_only_when_nothing_else_is_running() {
+    use shticker_book_unwritten::config::LaunchCheck;
+    use std::fs;
+
+    const GAME_V1: &[u8] = b"#!/bin/sh\n# version 1\n";
+    const GAME_V2: &[u8] = b"#!/bin/sh\n# version 2\n";
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, GAME_V1);
+    server.push_login_response(success());
+    server.push_login_response(success());
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.launch_check = LaunchCheck::Update;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    let config_path = dir.join("config.json");
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+    server.publish(common::GAME_EXECUTABLE, GAME_V2);
+    let game = config.install_dir.join(common::GAME_EXECUTABLE);
+
+    for (others_running, expected) in [(true, GAME_V1), (false, GAME_V2)] {
+        let (_, mut child, _) = login::relaunch(
+            &mut config,
+            &config_path,
+            &client,
+            true,
+            &Recorder::new(),
+            "toon",
+            &LaunchMode::Launch,
+            others_running,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(child.wait().unwrap().success());
+
+        assert_eq!(fs::read(&game).unwrap(), expected, "{others_running}");
+    }
+}
+
+/// An update that was staged while the game was running is applied before
+/// the game is relaunched after crashing, rather than once the relaunched
+/// game exits too.
+#[cfg(not(all(target_os = "linux", feature = "secret-store")))]
+#[test]
+fn staged_update_is_applied_before_relaunching() {
+    /// Crashes once it's told to.
+    const GAME_V1: &[u8] = b"#!/bin/sh\n\
+        while [ ! -e crash ]; do sleep 0.1; done\n\
+        exit 3\n";
+    const GAME_V2: &[u8] = b"#!/bin/sh\nexec sleep 60\n";
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, GAME_V1);
+    server.push_login_response(success());
+    server.push_login_response(success());
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    config.supervise = Some(RestartPolicy {
+        backoff: 0,
+        ..RestartPolicy::default()
+    });
+    Updater::new(&config, &common::client())
+        .update(false)
+        .unwrap();
+    let config_path = dir.join("config.json");
+    shticker_book_unwritten::config::commit_config(&config, &config_path)
+        .unwrap();
+
+    let mut child =
+        Command::new(env!("CARGO_BIN_EXE_shticker_book_unwritten"))
+            .arg("-c")
+            .arg(&config_path)
+            .args(["-n", "-o", "json", "-u", "toon"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut events: Vec<serde_json::Value> = Vec::new();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    for line in lines.by_ref() {
+        let event: serde_json::Value =
+            serde_json::from_str(&line.unwrap()).unwrap();
+        let launches = events
+            .iter()
+            .filter(|e| e["event"] == "game_launched")
+            .count();
+        if event["event"] == "game_launched" && launches == 0 {
+            // Staged, since the game is running.
+            server.publish(common::GAME_EXECUTABLE, GAME_V2);
+            stdin.write_all(b"update\n").unwrap();
+        } else if event["event"] == "update_finished" {
+            std::fs::write(config.install_dir.join("crash"), "").unwrap();
+        }
+        let relaunched = event["event"] == "game_launched" && launches == 1;
+        events.push(event);
+        if relaunched {
+            break;
+        }
+    }
+    stdin.write_all(b"kill toon\nquit\n").unwrap();
+    drop(stdin);
+    events.extend(
+        lines.map(|line| serde_json::from_str(&line.unwrap()).unwrap()),
+    );
+    assert!(child.wait().unwrap().success());
+
+    let positions = |name: &str| -> Vec<_> {
+        (0..events.len())
+            .filter(|&i| events[i]["event"] == name)
+            .collect()
+    };
+    let applied = positions("staged_update_applied");
+    let launches = positions("game_launched");
+    assert_eq!(applied.len(), 1, "{events:?}");
+    assert!(positions("instance_exited")[0] < applied[0]);
+    assert!(applied[0] < launches[1], "{events:?}");
+    assert_eq!(
+        std::fs::read(config.install_dir.join(common::GAME_EXECUTABLE))
+            .unwrap(),
+        GAME_V2,
+    );
+}
+
+/// A supervised instance that crashes is relaunched from command mode while
+/// it sits waiting for a command, & `instances` shows the crash.
+#[cfg(not(all(target_os = "linux", feature = "secret-store")))]
+#[test]
+fn command_mode_relaunches_crashes() {
+    /// Crashes the first time, & keeps running the second time.
+    const FLAKY_GAME: &[u8] = b"#!/bin/sh\n\
+        if [ -e crashed ]; then exec sleep 60; fi\n\
+        touch crashed\n\
+        exit 3\n";
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(common::GAME_EXECUTABLE, FLAKY_GAME);
+    server.push_login_response(success());
+    server.push_login_response(success());
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    config.supervise = Some(RestartPolicy {
+        backoff: 0,
+        ..RestartPolicy::default()
+    });
+    Updater::new(&config, &common::client())
+        .update(false)
+        .unwrap();
+    let config_path = dir.join("config.json");
+    shticker_book_unwritten::config::commit_config(&config, &config_path)
+        .unwrap();
+
+    let mut child =
+        Command::new(env!("CARGO_BIN_EXE_shticker_book_unwritten"))
+            .arg("-c")
+            .arg(&config_path)
+            .args(["-n", "-o", "json", "-u", "toon"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut events = Vec::new();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    for line in lines.by_ref() {
+        let event: serde_json::Value =
+            serde_json::from_str(&line.unwrap()).unwrap();
+        let relaunched = event["event"] == "game_launched"
+            && events.iter().any(|e: &serde_json::Value| {
+                e["event"] == "relaunch_scheduled"
+            });
+        events.push(event);
+        if relaunched {
+            break;
+        }
+    }
+    stdin.write_all(b"instances\nkill toon\nquit\n").unwrap();
+    drop(stdin);
+    events.extend(
+        lines.map(|line| serde_json::from_str(&line.unwrap()).unwrap()),
+    );
+    assert!(child.wait().unwrap().success());
+
+    let named = |name: &str| -> Vec<_> {
+        events.iter().filter(|e| e["event"] == name).collect()
+    };
+    let launches = named("game_launched");
+    assert_eq!(launches.len(), 2);
+    let scheduled = named("relaunch_scheduled");
+    assert_eq!(scheduled.len(), 1);
+    assert_eq!(scheduled[0]["username"], "toon");
+    assert_eq!(scheduled[0]["pid"], launches[0]["pid"]);
+    assert_eq!(scheduled[0]["delay_secs"], 0);
+    let exits = named("instance_exited");
+    assert_eq!(exits[0]["exit_code"], 3);
+    let listing = events.iter().find(|e| e.get("instances").is_some());
+    let instance = &listing.unwrap()["instances"][0];
+    assert_eq!(instance["pid"], launches[1]["pid"]);
+    assert_eq!(instance["supervised"], true);
+    let restarts = &instance["restarts"];
+    assert_eq!(restarts[0]["pid"], launches[0]["pid"]);
+    assert_eq!(restarts[0]["exit_code"], 3);
+    assert_eq!(restarts[0]["relaunched_pid"], launches[1]["pid"]);
+    // Killed on purpose, so not relaunched again.
+    assert_eq!(named("instance_exited").len(), 1);
+}
@@ -0,0 +1,43 @@
+use shticker_book_unwritten::{Error, totp};
+
+/// The ASCII secret "12345678901234567890" from RFC 6238's test vectors.
+const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+#[test]
+fn rfc_6238_test_vectors() {
+    // RFC 6238 gives 8-digit codes; these are their last 6 digits.
+    for (time, code) in [
+        (59, "287082"),
+        (1_111_111_109, "081804"),
+        (1_111_111_111, "050471"),
+        (1_234_567_890, "005924"),
+        (2_000_000_000, "279037"),
+        (20_000_000_000, "353130"),
+    ] {
+        assert_eq!(totp::code_at(RFC_SECRET, time).unwrap(), code, "{time}");
+    }
+}
+
+#[test]
+fn secrets_are_forgiving_of_formatting() {
+    let expected = totp::decode_secret(RFC_SECRET).unwrap();
+    assert_eq!(expected, b"12345678901234567890");
+
+    for secret in [
+        "gezd gnbv gy3t qojq gezd gnbv gy3t qojq",
+        "GEZDG-NBVGY-3TQOJ-QGEZD-GNBVG-Y3TQO-JQ======",
+        "otpauth://totp/Toontown:someone?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+         &issuer=Toontown",
+    ] {
+        assert_eq!(totp::decode_secret(secret).unwrap(), expected, "{secret}");
+    }
+}
+
+#[test]
+fn bad_secrets_are_rejected() {
+    for secret in ["", "   ", "GEZDGNBV1", "otpauth://totp/Toontown?issuer=x"]
+    {
+        let err = totp::decode_secret(secret).unwrap_err();
+        assert!(matches!(err, Error::BadTotpSecret(_)), "{secret}: {err:?}");
+    }
+}
@@ -0,0 +1,668 @@
+mod common;
+
+use common::{GAME_EXECUTABLE, MockServer, Recorder, TempDir};
+use shticker_book_unwritten::{
+    Error, Updater,
+    update::{self, Action},
+};
+use std::{fs, num::NonZeroUsize};
+
+const GAME_V1: &[u8] = b"#!/bin/sh\n# version 1 of the game\n";
+const GAME_V2: &[u8] = b"#!/bin/sh\n# version 2 of the game, now longer\n";
+const PHASE_V1: &[u8] = b"phase file, version 1";
+const PHASE_V2: &[u8] = b"phase file, version 2";
+
+fn tries(n: usize) -> NonZeroUsize {
+    NonZeroUsize::new(n).unwrap()
+}
+
+#[test]
+fn full_install() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    server.publish_for("OtherEngine.exe", b"not for us", &["amiga"]);
+    let config = server.config(&dir);
+    let client = common::client();
+    let recorder = Recorder::new();
+
+    Updater::new(&config, &client)
+        .observer(&recorder)
+        .update(false)
+        .unwrap();
+
+    let install = dir.join("install");
+    assert_eq!(fs::read(install.join(GAME_EXECUTABLE)).unwrap(), GAME_V1);
+    assert_eq!(fs::read(install.join("phase_3.mf")).unwrap(), PHASE_V1);
+    assert!(!install.join("OtherEngine.exe").exists());
+    assert_eq!(server.downloads_of("OtherEngine.exe.bz2"), 0);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(install.join(GAME_EXECUTABLE))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_ne!(mode & 0o100, 0);
+    }
+
+    let finished = recorder.named("update_finished");
+    assert_eq!(finished.len(), 1);
+    assert_eq!(finished[0]["needs_update"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn second_update_downloads_nothing() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+
+    Updater::new(&config, &client).update(false).unwrap();
+    let plan = Updater::new(&config, &client).plan().unwrap();
+
+    assert_eq!(plan.files.len(), 1);
+    assert_eq!(plan.files[0].action, Action::Skip);
+    assert_eq!(server.downloads_of(&format!("{GAME_EXECUTABLE}.bz2")), 1);
+}
+
+#[test]
+fn patch_upgrade() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+
+    Updater::new(&config, &client).update(false).unwrap();
+
+    server.publish(GAME_EXECUTABLE, GAME_V2);
+    server.publish("phase_3.mf", PHASE_V2);
+    let recorder = Recorder::new();
+    Updater::new(&config, &client)
+        .observer(&recorder)
+        .update(false)
+        .unwrap();
+
+    let install = dir.join("install");
+    assert_eq!(fs::read(install.join(GAME_EXECUTABLE)).unwrap(), GAME_V2);
+    assert_eq!(fs::read(install.join("phase_3.mf")).unwrap(), PHASE_V2);
+    // Only the original full downloads; the upgrade was done with patches.
+    assert_eq!(server.downloads_of(&format!("{GAME_EXECUTABLE}.bz2")), 1);
+    assert_eq!(server.downloads_of("phase_3.mf.bz2"), 1);
+    assert_eq!(recorder.named("patch_applied").len(), 2);
+}
+
+#[test]
+fn locally_modified_file_is_downloaded_again() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    server.publish("phase_3.mf", PHASE_V2);
+    let config = server.config(&dir);
+    let client = common::client();
+    fs::create_dir_all(&config.install_dir).unwrap();
+    fs::write(config.install_dir.join("phase_3.mf"), b"scribbled on").unwrap();
+    let recorder = Recorder::new();
+
+    Updater::new(&config, &client)
+        .observer(&recorder)
+        .update(false)
+        .unwrap();
+
+    assert_eq!(
+        fs::read(config.install_dir.join("phase_3.mf")).unwrap(),
+        PHASE_V2,
+    );
+    assert_eq!(server.downloads_of("phase_3.mf.bz2"), 1);
+    let status = recorder
+        .named("file_status")
+        .into_iter()
+        .find(|e| e["file"] == "phase_3.mf")
+        .unwrap();
+    assert_eq!(status["status"], "outdated");
+    assert_eq!(status["patch_available"], false);
+}
+
+/// Files are checked against their SHA-256 hashes rather than their SHA-1
+/// hashes when the manifest has both, & patches can be keyed by either.
+#[test]
+fn sha256_hashes_are_preferred() {
+    /// Gives `name`'s entry SHA-256 hashes of `contents`, makes its SHA-1
+    /// hashes wrong, & rekeys its patches by SHA-256.
+    fn switch_to_sha256(server: &MockServer, name: &str, contents: &[u8]) {
+        server.edit_manifest(|manifest| {
+            let entry = &mut manifest[name];
+            entry["sha256"] = common::sha256_hex(contents).into();
+            entry["compSha256"] =
+                common::sha256_hex(&common::bzip2(contents)).into();
+            entry["hash"] = common::sha1_hex(b"wrong").into();
+            entry["compHash"] = common::sha1_hex(b"wrong").into();
+            let patches = entry["patches"].as_object_mut().unwrap();
+            for from in [GAME_V1, GAME_V2] {
+                if let Some(patch) = patches.remove(&common::sha1_hex(from)) {
+                    patches.insert(common::sha256_hex(from), patch);
+                }
+            }
+        });
+    }
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    switch_to_sha256(&server, GAME_EXECUTABLE, GAME_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+
+    server.publish(GAME_EXECUTABLE, GAME_V2);
+    switch_to_sha256(&server, GAME_EXECUTABLE, GAME_V2);
+    let recorder = Recorder::new();
+    Updater::new(&config, &client)
+        .observer(&recorder)
+        .update(false)
+        .unwrap();
+
+    assert_eq!(
+        fs::read(config.install_dir.join(GAME_EXECUTABLE)).unwrap(),
+        GAME_V2,
+    );
+    assert_eq!(recorder.named("patch_applied").len(), 1);
+    assert_eq!(server.downloads_of(&format!("{GAME_EXECUTABLE}.bz2")), 1);
+}
+
+#[test]
+fn hash_mismatch_is_retried() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    server.corrupt_next("phase_3.mf.bz2", 2);
+    let config = server.config(&dir);
+    let client = common::client();
+    let recorder = Recorder::new();
+
+    Updater::new(&config, &client)
+        .max_tries(tries(3))
+        .observer(&recorder)
+        .update(false)
+        .unwrap();
+
+    assert_eq!(
+        fs::read(config.install_dir.join("phase_3.mf")).unwrap(),
+        PHASE_V1,
+    );
+    assert_eq!(server.downloads_of("phase_3.mf.bz2"), 3);
+    assert_eq!(recorder.named("hash_mismatch").len(), 2);
+}
+
+#[test]
+fn corrupt_patch_is_retried() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+
+    server.publish("phase_3.mf", PHASE_V2);
+    let plan = Updater::new(&config, &client).plan().unwrap();
+    let planned = plan.files.iter().find(|pf| pf.file == "phase_3.mf");
+    let Some(Action::Patch { patch, .. }) = planned.map(|pf| &pf.action)
+    else {
+        panic!("Expected a patch, got {planned:?}");
+    };
+    server.corrupt_next(patch, 1);
+    Updater::new(&config, &client).apply_plan(&plan).unwrap();
+
+    assert_eq!(
+        fs::read(config.install_dir.join("phase_3.mf")).unwrap(),
+        PHASE_V2,
+    );
+    assert_eq!(server.downloads_of(patch), 2);
+}
+
+#[test]
+fn hash_mismatch_gives_up_eventually() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish("phase_3.mf", PHASE_V1);
+    server.corrupt_next("phase_3.mf.bz2", 2);
+    let config = server.config(&dir);
+    let client = common::client();
+
+    let err = Updater::new(&config, &client)
+        .max_tries(tries(2))
+        .update(false)
+        .unwrap_err();
+
+    assert!(matches!(err, Error::HashMismatch(..)), "{err:?}");
+    assert!(!config.install_dir.join("phase_3.mf").exists());
+}
+
+/// A patch that produces something other than what the manifest says it
+/// should is an error, & leaves the installed file as it was.
+#[test]
+fn bad_patch_output_is_not_installed() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+    server.publish("phase_3.mf", PHASE_V2);
+    server.edit_manifest(|manifest| {
+        manifest["phase_3.mf"]["hash"] = common::sha1_hex(b"not v2").into();
+    });
+
+    let err = Updater::new(&config, &client).update(false).unwrap_err();
+
+    assert!(matches!(err, Error::HashMismatch(..)), "{err:?}");
+    let install = &config.install_dir;
+    assert_eq!(fs::read(install.join("phase_3.mf")).unwrap(), PHASE_V1);
+    assert!(!install.join("phase_3.mf.tmp").exists());
+}
+
+#[test]
+fn server_errors_are_retried() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    server.fail_next(common::MANIFEST_PATH, 1);
+    server.fail_next("/cdn/phase_3.mf.bz2", 1);
+    let config = server.config(&dir);
+    let client = common::client();
+    let recorder = Recorder::new();
+
+    Updater::new(&config, &client)
+        .max_tries(tries(2))
+        .observer(&recorder)
+        .update(false)
+        .unwrap();
+
+    let retries = recorder.named("retry");
+    assert_eq!(retries.len(), 2);
+    assert!(retries[0].get("file").is_none());
+    assert_eq!(retries[1]["file"], "phase_3.mf.bz2");
+}
+
+#[test]
+fn staged_update_leaves_install_alone_until_applied() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+
+    server.publish(GAME_EXECUTABLE, GAME_V2);
+    let staged = Updater::new(&config, &client).stage().unwrap();
+    let exe = config.install_dir.join(GAME_EXECUTABLE);
+    assert_eq!(staged.len(), 1);
+    assert_eq!(fs::read(&exe).unwrap(), GAME_V1);
+
+    let recorder = Recorder::new();
+    staged.apply(&config, &recorder).unwrap();
+    assert_eq!(fs::read(&exe).unwrap(), GAME_V2);
+    assert_eq!(recorder.named("staged_update_applied").len(), 1);
+}
+
+#[test]
+fn hostile_manifest_names_are_rejected() {
+    type Edit = fn(&mut serde_json::Map<String, serde_json::Value>);
+
+    let edits: [(&str, Edit); 6] = [
+        ("absolute file name", |manifest| {
+            let entry = manifest["phase_3.mf"].clone();
+            manifest.insert("/tmp/sbu-escape".to_owned(), entry);
+        }),
+        ("file name in a parent directory", |manifest| {
+            let entry = manifest["phase_3.mf"].clone();
+            manifest.insert("../escape".to_owned(), entry);
+        }),
+        ("file name that climbs out", |manifest| {
+            let entry = manifest["phase_3.mf"].clone();
+            manifest.insert("phase/../../escape".to_owned(), entry);
+        }),
+        ("download name in a parent directory", |manifest| {
+            manifest["phase_3.mf"]["dl"] = "../escape.bz2".into();
+        }),
+        ("absolute download name", |manifest| {
+            manifest["phase_3.mf"]["dl"] = "/tmp/sbu-escape.bz2".into();
+        }),
+        ("patch name in a parent directory", |manifest| {
+            let patches = manifest[GAME_EXECUTABLE]["patches"]
+                .as_object_mut()
+                .unwrap();
+            for patch in patches.values_mut() {
+                patch["filename"] = "../escape.patch.bz2".into();
+            }
+        }),
+    ];
+
+    for (case, edit) in edits {
+        let server = MockServer::start();
+        let dir = TempDir::new();
+        server.publish(GAME_EXECUTABLE, GAME_V1);
+        server.publish(GAME_EXECUTABLE, GAME_V2);
+        server.publish("phase_3.mf", PHASE_V1);
+        server.edit_manifest(edit);
+        let config = server.config(&dir);
+
+        let err = Updater::new(&config, &common::client())
+            .update(false)
+            .unwrap_err();
+
+        assert!(matches!(err, Error::UnsafePath(_, _)), "{case}: {err:?}");
+        assert!(!dir.join("escape").exists(), "{case}");
+        assert!(!dir.join("escape.bz2").exists(), "{case}");
+        assert!(!dir.join("escape.patch.bz2").exists(), "{case}");
+    }
+}
+
+/// Symlinks in the install directory, whether they're files or directories
+/// that files are in, are never followed.
+#[cfg(unix)]
+#[test]
+fn symlinks_in_the_install_are_not_followed() {
+    use std::os::unix::fs::symlink;
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase/phase_3.mf", PHASE_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    fs::create_dir_all(config.install_dir.join("phase")).unwrap();
+    Updater::new(&config, &client).update(false).unwrap();
+    let outside = dir.join("outside");
+    fs::create_dir(&outside).unwrap();
+    fs::write(outside.join("phase_3.mf"), b"not the game's").unwrap();
+    fs::write(outside.join("victim"), b"not the game's").unwrap();
+
+    // A symlinked parent directory.
+    let phase_dir = config.install_dir.join("phase");
+    fs::remove_dir_all(&phase_dir).unwrap();
+    symlink(&outside, &phase_dir).unwrap();
+    server.publish("phase/phase_3.mf", PHASE_V2);
+    let err = Updater::new(&config, &client).update(false).unwrap_err();
+    assert!(matches!(err, Error::UnsafePath(_, _)), "{err:?}");
+    assert_eq!(
+        fs::read(outside.join("phase_3.mf")).unwrap(),
+        b"not the game's",
+    );
+    fs::remove_file(&phase_dir).unwrap();
+    server.edit_manifest(|manifest| {
+        manifest.remove("phase/phase_3.mf");
+    });
+
+    // A symlinked file.
+    let exe_path = config.install_dir.join(GAME_EXECUTABLE);
+    fs::remove_file(&exe_path).unwrap();
+    symlink(outside.join("victim"), &exe_path).unwrap();
+    let err = Updater::new(&config, &client).update(false).unwrap_err();
+    assert!(matches!(err, Error::UnsafePath(_, _)), "{err:?}");
+    assert_eq!(fs::read(outside.join("victim")).unwrap(), b"not the game's");
+
+    // A symlink where the patched file is written before it's moved into
+    // place, which is replaced rather than written through.
+    fs::remove_file(&exe_path).unwrap();
+    fs::write(&exe_path, GAME_V1).unwrap();
+    let temp_path = config.install_dir.join(format!("{GAME_EXECUTABLE}.tmp"));
+    symlink(outside.join("victim"), &temp_path).unwrap();
+    server.publish(GAME_EXECUTABLE, GAME_V2);
+    let recorder = Recorder::new();
+    Updater::new(&config, &client)
+        .observer(&recorder)
+        .update(false)
+        .unwrap();
+    assert_eq!(recorder.named("patch_applied").len(), 1);
+    assert_eq!(fs::read(&exe_path).unwrap(), GAME_V2);
+    assert!(fs::symlink_metadata(&temp_path).is_err());
+    assert_eq!(fs::read(outside.join("victim")).unwrap(), b"not the game's");
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish("phase_3.mf", PHASE_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    let recorder = Recorder::new();
+
+    Updater::new(&config, &client)
+        .observer(&recorder)
+        .update(true)
+        .unwrap();
+
+    assert!(!config.install_dir.join("phase_3.mf").exists());
+    assert!(!config.cache_dir.join("hash_index.json").exists());
+    assert_eq!(server.downloads_of("phase_3.mf.bz2"), 0);
+    let finished = recorder.named("update_finished");
+    assert_eq!(finished[0]["dry"], true);
+    assert_eq!(finished[0]["needs_update"][0], "phase_3.mf");
+}
+
+#[test]
+fn plan_is_written_then_applied() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+    server.publish(GAME_EXECUTABLE, GAME_V2);
+    server.publish("phase_4.mf", PHASE_V2);
+    let plan_path = dir.join("plan.json");
+
+    Updater::new(&config, &client)
+        .plan()
+        .unwrap()
+        .write(&plan_path)
+        .unwrap();
+    // Planning downloads nothing.
+    assert!(!config.install_dir.join("phase_4.mf").exists());
+    assert_eq!(server.downloads_of("phase_4.mf.bz2"), 0);
+    let plan = update::Plan::read(&plan_path).unwrap();
+    let action = |name: &str| {
+        &plan.files.iter().find(|pf| pf.file == name).unwrap().action
+    };
+    assert!(matches!(action(GAME_EXECUTABLE), Action::Patch { .. }));
+    assert_eq!(*action("phase_3.mf"), Action::Skip);
+    assert!(matches!(action("phase_4.mf"), Action::Download { .. }));
+    let recorder = Recorder::new();
+    Updater::new(&config, &client)
+        .observer(&recorder)
+        .apply_plan(&plan)
+        .unwrap();
+
+    let install = &config.install_dir;
+    assert_eq!(fs::read(install.join(GAME_EXECUTABLE)).unwrap(), GAME_V2);
+    assert_eq!(fs::read(install.join("phase_3.mf")).unwrap(), PHASE_V1);
+    assert_eq!(fs::read(install.join("phase_4.mf")).unwrap(), PHASE_V2);
+    assert_eq!(recorder.named("patch_applied").len(), 1);
+    assert!(Updater::new(&config, &client).check().unwrap().is_empty());
+}
+
+#[test]
+fn outdated_plan_is_refused() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    Updater::new(&config, &client).update(false).unwrap();
+    server.publish(GAME_EXECUTABLE, GAME_V2);
+    let plan_path = dir.join("plan.json");
+    Updater::new(&config, &client)
+        .plan()
+        .unwrap()
+        .write(&plan_path)
+        .unwrap();
+    let plan = update::Plan::read(&plan_path).unwrap();
+
+    // Changed after the plan was written.
+    let exe_path = config.install_dir.join(GAME_EXECUTABLE);
+    fs::write(&exe_path, b"#!/bin/sh\n# modded\n").unwrap();
+    let err = Updater::new(&config, &client)
+        .apply_plan(&plan)
+        .unwrap_err();
+
+    assert!(
+        matches!(&err, Error::PlanOutdated(path) if *path == exe_path),
+        "{err:?}",
+    );
+    assert_eq!(fs::read(&exe_path).unwrap(), b"#!/bin/sh\n# modded\n");
+    assert_eq!(server.downloads_of(&format!("{GAME_EXECUTABLE}.bz2")), 1);
+}
+
+#[test]
+fn check_trusts_the_hash_index_but_updating_does_not() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    let updater = Updater::new(&config, &client);
+    updater.update(false).unwrap();
+    assert!(updater.check().unwrap().is_empty());
+
+    // Same size & modification time, so only hashing it would tell.
+    let phase_path = config.install_dir.join("phase_3.mf");
+    let modified = fs::metadata(&phase_path).unwrap().modified().unwrap();
+    fs::write(&phase_path, PHASE_V2).unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&phase_path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    assert!(updater.check().unwrap().is_empty());
+    // The game executable has changed size.
+    let exe_path = config.install_dir.join(GAME_EXECUTABLE);
+    fs::write(&exe_path, GAME_V2).unwrap();
+    assert_eq!(updater.check().unwrap(), [GAME_EXECUTABLE]);
+    // Compared against the indexed hash, which is of `PHASE_V1`.
+    server.publish(GAME_EXECUTABLE, GAME_V2);
+    server.publish("phase_3.mf", PHASE_V2);
+    assert_eq!(updater.check().unwrap(), ["phase_3.mf"]);
+
+    server.publish("phase_3.mf", PHASE_V1);
+    updater.update(false).unwrap();
+
+    assert_eq!(fs::read(&phase_path).unwrap(), PHASE_V1);
+    assert!(updater.check().unwrap().is_empty());
+}
+
+#[test]
+fn check_without_a_usable_hash_index() {
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, GAME_V1);
+    server.publish("phase_3.mf", PHASE_V1);
+    let config = server.config(&dir);
+    let client = common::client();
+    let updater = Updater::new(&config, &client);
+    updater.update(false).unwrap();
+    fs::write(config.cache_dir.join("hash_index.json"), b"{\"Ph").unwrap();
+    fs::remove_file(config.install_dir.join("phase_3.mf")).unwrap();
+
+    assert_eq!(updater.check().unwrap(), ["phase_3.mf"]);
+    assert_eq!(
+        fs::read(config.install_dir.join(GAME_EXECUTABLE)).unwrap(),
+        GAME_V1,
+    );
+    assert_eq!(server.downloads_of("phase_3.mf.bz2"), 1);
+}
+
+/// An update that's staged while the game is running is applied from
+/// command mode as soon as the game exits, without waiting for another
+/// command to be typed in.
+#[cfg(all(unix, not(all(target_os = "linux", feature = "secret-store"))))]
+#[test]
+fn staged_update_is_applied_once_the_game_exits() {
+    use serde_json::json;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        process::{Command, Stdio},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    const SHORT_LIVED_GAME: &[u8] = b"#!/bin/sh\nsleep 2\n";
+
+    let server = MockServer::start();
+    let dir = TempDir::new();
+    server.publish(GAME_EXECUTABLE, SHORT_LIVED_GAME);
+    server.push_login_response(json!({
+        "success": "true",
+        "cookie": "COOKIE",
+        "gameserver": "gameserver.example:7198",
+    }));
+    let mut config = server.config(&dir);
+    config.store_passwords = true;
+    config.add_account("toon".to_owned(), "hunter2".to_owned());
+    Updater::new(&config, &common::client())
+        .update(false)
+        .unwrap();
+    let config_path = dir.join("config.json");
+    shticker_book_unwritten::config::commit_config(&config, &config_path)
+        .unwrap();
+
+    let mut child =
+        Command::new(env!("CARGO_BIN_EXE_shticker_book_unwritten"))
+            .arg("-c")
+            .arg(&config_path)
+            .args(["-n", "-o", "json", "-u", "toon"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let (updating_tx, updating) = mpsc::channel::<()>();
+    let (applied_tx, applied) = mpsc::channel();
+    // Quits after the update is applied, or after giving up on it.
+    let typist = thread::spawn(move || {
+        updating.recv().unwrap();
+        stdin.write_all(b"update\n").unwrap();
+        let in_time = applied.recv_timeout(Duration::from_secs(30)).is_ok();
+        stdin.write_all(b"quit\n").unwrap();
+
+        in_time
+    });
+    let mut events = Vec::new();
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        let event: serde_json::Value =
+            serde_json::from_str(&line.unwrap()).unwrap();
+        if event["event"] == "game_launched" {
+            server.publish(GAME_EXECUTABLE, GAME_V2);
+            updating_tx.send(()).unwrap();
+        } else if event["event"] == "staged_update_applied" {
+            applied_tx.send(()).unwrap();
+        }
+        events.push(event);
+    }
+    assert!(typist.join().unwrap(), "not applied until quitting");
+    assert!(child.wait().unwrap().success());
+
+    let position =
+        |name: &str| events.iter().position(|e| e["event"] == name).unwrap();
+    assert!(position("instance_exited") < position("staged_update_applied"));
+    assert_eq!(
+        fs::read(config.install_dir.join(GAME_EXECUTABLE)).unwrap(),
+        GAME_V2,
+    );
+}